@@ -22,6 +22,8 @@ pub mod events;
 pub mod fieldpath;
 pub mod sandbox;
 pub mod sandbox_ttrpc;
+pub mod sandboxer;
+pub mod sandboxer_ttrpc;
 pub mod streaming;
 pub mod streaming_ttrpc;
 pub mod timestamp;
@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
 use containerd_sandbox::spec::Mount;
 use serde::{Deserialize, Serialize};
@@ -29,6 +29,7 @@ pub const DRIVERSCSITYPE: &str = "scsi";
 pub const DRIVERNVDIMMTYPE: &str = "nvdimm";
 pub const DRIVEREPHEMERALTYPE: &str = "ephemeral";
 pub const DRIVERLOCALTYPE: &str = "local";
+pub const DRIVEROVERLAYTYPE: &str = "overlay";
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Storage {
@@ -48,7 +49,7 @@ pub struct Storage {
 
 impl Storage {
     pub fn is_for_mount(&self, m: &Mount) -> bool {
-        self.host_source == m.source && self.r#type == m.r#type
+        self.host_source == mount_host_source(m) && self.r#type == m.r#type
     }
 
     pub fn ref_count(&self) -> u32 {
@@ -68,3 +69,49 @@ impl Storage {
         self.ref_container.remove(container_id);
     }
 }
+
+// mount_host_source is the host source of the storage for the mount,
+// all overlay mounts have the source "overlay" so they are told apart by the options.
+pub fn mount_host_source(m: &Mount) -> String {
+    if m.r#type == "overlay" {
+        format!("{}:{}", m.source, m.options.join(","))
+    } else {
+        m.source.clone()
+    }
+}
+
+// OverlayLayer is a lower layer of the overlay storage assembled in the guest,
+// which is on a block device attached to the vm, it is passed in the driver options
+// of the storage in the format "<driver>,<addr>,<fstype>,<path in the device>".
+#[derive(Clone, Debug, PartialEq)]
+pub struct OverlayLayer {
+    pub driver: String,
+    pub addr: String,
+    pub fstype: String,
+    pub path: String,
+}
+
+impl OverlayLayer {
+    pub fn parse(option: &str) -> Option<Self> {
+        let fields = option.splitn(4, ',').collect::<Vec<&str>>();
+        if fields.len() != 4 || fields[..3].iter().any(|x| x.is_empty()) {
+            return None;
+        }
+        Some(Self {
+            driver: fields[0].to_string(),
+            addr: fields[1].to_string(),
+            fstype: fields[2].to_string(),
+            path: fields[3].to_string(),
+        })
+    }
+}
+
+impl Display for OverlayLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.driver, self.addr, self.fstype, self.path
+        )
+    }
+}
@@ -0,0 +1,135 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::{Error, Result};
+use log::info;
+use serde::de::DeserializeOwned;
+use tokio::fs::remove_file;
+use ttrpc::{
+    asynchronous::{Server, TtrpcContext},
+    Code,
+};
+use vmm_common::api::{
+    empty::Empty,
+    sandboxer::{CheckpointRequest, InterfaceRequest, RestoreRequest},
+    sandboxer_ttrpc::{create_sandboxer_service, SandboxerService},
+};
+
+use crate::{
+    sandbox::KuasarSandboxer,
+    vm::{Hooks, VMFactory, VM},
+};
+
+// AdminService serves the operations on sandboxes that containerd never calls,
+// it shares the sandboxes with the sandboxer serving containerd.
+struct AdminService<F: VMFactory, H: Hooks<F::VM>> {
+    sandboxer: KuasarSandboxer<F, H>,
+}
+
+#[async_trait]
+impl<F, H> SandboxerService for AdminService<F, H>
+where
+    F: VMFactory + Sync + Send + 'static,
+    F::VM: VM + DeserializeOwned + Sync + Send + 'static,
+    H: Hooks<F::VM> + Sync + Send + 'static,
+{
+    async fn checkpoint(
+        &self,
+        _ctx: &TtrpcContext,
+        req: CheckpointRequest,
+    ) -> ttrpc::Result<Empty> {
+        self.sandboxer
+            .checkpoint(&req.sandbox_id, &req.dir, req.leave_running)
+            .await
+            .map_err(to_ttrpc_error)?;
+        Ok(Empty::new())
+    }
+
+    async fn restore(&self, _ctx: &TtrpcContext, req: RestoreRequest) -> ttrpc::Result<Empty> {
+        self.sandboxer
+            .restore(&req.sandbox_id, &req.dir)
+            .await
+            .map_err(to_ttrpc_error)?;
+        Ok(Empty::new())
+    }
+
+    async fn hot_plug_interface(
+        &self,
+        _ctx: &TtrpcContext,
+        req: InterfaceRequest,
+    ) -> ttrpc::Result<Empty> {
+        self.sandboxer
+            .hot_plug_interface(&req.sandbox_id, &req.name)
+            .await
+            .map_err(to_ttrpc_error)?;
+        Ok(Empty::new())
+    }
+
+    async fn hot_unplug_interface(
+        &self,
+        _ctx: &TtrpcContext,
+        req: InterfaceRequest,
+    ) -> ttrpc::Result<Empty> {
+        self.sandboxer
+            .hot_unplug_interface(&req.sandbox_id, &req.name)
+            .await
+            .map_err(to_ttrpc_error)?;
+        Ok(Empty::new())
+    }
+}
+
+impl<F, H> KuasarSandboxer<F, H>
+where
+    F: VMFactory + Sync + Send + 'static,
+    F::VM: VM + DeserializeOwned + Sync + Send + 'static,
+    H: Hooks<F::VM> + Sync + Send + 'static,
+{
+    // start_admin_server serves the admin service on the unix socket of the address,
+    // the server stops when the returned server is dropped.
+    pub async fn start_admin_server(&self, address: &str) -> Result<Server> {
+        // the socket is left if the previous sandboxer exits without removing it
+        remove_file(address).await.unwrap_or_default();
+        let service = create_sandboxer_service(Arc::new(Box::new(AdminService {
+            sandboxer: self.clone(),
+        })));
+        let mut server = Server::new()
+            .bind(&format!("unix://{}", address))
+            .map_err(|e| anyhow!("failed to bind admin socket {}: {}", address, e))?
+            .register_service(service);
+        server
+            .start()
+            .await
+            .map_err(|e| anyhow!("failed to start admin server: {}", e))?;
+        info!("admin server is listening on {}", address);
+        Ok(server)
+    }
+}
+
+fn to_ttrpc_error(e: Error) -> ttrpc::Error {
+    let code = match &e {
+        Error::NotFound(_) => Code::NOT_FOUND,
+        Error::AlreadyExist(_) => Code::ALREADY_EXISTS,
+        Error::InvalidArgument(_) => Code::INVALID_ARGUMENT,
+        Error::Unimplemented(_) => Code::UNIMPLEMENTED,
+        Error::ResourceExhausted(_) => Code::RESOURCE_EXHAUSTED,
+        _ => Code::INTERNAL,
+    };
+    ttrpc::Error::RpcStatus(ttrpc::get_status(code, e.to_string()))
+}
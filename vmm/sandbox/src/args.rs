@@ -44,6 +44,14 @@ pub struct Args {
     )]
     pub listen: String,
 
+    /// Address for sandboxer's admin server, default is `/run/vmm-sandboxer-admin.sock`
+    #[arg(
+        long,
+        value_name = "FILE",
+        default_value = "/run/vmm-sandboxer-admin.sock"
+    )]
+    pub admin: String,
+
     // log_level is optional and should not have default value if not given, since
     // it can be defined in configuration file.
     /// Logging level for sandboxer [trace, debug, info, warn, error, fatal, panic]
@@ -64,6 +72,7 @@ mod tests {
         assert_eq!(args.config, "/var/lib/kuasar/config.toml");
         assert_eq!(args.dir, "/run/kuasar-vmm");
         assert_eq!(args.listen, "/run/vmm-sandboxer.sock");
+        assert_eq!(args.admin, "/run/vmm-sandboxer-admin.sock");
         assert!(args.log_level.is_none());
     }
 }
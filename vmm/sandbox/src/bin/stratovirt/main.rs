@@ -17,6 +17,7 @@ limitations under the License.
 use std::path::Path;
 
 use clap::Parser;
+use log::error;
 use vmm_common::{signal, trace};
 use vmm_sandboxer::{
     args,
@@ -59,6 +60,22 @@ async fn main() {
         sandboxer.recover(&args.dir).await;
     }
 
+    // Reclaim the resources left by sandboxes unknown to the sandboxer
+    sandboxer.start_gc(&args.dir).await;
+
+    // Boot pre-warmed vms if the vm pool is enabled
+    sandboxer.start_vm_pool().await;
+
+    // Serve the operations not in the sandbox api, such as checkpoint and restore
+    // the sandboxer still serves containerd if the admin server fails to start
+    let _admin_server = match sandboxer.start_admin_server(&args.admin).await {
+        Ok(s) => Some(s),
+        Err(e) => {
+            error!("failed to start admin server on {}: {}", args.admin, e);
+            None
+        }
+    };
+
     // Run the sandboxer
     containerd_sandbox::run(
         "kuasar-vmm-sandboxer-stratovirt",
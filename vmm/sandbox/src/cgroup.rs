@@ -19,7 +19,8 @@ use std::error::Error;
 use anyhow::{anyhow, Ok, Result};
 use cgroups_rs::{
     cgroup_builder::CgroupBuilder, cpu::CpuController, cpuset::CpuSetController,
-    hugetlb::HugeTlbController, memory::MemController, Cgroup,
+    hierarchies::is_cgroup2_unified_mode, hugetlb::HugeTlbController, memory::MemController,
+    Cgroup, Controller,
 };
 use containerd_sandbox::{cri::api::v1::LinuxContainerResources, data::SandboxData};
 use serde::{Deserialize, Serialize};
@@ -33,6 +34,9 @@ pub const DEFAULT_CGROUP_PARENT_PATH: &str = "kuasar-vmm";
 pub const VCPU_CGROUP_NAME: &str = "vcpu";
 pub const POD_OVERHEAD_CGROUP_NAME: &str = "pod_overhead";
 
+const CGROUP_TYPE_FILE: &str = "cgroup.type";
+const CGROUP_TYPE_THREADED: &str = "threaded";
+
 /// `SandboxCgroup` represents a set of cgroups for a sandbox.
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct SandboxCgroup {
@@ -68,6 +72,13 @@ impl SandboxCgroup {
             .set_specified_controllers(vec!["cpu".to_string()])
             .build(cgroups_rs::hierarchies::auto())?;
 
+        // On cgroup v2 the vcpu threads are split from the vmm process, which is only
+        // allowed inside a threaded subtree, so both child cgroups have to be threaded.
+        if is_cgroup2_unified_mode() {
+            set_cgroup_threaded(&vcpu_cgroup)?;
+            set_cgroup_threaded(&pod_overhead_cgroup)?;
+        }
+
         Ok(SandboxCgroup {
             cgroup_parent_path: cgroup_parent_path.to_string(),
             sandbox_cgroup,
@@ -76,6 +87,31 @@ impl SandboxCgroup {
         })
     }
 
+    // load_sandbox_cgroups loads the existing cgroups of the sandbox without creating any of them,
+    // None is returned if the sandbox cgroup does not exist.
+    pub fn load_sandbox_cgroups(cgroup_parent_path: &str, sandbox_id: &str) -> Option<Self> {
+        let sandbox_cgroup_path = format!("{}/{}", cgroup_parent_path, sandbox_id);
+        let sandbox_cgroup_rela_path = sandbox_cgroup_path.trim_start_matches('/');
+        let sandbox_cgroup =
+            Cgroup::load(cgroups_rs::hierarchies::auto(), sandbox_cgroup_rela_path);
+        if !sandbox_cgroup.exists() {
+            return None;
+        }
+        let load_cpu_cgroup = |name: &str| {
+            Cgroup::load_with_specified_controllers(
+                cgroups_rs::hierarchies::auto(),
+                format!("{}/{}", sandbox_cgroup_rela_path, name),
+                vec!["cpu".to_string()],
+            )
+        };
+        Some(SandboxCgroup {
+            cgroup_parent_path: cgroup_parent_path.to_string(),
+            vcpu_cgroup: load_cpu_cgroup(VCPU_CGROUP_NAME),
+            pod_overhead_cgroup: load_cpu_cgroup(POD_OVERHEAD_CGROUP_NAME),
+            sandbox_cgroup,
+        })
+    }
+
     pub fn update_res_for_sandbox_cgroups(&self, sandbox_data: &SandboxData) -> Result<()> {
         // apply the total resources = sum(sum(containers_resources) + pod_overhead)) in the sandbox cgroup dir
         if let Some(total_resources) = get_total_resources(sandbox_data) {
@@ -110,7 +146,8 @@ impl SandboxCgroup {
 
         if let Some(all_vcpu_threads) = vcpu_threads {
             // Move vmm process from parent sandbox cgroup into pod_overhead cgroup
-            // Then move the all vcpu threads of vmm process into vcpu cgroup
+            // Then move the all vcpu threads of vmm process into vcpu cgroup,
+            // on cgroup v2 this is written to the cgroup.threads of the threaded vcpu cgroup.
             for (_, vcpu_thread_tid) in all_vcpu_threads.vcpus {
                 self.vcpu_cgroup.add_task((vcpu_thread_tid as u64).into())?;
             }
@@ -132,6 +169,24 @@ fn apply_cpu_resource(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<
         .controller_of()
         .ok_or_else(|| anyhow!("No cpu controller attached!"))?;
 
+    if is_cgroup2_unified_mode() {
+        // cgroup v2 sets quota and period together in cpu.max,
+        // and uses cpu.weight instead of cpu.shares
+        let quota = (res.cpu_quota != 0).then_some(res.cpu_quota);
+        let period = if res.cpu_period != 0 {
+            Some(res.cpu_period.try_into()?)
+        } else {
+            None
+        };
+        if quota.is_some() || period.is_some() {
+            cpu_controller.set_cfs_quota_and_period(quota, period)?;
+        }
+        if res.cpu_shares != 0 {
+            cpu_controller.set_shares(convert_shares_to_weight(res.cpu_shares.try_into()?))?;
+        }
+        return Ok(());
+    }
+
     if res.cpu_period != 0 {
         cpu_controller.set_cfs_period(res.cpu_period.try_into()?)?;
     }
@@ -154,13 +209,25 @@ fn apply_memory_resource(cgroup: &Cgroup, res: &LinuxContainerResources) -> Resu
         mem_controller.set_limit(res.memory_limit_in_bytes)?;
     }
     if res.memory_swap_limit_in_bytes != 0 {
-        mem_controller.set_memswap_limit(res.memory_swap_limit_in_bytes)?;
+        if is_cgroup2_unified_mode() {
+            // memory.swap.max of cgroup v2 limits the swap only, not memory plus swap
+            mem_controller.set_memswap_limit(convert_memswap_to_swap(
+                res.memory_limit_in_bytes,
+                res.memory_swap_limit_in_bytes,
+            ))?;
+        } else {
+            mem_controller.set_memswap_limit(res.memory_swap_limit_in_bytes)?;
+        }
     }
 
     Ok(())
 }
 
 fn apply_cpuset_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    // The cpuset controller may not be enabled on cgroup v2, so only require it if needed.
+    if res.cpuset_cpus.is_empty() && res.cpuset_mems.is_empty() {
+        return Ok(());
+    }
     let cpuset_controller: &CpuSetController = cgroup
         .controller_of()
         .ok_or_else(|| anyhow!("No cpuset controller attached!"))?;
@@ -176,6 +243,9 @@ fn apply_cpuset_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Res
 }
 
 fn apply_hugetlb_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
+    if res.hugepage_limits.is_empty() {
+        return Ok(());
+    }
     let hugetlb_controller: &HugeTlbController = cgroup
         .controller_of()
         .ok_or_else(|| anyhow!("No hugetlb controller attached!"))?;
@@ -185,6 +255,45 @@ fn apply_hugetlb_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Re
     Ok(())
 }
 
+// set_cgroup_threaded turns a cgroup v2 into a threaded cgroup,
+// its parent becomes the threaded domain of the subtree.
+fn set_cgroup_threaded(cgroup: &Cgroup) -> Result<()> {
+    let cpu_controller: &CpuController = cgroup
+        .controller_of()
+        .ok_or_else(|| anyhow!("No cpu controller attached!"))?;
+    let type_path = cpu_controller.path().join(CGROUP_TYPE_FILE);
+    // cgroup type can not be changed back once it is threaded, skip it when recovering.
+    let cgroup_type = std::fs::read_to_string(&type_path)
+        .map_err(|e| anyhow!("failed to read {}: {}", type_path.display(), e))?;
+    if cgroup_type.trim() != CGROUP_TYPE_THREADED {
+        std::fs::write(&type_path, CGROUP_TYPE_THREADED)
+            .map_err(|e| anyhow!("failed to write {}: {}", type_path.display(), e))?;
+    }
+    Ok(())
+}
+
+// convert_shares_to_weight converts cpu shares of cgroup v1 in range [2, 262144]
+// to cpu weight of cgroup v2 in range [1, 10000], the same as runc does.
+fn convert_shares_to_weight(shares: u64) -> u64 {
+    if shares == 0 {
+        return 0;
+    }
+    let shares = shares.clamp(2, 262144);
+    1 + ((shares - 2) * 9999) / 262142
+}
+
+// convert_memswap_to_swap converts the memory+swap limit of cgroup v1
+// to the swap only limit of cgroup v2, -1 means unlimited.
+fn convert_memswap_to_swap(memory: i64, memory_swap: i64) -> i64 {
+    if memory_swap == -1 || memory <= 0 {
+        return memory_swap;
+    }
+    if memory_swap < memory {
+        return 0;
+    }
+    memory_swap - memory
+}
+
 fn remove_sandbox_cgroup(cgroup: &Cgroup) -> Result<()> {
     // get the tids in the current cgroup and then move the tids to parent cgroup
     let tids = cgroup.tasks();
@@ -261,7 +370,7 @@ mod tests {
 
     #[test]
     fn test_create_sandbox_cgroups() {
-        // Currently only support cgroup V1, cgroup V2 is not supported now
+        // These cases check the cgroup v1 hierarchy layout
         if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
             return;
         }
@@ -335,7 +444,7 @@ mod tests {
 
     #[test]
     fn test_update_res_for_sandbox_cgroups_success() {
-        // Currently only support cgroup V1, cgroup V2 is not supported now
+        // These cases check the cgroup v1 hierarchy layout
         if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
             return;
         }
@@ -418,4 +527,24 @@ mod tests {
 
         assert_eq!(sandbox_cgroups.remove_sandbox_cgroups().is_ok(), true);
     }
+
+    #[test]
+    fn test_convert_shares_to_weight() {
+        assert_eq!(convert_shares_to_weight(0), 0);
+        assert_eq!(convert_shares_to_weight(2), 1);
+        assert_eq!(convert_shares_to_weight(1024), 39);
+        assert_eq!(convert_shares_to_weight(262144), 10000);
+        // out of range shares should be clamped
+        assert_eq!(convert_shares_to_weight(1), 1);
+        assert_eq!(convert_shares_to_weight(1 << 20), 10000);
+    }
+
+    #[test]
+    fn test_convert_memswap_to_swap() {
+        assert_eq!(convert_memswap_to_swap(1024, 2048), 1024);
+        assert_eq!(convert_memswap_to_swap(1024, 1024), 0);
+        assert_eq!(convert_memswap_to_swap(1024, -1), -1);
+        assert_eq!(convert_memswap_to_swap(0, 2048), 2048);
+        assert_eq!(convert_memswap_to_swap(2048, 1024), 0);
+    }
 }
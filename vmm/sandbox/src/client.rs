@@ -38,6 +38,7 @@ use nix::{
     time::{clock_gettime, ClockId},
     unistd::close,
 };
+use rand::RngCore;
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::UnixStream,
@@ -48,8 +49,12 @@ use ttrpc::{
     r#async::{Client, TtrpcContext},
 };
 use vmm_common::api::{
-    sandbox::{CheckRequest, SetupSandboxRequest, SyncClockPacket},
+    sandbox::{
+        CheckRequest, OnlineCPUMemRequest, ReseedRandomDevRequest, SetupSandboxRequest,
+        SyncClockPacket, UpdateInterfacesRequest, UpdateNeighborsRequest, UpdateRoutesRequest,
+    },
     sandbox_ttrpc::SandboxServiceClient,
+    streaming_ttrpc::StreamingClient,
 };
 
 const HVSOCK_RETRY_TIMEOUT_IN_MS: u64 = 10;
@@ -57,12 +62,18 @@ const HVSOCK_RETRY_TIMEOUT_IN_MS: u64 = 10;
 const NEW_TTRPC_CLIENT_TIMEOUT: u64 = 45;
 const TIME_SYNC_PERIOD: u64 = 60;
 const TIME_DIFF_TOLERANCE_IN_MS: u64 = 10;
+const RESEED_RANDOM_DATA_SIZE: usize = 512;
 
 pub(crate) async fn new_sandbox_client(address: &str) -> Result<SandboxServiceClient> {
     let client = new_ttrpc_client_with_timeout(address, NEW_TTRPC_CLIENT_TIMEOUT).await?;
     Ok(SandboxServiceClient::new(client))
 }
 
+pub(crate) async fn new_streaming_client(address: &str) -> Result<StreamingClient> {
+    let client = new_ttrpc_client_with_timeout(address, NEW_TTRPC_CLIENT_TIMEOUT).await?;
+    Ok(StreamingClient::new(client))
+}
+
 async fn new_ttrpc_client_with_timeout(address: &str, t: u64) -> Result<Client> {
     let mut last_err = Error::Other(anyhow!(""));
 
@@ -229,6 +240,20 @@ pub(crate) async fn client_check(client: &SandboxServiceClient) -> Result<()> {
     Ok(())
 }
 
+// client_check_once checks the agent only once, it is used by the health check
+// rather than the startup, in which the agent may be not ready yet.
+pub(crate) async fn client_check_once(client: &SandboxServiceClient, timeout: u64) -> Result<()> {
+    let req = CheckRequest::new();
+    client
+        .check(
+            with_timeout(Duration::from_secs(timeout).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("check agent: {:?}", e))?;
+    Ok(())
+}
+
 async fn do_check_agent(client: &SandboxServiceClient, timeout: u64) {
     let req = CheckRequest::new();
     let duration = Duration::from_secs(timeout).as_nanos() as i64;
@@ -253,6 +278,70 @@ pub(crate) async fn client_setup_sandbox(
     Ok(())
 }
 
+pub(crate) async fn client_update_interfaces(
+    client: &SandboxServiceClient,
+    req: &UpdateInterfacesRequest,
+) -> Result<()> {
+    client
+        .update_interfaces(with_timeout(Duration::from_secs(10).as_nanos() as i64), req)
+        .await
+        .map_err(|e| anyhow!("failed to update interfaces: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_update_routes(
+    client: &SandboxServiceClient,
+    req: &UpdateRoutesRequest,
+) -> Result<()> {
+    client
+        .update_routes(with_timeout(Duration::from_secs(10).as_nanos() as i64), req)
+        .await
+        .map_err(|e| anyhow!("failed to update routes: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_update_neighbors(
+    client: &SandboxServiceClient,
+    req: &UpdateNeighborsRequest,
+) -> Result<()> {
+    client
+        .update_neighbors(with_timeout(Duration::from_secs(10).as_nanos() as i64), req)
+        .await
+        .map_err(|e| anyhow!("failed to update neighbors: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_online_cpu_mem(
+    client: &SandboxServiceClient,
+    nb_cpus: u32,
+) -> Result<()> {
+    let mut req = OnlineCPUMemRequest::new();
+    req.nb_cpus = nb_cpus;
+    client
+        .online_cpu_mem(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to online cpu and memory: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_reseed_random(client: &SandboxServiceClient) -> Result<()> {
+    let mut data = vec![0u8; RESEED_RANDOM_DATA_SIZE];
+    rand::thread_rng().fill_bytes(&mut data);
+    let mut req = ReseedRandomDevRequest::new();
+    req.data = data;
+    client
+        .reseed_random_dev(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to reseed random device: {}", e))?;
+    Ok(())
+}
+
 pub(crate) fn client_sync_clock(
     client: &SandboxServiceClient,
     id: &str,
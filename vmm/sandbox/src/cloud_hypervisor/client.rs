@@ -15,28 +15,121 @@ limitations under the License.
 */
 
 use std::{
-    os::unix::net::UnixStream,
+    fmt::Debug,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
     thread::sleep,
     time::{Duration, SystemTime},
 };
 
 use anyhow::anyhow;
-use api_client::{simple_api_command, simple_api_full_command_with_fds_and_response};
-use containerd_sandbox::error::Result;
+use api_client::{
+    simple_api_command, simple_api_full_command_and_response,
+    simple_api_full_command_with_fds_and_response,
+};
+use containerd_sandbox::error::{Error, Result};
 use log::{debug, error, trace};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::task::spawn_blocking;
 
 use crate::{
-    cloud_hypervisor::devices::{block::DiskConfig, AddDeviceResponse, RemoveDeviceRequest},
+    cloud_hypervisor::devices::{
+        block::{DiskConfig, RateLimiterConfig},
+        vfio::DeviceConfig,
+        virtio_net::NetConfig,
+        AddDeviceResponse, RemoveDeviceRequest,
+    },
     device::DeviceInfo,
 };
 
 pub(crate) const CLOUD_HYPERVISOR_START_TIMEOUT_IN_SEC: u64 = 10;
+// Requests for diagnostics should not block the caller if the vmm hangs.
+const CLOUD_HYPERVISOR_QUERY_TIMEOUT_IN_SEC: u64 = 5;
+pub(crate) const VM_STATE_RUNNING: &str = "Running";
 
 pub struct ChClient {
+    socket_path: String,
     socket: UnixStream,
 }
 
+#[derive(Serialize, Debug)]
+pub struct VmResizeRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_vcpus: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_ram: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VmResizeDiskRequest {
+    pub id: String,
+    pub desired_size: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct VmSnapshotConfig {
+    pub destination_url: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RestoreConfig {
+    pub source_url: String,
+    pub prefault: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub net_fds: Vec<RestoredNetConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct VmmPingResponse {
+    pub version: String,
+    #[serde(default)]
+    pub build_version: String,
+    #[serde(default)]
+    pub pid: Option<i64>,
+}
+
+// VmInfo is the part of the response of vm.info used by kuasar,
+// the full vm config and the device tree are ignored.
+#[derive(Deserialize, Debug, Clone)]
+pub struct VmInfo {
+    pub state: String,
+    #[serde(default)]
+    pub memory_actual_size: Option<u64>,
+    #[serde(default)]
+    pub config: VmInfoConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct VmInfoConfig {
+    #[serde(default)]
+    pub cpus: Option<VmInfoCpus>,
+    #[serde(default)]
+    pub memory: Option<VmInfoMemory>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct VmInfoCpus {
+    pub boot_vcpus: u8,
+    pub max_vcpus: u8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct VmInfoMemory {
+    pub size: u64,
+    #[serde(default)]
+    pub hotplug_size: Option<u64>,
+}
+
+// RestoredNetConfig tells the net device in the snapshot to use the fds sent with the request,
+// as the fds of the tap devices are not valid in the new cloud hypervisor process.
+#[derive(Serialize, Debug, Clone)]
+pub struct RestoredNetConfig {
+    pub id: String,
+    pub num_fds: usize,
+}
+
 impl ChClient {
     pub async fn new(socket_path: String) -> Result<Self> {
         let s = socket_path.to_string();
@@ -63,7 +156,10 @@ impl ChClient {
         .await
         .map_err(|e| anyhow!("failed to join thread {}", e))??;
         debug!("connected to api server {}", s);
-        Ok(Self { socket })
+        Ok(Self {
+            socket_path: s,
+            socket,
+        })
     }
 
     pub fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<String> {
@@ -76,39 +172,64 @@ impl ChClient {
                     vhost_user: false,
                     vhost_socket: None,
                     id: blk.id,
+                    rate_limiter_config: RateLimiterConfig::from_throttle(&blk.throttle),
                 };
-                let request_body = serde_json::to_string(&disk_config)
-                    .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", disk_config, e))?;
-                let response_opt = simple_api_full_command_with_fds_and_response(
-                    &mut self.socket,
-                    "PUT",
-                    "vm.add-disk",
-                    Some(&request_body),
-                    vec![],
-                )
-                .map_err(|e| anyhow!("failed to hotplug disk {}, {}", request_body, e))?;
-                if let Some(response_body) = response_opt {
-                    let response = serde_json::from_str::<AddDeviceResponse>(&response_body)
-                        .map_err(|e| {
-                            anyhow!("failed to unmarshal response {}, {}", response_body, e)
-                        })?;
-                    Ok(response.bdf)
-                } else {
-                    Err(anyhow!("no response body from server").into())
-                }
+                self.add_device("vm.add-disk", &disk_config, vec![])
             }
-            DeviceInfo::Tap(_) => {
-                todo!()
+            DeviceInfo::Tap(tap) => {
+                let net_config = NetConfig::new_tap(
+                    &tap.id,
+                    &tap.name,
+                    &tap.mac_address,
+                    tap.fds.len(),
+                    &tap.bandwidth,
+                );
+                // the fds are kept open until the request is done
+                let fds = tap.fds.iter().map(|fd| fd.as_raw_fd()).collect();
+                self.add_device("vm.add-net", &net_config, fds)
             }
-            DeviceInfo::Physical(_) => {
-                todo!()
+            DeviceInfo::Physical(vfio) => {
+                let device_config = DeviceConfig::new(&vfio.id, &vfio.bdf);
+                self.add_device("vm.add-device", &device_config, vec![])
             }
-            DeviceInfo::VhostUser(_) => {
-                todo!()
-            }
-            DeviceInfo::Char(_) => {
-                unimplemented!()
+            DeviceInfo::VhostUser(vhost_user) => {
+                let net_config = NetConfig::new_vhost_user(
+                    &vhost_user.id,
+                    &vhost_user.socket_path,
+                    &vhost_user.mac_address,
+                );
+                self.add_device("vm.add-net", &net_config, vec![])
             }
+            DeviceInfo::Char(c) => Err(Error::Unimplemented(format!(
+                "char device {} of cloud hypervisor",
+                c.id
+            ))),
+        }
+    }
+
+    // add_device hot plugs the device by the command, and returns the pci address of it
+    fn add_device<T: Serialize + Debug>(
+        &mut self,
+        command: &str,
+        config: &T,
+        fds: Vec<RawFd>,
+    ) -> Result<String> {
+        let request_body = serde_json::to_string(config)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", config, e))?;
+        let response_opt = simple_api_full_command_with_fds_and_response(
+            &mut self.socket,
+            "PUT",
+            command,
+            Some(&request_body),
+            fds,
+        )
+        .map_err(|e| anyhow!("failed to request {} {}, {}", command, request_body, e))?;
+        if let Some(response_body) = response_opt {
+            let response = serde_json::from_str::<AddDeviceResponse>(&response_body)
+                .map_err(|e| anyhow!("failed to unmarshal response {}, {}", response_body, e))?;
+            Ok(response.bdf)
+        } else {
+            Err(anyhow!("no response body from server").into())
         }
     }
 
@@ -127,4 +248,118 @@ impl ChClient {
         .map_err(|e| anyhow!("failed to remove device {}, {}", request_body, e))?;
         Ok(())
     }
+
+    pub fn resize(&mut self, request: &VmResizeRequest) -> Result<()> {
+        let request_body = serde_json::to_string(request)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", request, e))?;
+        simple_api_command(&mut self.socket, "PUT", "resize", Some(&request_body))
+            .map_err(|e| anyhow!("failed to resize vm {}, {}", request_body, e))?;
+        Ok(())
+    }
+
+    pub fn resize_disk(&mut self, request: &VmResizeDiskRequest) -> Result<()> {
+        let request_body = serde_json::to_string(request)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", request, e))?;
+        simple_api_command(&mut self.socket, "PUT", "resize-disk", Some(&request_body))
+            .map_err(|e| anyhow!("failed to resize disk {}, {}", request_body, e))?;
+        Ok(())
+    }
+
+    pub fn pause(&mut self) -> Result<()> {
+        simple_api_command(&mut self.socket, "PUT", "pause", None)
+            .map_err(|e| anyhow!("failed to pause vm, {}", e))?;
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        simple_api_command(&mut self.socket, "PUT", "resume", None)
+            .map_err(|e| anyhow!("failed to resume vm, {}", e))?;
+        Ok(())
+    }
+
+    pub fn snapshot(&mut self, config: &VmSnapshotConfig) -> Result<()> {
+        let request_body = serde_json::to_string(config)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", config, e))?;
+        simple_api_command(&mut self.socket, "PUT", "snapshot", Some(&request_body))
+            .map_err(|e| anyhow!("failed to snapshot vm {}, {}", request_body, e))?;
+        Ok(())
+    }
+
+    pub async fn ping(&self) -> Result<VmmPingResponse> {
+        self.query("vmm.ping").await
+    }
+
+    pub async fn vm_info(&self) -> Result<VmInfo> {
+        self.query("vm.info").await
+    }
+
+    // query sends a GET request on a new connection in a blocking thread, so that the probes of
+    // a hanging vmm never block the runtime or the requests on the shared connection.
+    // The connection is dropped after the request, so a response arriving after the timeout
+    // is never read as the response of a later request.
+    async fn query<T: DeserializeOwned + Send + 'static>(&self, command: &str) -> Result<T> {
+        let socket_path = self.socket_path.to_string();
+        let command = command.to_string();
+        spawn_blocking(move || -> Result<T> {
+            let timeout = Some(Duration::from_secs(CLOUD_HYPERVISOR_QUERY_TIMEOUT_IN_SEC));
+            let mut socket = UnixStream::connect(&socket_path)
+                .map_err(|e| anyhow!("failed to connect api server {}, {}", socket_path, e))?;
+            socket
+                .set_read_timeout(timeout)
+                .and_then(|_| socket.set_write_timeout(timeout))
+                .map_err(|e| anyhow!("failed to set timeout of api socket, {}", e))?;
+            let response_body =
+                simple_api_full_command_and_response(&mut socket, "GET", &command, None)
+                    .map_err(|e| anyhow!("failed to request {}, {}", command, e))?
+                    .ok_or_else(|| anyhow!("no response body of {} from server", command))?;
+            let response = serde_json::from_str::<T>(&response_body)
+                .map_err(|e| anyhow!("failed to unmarshal response {}, {}", response_body, e))?;
+            Ok(response)
+        })
+        .await
+        .map_err(|e| anyhow!("failed to join thread {}", e))?
+    }
+
+    pub fn restore(&mut self, config: &RestoreConfig, fds: Vec<RawFd>) -> Result<()> {
+        let request_body = serde_json::to_string(config)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", config, e))?;
+        simple_api_full_command_with_fds_and_response(
+            &mut self.socket,
+            "PUT",
+            "vm.restore",
+            Some(&request_body),
+            fds,
+        )
+        .map_err(|e| anyhow!("failed to restore vm {}, {}", request_body, e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cloud_hypervisor::client::{VmInfo, VmmPingResponse};
+
+    #[test]
+    fn test_parse_vm_info() {
+        let body = r#"{"config":{"cpus":{"boot_vcpus":1,"max_vcpus":4,"kvm_hyperv":false},
+            "memory":{"size":1073741824,"hotplug_size":2147483648,"shared":true}},
+            "state":"Running","memory_actual_size":1073741824,"device_tree":{}}"#;
+        let info = serde_json::from_str::<VmInfo>(body).unwrap();
+        assert_eq!(info.state, "Running");
+        assert_eq!(info.memory_actual_size, Some(1073741824));
+        let cpus = info.config.cpus.unwrap();
+        assert_eq!((cpus.boot_vcpus, cpus.max_vcpus), (1, 4));
+        assert_eq!(info.config.memory.unwrap().hotplug_size, Some(2147483648));
+
+        let info = serde_json::from_str::<VmInfo>(r#"{"config":{},"state":"Paused"}"#).unwrap();
+        assert_eq!(info.state, "Paused");
+        assert!(info.config.cpus.is_none());
+
+        let ping = serde_json::from_str::<VmmPingResponse>(
+            r#"{"build_version":"v40.0","version":"40.0.0","pid":1234,"features":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(ping.version, "40.0.0");
+        assert_eq!(ping.pid, Some(1234));
+    }
 }
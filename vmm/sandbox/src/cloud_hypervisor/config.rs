@@ -17,7 +17,7 @@ limitations under the License.
 use sandbox_derive::{CmdLineParamSet, CmdLineParams};
 use serde::{Deserialize, Serialize};
 
-use crate::vm::HypervisorCommonConfig;
+use crate::{virtiofsd::VirtiofsdConfig, vm::HypervisorCommonConfig};
 
 const DEFAULT_KERNEL_PARAMS: &str = "console=hvc0 \
 root=/dev/pmem0p1 \
@@ -32,6 +32,10 @@ pub struct CloudHypervisorVMConfig {
     pub common: HypervisorCommonConfig,
     pub hugepages: bool,
     pub entropy_source: String,
+    #[serde(default)]
+    pub default_max_vcpus: u32,
+    #[serde(default)]
+    pub memory_hotplug_size_in_mb: u32,
     pub task: TaskConfig,
     pub virtiofsd: VirtiofsdConfig,
 }
@@ -43,6 +47,8 @@ impl Default for CloudHypervisorVMConfig {
             common: HypervisorCommonConfig::default(),
             hugepages: false,
             entropy_source: "/dev/urandom".to_string(),
+            default_max_vcpus: 0,
+            memory_hotplug_size_in_mb: 0,
             task: TaskConfig::default(),
             virtiofsd: VirtiofsdConfig::default(),
         }
@@ -55,35 +61,6 @@ pub struct TaskConfig {
     pub enable_tracing: bool,
 }
 
-#[derive(CmdLineParamSet, Deserialize, Clone, Serialize)]
-pub struct VirtiofsdConfig {
-    #[param(ignore)]
-    pub path: String,
-    pub log_level: String,
-    pub cache: String,
-    pub thread_pool_size: u32,
-    #[serde(default)]
-    pub socket_path: String,
-    #[serde(default)]
-    pub shared_dir: String,
-    #[serde(default)]
-    pub syslog: bool,
-}
-
-impl Default for VirtiofsdConfig {
-    fn default() -> Self {
-        Self {
-            path: "/usr/local/bin/virtiofsd".to_string(),
-            log_level: "info".to_string(),
-            cache: "never".to_string(),
-            thread_pool_size: 4,
-            socket_path: "".to_string(),
-            shared_dir: "".to_string(),
-            syslog: true,
-        }
-    }
-}
-
 #[derive(CmdLineParamSet, Default, Clone, Serialize, Deserialize)]
 pub struct CloudHypervisorConfig {
     #[param(ignore)]
@@ -95,6 +72,7 @@ pub struct CloudHypervisorConfig {
     pub cmdline: String,
     pub initramfs: Option<String>,
     pub log_file: Option<String>,
+    pub pvpanic: bool,
     #[param(ignore)]
     pub debug: bool,
 }
@@ -135,6 +113,7 @@ pub struct Memory {
     pub(crate) prefault: Option<bool>,
     #[property(generator = "crate::utils::bool_to_on_off")]
     pub(crate) thp: Option<bool>,
+    pub(crate) hotplug_size: Option<u64>,
 }
 
 impl Memory {
@@ -146,18 +125,25 @@ impl Memory {
             hugepage_size: None,
             prefault: None,
             thp: None,
+            hotplug_size: None,
         }
     }
 }
 
 impl CloudHypervisorConfig {
     pub fn from(vm_config: &CloudHypervisorVMConfig) -> Self {
-        let cpus = Cpus::new(vm_config.common.vcpus);
-        let memory = Memory::new(
+        let mut cpus = Cpus::new(vm_config.common.vcpus);
+        if vm_config.default_max_vcpus > vm_config.common.vcpus {
+            cpus.max = Some(vm_config.default_max_vcpus);
+        }
+        let mut memory = Memory::new(
             (vm_config.common.memory_in_mb as u64) * 1024 * 1024,
             true,
             vm_config.hugepages,
         );
+        if vm_config.memory_hotplug_size_in_mb > 0 {
+            memory.hotplug_size = Some((vm_config.memory_hotplug_size_in_mb as u64) * 1024 * 1024);
+        }
         let mut cmdline = format!(
             "{} {}",
             DEFAULT_KERNEL_PARAMS, vm_config.common.kernel_params
@@ -181,6 +167,7 @@ impl CloudHypervisorConfig {
             cmdline,
             initramfs: None,
             log_file: None,
+            pvpanic: vm_config.common.enable_pvpanic,
             debug: vm_config.common.debug,
         }
     }
@@ -213,11 +200,13 @@ mod tests {
                 hugepage_size: Some("2M".to_string()),
                 prefault: None,
                 thp: None,
+                hotplug_size: None,
             },
             kernel: "/path/to/kernel".to_string(),
             cmdline: "task.sharefs_type=virtiofs".to_string(),
             initramfs: None,
             log_file: None,
+            pvpanic: false,
             debug: false,
         };
         let params = config.to_cmdline_params("--");
@@ -17,6 +17,10 @@ limitations under the License.
 use sandbox_derive::CmdLineParams;
 use serde_derive::Serialize;
 
+use crate::device::IoThrottle;
+
+const RATE_LIMITER_REFILL_TIME_IN_MS: u32 = 1000;
+
 #[derive(CmdLineParams, Debug, Clone)]
 pub struct Disk {
     path: String,
@@ -25,6 +29,7 @@ pub struct Disk {
     direct: Option<bool>,
     iommu: Option<bool>,
     num_queues: Option<u32>,
+    bw_size: Option<u64>,
     bw_refill_time: Option<u32>,
     ops_size: Option<u32>,
     ope_one_time_burst: Option<u32>,
@@ -43,6 +48,7 @@ impl Disk {
             direct: Some(direct),
             iommu: None,
             num_queues: None,
+            bw_size: None,
             bw_refill_time: None,
             ops_size: None,
             ope_one_time_burst: None,
@@ -50,6 +56,17 @@ impl Disk {
             pci_segment: None,
         }
     }
+
+    pub fn set_throttle(&mut self, throttle: &IoThrottle) {
+        if throttle.bps > 0 {
+            self.bw_size = Some(throttle.bps);
+            self.bw_refill_time = Some(RATE_LIMITER_REFILL_TIME_IN_MS);
+        }
+        if throttle.iops > 0 {
+            self.ops_size = Some(throttle.iops as u32);
+            self.ops_refill_time = Some(RATE_LIMITER_REFILL_TIME_IN_MS);
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -60,4 +77,40 @@ pub struct DiskConfig {
     pub vhost_user: bool,
     pub vhost_socket: Option<String>,
     pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
+}
+
+// RateLimiterConfig is the token buckets of the bytes and operations,
+// which are refilled with the size of tokens every refill time.
+#[derive(Serialize, Debug)]
+pub struct RateLimiterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<TokenBucketConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ops: Option<TokenBucketConfig>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenBucketConfig {
+    pub size: u64,
+    pub refill_time: u64,
+}
+
+impl RateLimiterConfig {
+    pub fn from_throttle(throttle: &IoThrottle) -> Option<Self> {
+        if throttle.is_empty() {
+            return None;
+        }
+        let bucket = |size: u64| {
+            Some(size).filter(|x| *x > 0).map(|size| TokenBucketConfig {
+                size,
+                refill_time: RATE_LIMITER_REFILL_TIME_IN_MS as u64,
+            })
+        };
+        Some(Self {
+            bandwidth: bucket(throttle.bps),
+            ops: bucket(throttle.iops),
+        })
+    }
 }
@@ -15,6 +15,7 @@ limitations under the License.
 */
 
 use sandbox_derive::CmdLineParams;
+use serde_derive::Serialize;
 
 const VFIO_DEVICE_SYSFS_PATH: &str = "/sys/bus/pci/devices";
 
@@ -37,6 +38,24 @@ impl VfioDevice {
     }
 }
 
+// DeviceConfig is the request of vm.add-device to hot plug the vfio device
+#[derive(Serialize, Debug)]
+pub struct DeviceConfig {
+    pub path: String,
+    pub id: String,
+    pub iommu: bool,
+}
+
+impl DeviceConfig {
+    pub fn new(id: &str, bdf: &str) -> Self {
+        Self {
+            path: format!("{}/{}", VFIO_DEVICE_SYSFS_PATH, bdf),
+            id: id.to_string(),
+            iommu: false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{cloud_hypervisor::devices::vfio::VfioDevice, param::ToParams};
@@ -17,13 +17,21 @@ limitations under the License.
 use std::os::unix::io::RawFd;
 
 use sandbox_derive::CmdLineParams;
+use serde_derive::Serialize;
+
+use crate::{
+    cloud_hypervisor::devices::block::{RateLimiterConfig, TokenBucketConfig},
+    network::Bandwidth,
+};
+
+const RATE_LIMITER_REFILL_TIME_IN_MS: u64 = 1000;
 
 #[derive(CmdLineParams, Debug, Clone)]
 #[params("net")]
 pub struct VirtioNetDevice {
     id: String,
 
-    #[property(key = "tap", predicate = "self.fds.len()<=0")]
+    #[property(key = "tap", predicate = "self.fds.len()<=0 && self.ifname.is_some()")]
     pub(crate) ifname: Option<String>,
 
     #[property(
@@ -37,6 +45,12 @@ pub struct VirtioNetDevice {
 
     #[property(key = "num_queues", predicate = "self.fds.len()>0")]
     pub(crate) num_queues: u32,
+
+    pub(crate) bw_size: Option<u64>,
+    pub(crate) bw_refill_time: Option<u64>,
+
+    pub(crate) vhost_user: Option<bool>,
+    pub(crate) socket: Option<String>,
 }
 
 impl_device_no_bus!(VirtioNetDevice);
@@ -49,10 +63,91 @@ impl VirtioNetDevice {
             num_queues: (fds.len() * 2) as u32,
             mac: mac.to_string(),
             fds,
+            bw_size: None,
+            bw_refill_time: None,
+            vhost_user: None,
+            socket: None,
+        }
+    }
+
+    // new_vhost_user creates the net device of the vhost-user backend listening on the socket,
+    // which requires the memory of the vm to be shared.
+    pub fn new_vhost_user(id: &str, socket: &str, mac: &str) -> Self {
+        let mut device = Self::new(id, None, mac, vec![]);
+        device.vhost_user = Some(true);
+        device.socket = Some(socket.to_string());
+        device
+    }
+
+    // set_bandwidth sets the rate limiter of the device
+    pub fn set_bandwidth(&mut self, bandwidth: &Bandwidth) {
+        if let Some(size) = bucket_size(bandwidth) {
+            self.bw_size = Some(size);
+            self.bw_refill_time = Some(RATE_LIMITER_REFILL_TIME_IN_MS);
+        }
+    }
+}
+
+// NetConfig is the request of vm.add-net, the fds of the tap are sent along with it
+#[derive(Serialize, Debug)]
+pub struct NetConfig {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap: Option<String>,
+    pub mac: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_queues: Option<usize>,
+    pub vhost_user: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vhost_socket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
+}
+
+impl NetConfig {
+    pub fn new_tap(id: &str, name: &str, mac: &str, num_fds: usize, bandwidth: &Bandwidth) -> Self {
+        Self {
+            id: id.to_string(),
+            // the tap is opened by the name only if no fds are sent
+            tap: Some(name.to_string()).filter(|_| num_fds == 0),
+            mac: mac.to_string(),
+            num_queues: Some(num_fds * 2).filter(|x| *x > 0),
+            vhost_user: false,
+            vhost_socket: None,
+            rate_limiter_config: bucket_size(bandwidth).map(|size| RateLimiterConfig {
+                bandwidth: Some(TokenBucketConfig {
+                    size,
+                    refill_time: RATE_LIMITER_REFILL_TIME_IN_MS,
+                }),
+                ops: None,
+            }),
+        }
+    }
+
+    pub fn new_vhost_user(id: &str, socket: &str, mac: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            tap: None,
+            mac: mac.to_string(),
+            num_queues: None,
+            vhost_user: true,
+            vhost_socket: Some(socket.to_string()),
+            rate_limiter_config: None,
         }
     }
 }
 
+// bucket_size returns the bytes refilled to the bucket of the rate limiter every second,
+// the limiter of cloud hypervisor applies to both directions, so the lower one is taken.
+fn bucket_size(bandwidth: &Bandwidth) -> Option<u64> {
+    let rate = match (bandwidth.ingress, bandwidth.egress) {
+        (0, 0) => return None,
+        (0, r) | (r, 0) => r,
+        (i, e) => i.min(e),
+    };
+    Some(rate / 8)
+}
+
 pub fn vec_to_string<T: ToString>(v: &[T]) -> String {
     format!(
         "[{}]",
@@ -62,3 +157,34 @@ pub fn vec_to_string<T: ToString>(v: &[T]) -> String {
             .join(",")
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cloud_hypervisor::devices::virtio_net::VirtioNetDevice, network::Bandwidth, param::ToParams,
+    };
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut device = VirtioNetDevice::new("intf-2", None, "", vec![3]);
+        device.set_bandwidth(&Bandwidth {
+            ingress: 20_000_000,
+            egress: 10_000_000,
+        });
+        let params = device.to_params();
+        let property = params.get(0).unwrap();
+        assert_eq!(property.get("bw_size").unwrap(), "1250000");
+        assert_eq!(property.get("bw_refill_time").unwrap(), "1000");
+    }
+
+    #[test]
+    fn test_vhost_user() {
+        let device = VirtioNetDevice::new_vhost_user("intf-3", "/run/vhost.sock", "mac");
+        let params = device.to_params();
+        let property = params.get(0).unwrap();
+        assert_eq!(property.get("vhost_user").unwrap(), "true");
+        assert_eq!(property.get("socket").unwrap(), "/run/vhost.sock");
+        assert!(property.get("tap").is_none());
+        assert!(property.get("num_queues").is_none());
+    }
+}
@@ -20,9 +20,10 @@ use crate::{
     cloud_hypervisor::{
         config::CloudHypervisorVMConfig,
         devices::{console::Console, fs::Fs, pmem::Pmem, rng::Rng, vsock::Vsock},
-        CloudHypervisorVM,
+        CloudHypervisorVM, CONSOLE_LOG_FILENAME,
     },
     utils::get_netns,
+    virtiofsd::VirtiofsDaemon,
     vm::VMFactory,
 };
 
@@ -45,7 +46,9 @@ impl VMFactory for CloudHypervisorVMFactory {
         s: &SandboxOption,
     ) -> containerd_sandbox::error::Result<Self::VM> {
         let netns = get_netns(&s.sandbox);
-        let mut vm = CloudHypervisorVM::new(id, &netns, &s.base_dir, &self.vm_config);
+        let virtiofs_daemon = VirtiofsDaemon::new(id, &self.vm_config.virtiofsd, s, false);
+        let mut vm =
+            CloudHypervisorVM::new(id, &netns, &s.base_dir, &self.vm_config, virtiofs_daemon);
         // add image as a disk
         if !self.vm_config.common.image_path.is_empty() {
             let rootfs_device = Pmem::new("rootfs", &self.vm_config.common.image_path, true);
@@ -66,17 +69,14 @@ impl VMFactory for CloudHypervisorVMFactory {
         vm.add_device(vsock);
         vm.agent_socket = format!("hvsock://{}:1024", guest_socket_path);
 
-        // add console device
-        // TODO add log path parameter
-        let console_path = format!("/tmp/{}-task.log", id);
+        // add console device, the console log is kept for the crash report
+        let console_path = format!("{}/{}", s.base_dir, CONSOLE_LOG_FILENAME);
         let console = Console::new(&console_path, "console");
         vm.add_device(console);
 
         // add virtio-fs device
-        if !vm.virtiofsd_config.socket_path.is_empty() {
-            let fs = Fs::new("fs", &vm.virtiofsd_config.socket_path, "kuasar");
-            vm.add_device(fs);
-        }
+        let fs = Fs::new("fs", vm.virtiofs_daemon.socket_path(), "kuasar");
+        vm.add_device(fs);
 
         Ok(vm)
     }
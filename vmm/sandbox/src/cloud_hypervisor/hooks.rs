@@ -47,9 +47,11 @@ async fn process_config(sandbox: &mut KuasarSandbox<CloudHypervisorVM>) -> Resul
     if let Some(resources) = get_resources(&sandbox.data) {
         if resources.cpu_period > 0 && resources.cpu_quota > 0 {
             // get ceil of cpus if it is not integer
-            let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
-            sandbox.vm.config.cpus.boot = base as u32;
-            sandbox.vm.config.cpus.max = Some(base as u32);
+            let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil() as u32;
+            // keep the max cpus from default_max_vcpus so that vcpus can be resized later
+            let max = sandbox.vm.config.cpus.max.unwrap_or_default().max(base);
+            sandbox.vm.config.cpus.boot = base;
+            sandbox.vm.config.cpus.max = Some(max);
         }
         if resources.memory_limit_in_bytes > 0 {
             sandbox.vm.config.memory.size = resources.memory_limit_in_bytes as u64;
@@ -14,7 +14,12 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{os::fd::OwnedFd, process::Stdio, time::Duration};
+use std::{
+    io::BufReader,
+    os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+    process::Stdio,
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -26,24 +31,31 @@ use time::OffsetDateTime;
 use tokio::{
     fs::create_dir_all,
     process::Child,
-    sync::watch::{channel, Receiver, Sender},
+    sync::{
+        mpsc,
+        watch::{channel, Receiver, Sender},
+    },
     task::JoinHandle,
 };
 use tracing::instrument;
-use vmm_common::SHARED_DIR_SUFFIX;
 
 use crate::{
     cloud_hypervisor::{
-        client::ChClient,
-        config::{CloudHypervisorConfig, CloudHypervisorVMConfig, VirtiofsdConfig},
+        client::{
+            ChClient, RestoreConfig, RestoredNetConfig, VmInfo, VmResizeDiskRequest,
+            VmResizeRequest, VmSnapshotConfig, VM_STATE_RUNNING,
+        },
+        config::{CloudHypervisorConfig, CloudHypervisorVMConfig},
         devices::{
             block::Disk, vfio::VfioDevice, virtio_net::VirtioNetDevice, CloudHypervisorDevice,
         },
     },
+    crash::{read_file_tail, CONSOLE_TAIL_SIZE, GUEST_PANIC_EVENT},
     device::{BusType, DeviceInfo},
     param::ToCmdLineParams,
     utils::{read_std, set_cmd_fd, set_cmd_netns, wait_channel, wait_pid, write_file_atomic},
-    vm::{Pids, VcpuThreads, VM},
+    virtiofsd::VirtiofsDaemon,
+    vm::{Pids, VMEvent, VcpuThreads, VM},
 };
 
 mod client;
@@ -53,6 +65,8 @@ pub mod factory;
 pub mod hooks;
 
 const VCPU_PREFIX: &str = "vcpu";
+const VM_EVENT_CHANNEL_SIZE: usize = 16;
+pub(crate) const CONSOLE_LOG_FILENAME: &str = "console.log";
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct CloudHypervisorVM {
@@ -63,27 +77,43 @@ pub struct CloudHypervisorVM {
     netns: String,
     base_dir: String,
     agent_socket: String,
-    virtiofsd_config: VirtiofsdConfig,
+    #[serde(default)]
+    virtiofs_daemon: VirtiofsDaemon,
     #[serde(skip)]
     wait_chan: Option<Receiver<(u32, i128)>>,
     #[serde(skip)]
     client: Option<ChClient>,
     #[serde(skip)]
     fds: Vec<OwnedFd>,
+    #[serde(skip)]
+    net_fds: Vec<RestoredNetConfig>,
     pids: Pids,
+    #[serde(skip)]
+    vm_events: Option<mpsc::Receiver<VMEvent>>,
+}
+
+// MonitorEvent is the event written by cloud hypervisor to the file of --event-monitor
+#[derive(Deserialize)]
+struct MonitorEvent {
+    source: String,
+    event: String,
+    #[serde(default)]
+    properties: serde_json::Value,
 }
 
 impl CloudHypervisorVM {
-    pub fn new(id: &str, netns: &str, base_dir: &str, vm_config: &CloudHypervisorVMConfig) -> Self {
+    pub fn new(
+        id: &str,
+        netns: &str,
+        base_dir: &str,
+        vm_config: &CloudHypervisorVMConfig,
+        virtiofs_daemon: VirtiofsDaemon,
+    ) -> Self {
         let mut config = CloudHypervisorConfig::from(vm_config);
         config.api_socket = format!("{}/api.sock", base_dir);
         if !vm_config.common.initrd_path.is_empty() {
             config.initramfs = Some(vm_config.common.initrd_path.clone());
         }
-
-        let mut virtiofsd_config = vm_config.virtiofsd.clone();
-        virtiofsd_config.socket_path = format!("{}/virtiofs.sock", base_dir);
-        virtiofsd_config.shared_dir = format!("{}/{}", base_dir, SHARED_DIR_SUFFIX);
         Self {
             id: id.to_string(),
             config,
@@ -91,11 +121,13 @@ impl CloudHypervisorVM {
             netns: netns.to_string(),
             base_dir: base_dir.to_string(),
             agent_socket: "".to_string(),
-            virtiofsd_config,
+            virtiofs_daemon,
             wait_chan: None,
             client: None,
             fds: vec![],
+            net_fds: vec![],
             pids: Pids::default(),
+            vm_events: None,
         }
     }
 
@@ -114,72 +146,68 @@ impl CloudHypervisorVM {
         ChClient::new(self.config.api_socket.to_string()).await
     }
 
+    // vm_info checks that the vmm is responsive and returns the state, vcpus and memory of the vm.
+    pub async fn vm_info(&self) -> Result<VmInfo> {
+        let client = self.client.as_ref().ok_or(Error::NotFound(
+            "cloud hypervisor client not inited".to_string(),
+        ))?;
+        let vmm = client.ping().await?;
+        let info = client.vm_info().await?;
+        debug!(
+            "vm {} of cloud hypervisor {}: state {}, vcpus {:?}, memory {:?}, actual memory {:?}",
+            self.id,
+            vmm.version,
+            info.state,
+            info.config.cpus,
+            info.config.memory,
+            info.memory_actual_size
+        );
+        Ok(info)
+    }
+
     fn get_client(&mut self) -> Result<&mut ChClient> {
         self.client.as_mut().ok_or(Error::NotFound(
             "cloud hypervisor client not inited".to_string(),
         ))
     }
 
-    async fn start_virtiofsd(&self) -> Result<u32> {
-        create_dir_all(&self.virtiofsd_config.shared_dir).await?;
-        let params = self.virtiofsd_config.to_cmdline_params("--");
-        let mut cmd = tokio::process::Command::new(&self.virtiofsd_config.path);
-        cmd.args(params.as_slice());
-        debug!("start virtiofsd with cmdline: {:?}", cmd);
-        set_cmd_netns(&mut cmd, self.netns.to_string())?;
-        cmd.stderr(Stdio::piped());
-        cmd.stdout(Stdio::piped());
-        let child = cmd
-            .spawn()
-            .map_err(|e| anyhow!("failed to spawn virtiofsd command: {}", e))?;
-        let pid = child
-            .id()
-            .ok_or(anyhow!("the virtiofsd has been polled to completion"))?;
-        info!("virtiofsd for {} is running with pid {}", self.id, pid);
-        spawn_wait(child, format!("virtiofsd {}", self.id), None, None);
-        Ok(pid)
-    }
-
     fn append_fd(&mut self, fd: OwnedFd) -> usize {
         self.fds.push(fd);
         self.fds.len() - 1 + 3
     }
 
-    async fn wait_stop(&mut self, t: Duration) -> Result<()> {
-        if let Some(rx) = self.wait_channel().await {
-            let (_, ts) = *rx.borrow();
-            if ts == 0 {
-                wait_channel(t, rx).await?;
-            }
-        }
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl VM for CloudHypervisorVM {
-    #[instrument(skip_all)]
-    async fn start(&mut self) -> Result<u32> {
+    // launch starts the virtiofsd and the cloud hypervisor process with the params. The tap fds
+    // of the attached devices are taken only here, they are passed to the process on boot, or
+    // sent with the restore request if the vm is restored from the snapshot in restore_dir.
+    async fn launch(&mut self, mut params: Vec<String>, restore_dir: Option<&str>) -> Result<u32> {
+        let tap_fds: Vec<OwnedFd> = self.fds.drain(..).collect();
+        let (mut fds, restore_fds) = match restore_dir {
+            Some(_) => (vec![], tap_fds),
+            None => (tap_fds, vec![]),
+        };
         create_dir_all(&self.base_dir).await?;
-        let virtiofsd_pid = self.start_virtiofsd().await?;
-        // TODO: add child virtiofsd process
-        self.pids.affiliated_pids.push(virtiofsd_pid);
-        let mut params = self.config.to_cmdline_params("--");
-        for d in self.devices.iter() {
-            params.extend(d.to_cmdline_params("--"));
-        }
-
+        self.virtiofs_daemon.start().await?;
         // the log level is single hyphen parameter, has to handle separately
         if self.config.debug {
             params.push("-vv".to_string());
         }
+        // the panic reported by the pvpanic device is only written to the event monitor
+        if self.config.pvpanic {
+            let (reader, writer) = os_pipe::pipe()
+                .map_err(|e| anyhow!("failed to create pipe of event monitor: {}", e))?;
+            params.push("--event-monitor".to_string());
+            params.push(format!("fd={}", fds.len() + 3));
+            // SAFETY: the fd of the writer is owned by nobody else
+            fds.push(unsafe { OwnedFd::from_raw_fd(writer.into_raw_fd()) });
+            self.vm_events = Some(watch_event_monitor(&self.id, reader));
+        }
 
         // Drop cmd immediately to let the fds in pre_exec be closed.
         let child = {
             let mut cmd = tokio::process::Command::new(&self.config.path);
             cmd.args(params.as_slice());
 
-            set_cmd_fd(&mut cmd, self.fds.drain(..).collect())?;
+            set_cmd_fd(&mut cmd, fds)?;
             set_cmd_netns(&mut cmd, self.netns.to_string())?;
             cmd.stdout(Stdio::piped());
             cmd.stderr(Stdio::piped());
@@ -194,6 +222,7 @@ impl VM for CloudHypervisorVM {
             pid.unwrap_or_default()
         );
         self.pids.vmm_pid = pid;
+        self.virtiofs_daemon.set_vmm_pid(pid.unwrap_or_default());
         let pid_file = format!("{}/pid", self.base_dir);
         let (tx, rx) = channel((0u32, 0i128));
         self.wait_chan = Some(rx);
@@ -214,9 +243,44 @@ impl VM for CloudHypervisorVM {
                 return Err(e);
             }
         };
+        if let Some(dir) = restore_dir {
+            let config = RestoreConfig {
+                source_url: format!("file://{}", dir),
+                prefault: false,
+                net_fds: self.net_fds.clone(),
+            };
+            let client = self.get_client()?;
+            client.restore(
+                &config,
+                restore_fds.iter().map(|fd| fd.as_raw_fd()).collect(),
+            )?;
+            client.resume()?;
+        }
         Ok(pid.unwrap_or_default())
     }
 
+    async fn wait_stop(&mut self, t: Duration) -> Result<()> {
+        if let Some(rx) = self.wait_channel().await {
+            let (_, ts) = *rx.borrow();
+            if ts == 0 {
+                wait_channel(t, rx).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VM for CloudHypervisorVM {
+    #[instrument(skip_all)]
+    async fn start(&mut self) -> Result<u32> {
+        let mut params = self.config.to_cmdline_params("--");
+        for d in self.devices.iter() {
+            params.extend(d.to_cmdline_params("--"));
+        }
+        self.launch(params, None).await
+    }
+
     #[instrument(skip_all)]
     async fn stop(&mut self, force: bool) -> Result<()> {
         let signal = if force {
@@ -226,6 +290,8 @@ impl VM for CloudHypervisorVM {
         };
 
         let pids = self.pids();
+        // the virtiofsd exits with the vmm, it should not be taken as crashed
+        self.virtiofs_daemon.stop_supervising();
         if let Some(vmm_pid) = pids.vmm_pid {
             if vmm_pid > 0 {
                 // TODO: Consider pid reused
@@ -239,6 +305,8 @@ impl VM for CloudHypervisorVM {
                 }
             }
         }
+        // the virtiofsd should not be restarted once it is killed
+        self.virtiofs_daemon.stop();
         for affiliated_pid in pids.affiliated_pids {
             if affiliated_pid > 0 {
                 // affiliated process may exits automatically, so it's ok not handle error
@@ -253,7 +321,8 @@ impl VM for CloudHypervisorVM {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true);
+                let mut device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true);
+                device.set_throttle(&blk_info.throttle);
                 self.add_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
@@ -262,23 +331,37 @@ impl VM for CloudHypervisorVM {
                     let index = self.append_fd(fd);
                     fd_ints.push(index as i32);
                 }
-                let device = VirtioNetDevice::new(
+                self.net_fds.push(RestoredNetConfig {
+                    id: tap_info.id.to_string(),
+                    num_fds: fd_ints.len(),
+                });
+                let mut device = VirtioNetDevice::new(
                     &tap_info.id,
                     Some(tap_info.name),
                     &tap_info.mac_address,
                     fd_ints,
                 );
+                device.set_bandwidth(&tap_info.bandwidth);
                 self.add_device(device);
             }
             DeviceInfo::Physical(vfio_info) => {
                 let device = VfioDevice::new(&vfio_info.id, &vfio_info.bdf);
                 self.add_device(device);
             }
-            DeviceInfo::VhostUser(_vhost_user_info) => {
-                todo!()
+            DeviceInfo::VhostUser(vhost_user_info) => {
+                let device = VirtioNetDevice::new_vhost_user(
+                    &vhost_user_info.id,
+                    &vhost_user_info.socket_path,
+                    &vhost_user_info.mac_address,
+                );
+                self.add_device(device);
             }
-            DeviceInfo::Char(_char_info) => {
-                unimplemented!()
+            DeviceInfo::Char(char_info) => {
+                // cloud hypervisor has only one console and one serial, but no virtio-serial ports
+                return Err(Error::Unimplemented(format!(
+                    "char device {} of cloud hypervisor",
+                    char_info.id
+                )));
             }
         };
         Ok(())
@@ -298,9 +381,81 @@ impl VM for CloudHypervisorVM {
         Ok(())
     }
 
+    #[instrument(skip_all)]
+    async fn resize(&mut self, vcpus: u32, memory_in_mb: u64) -> Result<()> {
+        let desired_vcpus = if vcpus > 0 {
+            Some(
+                u8::try_from(vcpus)
+                    .map_err(|_| Error::InvalidArgument(format!("{} vcpus", vcpus)))?,
+            )
+        } else {
+            None
+        };
+        let desired_ram = if memory_in_mb > 0 {
+            Some(memory_in_mb * 1024 * 1024)
+        } else {
+            None
+        };
+        let request = VmResizeRequest {
+            desired_vcpus,
+            desired_ram,
+        };
+        let client = self.get_client()?;
+        client.resize(&request)?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn resize_block(&mut self, id: &str, size: u64) -> Result<()> {
+        let client = self.get_client()?;
+        client.resize_disk(&VmResizeDiskRequest {
+            id: id.to_string(),
+            desired_size: size,
+        })?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn checkpoint(&mut self, dir: &str) -> Result<()> {
+        let client = self.get_client()?;
+        client.pause()?;
+        let res = client.snapshot(&VmSnapshotConfig {
+            destination_url: format!("file://{}", dir),
+        });
+        client.resume()?;
+        res
+    }
+
+    #[instrument(skip_all)]
+    async fn restore(&mut self, dir: &str) -> Result<u32> {
+        // the vm config is loaded from the snapshot, but the tap fds have to be sent again
+        let params = vec![
+            "--api-socket".to_string(),
+            self.config.api_socket.to_string(),
+        ];
+        self.launch(params, Some(dir)).await
+    }
+
+    // the hot attached devices are in the vm config of the snapshot
+    fn unrestorable_devices(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn support_network_hotplug(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip_all)]
+    fn support_net_rate_limiter(&self) -> bool {
+        true
+    }
+
     #[instrument(skip_all)]
     async fn ping(&self) -> Result<()> {
-        // TODO
+        let info = self.vm_info().await?;
+        if info.state != VM_STATE_RUNNING {
+            return Err(anyhow!("vm {} is in state {}", self.id, info.state).into());
+        }
         Ok(())
     }
 
@@ -338,7 +493,21 @@ impl VM for CloudHypervisorVM {
 
     #[instrument(skip_all)]
     fn pids(&self) -> Pids {
-        self.pids.clone()
+        // the pid of the virtiofsd changes once it is restarted
+        let mut pids = self.pids.clone();
+        pids.affiliated_pids.extend(self.virtiofs_daemon.pid());
+        pids
+    }
+
+    // the events are read from the pipe of the event monitor, which is closed with the sandboxer,
+    // so the events of the vm are not watched any more after the sandboxer restarts.
+    fn take_vm_events(&mut self) -> Option<mpsc::Receiver<VMEvent>> {
+        self.vm_events.take()
+    }
+
+    fn console_tail(&self) -> String {
+        let path = format!("{}/{}", self.base_dir, CONSOLE_LOG_FILENAME);
+        read_file_tail(&path, CONSOLE_TAIL_SIZE)
     }
 }
 
@@ -354,6 +523,7 @@ impl crate::vm::Recoverable for CloudHypervisorVM {
             tx.send(wait_result).unwrap_or_default();
         });
         self.wait_chan = Some(rx);
+        self.virtiofs_daemon.recover();
         Ok(())
     }
 }
@@ -412,3 +582,33 @@ fn spawn_wait(
         }
     })
 }
+
+// watch_event_monitor reads the events from the pipe of the event monitor until cloud hypervisor
+// exits, and sends the guest panic reported by the pvpanic device as the vm event.
+fn watch_event_monitor(id: &str, reader: os_pipe::PipeReader) -> mpsc::Receiver<VMEvent> {
+    let (tx, rx) = mpsc::channel(VM_EVENT_CHANNEL_SIZE);
+    let id = id.to_string();
+    std::thread::spawn(move || {
+        let events = serde_json::Deserializer::from_reader(BufReader::new(reader))
+            .into_iter::<MonitorEvent>();
+        for event in events {
+            let event = match event {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("failed to read event monitor of vm {}: {}", id, e);
+                    break;
+                }
+            };
+            debug!("vm {} event {}/{}", id, event.source, event.event);
+            if event.source == "guest" && event.event == "panic" {
+                let vm_event = VMEvent {
+                    name: GUEST_PANIC_EVENT.to_string(),
+                    detail: event.properties.to_string(),
+                };
+                warn!("vm event {}: {}", vm_event.name, vm_event.detail);
+                tx.blocking_send(vm_event).unwrap_or_default();
+            }
+        }
+    });
+    rx
+}
@@ -48,6 +48,7 @@ where
             id: self.id.to_string(),
             data: self.option.container.clone(),
             io_devices: vec![],
+            io_pipes: None,
             processes: vec![],
         };
         let bundle = format!(
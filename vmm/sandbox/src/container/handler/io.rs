@@ -44,58 +44,35 @@ where
     T: VM + Sync + Send,
 {
     async fn handle(&self, sandbox: &mut KuasarSandbox<T>) -> Result<()> {
-        let mut io_devices = vec![];
         debug!("handle io {:?}", self.io);
-        // TODO: what if it is not named pipe
-        let stdin = attach_pipe(&self.io.stdin, sandbox, &mut io_devices).await?;
-        let stdout = attach_pipe(&self.io.stdout, sandbox, &mut io_devices).await?;
-        let stderr = attach_pipe(&self.io.stderr, sandbox, &mut io_devices).await?;
+        let io = sandbox.stream_io(&self.container_id, &self.io).await?;
         let container = sandbox.container_mut(&self.container_id)?;
-        container.data.io = Some(Io {
-            stdin,
-            stdout,
-            stderr,
-            terminal: self.io.terminal,
-        });
+        container.data.io = Some(io);
+        container.io_pipes = Some(self.io.clone());
         let io_str = serde_json::to_string(&container.data.io)
             .map_err(|e| anyhow!("failed to parse io in container, {}", e))?;
         let io_file_path = format!(
             "{}/{}-{}",
             container.data.bundle, IO_FILE_PREFIX, self.container_id
         );
-        write_file_atomic(io_file_path, &io_str).await?;
-        container.io_devices = io_devices;
+        if let Err(e) = write_file_atomic(io_file_path, &io_str).await {
+            sandbox.stop_io_streams(&self.container_id);
+            return Err(e);
+        }
         Ok(())
     }
 
     async fn rollback(&self, sandbox: &mut KuasarSandbox<T>) -> Result<()> {
+        sandbox.stop_io_streams(&self.container_id);
         let container = sandbox.container(&self.container_id).await?;
         let bundle = container.data.bundle.to_string();
-        for device_id in container.io_devices.clone() {
-            sandbox.vm.hot_detach(&device_id).await?;
-        }
         let io_file_path = format!("{}/{}-{}", bundle, IO_FILE_PREFIX, self.container_id);
         tokio::fs::remove_file(&io_file_path)
             .await
             .unwrap_or_default();
         let container = sandbox.container_mut(&self.container_id)?;
-        container.io_devices = vec![];
+        container.io_pipes = None;
         container.data.io = None;
         Ok(())
     }
 }
-
-pub async fn attach_pipe<T: VM + Sync + Send>(
-    path: &str,
-    sandbox: &mut KuasarSandbox<T>,
-    io_devices: &mut Vec<String>,
-) -> Result<String> {
-    let name = if !path.is_empty() && !path.contains("://") {
-        let (id, chardev_id) = sandbox.hot_attach_pipe(path).await?;
-        io_devices.push(id);
-        chardev_id
-    } else {
-        path.to_string()
-    };
-    Ok(name)
-}
@@ -28,6 +28,7 @@ use crate::{
         spec::SpecHandler,
         storage::StorageHandler,
     },
+    journal::Journaled,
     sandbox::KuasarSandbox,
     vm::VM,
 };
@@ -87,6 +88,30 @@ where
         Ok(())
     }
 
+    // handle_journaled is the same as handle, except that the sandbox state is persisted
+    // after each handler, so that the resources of the handled steps can be found and
+    // released by the recovery, which removes the container if the sandboxer exits in the middle.
+    pub async fn handle_journaled(&self, sandbox: &mut S) -> Result<()>
+    where
+        S: Journaled,
+    {
+        for (i, handler) in self.handlers.iter().enumerate() {
+            let res = match handler.handle(sandbox).await {
+                Ok(_) => sandbox.persist().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                for handler in self.handlers[..=i].iter().rev() {
+                    handler.rollback(sandbox).await.unwrap_or_else(|e| {
+                        warn!("rollback failed for sandbox {:?}", e);
+                    });
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
     pub async fn rollback(&self, sandbox: &mut S) -> Result<()> {
         for handler in self.handlers.iter().rev() {
             handler.rollback(sandbox).await.unwrap_or_else(|e| {
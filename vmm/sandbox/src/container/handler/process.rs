@@ -16,17 +16,12 @@ limitations under the License.
 
 use anyhow::anyhow;
 use async_trait::async_trait;
-use containerd_sandbox::{
-    data::{Io, ProcessData},
-    Sandbox,
-};
+use containerd_sandbox::{data::ProcessData, Sandbox};
 use vmm_common::IO_FILE_PREFIX;
 
 use crate::{
-    container::{
-        handler::{io::attach_pipe, Handler},
-        KuasarProcess,
-    },
+    container::{handler::Handler, KuasarProcess},
+    io::stream_io_id,
     sandbox::KuasarSandbox,
     utils::write_file_atomic,
     vm::VM,
@@ -64,25 +59,21 @@ where
         }
         let mut new_proc = KuasarProcess::new(self.proc.clone());
         if let Some(io) = &self.proc.io {
-            let mut io_devices = vec![];
-            // TODO what if it is not named pipe
-            let stdin = attach_pipe(&io.stdin, sandbox, &mut io_devices).await?;
-            let stdout = attach_pipe(&io.stdout, sandbox, &mut io_devices).await?;
-            let stderr = attach_pipe(&io.stderr, sandbox, &mut io_devices).await?;
-            new_proc.data.io = Some(Io {
-                stdin,
-                stdout,
-                stderr,
-                terminal: io.terminal,
-            });
-            new_proc.io_devices = io_devices;
+            let stream_io = sandbox
+                .stream_io(&stream_io_id(&self.container_id, &self.proc.id), io)
+                .await?;
+            new_proc.data.io = Some(stream_io);
+            new_proc.io_pipes = Some(io.clone());
             let io_str = serde_json::to_string(&new_proc.data.io)
                 .map_err(|e| anyhow!("failed to parse io in container, {}", e))?;
             let io_file_path = format!(
                 "{}/{}-{}-{}",
                 bundle, IO_FILE_PREFIX, self.container_id, self.proc.id
             );
-            write_file_atomic(io_file_path, &io_str).await?;
+            if let Err(e) = write_file_atomic(io_file_path, &io_str).await {
+                sandbox.stop_io_streams(&stream_io_id(&self.container_id, &self.proc.id));
+                return Err(e);
+            }
         }
         let container = sandbox.container_mut(&self.container_id)?;
         container.processes.push(new_proc);
@@ -145,6 +136,7 @@ async fn remove_io_devices_for_process<T: VM + Sync + Send>(
     container_id: &str,
     process_id: &str,
 ) -> containerd_sandbox::error::Result<()> {
+    sandbox.stop_io_streams(&stream_io_id(container_id, process_id));
     let container = match sandbox.container(container_id).await {
         Ok(c) => c,
         Err(_e) => {
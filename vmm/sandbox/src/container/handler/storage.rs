@@ -61,7 +61,8 @@ where
         let mut storages: Vec<&Storage> = vec![];
 
         for mut m in mounts {
-            if let Some(storage) = sandbox.storages.iter().find(|x| x.is_for_mount(&m)) {
+            let storage = sandbox.storages.iter().find(|x| x.is_for_mount(&m));
+            if let Some(storage) = storage {
                 debug!("found storage {:?} for mount {:?}", storage, m);
                 m.source.clone_from(&storage.mount_point);
                 m.options.push("bind".to_string());
@@ -73,8 +74,8 @@ where
                     storages.push(storage);
                 }
             }
-            // TODO if vmm-task mount shm when startup, then just use the same shm
-            if is_bind_shm(&m) {
+            // the shm of the guest is shared if there is no shm storage, like the old sandboxes
+            if storage.is_none() && is_bind_shm(&m) {
                 m.source = DEV_SHM.to_string();
                 m.options.push("rbind".to_string());
             }
@@ -15,7 +15,7 @@ limitations under the License.
 */
 
 use containerd_sandbox::{
-    data::{ContainerData, ProcessData},
+    data::{ContainerData, Io, ProcessData},
     error, Container,
 };
 use serde::{Deserialize, Serialize};
@@ -26,7 +26,11 @@ mod handler;
 pub struct KuasarContainer {
     pub(crate) id: String,
     pub(crate) data: ContainerData,
+    // serial ports of the io attached by the previous versions
     pub(crate) io_devices: Vec<String>,
+    // named pipes of the io that are streamed to the task server
+    #[serde(default)]
+    pub(crate) io_pipes: Option<Io>,
     pub(crate) processes: Vec<KuasarProcess>,
 }
 
@@ -42,6 +46,8 @@ impl Container for KuasarContainer {
 pub struct KuasarProcess {
     pub id: String,
     pub io_devices: Vec<String>,
+    #[serde(default)]
+    pub io_pipes: Option<Io>,
     pub data: ProcessData,
 }
 
@@ -50,6 +56,7 @@ impl KuasarProcess {
         Self {
             id: data.id.to_string(),
             io_devices: vec![],
+            io_pipes: None,
             data,
         }
     }
@@ -0,0 +1,164 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+};
+
+use containerd_sandbox::error::Result;
+use log::{error, info};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::{health::publish_vm_event, sandbox::KuasarSandbox, utils::write_file_atomic, vm::VM};
+
+// the size of the guest console log kept for the crash report
+pub const CONSOLE_TAIL_SIZE: usize = 16 * 1024;
+pub const CRASH_REPORT_FILENAME: &str = "crash.log";
+pub const GUEST_PANIC_EVENT: &str = "GUEST_PANICKED";
+// the exit code of the sandbox whose guest kernel panicked, it is out of the range of
+// the exit codes of a vmm killed by a signal, which are 128 plus the signal number.
+pub const GUEST_PANIC_EXIT_CODE: u32 = 250;
+const KERNEL_PANIC_PATTERN: &str = "Kernel panic - not syncing";
+
+// ConsoleTail keeps the last lines of the guest console, no more than CONSOLE_TAIL_SIZE bytes
+#[derive(Clone, Default)]
+pub struct ConsoleTail {
+    inner: Arc<Mutex<ConsoleLines>>,
+}
+
+#[derive(Default)]
+struct ConsoleLines {
+    lines: VecDeque<String>,
+    size: usize,
+}
+
+impl ConsoleTail {
+    pub fn push(&self, line: &str) {
+        let mut inner = match self.inner.lock() {
+            Ok(i) => i,
+            Err(e) => e.into_inner(),
+        };
+        inner.size += line.len() + 1;
+        inner.lines.push_back(line.to_string());
+        while inner.size > CONSOLE_TAIL_SIZE {
+            match inner.lines.pop_front() {
+                Some(l) => inner.size -= l.len() + 1,
+                None => break,
+            }
+        }
+    }
+
+    pub fn content(&self) -> String {
+        let inner = match self.inner.lock() {
+            Ok(i) => i,
+            Err(e) => e.into_inner(),
+        };
+        inner
+            .lines
+            .iter()
+            .fold(String::new(), |acc, l| acc + l + "\n")
+    }
+}
+
+// read_console logs the guest console like read_std, and keeps the tail of it
+pub async fn read_console<T: AsyncRead + Unpin>(std: T, tail: ConsoleTail) -> Result<()> {
+    let mut buf_reader = BufReader::new(std);
+    loop {
+        let mut line = String::new();
+        let res = buf_reader.read_line(&mut line).await;
+        match res {
+            Ok(c) => {
+                if c == 0 {
+                    return Ok(());
+                }
+                info!("console: {}", line.trim());
+                tail.push(line.trim_end());
+            }
+            Err(e) => {
+                error!("failed to read console log {}", e);
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+// read_file_tail returns the last bytes of the console log file written by the vmm
+pub fn read_file_tail(path: &str, size: usize) -> String {
+    let mut buf = vec![];
+    let res = std::fs::File::open(path).and_then(|mut f| {
+        let len = f.metadata()?.len();
+        f.seek(SeekFrom::Start(len.saturating_sub(size as u64)))?;
+        f.read_to_end(&mut buf)
+    });
+    if let Err(e) = res {
+        error!("failed to read console log {}: {}", path, e);
+    }
+    String::from_utf8_lossy(&buf).to_string()
+}
+
+impl<V> KuasarSandbox<V>
+where
+    V: VM + Sync + Send,
+{
+    // report_crash writes the crash report into the sandbox dir if the guest panicked,
+    // and returns the exit code of the sandbox. The panic is reported by the pvpanic device,
+    // or found in the console log if the vm has no pvpanic device.
+    pub(crate) async fn report_crash(&mut self, code: u32) -> u32 {
+        let console = self.vm.console_tail();
+        let reason = match self.guest_panic.take() {
+            Some(detail) => detail,
+            None => {
+                if !console.contains(KERNEL_PANIC_PATTERN) {
+                    return code;
+                }
+                let detail = "kernel panic found in the console log".to_string();
+                publish_vm_event(&self.id, GUEST_PANIC_EVENT, &detail).await;
+                detail
+            }
+        };
+        error!(
+            "guest of sandbox {} panicked, vmm exited with {}: {}",
+            self.id, code, reason
+        );
+        let path = format!("{}/{}", self.base_dir, CRASH_REPORT_FILENAME);
+        let report = format!("{}\n\n{}", reason, console);
+        if let Err(e) = write_file_atomic(&path, &report).await {
+            error!("failed to write crash report {}: {}", path, e);
+        }
+        GUEST_PANIC_EXIT_CODE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConsoleTail, CONSOLE_TAIL_SIZE};
+
+    #[test]
+    fn test_console_tail() {
+        let tail = ConsoleTail::default();
+        let line = "a".repeat(1023);
+        for _ in 0..20 {
+            tail.push(&line);
+        }
+        tail.push("Kernel panic - not syncing: Fatal exception");
+        let content = tail.content();
+        assert!(content.len() <= CONSOLE_TAIL_SIZE);
+        assert!(content.ends_with("Kernel panic - not syncing: Fatal exception\n"));
+        assert_eq!(content.lines().count(), 16);
+    }
+}
@@ -18,6 +18,8 @@ use std::os::fd::OwnedFd;
 
 use containerd_sandbox::error::{Error, Result};
 
+use crate::network::Bandwidth;
+
 macro_rules! impl_device_no_bus {
     ($ty:ty) => {
         impl crate::device::Device for $ty {
@@ -190,6 +192,20 @@ pub struct BlockDeviceInfo {
     pub id: String,
     pub path: String,
     pub read_only: bool,
+    pub throttle: IoThrottle,
+}
+
+// IoThrottle limits the io operations and bytes per second of a block device, 0 means no limit
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IoThrottle {
+    pub iops: u64,
+    pub bps: u64,
+}
+
+impl IoThrottle {
+    pub fn is_empty(&self) -> bool {
+        self.iops == 0 && self.bps == 0
+    }
 }
 
 #[derive(Debug)]
@@ -199,6 +215,7 @@ pub struct TapDeviceInfo {
     pub name: String,
     pub mac_address: String,
     pub fds: Vec<OwnedFd>,
+    pub bandwidth: Bandwidth,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,669 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use cgroups_rs::hierarchies::is_cgroup2_unified_mode;
+use containerd_sandbox::{error::Result, utils::cleanup_mounts};
+use futures_util::TryStreamExt;
+use log::{debug, info, warn};
+use netlink_packet_route::link::LinkAttribute;
+use nix::{sys::signal, unistd::Pid};
+use serde::Deserialize;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::{
+    cgroup::{SandboxCgroup, DEFAULT_CGROUP_PARENT_PATH},
+    network::{
+        create_netlink_handle,
+        link::{
+            del_qdisc_ingress, get_ingress_redirects, get_pci_driver, LinkType, DEVICE_DRIVER_VFIO,
+        },
+        Network,
+    },
+    persist::read_dump_or_backup,
+    pool::VMPool,
+    sandbox::KuasarSandbox,
+    utils::write_file_async,
+    vm::VM,
+};
+
+const QMP_SOCKET_DIR: &str = "/run";
+const QMP_SOCKET_SUFFIX: &str = "-qmp.sock";
+const TAP_NAME_PREFIX: &str = "tap_kua_";
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const PCI_DEVICES_DIR: &str = "/sys/bus/pci/devices";
+const PCI_CLASS_NETWORK_PREFIX: &str = "0x02";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct GCConfig {
+    /// Interval of the periodic gc after the startup one, 0 means gc only runs at startup
+    #[serde(default)]
+    pub interval_in_sec: u64,
+    /// Only report the orphan resources without reclaiming them, which is the default
+    #[serde(default = "default_dry_run")]
+    pub dry_run: bool,
+}
+
+impl Default for GCConfig {
+    fn default() -> Self {
+        Self {
+            interval_in_sec: 0,
+            dry_run: default_dry_run(),
+        }
+    }
+}
+
+fn default_dry_run() -> bool {
+    true
+}
+
+// Orphan is a host resource created for a sandbox or a pooled vm
+// that is not known by the sandboxer any more.
+// Only the resources of the sandboxes and pooled vms in the working dirs of the sandboxer
+// are scanned, so the resources of other sandboxers and runtimes on the host are never touched.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Orphan {
+    Process {
+        pid: i32,
+        owner: String,
+    },
+    QmpSocket(String),
+    Tap {
+        netns: String,
+        name: String,
+        index: u32,
+    },
+    IngressQdisc {
+        netns: String,
+        dev: String,
+        index: u32,
+    },
+    Cgroup {
+        parent: String,
+        id: String,
+    },
+    VfioDevice(String),
+    SandboxDir(String),
+}
+
+impl Display for Orphan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Orphan::Process { pid, owner } => write!(f, "process {} of {}", pid, owner),
+            Orphan::QmpSocket(path) => write!(f, "qmp socket {}", path),
+            Orphan::Tap { netns, name, .. } => write!(f, "tap {} in {}", name, netns),
+            Orphan::IngressQdisc { netns, dev, .. } => {
+                write!(f, "ingress qdisc of {} in {}", dev, netns)
+            }
+            Orphan::Cgroup { parent, id } => write!(f, "cgroup {}/{}", parent, id),
+            Orphan::VfioDevice(bdf) => write!(f, "{} device {}", DEVICE_DRIVER_VFIO, bdf),
+            Orphan::SandboxDir(path) => write!(f, "sandbox dir {}", path),
+        }
+    }
+}
+
+#[derive(Default)]
+struct KnownResources {
+    // ids of the sandboxes and the pooled vms
+    ids: HashSet<String>,
+    netns: HashSet<PathBuf>,
+    bdfs: HashSet<String>,
+}
+
+// UnknownSandbox is a sandbox or a pooled vm with a dir in the working dirs of the sandboxer,
+// but not known by it, such as the sandbox failed to be recovered.
+#[derive(Debug, Default)]
+struct UnknownSandbox {
+    id: String,
+    // the dir is removed after the other resources are reclaimed, the dirs of pooled vms are
+    // left to the vm pool, which removes the ones not in use when it is started.
+    dir: Option<String>,
+    netns: String,
+    cgroup_parent: String,
+    bdfs: Vec<String>,
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) struct OrphanGC<V: VM> {
+    config: GCConfig,
+    work_dir: String,
+    sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<V>>>>>>,
+    pool: Arc<VMPool<V>>,
+    // orphans found by the last periodic gc, they are reclaimed only if found again,
+    // so that the resources of a sandbox in creation are not taken as orphans.
+    suspects: Mutex<HashSet<Orphan>>,
+}
+
+impl<V> OrphanGC<V>
+where
+    V: VM + Sync + Send + 'static,
+{
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn new(
+        config: GCConfig,
+        work_dir: &str,
+        sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<V>>>>>>,
+        pool: Arc<VMPool<V>>,
+    ) -> Self {
+        Self {
+            config,
+            work_dir: work_dir.trim_end_matches('/').to_string(),
+            sandboxes,
+            pool,
+            suspects: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // run_at_startup should be called before the sandboxer serves any request,
+    // all the orphans are reclaimed at once as no sandbox is in creation.
+    pub(crate) async fn run_at_startup(&self) {
+        let orphans = self.find_orphans().await;
+        self.reclaim(orphans).await;
+    }
+
+    pub(crate) fn run(self: &Arc<Self>) {
+        if self.config.interval_in_sec == 0 {
+            return;
+        }
+        let gc = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(gc.config.interval_in_sec));
+            // the first tick completes immediately, which is done by run_at_startup
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let orphans = gc.find_orphans().await;
+                let confirmed = {
+                    let mut suspects = gc.suspects.lock().await;
+                    let confirmed = orphans
+                        .iter()
+                        .filter(|o| suspects.contains(o))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    *suspects = orphans.into_iter().collect();
+                    confirmed
+                };
+                gc.reclaim(confirmed).await;
+            }
+        });
+    }
+
+    async fn reclaim(&self, orphans: Vec<Orphan>) {
+        for orphan in orphans {
+            if self.config.dry_run {
+                info!("found orphan {}, skip reclaiming in dry run", orphan);
+                continue;
+            }
+            match reclaim_orphan(&orphan).await {
+                Ok(_) => info!("reclaimed orphan {}", orphan),
+                Err(e) => warn!("failed to reclaim orphan {}: {}", orphan, e),
+            }
+        }
+    }
+
+    // find_orphans returns the orphans in the order of reclaiming,
+    // processes go first as they may still hold the other resources.
+    async fn find_orphans(&self) -> Vec<Orphan> {
+        let known = self.known_resources().await;
+        let mut unknown = self.unknown_sandboxes(&known).await;
+        let processes = self.find_orphan_processes(&known);
+        // the process of a sandbox may be left after its dir is removed
+        for p in processes.iter() {
+            if let Orphan::Process { owner, .. } = p {
+                if !unknown.iter().any(|u| &u.id == owner) {
+                    unknown.push(UnknownSandbox {
+                        id: owner.to_string(),
+                        cgroup_parent: DEFAULT_CGROUP_PARENT_PATH.to_string(),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        let mut orphans = processes;
+        orphans.extend(find_orphan_qmp_sockets(&unknown));
+        orphans.extend(find_orphan_taps(&known, &unknown).await);
+        orphans.extend(find_orphan_cgroups(&unknown));
+        orphans.extend(find_orphan_vfio_devices(&known, &unknown).await);
+        orphans.extend(
+            unknown
+                .iter()
+                .filter_map(|u| u.dir.clone())
+                .map(Orphan::SandboxDir),
+        );
+        debug!("gc found {} orphans", orphans.len());
+        orphans
+    }
+
+    async fn unknown_sandboxes(&self, known: &KnownResources) -> Vec<UnknownSandbox> {
+        let mut unknown = vec![];
+        for (path, id) in list_dir(&self.work_dir).await {
+            if !path.is_dir() || known.ids.contains(&id) {
+                continue;
+            }
+            let mut sandbox = UnknownSandbox {
+                id,
+                dir: Some(path.to_string_lossy().to_string()),
+                cgroup_parent: DEFAULT_CGROUP_PARENT_PATH.to_string(),
+                ..Default::default()
+            };
+            // the resources of the sandbox are found in its dump
+            match read_dump_or_backup(&path).await {
+                Ok(dump) => {
+                    if let Some(netns) = dump["data"]["netns"].as_str() {
+                        sandbox.netns = netns.to_string();
+                    }
+                    if let Some(parent) = dump["sandbox_cgroups"]["cgroup_parent_path"]
+                        .as_str()
+                        .filter(|p| !p.is_empty())
+                    {
+                        sandbox.cgroup_parent = parent.to_string();
+                    }
+                    if let Ok(network) = serde_json::from_value::<Network>(dump["network"].clone())
+                    {
+                        sandbox.bdfs = physical_bdfs(&network);
+                    }
+                }
+                Err(e) => debug!("failed to read dump of unknown sandbox {:?}: {}", path, e),
+            }
+            unknown.push(sandbox);
+        }
+        for (path, id) in list_dir(self.pool.work_dir()).await {
+            if path.is_dir() && !known.ids.contains(&id) {
+                unknown.push(UnknownSandbox {
+                    id,
+                    cgroup_parent: DEFAULT_CGROUP_PARENT_PATH.to_string(),
+                    ..Default::default()
+                });
+            }
+        }
+        unknown
+    }
+
+    async fn known_resources(&self) -> KnownResources {
+        let mut known = KnownResources::default();
+        let sandboxes = self.sandboxes.read().await.clone();
+        for (id, sb_mutex) in sandboxes {
+            known.ids.insert(id);
+            let sb = sb_mutex.lock().await;
+            if let Some(name) = sb
+                .pooled_vm_dir
+                .as_ref()
+                .and_then(|d| Path::new(d).file_name())
+            {
+                known.ids.insert(name.to_string_lossy().to_string());
+            }
+            if !sb.data.netns.is_empty() {
+                known.netns.insert(canonical_path(&sb.data.netns));
+            }
+            if let Some(network) = &sb.network {
+                known.bdfs.extend(physical_bdfs(network));
+            }
+        }
+        known.ids.extend(self.pool.vm_ids().await);
+        known
+    }
+
+    fn find_orphan_processes(&self, known: &KnownResources) -> Vec<Orphan> {
+        let dirs = [self.work_dir.as_str(), self.pool.work_dir()];
+        let me = std::process::id() as i32;
+        let mut orphans = vec![];
+        let processes = match procfs::process::all_processes() {
+            Ok(p) => p,
+            Err(e) => {
+                warn!("failed to list processes for gc: {}", e);
+                return orphans;
+            }
+        };
+        for p in processes.flatten() {
+            if p.pid == me {
+                continue;
+            }
+            let cmdline = match p.cmdline() {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+            let owner = cmdline
+                .iter()
+                .flat_map(|arg| owners_in_arg(arg, &dirs))
+                .find(|id| !known.ids.contains(id));
+            if let Some(owner) = owner {
+                orphans.push(Orphan::Process { pid: p.pid, owner });
+            }
+        }
+        orphans
+    }
+}
+
+// owners_in_arg returns the ids of the sandboxes or pooled vms referred by the command line arg,
+// which are the sub directories of the given dirs, every vmm process of the sandboxer
+// refers to the dir of its sandbox, by the console socket or the virtiofsd socket.
+fn owners_in_arg(arg: &str, dirs: &[&str]) -> Vec<String> {
+    let mut owners = vec![];
+    let prefixes = dirs
+        .iter()
+        .map(|d| format!("{}/", d.trim_end_matches('/')))
+        .collect::<Vec<_>>();
+    for prefix in prefixes.iter() {
+        for (i, _) in arg.match_indices(prefix.as_str()) {
+            let rest = &arg[i + prefix.len()..];
+            let end = rest
+                .find(|c: char| c == '/' || c == ',' || c == ':' || c == '=' || c == ' ')
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            if !name.is_empty() {
+                owners.push(name.to_string());
+            }
+        }
+    }
+    owners
+}
+
+fn physical_bdfs(network: &Network) -> Vec<String> {
+    network
+        .interfaces()
+        .iter()
+        .filter_map(|intf| match &intf.r#type {
+            LinkType::Physical(bdf, _) => Some(bdf.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn canonical_path(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+async fn list_dir(dir: &str) -> Vec<(PathBuf, String)> {
+    let mut entries = vec![];
+    let mut subs = match tokio::fs::read_dir(dir).await {
+        Ok(subs) => subs,
+        Err(_) => return entries,
+    };
+    while let Ok(Some(entry)) = subs.next_entry().await {
+        entries.push((
+            entry.path(),
+            entry.file_name().to_string_lossy().to_string(),
+        ));
+    }
+    entries
+}
+
+fn find_orphan_qmp_sockets(unknown: &[UnknownSandbox]) -> Vec<Orphan> {
+    unknown
+        .iter()
+        .map(|u| format!("{}/{}{}", QMP_SOCKET_DIR, u.id, QMP_SOCKET_SUFFIX))
+        .filter(|path| Path::new(path).exists())
+        .map(Orphan::QmpSocket)
+        .collect()
+}
+
+// find_orphan_taps finds the taps created by kuasar in the network namespaces of
+// the unknown sandboxes, together with the ingress qdisc redirecting to them.
+async fn find_orphan_taps(known: &KnownResources, unknown: &[UnknownSandbox]) -> Vec<Orphan> {
+    let mut orphans = vec![];
+    let mut visited = HashSet::new();
+    for netns in unknown.iter().map(|u| &u.netns).filter(|n| !n.is_empty()) {
+        if !Path::new(netns).exists() {
+            continue;
+        }
+        // the netns may be shared with a known sandbox
+        let canonical = canonical_path(netns);
+        if known.netns.contains(&canonical) || !visited.insert(canonical) {
+            continue;
+        }
+        match find_taps_in_netns(netns).await {
+            Ok(o) => orphans.extend(o),
+            Err(e) => debug!("failed to list links in {}: {}", netns, e),
+        }
+    }
+    orphans
+}
+
+async fn find_taps_in_netns(netns: &str) -> Result<Vec<Orphan>> {
+    let handle = create_netlink_handle(netns).await?;
+    let mut links = handle.link().get().execute();
+    let mut taps = vec![];
+    let mut others = vec![];
+    while let Some(msg) = links.try_next().await.map_err(|e| anyhow!(e))? {
+        let index = msg.header.index;
+        let name = msg.attributes.into_iter().find_map(|a| match a {
+            LinkAttribute::IfName(n) => Some(n),
+            _ => None,
+        });
+        match name {
+            Some(n) if n.starts_with(TAP_NAME_PREFIX) => taps.push(Orphan::Tap {
+                netns: netns.to_string(),
+                name: n,
+                index,
+            }),
+            Some(n) if n != "lo" => others.push((n, index)),
+            _ => {}
+        }
+    }
+    if taps.is_empty() {
+        return Ok(vec![]);
+    }
+    // only the ingress qdiscs redirecting to the orphan taps are added by kuasar,
+    // the others, such as the ones of the cni plugins, are left untouched.
+    let tap_indexes: HashSet<u32> = taps
+        .iter()
+        .filter_map(|t| match t {
+            Orphan::Tap { index, .. } => Some(*index),
+            _ => None,
+        })
+        .collect();
+    for (dev, index) in others {
+        let redirects = match get_ingress_redirects(&handle, index).await {
+            Ok(r) => r,
+            Err(e) => {
+                debug!(
+                    "failed to list ingress filters of {} in {}: {}",
+                    dev, netns, e
+                );
+                continue;
+            }
+        };
+        if redirects.iter().any(|r| tap_indexes.contains(r)) {
+            taps.push(Orphan::IngressQdisc {
+                netns: netns.to_string(),
+                dev,
+                index,
+            });
+        }
+    }
+    Ok(taps)
+}
+
+fn find_orphan_cgroups(unknown: &[UnknownSandbox]) -> Vec<Orphan> {
+    unknown
+        .iter()
+        .filter(|u| {
+            // the sandbox cgroups are always created in the cpu subsystem
+            let path = if is_cgroup2_unified_mode() {
+                format!("{}/{}/{}", CGROUP_ROOT, u.cgroup_parent, u.id)
+            } else {
+                format!("{}/cpu/{}/{}", CGROUP_ROOT, u.cgroup_parent, u.id)
+            };
+            Path::new(&path).is_dir()
+        })
+        .map(|u| Orphan::Cgroup {
+            parent: u.cgroup_parent.to_string(),
+            id: u.id.to_string(),
+        })
+        .collect()
+}
+
+// find_orphan_vfio_devices finds the network devices passed through to the unknown sandboxes,
+// which are still bound to vfio-pci and not opened by any process.
+async fn find_orphan_vfio_devices(
+    known: &KnownResources,
+    unknown: &[UnknownSandbox],
+) -> Vec<Orphan> {
+    let mut candidates = vec![];
+    for bdf in unknown.iter().flat_map(|u| u.bdfs.iter()) {
+        if known.bdfs.contains(bdf) {
+            continue;
+        }
+        let path = Path::new(PCI_DEVICES_DIR).join(bdf);
+        let driver_override = tokio::fs::read_to_string(path.join("driver_override"))
+            .await
+            .unwrap_or_default();
+        if driver_override.trim() != DEVICE_DRIVER_VFIO {
+            continue;
+        }
+        let class = tokio::fs::read_to_string(path.join("class"))
+            .await
+            .unwrap_or_default();
+        if !class.starts_with(PCI_CLASS_NETWORK_PREFIX) {
+            continue;
+        }
+        if get_pci_driver(bdf).await.ok().as_deref() != Some(DEVICE_DRIVER_VFIO) {
+            continue;
+        }
+        let group = match tokio::fs::read_link(path.join("iommu_group")).await {
+            Ok(g) => g,
+            Err(_) => continue,
+        };
+        if let Some(group) = group.file_name() {
+            candidates.push((
+                bdf.to_string(),
+                format!("/dev/vfio/{}", group.to_string_lossy()),
+            ));
+        }
+    }
+    if candidates.is_empty() {
+        return vec![];
+    }
+    let opened = opened_files();
+    candidates
+        .into_iter()
+        .filter(|(_, group)| !opened.contains(&PathBuf::from(group)))
+        .map(|(bdf, _)| Orphan::VfioDevice(bdf))
+        .collect()
+}
+
+fn opened_files() -> HashSet<PathBuf> {
+    let mut files = HashSet::new();
+    let processes = match procfs::process::all_processes() {
+        Ok(p) => p,
+        Err(_) => return files,
+    };
+    for p in processes.flatten() {
+        if let Ok(fds) = p.fd() {
+            for fd in fds.flatten() {
+                if let procfs::process::FDTarget::Path(path) = fd.target {
+                    files.insert(path);
+                }
+            }
+        }
+    }
+    files
+}
+
+async fn reclaim_orphan(orphan: &Orphan) -> Result<()> {
+    match orphan {
+        Orphan::Process { pid, .. } => {
+            signal::kill(Pid::from_raw(*pid), signal::SIGKILL)
+                .map_err(|e| anyhow!("kill process {}: {}", pid, e))?;
+        }
+        Orphan::QmpSocket(path) => {
+            tokio::fs::remove_file(path)
+                .await
+                .map_err(|e| anyhow!("remove {}: {}", path, e))?;
+        }
+        Orphan::Tap { netns, index, .. } => {
+            let handle = create_netlink_handle(netns).await?;
+            handle
+                .link()
+                .del(*index)
+                .execute()
+                .await
+                .map_err(|e| anyhow!("delete link {}: {}", index, e))?;
+        }
+        Orphan::IngressQdisc { netns, dev, index } => {
+            let handle = create_netlink_handle(netns).await?;
+            del_qdisc_ingress(&handle, *index)
+                .await
+                .map_err(|e| anyhow!("delete ingress qdisc of {}: {}", dev, e))?;
+        }
+        Orphan::Cgroup { parent, id } => {
+            // the cgroups may be removed since found, they are never created again here
+            if let Some(cgroups) = SandboxCgroup::load_sandbox_cgroups(parent, id) {
+                cgroups.remove_sandbox_cgroups()?;
+            }
+        }
+        Orphan::VfioDevice(bdf) => {
+            // give the device back to its native driver
+            let path = format!("{}/{}", PCI_DEVICES_DIR, bdf);
+            write_file_async(format!("{}/driver_override", path), "\n").await?;
+            write_file_async(format!("{}/driver/unbind", path), bdf).await?;
+            write_file_async("/sys/bus/pci/drivers_probe", bdf).await?;
+        }
+        Orphan::SandboxDir(path) => {
+            cleanup_mounts(path).await?;
+            tokio::fs::remove_dir_all(path)
+                .await
+                .map_err(|e| anyhow!("remove {}: {}", path, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gc::{owners_in_arg, GCConfig};
+
+    #[test]
+    fn test_gc_config_default() {
+        let config: GCConfig = toml::from_str("interval_in_sec = 600").unwrap();
+        assert_eq!(config.interval_in_sec, 600);
+        assert!(config.dry_run);
+        assert!(GCConfig::default().dry_run);
+    }
+
+    #[test]
+    fn test_owners_in_arg() {
+        let dirs = ["/run/kuasar-vmm", "/run/kuasar-vmm-pool/"];
+        assert_eq!(
+            owners_in_arg(
+                "socket,id=charch0,path=/run/kuasar-vmm/sb1/task.vsock",
+                &dirs
+            ),
+            vec!["sb1"]
+        );
+        // the qmp socket of other sandboxers is not taken as one of the sandboxer
+        assert!(owners_in_arg("unix:/run/sb2-qmp.sock,server,nowait", &dirs).is_empty());
+        assert_eq!(
+            owners_in_arg(
+                "--socket-path=/run/kuasar-vmm-pool/pool-1/virtiofs.sock",
+                &dirs
+            ),
+            vec!["pool-1"]
+        );
+        assert!(owners_in_arg("/run/kuasar-vmm", &dirs).is_empty());
+        assert!(owners_in_arg("/run/containerd/containerd.sock", &dirs).is_empty());
+    }
+}
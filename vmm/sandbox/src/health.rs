@@ -0,0 +1,275 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use containerd_sandbox::{error::Result, SandboxStatus};
+use containerd_shim::protos::api::Envelope;
+use log::{debug, error, info, warn};
+use protobuf::{well_known_types::any::Any, Message, MessageField};
+use serde::Deserialize;
+use tokio::{sync::Mutex, time::timeout};
+use vmm_common::api::sandbox::{SandboxUnhealthy, SandboxVMEvent};
+
+use crate::{
+    client::{client_check_once, publish_event},
+    crash::GUEST_PANIC_EVENT,
+    sandbox::KuasarSandbox,
+    vm::VM,
+};
+
+const DEFAULT_HEALTH_CHECK_TIMEOUT_IN_SEC: u64 = 5;
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+const EVENT_NAMESPACE: &str = "k8s.io";
+const SANDBOX_UNHEALTHY_EVENT_TOPIC: &str = "/sandbox/unhealthy";
+const SANDBOX_UNHEALTHY_EVENT_TYPE: &str = "grpc.SandboxUnhealthy";
+const SANDBOX_VM_EVENT_TOPIC: &str = "/sandbox/vm-event";
+const SANDBOX_VM_EVENT_TYPE: &str = "grpc.SandboxVMEvent";
+// the vm is stopped if it is still running a while after the guest panicked
+const GUEST_PANIC_STOP_DELAY_IN_SEC: u64 = 3;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct HealthCheckConfig {
+    /// Interval of checking the vm and the agent of running sandboxes, 0 disables the check
+    #[serde(default)]
+    pub interval_in_sec: u64,
+    /// Timeout of each vm ping and agent check
+    #[serde(default = "default_health_check_timeout_in_sec")]
+    pub timeout_in_sec: u64,
+    /// The sandbox is unhealthy after the checks fail for these times in a row
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Force stop the unhealthy sandbox, so that it can be recreated by kubelet
+    #[serde(default)]
+    pub stop_unhealthy: bool,
+}
+
+fn default_health_check_timeout_in_sec() -> u64 {
+    DEFAULT_HEALTH_CHECK_TIMEOUT_IN_SEC
+}
+
+fn default_failure_threshold() -> u32 {
+    DEFAULT_FAILURE_THRESHOLD
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval_in_sec: 0,
+            timeout_in_sec: DEFAULT_HEALTH_CHECK_TIMEOUT_IN_SEC,
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            stop_unhealthy: false,
+        }
+    }
+}
+
+// watch_health checks the running sandbox periodically until it exits,
+// the monitor of the vm process can not find a hung guest or agent.
+pub(crate) fn watch_health<V: VM + Sync + Send + 'static>(
+    config: HealthCheckConfig,
+    sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>,
+) {
+    if config.interval_in_sec == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let (id, exit_signal) = {
+            let sandbox = sandbox_mutex.lock().await;
+            (sandbox.id.to_string(), sandbox.exit_signal.clone())
+        };
+        let mut failures = 0u32;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(config.interval_in_sec)) => {},
+                _ = exit_signal.wait() => {
+                    debug!("stop health check of exited sandbox {}", id);
+                    return;
+                },
+            }
+            let res = match check(&config, &sandbox_mutex).await {
+                Some(res) => res,
+                None => return,
+            };
+            match res {
+                Ok(_) => {
+                    if failures >= config.failure_threshold {
+                        info!("sandbox {} is healthy again", id);
+                    }
+                    failures = 0;
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(
+                        "health check of sandbox {} failed {} times: {}",
+                        id, failures, e
+                    );
+                    // report only once until the sandbox is healthy again
+                    if failures != config.failure_threshold {
+                        continue;
+                    }
+                    if let Err(pe) =
+                        publish_event(unhealthy_event(&id, &e.to_string(), failures)).await
+                    {
+                        error!(
+                            "failed to publish unhealthy event of sandbox {}: {}",
+                            id, pe
+                        );
+                    }
+                    if config.stop_unhealthy {
+                        warn!("force stop unhealthy sandbox {}", id);
+                        // the monitor of the sandbox will handle the exit of the vm
+                        if let Err(se) = sandbox_mutex.lock().await.vm.stop(true).await {
+                            error!("failed to stop unhealthy sandbox {}: {}", id, se);
+                        }
+                        return;
+                    }
+                }
+            }
+        }
+    });
+}
+
+// watch_vm_events publishes the notable events reported by the vmm of the running sandbox,
+// the channel is closed when the vmm exits.
+pub(crate) fn watch_vm_events<V: VM + Sync + Send + 'static>(
+    sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>,
+) {
+    tokio::spawn(async move {
+        let (id, mut events) = {
+            let mut sandbox = sandbox_mutex.lock().await;
+            match sandbox.vm.take_vm_events() {
+                Some(events) => (sandbox.id.to_string(), events),
+                None => return,
+            }
+        };
+        while let Some(event) = events.recv().await {
+            warn!(
+                "sandbox {} got vm event {}: {}",
+                id, event.name, event.detail
+            );
+            publish_vm_event(&id, &event.name, &event.detail).await;
+            if event.name == GUEST_PANIC_EVENT {
+                sandbox_mutex.lock().await.guest_panic = Some(event.detail.to_string());
+                stop_panicked_vm(&id, &sandbox_mutex).await;
+            }
+        }
+        debug!("stop watching vm events of sandbox {}", id);
+    });
+}
+
+pub(crate) async fn publish_vm_event(id: &str, name: &str, detail: &str) {
+    if let Err(e) = publish_event(vm_event(id, name, detail)).await {
+        error!(
+            "failed to publish vm event {} of sandbox {}: {}",
+            name, id, e
+        );
+    }
+}
+
+// stop_panicked_vm stops the vm which is paused or hung after the guest panicked,
+// the exit of the vm is handled by the monitor of the sandbox.
+async fn stop_panicked_vm<V: VM + Sync + Send>(id: &str, sandbox_mutex: &Mutex<KuasarSandbox<V>>) {
+    tokio::time::sleep(Duration::from_secs(GUEST_PANIC_STOP_DELAY_IN_SEC)).await;
+    let mut sandbox = sandbox_mutex.lock().await;
+    if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+        return;
+    }
+    warn!("force stop sandbox {} after the guest panicked", id);
+    if let Err(e) = sandbox.vm.stop(true).await {
+        error!("failed to stop panicked sandbox {}: {}", id, e);
+    }
+}
+
+// check pings the vm and then checks the agent,
+// None is returned if the sandbox is not running any more.
+async fn check<V: VM + Sync + Send>(
+    config: &HealthCheckConfig,
+    sandbox_mutex: &Mutex<KuasarSandbox<V>>,
+) -> Option<Result<()>> {
+    let duration = Duration::from_secs(config.timeout_in_sec);
+    let sandbox = sandbox_mutex.lock().await;
+    if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+        return None;
+    }
+    match timeout(duration, sandbox.vm.ping()).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Some(Err(anyhow!("ping vm: {}", e).into())),
+        Err(_) => return Some(Err(anyhow!("ping vm timeout").into())),
+    }
+    let client = sandbox.client.lock().await.clone();
+    // the agent is checked without holding the sandbox lock
+    drop(sandbox);
+    match client {
+        Some(c) => Some(client_check_once(&c, config.timeout_in_sec).await),
+        None => Some(Err(anyhow!("agent is not connected").into())),
+    }
+}
+
+fn unhealthy_event(id: &str, reason: &str, failures: u32) -> Envelope {
+    let mut event = SandboxUnhealthy::new();
+    event.sandbox_id = id.to_string();
+    event.reason = reason.to_string();
+    event.failures = failures;
+
+    new_envelope(
+        SANDBOX_UNHEALTHY_EVENT_TOPIC,
+        SANDBOX_UNHEALTHY_EVENT_TYPE,
+        event.write_to_bytes().unwrap_or_default(),
+    )
+}
+
+fn vm_event(id: &str, name: &str, detail: &str) -> Envelope {
+    let mut event = SandboxVMEvent::new();
+    event.sandbox_id = id.to_string();
+    event.event = name.to_string();
+    event.detail = detail.to_string();
+    new_envelope(
+        SANDBOX_VM_EVENT_TOPIC,
+        SANDBOX_VM_EVENT_TYPE,
+        event.write_to_bytes().unwrap_or_default(),
+    )
+}
+
+fn new_envelope(topic: &str, type_url: &str, value: Vec<u8>) -> Envelope {
+    let mut any = Any::new();
+    any.type_url = type_url.to_string();
+    any.value = value;
+
+    let mut envelope = Envelope::new();
+    envelope.timestamp = MessageField::some(SystemTime::now().into());
+    envelope.namespace = EVENT_NAMESPACE.to_string();
+    envelope.topic = topic.to_string();
+    envelope.event = MessageField::some(any);
+    envelope
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::health::HealthCheckConfig;
+
+    #[test]
+    fn test_health_check_config_default() {
+        let config: HealthCheckConfig = toml::from_str("interval_in_sec = 10").unwrap();
+        assert_eq!(config.interval_in_sec, 10);
+        assert_eq!(config.timeout_in_sec, 5);
+        assert_eq!(config.failure_threshold, 3);
+        assert!(!config.stop_unhealthy);
+    }
+}
@@ -14,29 +14,293 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use containerd_sandbox::error::Result;
+use std::sync::Arc;
 
-use crate::{
-    device::{CharBackendType, CharDeviceInfo, DeviceInfo},
-    sandbox::KuasarSandbox,
-    vm::VM,
+use anyhow::anyhow;
+use containerd_sandbox::{data::Io, error::Result, signal::ExitSignal};
+use log::{debug, warn};
+use protobuf::{Message, MessageFull};
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Semaphore,
 };
+use ttrpc::{
+    context::with_timeout,
+    r#async::{ClientStream, ClientStreamReceiver, ClientStreamSender},
+};
+use vmm_common::api::{
+    any::Any,
+    data::{Data, WindowUpdate},
+    streaming::StreamInit,
+    streaming_ttrpc::StreamingClient,
+};
+
+use crate::{client::new_streaming_client, sandbox::KuasarSandbox, vm::VM};
+
+// the task server takes the io with "streaming" in the url as a stream of its Streaming service,
+// and the stream id after "id=" tells which one of the stdio it is by the suffix.
+const STREAMING_URL_PREFIX: &str = "ttrpc+hvsock://kuasar/streaming?id=";
+const STREAMING_BUFFER_SIZE: usize = 32 * 1024;
+
+const STDIN: &str = "stdin";
+const STDOUT: &str = "stdout";
+const STDERR: &str = "stderr";
+
+pub fn stream_io_id(container_id: &str, process_id: &str) -> String {
+    format!("{}-{}", container_id, process_id)
+}
 
 impl<V> KuasarSandbox<V>
 where
     V: VM + Sync + Send,
 {
-    pub async fn hot_attach_pipe(&mut self, path: &str) -> Result<(String, String)> {
-        let id = self.increment_and_get_id();
-        let device_id = format!("virtioserial{}", id);
-        let chardev_id = format!("chardev{}", id);
-        let char_dev = CharDeviceInfo {
-            id: device_id.to_string(),
-            chardev_id: chardev_id.to_string(),
-            name: chardev_id.to_string(),
-            backend: CharBackendType::Pipe(path.to_string()),
+    // stream_io proxies the named pipes of the io over the Streaming service of the task server,
+    // so that no serial port is attached for each of them, and returns the io of the streams.
+    pub(crate) async fn stream_io(&mut self, id: &str, io: &Io) -> Result<Io> {
+        let exit_signal = Arc::new(ExitSignal::default());
+        match self.start_io_streams(id, io, &exit_signal).await {
+            Ok(stream_io) => {
+                self.io_streams.insert(id.to_string(), exit_signal);
+                Ok(stream_io)
+            }
+            Err(e) => {
+                exit_signal.signal();
+                Err(e)
+            }
+        }
+    }
+
+    pub(crate) fn stop_io_streams(&mut self, id: &str) {
+        if let Some(exit_signal) = self.io_streams.remove(id) {
+            exit_signal.signal();
+        }
+    }
+
+    // recover_io_streams reconnects the streams after the sandboxer restarts, the output buffered
+    // in the task server is taken over by the new streams, and the stdin of the process is kept
+    // open by the task server when the previous connection is broken, so it is streamed again.
+    pub(crate) async fn recover_io_streams(&mut self) {
+        let mut ios = vec![];
+        for c in self.containers.values() {
+            if let Some(io) = &c.io_pipes {
+                ios.push((c.id.to_string(), io.clone()));
+            }
+            for p in &c.processes {
+                if let Some(io) = &p.io_pipes {
+                    ios.push((stream_io_id(&c.id, &p.id), io.clone()));
+                }
+            }
+        }
+        for (id, io) in ios {
+            let exit_signal = Arc::new(ExitSignal::default());
+            match self.start_io_streams(&id, &io, &exit_signal).await {
+                Ok(_) => {
+                    self.io_streams.insert(id, exit_signal);
+                }
+                Err(e) => {
+                    exit_signal.signal();
+                    warn!("failed to recover io streams of {}: {}", id, e);
+                }
+            }
+        }
+    }
+
+    async fn start_io_streams(
+        &self,
+        id: &str,
+        io: &Io,
+        exit_signal: &Arc<ExitSignal>,
+    ) -> Result<Io> {
+        let mut client = None;
+        let stdin = self
+            .start_stream(&mut client, id, &io.stdin, STDIN, exit_signal)
+            .await?;
+        let stdout = self
+            .start_stream(&mut client, id, &io.stdout, STDOUT, exit_signal)
+            .await?;
+        let stderr = self
+            .start_stream(&mut client, id, &io.stderr, STDERR, exit_signal)
+            .await?;
+        Ok(Io {
+            stdin,
+            stdout,
+            stderr,
+            terminal: io.terminal,
+        })
+    }
+
+    async fn start_stream(
+        &self,
+        client: &mut Option<StreamingClient>,
+        id: &str,
+        path: &str,
+        name: &str,
+        exit_signal: &Arc<ExitSignal>,
+    ) -> Result<String> {
+        let url = stream_url(id, path, name);
+        if url == path {
+            return Ok(url);
+        }
+        // all the stdio of a process share one connection to the task server
+        let client = match client {
+            Some(c) => c.clone(),
+            None => {
+                let c = new_streaming_client(&self.vm.socket_address()).await?;
+                *client = Some(c.clone());
+                c
+            }
         };
-        self.vm.hot_attach(DeviceInfo::Char(char_dev)).await?;
-        Ok((device_id, chardev_id))
+        let stream_id = format!("{}-{}", id, name);
+        let mut stream = client
+            .stream(with_timeout(0))
+            .await
+            .map_err(|e| anyhow!("failed to open stream {}, {}", stream_id, e))?;
+        let mut init = StreamInit::new();
+        init.id = stream_id.to_string();
+        stream
+            .send(&new_any(&init)?)
+            .await
+            .map_err(|e| anyhow!("failed to init stream {}, {}", stream_id, e))?;
+        // the io channel is ready in the task server once the stream is acknowledged
+        stream
+            .recv()
+            .await
+            .map_err(|e| anyhow!("failed to wait ack of stream {}, {}", stream_id, e))?;
+        debug!("stream {} is set up for {}", stream_id, path);
+
+        let path = path.to_string();
+        let is_stdin = name == STDIN;
+        let exit_signal = exit_signal.clone();
+        tokio::spawn(async move {
+            // the connection is closed once the client is dropped
+            let _client = client;
+            let res = tokio::select! {
+                _ = exit_signal.wait() => Ok(()),
+                res = copy_stream(&path, stream, is_stdin) => res,
+            };
+            if let Err(e) = res {
+                warn!("failed to copy io of stream {}: {}", stream_id, e);
+            }
+            debug!("stream {} of {} finished", stream_id, path);
+        });
+        Ok(url)
+    }
+}
+
+// stream_url returns the url of the stream for the named pipe, the other kinds of io,
+// such as the ones already streamed by containerd, are passed through.
+fn stream_url(id: &str, path: &str, name: &str) -> String {
+    if path.is_empty() || path.contains("://") {
+        return path.to_string();
+    }
+    format!("{}{}-{}", STREAMING_URL_PREFIX, id, name)
+}
+
+async fn copy_stream(path: &str, stream: ClientStream<Any, Any>, is_stdin: bool) -> Result<()> {
+    if is_stdin {
+        let (sender, receiver) = stream.split();
+        let res = copy_to_stream(path, &sender, receiver).await;
+        // the stdin of the process is closed when the stream is closed
+        sender.close_send().await.unwrap_or_default();
+        res
+    } else {
+        copy_from_stream(path, stream).await
+    }
+}
+
+// copy_to_stream copies the stdin from the named pipe to the stream, no more than the window
+// granted by the task server is sent.
+async fn copy_to_stream(
+    path: &str,
+    sender: &ClientStreamSender<Any, Any>,
+    mut receiver: ClientStreamReceiver<Any>,
+) -> Result<()> {
+    let window = Arc::new(Semaphore::new(0));
+    let window_clone = window.clone();
+    let updater = tokio::spawn(async move {
+        while let Ok(a) = receiver.recv().await {
+            if let Ok(update) = WindowUpdate::parse_from_bytes(&a.value) {
+                window_clone.add_permits(update.update.max(0) as usize);
+            }
+        }
+        window_clone.close();
+    });
+
+    let res = async {
+        let mut pipe = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .await
+            .map_err(|e| anyhow!("failed to open {}, {}", path, e))?;
+        let mut buf = vec![0u8; STREAMING_BUFFER_SIZE];
+        loop {
+            let n = pipe
+                .read(&mut buf)
+                .await
+                .map_err(|e| anyhow!("failed to read {}, {}", path, e))?;
+            if n == 0 {
+                return Ok(());
+            }
+            window
+                .acquire_many(n as u32)
+                .await
+                .map_err(|_| anyhow!("stream of {} is closed", path))?
+                .forget();
+            let mut data = Data::new();
+            data.data = buf[..n].to_vec();
+            sender
+                .send(&new_any(&data)?)
+                .await
+                .map_err(|e| anyhow!("failed to send data of {}, {}", path, e))?;
+        }
+    }
+    .await;
+    updater.abort();
+    res
+}
+
+// copy_from_stream copies the output from the stream to the named pipe, until the stream is
+// closed by the task server after the process exits.
+async fn copy_from_stream(path: &str, mut stream: ClientStream<Any, Any>) -> Result<()> {
+    let mut pipe = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|e| anyhow!("failed to open {}, {}", path, e))?;
+    while let Ok(a) = stream.recv().await {
+        let data = Data::parse_from_bytes(&a.value)
+            .map_err(|e| anyhow!("failed to unmarshal data of {}, {}", path, e))?;
+        pipe.write_all(&data.data)
+            .await
+            .map_err(|e| anyhow!("failed to write {}, {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn new_any<M: MessageFull>(m: &M) -> Result<Any> {
+    let mut a = Any::new();
+    a.type_url = M::descriptor().full_name().to_string();
+    a.value = m
+        .write_to_bytes()
+        .map_err(|e| anyhow!("failed to marshal {}, {}", a.type_url, e))?;
+    Ok(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stream_url, STDERR, STDIN};
+
+    #[test]
+    fn test_stream_url() {
+        assert_eq!(
+            stream_url("c1-exec1", "/run/containerd/fifo/1/c1-stdin", STDIN),
+            "ttrpc+hvsock://kuasar/streaming?id=c1-exec1-stdin"
+        );
+        assert_eq!(stream_url("c1", "", STDERR), "");
+        assert_eq!(
+            stream_url("c1", "ttrpc+hvsock://x/y?id=c1-stderr", STDERR),
+            "ttrpc+hvsock://x/y?id=c1-stderr"
+        );
     }
 }
@@ -0,0 +1,146 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{io::ErrorKind, path::Path};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::write_file_atomic;
+
+const JOURNAL_FILE: &str = "journal.json";
+
+pub(crate) const STEP_NETWORK_PREPARED: &str = "network_prepared";
+pub(crate) const STEP_VM_STARTED: &str = "vm_started";
+// STEP_COMMITTED is recorded with the final dump of the operation, an operation with it
+// is complete, only the journal is left if the sandboxer exits before finishing it.
+pub(crate) const STEP_COMMITTED: &str = "committed";
+
+// Operation is a sandbox lifecycle call that changes resources out of the sandboxer process,
+// if the sandboxer exits in the middle of it, the recovery finishes or rolls back it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Operation {
+    Start,
+    AppendContainer(String),
+    RemoveContainer(String),
+}
+
+// JournalEntry is the in-flight operation of a sandbox and its completed steps,
+// there is at most one of them as the operations of a sandbox are serialized by its lock.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct JournalEntry {
+    pub(crate) operation: Operation,
+    pub(crate) steps: Vec<String>,
+}
+
+impl JournalEntry {
+    pub(crate) fn has_step(&self, step: &str) -> bool {
+        self.steps.iter().any(|s| s == step)
+    }
+}
+
+// Journaled is implemented by the sandbox to persist its state together with the journal,
+// so that resources created by a step can be found by the recovery.
+#[async_trait]
+pub(crate) trait Journaled {
+    async fn persist(&self) -> Result<()>;
+    async fn record_step(&mut self, step: &str) -> Result<()>;
+}
+
+fn journal_path(dir: &str) -> String {
+    format!("{}/{}", dir, JOURNAL_FILE)
+}
+
+async fn write_journal(dir: &str, entry: &JournalEntry) -> Result<()> {
+    let content =
+        serde_json::to_string(entry).map_err(|e| anyhow!("failed to serialize journal, {}", e))?;
+    write_file_atomic(journal_path(dir), &content).await
+}
+
+// begin records the operation before any change is made.
+pub(crate) async fn begin(dir: &str, operation: Operation) -> Result<()> {
+    write_journal(
+        dir,
+        &JournalEntry {
+            operation,
+            steps: vec![],
+        },
+    )
+    .await
+}
+
+pub(crate) async fn step(dir: &str, step: &str) -> Result<()> {
+    let mut entry = read(dir)
+        .await?
+        .ok_or_else(|| anyhow!("no operation in flight in {}", dir))?;
+    entry.steps.push(step.to_string());
+    write_journal(dir, &entry).await
+}
+
+// finish removes the journal after the operation is done or rolled back.
+pub(crate) async fn finish(dir: &str) -> Result<()> {
+    match tokio::fs::remove_file(journal_path(dir)).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(anyhow!("failed to remove journal in {}, {}", dir, e).into()),
+    }
+}
+
+pub(crate) async fn read(dir: &str) -> Result<Option<JournalEntry>> {
+    let path = journal_path(dir);
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+    let content = tokio::fs::read(&path).await.map_err(Error::IO)?;
+    let entry = serde_json::from_slice::<JournalEntry>(&content)
+        .map_err(|e| anyhow!("failed to parse journal {}, {}", path, e))?;
+    Ok(Some(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use crate::journal::{begin, finish, read, step, Operation, STEP_COMMITTED, STEP_VM_STARTED};
+
+    #[tokio::test]
+    async fn test_journal() {
+        let dir = TempDir::new().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        assert!(read(dir).await.unwrap().is_none());
+        assert!(step(dir, STEP_VM_STARTED).await.is_err());
+
+        begin(dir, Operation::AppendContainer("c1".to_string()))
+            .await
+            .unwrap();
+        let entry = read(dir).await.unwrap().unwrap();
+        assert!(!entry.has_step(STEP_COMMITTED));
+        step(dir, STEP_COMMITTED).await.unwrap();
+        let entry = read(dir).await.unwrap().unwrap();
+        assert_eq!(
+            entry.operation,
+            Operation::AppendContainer("c1".to_string())
+        );
+        assert!(entry.has_step(STEP_COMMITTED));
+        assert!(!entry.has_step(STEP_VM_STARTED));
+
+        finish(dir).await.unwrap();
+        assert!(read(dir).await.unwrap().is_none());
+        finish(dir).await.unwrap();
+    }
+}
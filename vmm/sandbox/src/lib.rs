@@ -18,13 +18,22 @@ limitations under the License.
 #[macro_use]
 mod device;
 
+mod admin;
 mod cgroup;
 mod client;
 mod container;
+mod crash;
+mod gc;
+mod health;
 mod io;
+mod journal;
 mod network;
 mod param;
+mod persist;
+mod pool;
 mod storage;
+mod template;
+mod virtiofsd;
 mod vm;
 
 pub mod args;
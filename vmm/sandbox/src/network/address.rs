@@ -41,7 +41,7 @@ impl From<&mut CniIPAddress> for String {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(from = "String")]
 #[serde(into = "String")]
 pub struct IpNet {
@@ -110,7 +110,7 @@ impl IpNet {
     }
 }
 
-#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+#[derive(Default, Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(from = "String")]
 #[serde(into = "String")]
 pub struct MacAddress(pub(crate) Vec<u8>);
@@ -0,0 +1,110 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use serde_derive::{Deserialize, Serialize};
+
+pub const INGRESS_BANDWIDTH_ANNOTATION: &str = "kubernetes.io/ingress-bandwidth";
+pub const EGRESS_BANDWIDTH_ANNOTATION: &str = "kubernetes.io/egress-bandwidth";
+
+// Bandwidth is the rate limit of the pod network in bits per second, 0 means no limit,
+// ingress is the traffic into the pod and egress is the traffic out of the pod.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bandwidth {
+    #[serde(default)]
+    pub ingress: u64,
+    #[serde(default)]
+    pub egress: u64,
+}
+
+impl Bandwidth {
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let mut bandwidth = Bandwidth::default();
+        if let Some(v) = annotations.get(INGRESS_BANDWIDTH_ANNOTATION) {
+            bandwidth.ingress = parse_quantity(v)?;
+        }
+        if let Some(v) = annotations.get(EGRESS_BANDWIDTH_ANNOTATION) {
+            bandwidth.egress = parse_quantity(v)?;
+        }
+        Ok(bandwidth)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ingress == 0 && self.egress == 0
+    }
+}
+
+// parse_quantity parses the kubernetes quantity, like "10M" or "1Gi"
+pub(crate) fn parse_quantity(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let pos = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, suffix) = s.split_at(pos);
+    let multiplier: u64 = match suffix {
+        "" => 1,
+        "k" => 1000,
+        "M" => 1000u64.pow(2),
+        "G" => 1000u64.pow(3),
+        "T" => 1000u64.pow(4),
+        "P" => 1000u64.pow(5),
+        "Ki" => 1 << 10,
+        "Mi" => 1 << 20,
+        "Gi" => 1 << 30,
+        "Ti" => 1 << 40,
+        "Pi" => 1 << 50,
+        _ => return Err(anyhow!("invalid suffix of quantity {}", s).into()),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|e| anyhow!("invalid quantity {}: {}", s, e))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::network::bandwidth::{parse_quantity, Bandwidth};
+
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!(parse_quantity("1000").unwrap(), 1000);
+        assert_eq!(parse_quantity("10M").unwrap(), 10_000_000);
+        assert_eq!(parse_quantity("1.5G").unwrap(), 1_500_000_000);
+        assert_eq!(parse_quantity("1Gi").unwrap(), 1 << 30);
+        assert!(parse_quantity("10X").is_err());
+        assert!(parse_quantity("M").is_err());
+    }
+
+    #[test]
+    fn test_from_annotations() {
+        let mut annotations = HashMap::new();
+        assert!(Bandwidth::from_annotations(&annotations)
+            .unwrap()
+            .is_empty());
+        annotations.insert(
+            "kubernetes.io/ingress-bandwidth".to_string(),
+            "10M".to_string(),
+        );
+        let bandwidth = Bandwidth::from_annotations(&annotations).unwrap();
+        assert_eq!(bandwidth.ingress, 10_000_000);
+        assert_eq!(bandwidth.egress, 0);
+    }
+}
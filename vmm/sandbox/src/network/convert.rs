@@ -16,7 +16,7 @@ limitations under the License.
 
 use netlink_packet_route::AddressFamily;
 use protobuf::{EnumOrUnknown, SpecialFields};
-use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Route};
+use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Neighbor, Route};
 
 use crate::network::{IpNet, NetworkInterface};
 
@@ -68,3 +68,15 @@ impl From<&crate::network::Route> for Route {
         }
     }
 }
+
+impl From<&crate::network::Neighbor> for Neighbor {
+    fn from(n: &crate::network::Neighbor) -> Self {
+        Self {
+            ip_address: n.ip_address.to_string(),
+            device: n.device.to_string(),
+            lladdr: n.lladdr.to_string(),
+            state: n.state as u32,
+            special_fields: Default::default(),
+        }
+    }
+}
@@ -28,13 +28,20 @@ use anyhow::anyhow;
 use containerd_sandbox::error::Result;
 use futures_util::TryStreamExt;
 use libc::{IFF_MULTI_QUEUE, IFF_NO_PI, IFF_TAP, IFF_VNET_HDR};
+use log::{debug, warn};
 use netlink_packet_route::link::{
     InfoData, InfoIpVlan, InfoKind, InfoMacVlan, InfoMacVtap, InfoVlan, InfoVxlan, LinkFlag,
     LinkInfo, LinkMessage,
 };
 use nix::{
+    fcntl::OFlag,
     ioctl_read_bad, ioctl_write_ptr_bad, libc,
-    sys::socket::{socket, AddressFamily, SockFlag, SockType},
+    mount::{mount, MsFlags},
+    sched::{setns, unshare, CloneFlags},
+    sys::{
+        socket::{socket, AddressFamily, SockFlag, SockType},
+        stat::{makedev, mknod, Mode, SFlag},
+    },
     unistd::close,
 };
 use rtnetlink::Handle;
@@ -44,18 +51,27 @@ use crate::{
     device::{DeviceInfo, PhysicalDeviceInfo, TapDeviceInfo, VhostUserDeviceInfo},
     network::{
         address::{CniIPAddress, IpNet, MacAddress},
-        create_netlink_handle, execute_in_netns, run_in_new_netns,
+        bandwidth::Bandwidth,
+        create_netlink_handle,
+        netlink::{
+            redirect_dests, QDiscAddRequest, QDiscDelRequest, TrafficFilterGetRequest,
+            TrafficFilterSetRequest,
+        },
+        run_in_new_netns,
     },
     sandbox::KuasarSandbox,
-    utils::write_file_async,
+    utils::{safe_open_file, write_file_async},
     vm::VM,
 };
 
-const DEVICE_DRIVER_VFIO: &str = "vfio-pci";
+pub(crate) const DEVICE_DRIVER_VFIO: &str = "vfio-pci";
 
 const SIOCETHTOOL: u64 = 0x8946;
 const ETHTOOL_GDRVINFO: u32 = 0x00000003;
 
+// the taps created for the links redirected to them are named with the index of the link
+pub(crate) const TWIN_TAP_PREFIX: &str = "tap_kua_";
+
 const TUNSETIFF: u64 = 0x400454ca;
 const TUNSETPERSIST: u64 = 0x400454cb;
 
@@ -174,9 +190,9 @@ impl From<InfoData> for LinkType {
 pub struct NetworkInterface {
     #[serde(default)]
     pub device: String,
-    #[serde(skip)]
+    #[serde(default)]
     pub r#type: LinkType,
-    #[serde(skip)]
+    #[serde(default)]
     pub index: u32,
     #[serde(default)]
     pub name: String,
@@ -198,7 +214,7 @@ pub struct NetworkInterface {
     pub cni_link_type: String,
     #[serde(rename = "vhostUserSocket")]
     pub vhost_user_socket: String,
-    #[serde(skip)]
+    #[serde(default)]
     pub twin: Option<Box<NetworkInterface>>,
     #[serde(skip)]
     pub fds: Vec<OwnedFd>,
@@ -241,7 +257,6 @@ impl NetworkInterface {
                         } else if let LinkInfo::Kind(InfoKind::Veth) = info {
                             // for veth, there is no Info::Data, but SlaveKind and SlaveData,
                             // so we have to get the type from Info::Kind
-                            intf.queue = queue;
                             intf.r#type = LinkType::Veth;
                         }
                     }
@@ -255,6 +270,13 @@ impl NetworkInterface {
                 _ => {}
             }
         }
+        // the queues of the tap created or opened for the interface
+        if matches!(
+            intf.r#type,
+            LinkType::Veth | LinkType::Macvlan(_) | LinkType::Ipvlan(_) | LinkType::Macvtap(_)
+        ) {
+            intf.queue = queue;
+        }
         let mut addresses = handle
             .address()
             .get()
@@ -311,17 +333,22 @@ impl NetworkInterface {
 
     pub async fn prepare_attaching(&mut self, netns: &str) -> Result<()> {
         match &self.r#type {
-            LinkType::Veth => {
+            // packets of the link are redirected to and from a tap created for it,
+            // as these links can not be passed to the vm directly
+            LinkType::Veth | LinkType::Macvlan(_) | LinkType::Ipvlan(_) => {
                 let handle = create_netlink_handle(netns).await?;
-                let tap_name = format!("tap_kua_{}", self.index);
+                let tap_name = format!("{}{}", TWIN_TAP_PREFIX, self.index);
                 let tap_intf =
                     create_tap_in_netns(netns, &tap_name, self.queue, self.mtu, &handle).await?;
-                tap_intf.add_qdisc_ingress(netns, &handle).await?;
-                self.add_qdisc_ingress(netns, &handle).await?;
-                tap_intf.add_redirect_tc_filter(netns, &self.name).await?;
-                self.add_redirect_tc_filter(netns, &tap_intf.name).await?;
+                tap_intf.add_qdisc_ingress(&handle).await?;
+                self.add_qdisc_ingress(&handle).await?;
+                tap_intf.add_redirect_tc_filter(&handle, self.index).await?;
+                self.add_redirect_tc_filter(&handle, tap_intf.index).await?;
                 self.twin = Some(Box::new(tap_intf));
             }
+            LinkType::Macvtap(_) => {
+                self.fds = open_macvtap_device(netns, &self.name, self.index, self.queue).await?;
+            }
             LinkType::Physical(bdf, _driver) => {
                 bind_device_to_driver(DEVICE_DRIVER_VFIO, bdf).await?
             }
@@ -330,61 +357,85 @@ impl NetworkInterface {
         Ok(())
     }
 
-    pub async fn attach_to<V: VM>(&mut self, sandbox: &mut KuasarSandbox<V>) -> Result<()> {
-        let id = format!("intf-{}", self.index);
-        match &self.r#type {
-            LinkType::Veth => {
+    pub async fn attach_to<V: VM>(
+        &mut self,
+        sandbox: &mut KuasarSandbox<V>,
+        bandwidth: &Bandwidth,
+    ) -> Result<()> {
+        if let Some(device_info) = self.device_info(bandwidth)? {
+            sandbox.vm.attach(device_info).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn hot_attach_to<V: VM>(
+        &mut self,
+        sandbox: &mut KuasarSandbox<V>,
+        bandwidth: &Bandwidth,
+    ) -> Result<()> {
+        if let Some(device_info) = self.device_info(bandwidth)? {
+            sandbox.vm.hot_attach(device_info).await?;
+        }
+        Ok(())
+    }
+
+    // device_id is the id of the device attached to the vm for the interface
+    pub(crate) fn device_id(&self) -> String {
+        format!("intf-{}", self.index)
+    }
+
+    fn device_info(&mut self, bandwidth: &Bandwidth) -> Result<Option<DeviceInfo>> {
+        let id = self.device_id();
+        let device_info = match &self.r#type {
+            LinkType::Veth | LinkType::Macvlan(_) | LinkType::Ipvlan(_) => {
                 if let Some(intf) = self.twin.as_mut() {
-                    sandbox
-                        .vm
-                        .attach(DeviceInfo::Tap(TapDeviceInfo {
-                            id,
-                            index: self.index,
-                            name: intf.name.to_string(),
-                            mac_address: self.mac_address.to_string(),
-                            fds: intf.fds.drain(..).collect(),
-                        }))
-                        .await?;
-                } else {
-                    return Err(anyhow!("no tap interface created for veth {}", self.name).into());
-                }
-            }
-            LinkType::VhostUser(sock) => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::VhostUser(VhostUserDeviceInfo {
-                        id,
-                        socket_path: sock.to_string(),
-                        mac_address: self.mac_address.to_string(),
-                        r#type: "virtio-net-pci".to_string(),
-                    }))
-                    .await?;
-            }
-            LinkType::Physical(bdf, _driver) => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::Physical(PhysicalDeviceInfo {
-                        id,
-                        bdf: bdf.to_string(),
-                    }))
-                    .await?;
-            }
-            LinkType::Tap => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::Tap(TapDeviceInfo {
+                    DeviceInfo::Tap(TapDeviceInfo {
                         id,
                         index: self.index,
-                        name: self.name.to_string(),
+                        name: intf.name.to_string(),
                         mac_address: self.mac_address.to_string(),
-                        fds: vec![],
-                    }))
-                    .await?;
+                        fds: intf.fds.drain(..).collect(),
+                        bandwidth: bandwidth.clone(),
+                    })
+                } else {
+                    return Err(anyhow!(
+                        "no tap interface created for {} {}",
+                        self.r#type,
+                        self.name
+                    )
+                    .into());
+                }
             }
-            LinkType::Loopback => {}
-            _ => {}
-        }
-        Ok(())
+            LinkType::VhostUser(sock) => DeviceInfo::VhostUser(VhostUserDeviceInfo {
+                id,
+                socket_path: sock.to_string(),
+                mac_address: self.mac_address.to_string(),
+                r#type: "virtio-net-pci".to_string(),
+            }),
+            LinkType::Physical(bdf, _driver) => DeviceInfo::Physical(PhysicalDeviceInfo {
+                id,
+                bdf: bdf.to_string(),
+            }),
+            LinkType::Macvtap(_) => DeviceInfo::Tap(TapDeviceInfo {
+                id,
+                index: self.index,
+                name: self.name.to_string(),
+                mac_address: self.mac_address.to_string(),
+                fds: self.fds.drain(..).collect(),
+                bandwidth: bandwidth.clone(),
+            }),
+            LinkType::Tap => DeviceInfo::Tap(TapDeviceInfo {
+                id,
+                index: self.index,
+                name: self.name.to_string(),
+                mac_address: self.mac_address.to_string(),
+                fds: vec![],
+                bandwidth: bandwidth.clone(),
+            }),
+            LinkType::Loopback => return Ok(None),
+            _ => return Ok(None),
+        };
+        Ok(Some(device_info))
     }
 
     pub async fn after_detach(&mut self, _netns: &str) -> Result<()> {
@@ -394,41 +445,144 @@ impl NetworkInterface {
         Ok(())
     }
 
-    async fn add_qdisc_ingress(&self, netns: &str, _handle: &Handle) -> Result<()> {
-        // TODO use netlink to add ingress
-        let mut cmd = std::process::Command::new("tc");
-        cmd.args(["qdisc", "add", "dev", &*self.name, "ingress"]);
-        execute_in_netns(netns, cmd).await?;
+    // remove_twin removes the tap created for the veth and the redirection to it,
+    // it is called after the interface is hot detached from the running vm.
+    pub async fn remove_twin(&mut self, netns: &str) -> Result<()> {
+        if !matches!(
+            self.r#type,
+            LinkType::Veth | LinkType::Macvlan(_) | LinkType::Ipvlan(_)
+        ) {
+            return Ok(());
+        }
+        let handle = create_netlink_handle(netns).await?;
+        let twin_index = match self.twin.take() {
+            Some(twin) => Some(twin.index),
+            // the twin is not in the dump of old versions, find it by the name
+            None => {
+                let name = format!("{}{}", TWIN_TAP_PREFIX, self.index);
+                let mut links = handle.link().get().match_name(name).execute();
+                match links.try_next().await {
+                    Ok(Some(msg)) => Some(msg.header.index),
+                    _ => None,
+                }
+            }
+        };
+        if let Some(index) = twin_index {
+            handle
+                .link()
+                .del(index)
+                .execute()
+                .await
+                .map_err(|e| anyhow!("failed to delete tap of {}: {}", self.name, e))?;
+            // the qdisc is gone with the link if the link is removed from the netns
+            if let Err(e) = self.del_qdisc_ingress(&handle).await {
+                debug!("{}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // set_bandwidth shapes the traffic of the interface by tbf qdiscs, packets into the pod
+    // leave from the tap to the vm, and packets out of the pod leave from the redirected link.
+    pub async fn set_bandwidth(&self, netns: &str, bandwidth: &Bandwidth) -> Result<()> {
+        let (tap_index, link_index) = match (&self.r#type, &self.twin) {
+            (_, Some(twin)) => (twin.index, Some(self.index)),
+            (LinkType::Tap, None) => (self.index, None),
+            _ => {
+                if !bandwidth.is_empty() {
+                    warn!("bandwidth of {} {} is not limited", self.r#type, self.name);
+                }
+                return Ok(());
+            }
+        };
+        let handle = create_netlink_handle(netns).await?;
+        set_tbf(&handle, tap_index, bandwidth.ingress)
+            .await
+            .map_err(|e| anyhow!("failed to limit ingress of {}: {}", self.name, e))?;
+        match link_index {
+            Some(index) => set_tbf(&handle, index, bandwidth.egress)
+                .await
+                .map_err(|e| anyhow!("failed to limit egress of {}: {}", self.name, e))?,
+            None if bandwidth.egress > 0 => {
+                warn!("egress bandwidth of tap {} is not limited", self.name)
+            }
+            None => {}
+        }
         Ok(())
     }
 
-    async fn add_redirect_tc_filter(&self, netns: &str, dest: &str) -> Result<()> {
-        // TODO do this with netlink library
-        let mut cmd = std::process::Command::new("tc");
-        cmd.args([
-            "filter",
-            "add",
-            "dev",
-            &*self.name,
-            "parent",
-            "ffff:",
-            "protocol",
-            "all",
-            "u32",
-            "match",
-            "u8",
-            "0",
-            "0",
-            "action",
-            "mirred",
-            "egress",
-            "redirect",
-            "dev",
-            dest,
-        ]);
-        execute_in_netns(netns, cmd).await?;
+    async fn add_qdisc_ingress(&self, handle: &Handle) -> Result<()> {
+        QDiscAddRequest::new(handle.clone())
+            .if_index(self.index as i32)
+            .ingress()
+            .execute()
+            .await
+            .map_err(|e| anyhow!("failed to add ingress qdisc to {}: {}", self.name, e))?;
         Ok(())
     }
+
+    async fn del_qdisc_ingress(&self, handle: &Handle) -> Result<()> {
+        del_qdisc_ingress(handle, self.index)
+            .await
+            .map_err(|e| anyhow!("failed to delete ingress qdisc of {}: {}", self.name, e))?;
+        Ok(())
+    }
+
+    async fn add_redirect_tc_filter(&self, handle: &Handle, dest_index: u32) -> Result<()> {
+        TrafficFilterSetRequest::new(handle.clone(), self.index as i32)
+            .redirect(dest_index)
+            .execute()
+            .await
+            .map_err(|e| anyhow!("failed to add redirect filter to {}: {}", self.name, e))?;
+        Ok(())
+    }
+}
+
+// set_tbf replaces the root qdisc of the link with tbf of the rate in bits per second,
+// and removes the tbf if the rate is 0.
+async fn set_tbf(handle: &Handle, index: u32, rate: u64) -> Result<()> {
+    if rate == 0 {
+        // the link may be not limited before
+        if let Err(e) = QDiscDelRequest::new(handle.clone())
+            .if_index(index as i32)
+            .root()
+            .execute()
+            .await
+        {
+            debug!("no root qdisc of link {} is deleted: {}", index, e);
+        }
+        return Ok(());
+    }
+    QDiscAddRequest::new(handle.clone())
+        .if_index(index as i32)
+        .tbf(rate / 8)
+        .replace()
+        .execute()
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+// del_qdisc_ingress deletes the ingress qdisc of the link,
+// together with the redirect filter attached to it.
+pub(crate) async fn del_qdisc_ingress(handle: &Handle, index: u32) -> Result<()> {
+    QDiscDelRequest::new(handle.clone())
+        .if_index(index as i32)
+        .ingress()
+        .execute()
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+// get_ingress_redirects returns the links that the ingress filters of the link redirect to,
+// the link without ingress qdisc has no filters.
+pub(crate) async fn get_ingress_redirects(handle: &Handle, index: u32) -> Result<Vec<u32>> {
+    let filters = TrafficFilterGetRequest::new(handle.clone(), index as i32)
+        .execute()
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    Ok(filters.iter().flat_map(redirect_dests).collect())
 }
 
 fn get_bdf_for_eth(if_name: &str) -> Result<String> {
@@ -515,6 +669,115 @@ async fn create_tap_in_netns(
     }
 }
 
+// open_macvtap_device opens the char device of the macvtap for each queue. The device in
+// /dev of the host is named by the index of the link, which is only unique in a netns, so
+// the device number is read from the sysfs of the netns and a private node of it is opened.
+async fn open_macvtap_device(
+    netns: &str,
+    name: &str,
+    index: u32,
+    queue: u32,
+) -> Result<Vec<OwnedFd>> {
+    let netns_fd = if netns.is_empty() {
+        None
+    } else {
+        Some(
+            safe_open_file(Path::new(netns), OFlag::O_CLOEXEC, Mode::empty())
+                .map_err(|e| anyhow!("failed to open netns {}: {}", netns, e))?,
+        )
+    };
+    let name = name.to_string();
+    // the thread leaves the mount namespace of the sandboxer, so it must not be reused
+    let handle = std::thread::spawn(move || {
+        open_macvtap_device_in_netns(netns_fd.as_ref(), &name, index, queue)
+    });
+    tokio::task::spawn_blocking(move || handle.join())
+        .await
+        .map_err(|e| anyhow!("failed to wait for opening macvtap: {}", e))?
+        .map_err(|_| anyhow!("thread of opening macvtap panicked"))?
+}
+
+fn open_macvtap_device_in_netns(
+    netns_fd: Option<&OwnedFd>,
+    name: &str,
+    index: u32,
+    mut queue: u32,
+) -> Result<Vec<OwnedFd>> {
+    if queue == 0 {
+        queue = 1
+    };
+    if let Some(fd) = netns_fd {
+        setns(fd.as_raw_fd(), CloneFlags::CLONE_NEWNET)
+            .map_err(|e| anyhow!("failed to set netns: {}", e))?;
+    }
+    // sysfs shows the links of the netns it is mounted in, and the mounts made here
+    // are gone with the private mount namespace when the thread exits.
+    unshare(CloneFlags::CLONE_NEWNS)
+        .map_err(|e| anyhow!("failed to unshare mount namespace: {}", e))?;
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_SLAVE,
+        None::<&str>,
+    )
+    .map_err(|e| anyhow!("failed to make mounts slave: {}", e))?;
+    mount(
+        Some("sysfs"),
+        "/sys",
+        Some("sysfs"),
+        MsFlags::empty(),
+        None::<&str>,
+    )
+    .map_err(|e| anyhow!("failed to mount sysfs: {}", e))?;
+    let dev_path = format!("/sys/class/net/{}/macvtap/tap{}/dev", name, index);
+    let dev = std::fs::read_to_string(&dev_path)
+        .map_err(|e| anyhow!("failed to read {}: {}", dev_path, e))?;
+    let (major, minor) = parse_device_number(&dev)?;
+
+    mount(
+        Some("tmpfs"),
+        "/tmp",
+        Some("tmpfs"),
+        MsFlags::empty(),
+        None::<&str>,
+    )
+    .map_err(|e| anyhow!("failed to mount tmpfs: {}", e))?;
+    let path = format!("/tmp/tap{}", index);
+    mknod(
+        path.as_str(),
+        SFlag::S_IFCHR,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+        makedev(major, minor),
+    )
+    .map_err(|e| anyhow!("failed to create macvtap device {}: {}", path, e))?;
+    let mut fds: Vec<OwnedFd> = Vec::new();
+    for _ in 0..queue {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| anyhow!("failed to open macvtap device {}: {}", path, e))?;
+        fds.push(OwnedFd::from(file));
+    }
+    Ok(fds)
+}
+
+// parse_device_number parses the "major:minor" in the dev file of sysfs
+fn parse_device_number(dev: &str) -> Result<(u64, u64)> {
+    let (major, minor) = dev
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid device number {}", dev.trim()))?;
+    let major = major
+        .parse()
+        .map_err(|e| anyhow!("invalid major of {}: {}", dev.trim(), e))?;
+    let minor = minor
+        .parse()
+        .map_err(|e| anyhow!("invalid minor of {}: {}", dev.trim(), e))?;
+    Ok((major, minor))
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct ifreq {
@@ -564,7 +827,7 @@ fn create_tap_device(tap_name: &str, mut queue: u32) -> Result<Vec<OwnedFd>> {
     Ok(fds)
 }
 
-async fn get_pci_driver(bdf: &str) -> Result<String> {
+pub(crate) async fn get_pci_driver(bdf: &str) -> Result<String> {
     let driver_path = format!("/sys/bus/pci/devices/{}/driver", bdf);
     let driver_dest = tokio::fs::read_link(&driver_path)
         .await
@@ -618,7 +881,14 @@ async fn bind_device_to_driver(driver: &str, bdf: &str) -> Result<()> {
 mod tests {
     use std::process::Command;
 
-    use crate::network::link::create_tap_device;
+    use crate::network::link::{create_tap_device, parse_device_number};
+
+    #[test]
+    fn test_parse_device_number() {
+        assert_eq!(parse_device_number("241:3\n").unwrap(), (241, 3));
+        assert!(parse_device_number("241").is_err());
+        assert!(parse_device_number("a:3").is_err());
+    }
 
     #[test]
     fn add_tap_device_with_long_name() {
@@ -21,7 +21,7 @@ use std::{
 };
 
 use anyhow::anyhow;
-use containerd_sandbox::error::Result;
+use containerd_sandbox::error::{Error, Result};
 use futures_util::TryStreamExt;
 use log::{debug, error, info, warn};
 use nix::{
@@ -33,20 +33,55 @@ use rtnetlink::{new_connection, Handle, IpVersion};
 use serde_derive::{Deserialize, Serialize};
 use tokio::task::spawn_blocking;
 
-pub use crate::network::{address::IpNet, link::NetworkInterface, route::Route};
-use crate::{network::link::LinkType, sandbox::KuasarSandbox, utils::safe_open_file, vm::VM};
+pub use crate::network::{
+    address::IpNet, bandwidth::Bandwidth, link::NetworkInterface, neighbor::Neighbor, route::Route,
+};
+use crate::{
+    network::link::{LinkType, TWIN_TAP_PREFIX},
+    sandbox::KuasarSandbox,
+    utils::safe_open_file,
+    vm::VM,
+};
 
 pub mod address;
+pub mod bandwidth;
 mod convert;
 pub mod link;
+pub mod neighbor;
 mod netlink;
 pub mod route;
+pub(crate) mod watch;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Network {
     pub(crate) config: NetworkConfig,
     pub(crate) intfs: Vec<NetworkInterface>,
     routes: Vec<Route>,
+    #[serde(default)]
+    neighbors: Vec<Neighbor>,
+}
+
+// NetworkChanges is what changed in the netns since the network of the sandbox is got.
+#[derive(Debug, Default)]
+pub struct NetworkChanges {
+    // interfaces added to the netns, they should be hot plugged
+    pub added: Vec<NetworkInterface>,
+    // names of interfaces removed from the netns, they should be hot unplugged
+    pub removed: Vec<String>,
+    // names of interfaces whose addresses or mtu are changed
+    pub updated: Vec<String>,
+    pub routes_changed: bool,
+    pub neighbors_changed: bool,
+}
+
+impl NetworkChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.updated.is_empty()
+            && !self.routes_changed
+            && !self.neighbors_changed
+    }
 }
 
 async fn get_route(
@@ -72,6 +107,25 @@ async fn get_route(
     Ok(())
 }
 
+async fn get_neighbors(
+    handle: &Handle,
+    intfs: &[NetworkInterface],
+    neighbors: &mut Vec<Neighbor>,
+) -> Result<()> {
+    let mut neighbor_msgs = handle.neighbours().get().execute();
+    while let Some(neighbor_msg) = neighbor_msgs
+        .try_next()
+        .await
+        .map_err(|e| anyhow!("{}", e))?
+    {
+        // ignore those neighbors that are not permanent or not on the interfaces
+        if let Ok(n) = Neighbor::parse_from_message(neighbor_msg, intfs) {
+            neighbors.push(n);
+        }
+    }
+    Ok(())
+}
+
 impl Network {
     pub async fn new(config: NetworkConfig) -> Result<Self> {
         debug!("create network with config: {:?}", config);
@@ -118,10 +172,14 @@ impl Network {
         get_route(IpVersion::V4, &handle, &intfs, &mut routes).await?;
         get_route(IpVersion::V6, &handle, &intfs, &mut routes).await?;
 
+        let mut neighbors = vec![];
+        get_neighbors(&handle, &intfs, &mut neighbors).await?;
+
         Ok(Network {
             config,
             intfs,
             routes,
+            neighbors,
         })
     }
 
@@ -130,14 +188,203 @@ impl Network {
         let mut me = self;
         for intf in &mut me.intfs {
             intf.prepare_attaching(&netns).await?;
-            intf.attach_to(sandbox).await?;
+            limit_bandwidth(&netns, intf, &me.config.bandwidth, &sandbox.vm).await?;
+            intf.attach_to(sandbox, &me.config.bandwidth).await?;
+        }
+        sandbox.network = Some(me);
+        Ok(())
+    }
+
+    // hot_attach_to attaches the interfaces into a running vm, the network is kept
+    // in the sandbox even if it fails, so that it can be destroyed by the caller.
+    pub async fn hot_attach_to<V: VM>(self, sandbox: &mut KuasarSandbox<V>) -> Result<()> {
+        let netns = self.config.netns.to_string();
+        let mut me = self;
+        let mut res = Ok(());
+        for intf in &mut me.intfs {
+            res = intf.prepare_attaching(&netns).await;
+            if res.is_ok() {
+                res = limit_bandwidth(&netns, intf, &me.config.bandwidth, &sandbox.vm).await;
+            }
+            if res.is_ok() {
+                res = intf.hot_attach_to(sandbox, &me.config.bandwidth).await;
+            }
+            if res.is_err() {
+                break;
+            }
         }
         sandbox.network = Some(me);
+        res
+    }
+
+    // hot_plug_interface attaches a new interface of the netns into the running vm,
+    // and refreshes the routes as routes of the new interface may appear.
+    pub async fn hot_plug_interface<V: VM>(
+        &mut self,
+        mut intf: NetworkInterface,
+        sandbox: &mut KuasarSandbox<V>,
+    ) -> Result<()> {
+        let netns = self.config.netns.to_string();
+        let mut res = intf.prepare_attaching(&netns).await;
+        if res.is_ok() {
+            res = limit_bandwidth(&netns, &intf, &self.config.bandwidth, &sandbox.vm).await;
+        }
+        if res.is_ok() {
+            res = intf.hot_attach_to(sandbox, &self.config.bandwidth).await;
+        }
+        if let Err(e) = res {
+            if let Err(re) = intf.remove_twin(&netns).await {
+                warn!("roll back in remove twin of {}: {}", intf.name, re);
+            }
+            if let Err(re) = intf.after_detach(&netns).await {
+                warn!("roll back in recycle interface {}: {}", intf.name, re);
+            }
+            return Err(e);
+        }
+        info!(
+            "interface {} is hot plugged into sandbox {}",
+            intf.name, self.config.sandbox_id
+        );
+        self.intfs.push(intf);
+        self.refresh().await
+    }
+
+    // hot_unplug_interface detaches the interface from the running vm and recycles it,
+    // the interface is kept in the network if the vm fails to detach it.
+    pub async fn hot_unplug_interface<V: VM>(
+        &mut self,
+        name: &str,
+        sandbox: &mut KuasarSandbox<V>,
+    ) -> Result<()> {
+        let index = self
+            .intfs
+            .iter()
+            .position(|x| x.name == name)
+            .ok_or_else(|| Error::NotFound(format!("interface {}", name)))?;
+        sandbox
+            .vm
+            .hot_detach(&self.intfs[index].device_id())
+            .await?;
+        let mut intf = self.intfs.remove(index);
+        let netns = self.config.netns.to_string();
+        if let Err(e) = intf.remove_twin(&netns).await {
+            warn!("failed to remove twin of interface {}: {}", intf.name, e);
+        }
+        if let Err(e) = intf.after_detach(&netns).await {
+            warn!("failed to recycle interface {}: {}", intf.name, e);
+        }
+        info!(
+            "interface {} is hot unplugged from sandbox {}",
+            name, self.config.sandbox_id
+        );
+        self.refresh().await
+    }
+
+    // refresh gets the routes and neighbors in the netns again for the current interfaces.
+    async fn refresh(&mut self) -> Result<()> {
+        let handle = create_netlink_handle(&self.config.netns).await?;
+        let mut routes = vec![];
+        get_route(IpVersion::V4, &handle, &self.intfs, &mut routes).await?;
+        get_route(IpVersion::V6, &handle, &self.intfs, &mut routes).await?;
+        let mut neighbors = vec![];
+        get_neighbors(&handle, &self.intfs, &mut neighbors).await?;
+        self.routes = routes;
+        self.neighbors = neighbors;
+        Ok(())
+    }
+
+    // apply_snapshot updates the addresses and mtu of the interfaces, the routes and
+    // the neighbors with those in the latest snapshot of the netns. Interfaces added or
+    // removed are only returned in the changes, as they should be hot (un)plugged first.
+    pub fn apply_snapshot(&mut self, latest: Network) -> NetworkChanges {
+        let mut changes = NetworkChanges::default();
+        let Network {
+            intfs: mut latest_intfs,
+            routes,
+            neighbors,
+            ..
+        } = latest;
+        for intf in &mut self.intfs {
+            let l = match latest_intfs.iter().position(|x| x.name == intf.name) {
+                Some(i) => latest_intfs.remove(i),
+                None => {
+                    // a physical interface leaves the netns when it is bound to vfio,
+                    // and the type is unknown if the sandbox is recovered from an old dump.
+                    if matches!(
+                        intf.r#type,
+                        LinkType::Veth
+                            | LinkType::Tap
+                            | LinkType::Macvlan(_)
+                            | LinkType::Ipvlan(_)
+                            | LinkType::Macvtap(_)
+                    ) {
+                        changes.removed.push(intf.name.to_string());
+                    }
+                    continue;
+                }
+            };
+            // the index and type are not in the dump of old versions, take them back
+            if intf.index == 0 {
+                intf.index = l.index;
+                intf.r#type = l.r#type.clone();
+            }
+            if intf.ip_addresses != l.ip_addresses || intf.mtu != l.mtu {
+                intf.ip_addresses = l.ip_addresses;
+                intf.mtu = l.mtu;
+                changes.updated.push(intf.name.to_string());
+            }
+        }
+        changes.added = latest_intfs;
+
+        // routes and neighbors of the added or removed interfaces are refreshed after
+        // they are hot (un)plugged, so only those of the kept interfaces are taken here.
+        let kept = |device: &str| {
+            device.is_empty()
+                || (self.intfs.iter().any(|x| x.name == device)
+                    && !changes.removed.iter().any(|x| x == device))
+        };
+        let routes: Vec<Route> = routes.into_iter().filter(|r| kept(&r.device)).collect();
+        let neighbors: Vec<Neighbor> = neighbors.into_iter().filter(|n| kept(&n.device)).collect();
+        if routes != self.routes {
+            self.routes = routes;
+            changes.routes_changed = true;
+        }
+        if neighbors != self.neighbors {
+            self.neighbors = neighbors;
+            changes.neighbors_changed = true;
+        }
+        changes
+    }
+
+    // update_bandwidth applies the new bandwidth to the interfaces attached to the running vm,
+    // and keeps it for the interfaces attached later only after all of them are updated.
+    pub async fn update_bandwidth<V: VM>(&mut self, bandwidth: Bandwidth, vm: &V) -> Result<()> {
+        if bandwidth == self.config.bandwidth {
+            return Ok(());
+        }
+        // the rate limiters of the vmm are set only when the interfaces are attached,
+        // so the attached interfaces are shaped by the tc qdiscs on the host side instead.
+        if vm.support_net_rate_limiter() && !self.intfs.is_empty() {
+            warn!(
+                "rate limiters of attached interfaces in {} are kept, bandwidth is limited by tc",
+                self.config.netns
+            );
+        }
+        for intf in &self.intfs {
+            intf.set_bandwidth(&self.config.netns, &bandwidth).await?;
+        }
+        self.config.bandwidth = bandwidth;
         Ok(())
     }
 
     pub async fn destroy(&mut self) {
         for intf in &mut self.intfs {
+            if let Err(e) = intf.remove_twin(&self.config.netns).await {
+                error!(
+                    "failed to remove tap of interface {} when destroying, err {:?}",
+                    intf.name, e
+                );
+            }
             if let Err(e) = intf.after_detach(&self.config.netns).await {
                 error!(
                     "failed to recycle interface {} when destroying, err {:?}",
@@ -155,14 +402,26 @@ impl Network {
         self.routes.as_ref()
     }
 
+    pub fn neighbors(&self) -> &Vec<Neighbor> {
+        self.neighbors.as_ref()
+    }
+
+    pub fn config(&self) -> &NetworkConfig {
+        &self.config
+    }
+
     fn filter_intfs(intfs: Vec<NetworkInterface>) -> Vec<NetworkInterface> {
         intfs
             .into_iter()
             .filter(|intf| match intf.r#type {
                 LinkType::Veth => true,
+                LinkType::Macvlan(_) => true,
+                LinkType::Ipvlan(_) => true,
+                LinkType::Macvtap(_) => true,
                 LinkType::VhostUser(_) => true,
                 LinkType::Physical(_, _) => true,
-                LinkType::Tap => true,
+                // the taps created by the sandboxer are a part of the interfaces redirected to them
+                LinkType::Tap => !intf.name.starts_with(TWIN_TAP_PREFIX),
                 LinkType::Loopback => {
                     // do we have to drop loopback?
                     true
@@ -178,6 +437,22 @@ pub struct NetworkConfig {
     pub(crate) netns: String,
     pub(crate) sandbox_id: String,
     pub(crate) queue: u32,
+    #[serde(default)]
+    pub(crate) bandwidth: Bandwidth,
+}
+
+// limit_bandwidth shapes the interface with tc in the netns,
+// if the vm can not limit the rate of the network device by itself.
+async fn limit_bandwidth<V: VM>(
+    netns: &str,
+    intf: &NetworkInterface,
+    bandwidth: &Bandwidth,
+    vm: &V,
+) -> Result<()> {
+    if bandwidth.is_empty() || vm.support_net_rate_limiter() {
+        return Ok(());
+    }
+    intf.set_bandwidth(netns, bandwidth).await
 }
 
 async fn run_in_new_netns<P: AsRef<Path>, F, T>(netns: P, f: F) -> Result<T>
@@ -256,7 +531,151 @@ impl Display for NetType {
 
 #[cfg(test)]
 mod tests {
-    use crate::network::{Network, NetworkConfig};
+    use crate::network::{
+        link::LinkType, IpNet, Neighbor, Network, NetworkConfig, NetworkInterface, Route,
+    };
+
+    fn new_intf(name: &str, index: u32, ip: &str) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_string(),
+            index,
+            r#type: LinkType::Veth,
+            ip_addresses: vec![IpNet::from(ip.to_string())],
+            mtu: 1500,
+            ..NetworkInterface::default()
+        }
+    }
+
+    fn new_route(device: &str, dest: &str) -> Route {
+        Route {
+            device: device.to_string(),
+            dest: dest.to_string(),
+            ..Route::default()
+        }
+    }
+
+    #[test]
+    fn test_apply_snapshot() {
+        let config = NetworkConfig {
+            netns: "".to_string(),
+            sandbox_id: "sb1".to_string(),
+            queue: 1,
+            bandwidth: Default::default(),
+        };
+        let mut network = Network {
+            config: config.clone(),
+            intfs: vec![
+                new_intf("eth0", 2, "10.0.0.2/24"),
+                new_intf("eth1", 3, "10.0.1.2/24"),
+            ],
+            routes: vec![new_route("eth0", "10.0.0.0/24")],
+            neighbors: vec![],
+        };
+
+        let mut eth0 = new_intf("eth0", 2, "10.0.0.3/24");
+        eth0.mtu = 1450;
+        let latest = Network {
+            config: config.clone(),
+            intfs: vec![eth0, new_intf("eth2", 4, "10.0.2.2/24")],
+            routes: vec![
+                new_route("eth0", "10.0.0.0/24"),
+                new_route("eth2", "10.0.2.0/24"),
+            ],
+            neighbors: vec![Neighbor {
+                device: "eth0".to_string(),
+                ip_address: "10.0.0.1".to_string(),
+                lladdr: "ee:ee:ee:ee:ee:ee".to_string(),
+                state: 128,
+            }],
+        };
+        let changes = network.apply_snapshot(latest);
+        assert_eq!(changes.added.len(), 1);
+        assert_eq!(changes.added[0].name, "eth2");
+        assert_eq!(changes.removed, vec!["eth1".to_string()]);
+        assert_eq!(changes.updated, vec!["eth0".to_string()]);
+        assert_eq!(network.intfs[0].mtu, 1450);
+        assert_eq!(network.intfs[0].ip_addresses[0].addr_string(), "10.0.0.3");
+        // routes of the added interface are taken after it is hot plugged
+        assert!(!changes.routes_changed);
+        assert!(changes.neighbors_changed);
+        assert_eq!(network.neighbors.len(), 1);
+
+        // nothing changed if the same snapshot is applied again
+        let mut eth0 = new_intf("eth0", 2, "10.0.0.3/24");
+        eth0.mtu = 1450;
+        let latest = Network {
+            config,
+            intfs: vec![eth0, new_intf("eth1", 3, "10.0.1.2/24")],
+            routes: vec![new_route("eth0", "10.0.0.0/24")],
+            neighbors: network.neighbors.clone(),
+        };
+        assert!(network.apply_snapshot(latest).is_empty());
+    }
+
+    #[test]
+    fn test_apply_snapshot_after_recovery() {
+        let config = NetworkConfig {
+            netns: "".to_string(),
+            sandbox_id: "sb1".to_string(),
+            queue: 1,
+            bandwidth: Default::default(),
+        };
+        let mut eth0 = new_intf("eth0", 2, "10.0.0.2/24");
+        eth0.r#type = LinkType::Macvlan(4);
+        eth0.twin = Some(Box::new(new_intf("tap_kua_2", 5, "")));
+        // the interfaces are recovered from the dump of the sandbox
+        let dump = serde_json::to_string(&vec![eth0]).unwrap();
+        let intfs: Vec<NetworkInterface> = serde_json::from_str(&dump).unwrap();
+        assert_eq!(intfs[0].index, 2);
+        assert_eq!(intfs[0].twin.as_ref().unwrap().index, 5);
+        let mut network = Network {
+            config: config.clone(),
+            intfs,
+            routes: vec![],
+            neighbors: vec![],
+        };
+
+        let latest = Network {
+            config,
+            intfs: vec![],
+            routes: vec![],
+            neighbors: vec![],
+        };
+        let changes = network.apply_snapshot(latest);
+        assert_eq!(changes.removed, vec!["eth0".to_string()]);
+    }
+
+    #[test]
+    fn test_filter_intfs() {
+        let types = vec![
+            LinkType::Veth,
+            LinkType::Macvlan(4),
+            LinkType::Ipvlan(0),
+            LinkType::Macvtap(4),
+            LinkType::Vlan(100),
+            LinkType::Bridge,
+            LinkType::Tap,
+        ];
+        let intfs = types
+            .into_iter()
+            .enumerate()
+            .map(|(i, t)| NetworkInterface {
+                name: format!("eth{}", i),
+                r#type: t,
+                ..NetworkInterface::default()
+            })
+            .chain(std::iter::once(NetworkInterface {
+                name: "tap_kua_2".to_string(),
+                r#type: LinkType::Tap,
+                ..NetworkInterface::default()
+            }))
+            .collect();
+        let names = Network::filter_intfs(intfs)
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["eth0", "eth1", "eth2", "eth3", "eth6"]);
+    }
 
     #[tokio::test]
     async fn test_new() {
@@ -264,6 +683,7 @@ mod tests {
             netns: "".to_string(),
             sandbox_id: "".to_string(),
             queue: 1,
+            bandwidth: Default::default(),
         })
         .await
         .unwrap();
@@ -0,0 +1,79 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::net::IpAddr;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use netlink_packet_route::neighbour::{
+    NeighbourAddress, NeighbourAttribute, NeighbourMessage, NeighbourState,
+};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::network::{address::MacAddress, link::NetworkInterface};
+
+// Neighbor is a static ARP or NDP entry in the netns, some CNI plugins add them
+// for the gateway as it is not reachable by the ARP or NDP from the pod.
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Neighbor {
+    pub device: String,
+    pub ip_address: String,
+    #[serde(default)]
+    pub lladdr: String,
+    #[serde(default)]
+    pub state: u16,
+}
+
+impl Neighbor {
+    // only the permanent neighbors of the interfaces are parsed,
+    // the others are learned by the guest itself.
+    pub fn parse_from_message(msg: NeighbourMessage, intfs: &[NetworkInterface]) -> Result<Self> {
+        if msg.header.state != NeighbourState::Permanent {
+            return Err(anyhow!("ignore neighbors not permanent").into());
+        }
+        let device = intfs
+            .iter()
+            .find(|x| x.index == msg.header.ifindex)
+            .map(|x| x.name.to_string())
+            .ok_or(anyhow!(
+                "can not find the device by index {}",
+                msg.header.ifindex
+            ))?;
+        let mut neighbor = Neighbor {
+            device,
+            state: msg.header.state.into(),
+            ..Neighbor::default()
+        };
+        for attribute in msg.attributes.into_iter() {
+            match attribute {
+                NeighbourAttribute::Destination(NeighbourAddress::Inet(ip)) => {
+                    neighbor.ip_address = IpAddr::V4(ip).to_string();
+                }
+                NeighbourAttribute::Destination(NeighbourAddress::Inet6(ip)) => {
+                    neighbor.ip_address = IpAddr::V6(ip).to_string();
+                }
+                NeighbourAttribute::LinkLocalAddress(addr) => {
+                    neighbor.lladdr = MacAddress(addr).to_string();
+                }
+                _ => {}
+            }
+        }
+        if neighbor.ip_address.is_empty() {
+            return Err(anyhow!("no ip address of the neighbor").into());
+        }
+        Ok(neighbor)
+    }
+}
@@ -15,41 +15,179 @@ limitations under the License.
 */
 
 use futures_util::StreamExt;
-use netlink_packet_core::{NetlinkMessage, NLM_F_ACK, NLM_F_CREATE, NLM_F_EXCL, NLM_F_REQUEST};
-use netlink_packet_route::{tc::TcMessage, RouteNetlinkMessage};
+use netlink_packet_core::{
+    NetlinkMessage, NetlinkPayload, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL, NLM_F_REPLACE,
+    NLM_F_REQUEST,
+};
+use netlink_packet_route::{
+    tc::{
+        TcAction, TcActionAttribute, TcActionMirrorOption, TcActionOption, TcActionType,
+        TcAttribute, TcFilterMatchAllOption, TcMessage, TcMirror, TcMirrorActionType, TcOption,
+    },
+    RouteNetlinkMessage,
+};
+use netlink_packet_utils::nla::DefaultNla;
+use nix::libc::ETH_P_ALL;
 use rtnetlink::{try_nl, Error, Handle};
 
 const HANDLE_INGRESS: u32 = 0xfffffff1;
 const HANDLE_TC_FILTER: u32 = 0xffff0000;
+const HANDLE_ROOT: u32 = 0xffffffff;
 
+// linux/pkt_sched.h
+const TCA_TBF_PARMS: u16 = 1;
+const TCA_TBF_RATE64: u16 = 4;
+const TCA_TBF_BURST: u16 = 6;
+const TC_LINKLAYER_ETHERNET: u8 = 1;
+// a psched tick is 64 nanoseconds
+const PSCHED_SHIFT: u32 = 6;
+
+// the bucket holds the traffic of 100ms, and packets wait in the queue for at most 50ms
+const TBF_BURST_IN_MS: u64 = 100;
+const TBF_LATENCY_IN_MS: u64 = 50;
+const TBF_MIN_BURST: u64 = 64 * 1024;
+
+const QDISC_KIND_INGRESS: &str = "ingress";
+const QDISC_KIND_TBF: &str = "tbf";
+const FILTER_KIND_MATCHALL: &str = "matchall";
+const ACTION_KIND_MIRRED: &str = "mirred";
+
+async fn execute(
+    mut handle: Handle,
+    message: RouteNetlinkMessage,
+    flags: u16,
+) -> Result<(), Error> {
+    let mut req = NetlinkMessage::from(message);
+    req.header.flags = flags;
+
+    let mut response = handle.request(req)?;
+    while let Some(message) = response.next().await {
+        try_nl!(message);
+    }
+    Ok(())
+}
+
+// QDiscAddRequest adds a qdisc to the link, like `tc qdisc add dev <link> ingress`.
 pub struct QDiscAddRequest {
     handle: Handle,
     message: TcMessage,
+    replace: bool,
 }
 
-#[allow(dead_code)]
 impl QDiscAddRequest {
     pub(crate) fn new(handle: Handle) -> Self {
         QDiscAddRequest {
             handle,
             message: TcMessage::default(),
+            replace: false,
         }
     }
 
     pub async fn execute(self) -> Result<(), Error> {
-        let QDiscAddRequest {
-            mut handle,
-            message,
-        } = self;
+        let flags = if self.replace {
+            NLM_F_REQUEST | NLM_F_CREATE | NLM_F_REPLACE | NLM_F_ACK
+        } else {
+            NLM_F_REQUEST | NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK
+        };
+        execute(
+            self.handle,
+            RouteNetlinkMessage::NewQueueDiscipline(self.message),
+            flags,
+        )
+        .await
+    }
+
+    // replace the existing qdisc, like `tc qdisc replace`
+    pub fn replace(mut self) -> Self {
+        self.replace = true;
+        self
+    }
 
-        let mut req = NetlinkMessage::from(RouteNetlinkMessage::NewQueueDiscipline(message));
-        req.header.flags = NLM_F_REQUEST | NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK;
+    pub fn if_index(mut self, if_index: i32) -> Self {
+        self.message.header.index = if_index;
+        self
+    }
 
-        let mut response = handle.request(req)?;
-        while let Some(message) = response.next().await {
-            try_nl!(message);
+    pub fn ingress(mut self) -> Self {
+        self.message.header.parent = HANDLE_INGRESS.into();
+        self.message.header.handle = HANDLE_TC_FILTER.into();
+        self.message
+            .attributes
+            .push(TcAttribute::Kind(QDISC_KIND_INGRESS.to_string()));
+        self
+    }
+
+    // tbf shapes the egress of the link to the rate in bytes per second,
+    // like `tc qdisc add dev <link> root tbf rate <rate> burst <burst> latency 50ms`.
+    pub fn tbf(mut self, rate: u64) -> Self {
+        self.message.header.parent = HANDLE_ROOT.into();
+        let burst = (rate * TBF_BURST_IN_MS / 1000).max(TBF_MIN_BURST);
+        let limit = burst + rate * TBF_LATENCY_IN_MS / 1000;
+        let mut options = vec![
+            TcOption::Other(DefaultNla::new(TCA_TBF_PARMS, tbf_qopt(rate, burst, limit))),
+            TcOption::Other(DefaultNla::new(
+                TCA_TBF_BURST,
+                (burst.min(u32::MAX as u64) as u32).to_ne_bytes().to_vec(),
+            )),
+        ];
+        if rate > u32::MAX as u64 {
+            options.push(TcOption::Other(DefaultNla::new(
+                TCA_TBF_RATE64,
+                rate.to_ne_bytes().to_vec(),
+            )));
         }
-        Ok(())
+        self.message.attributes = vec![
+            TcAttribute::Kind(QDISC_KIND_TBF.to_string()),
+            TcAttribute::Options(options),
+        ];
+        self
+    }
+}
+
+// tbf_qopt is the struct tc_tbf_qopt of the rate without peak rate,
+// the rate table is not needed as the link layer is given.
+fn tbf_qopt(rate: u64, burst: u64, limit: u64) -> Vec<u8> {
+    let buffer_in_ns = burst as u128 * 1_000_000_000 / rate.max(1) as u128;
+    let buffer = (buffer_in_ns >> PSCHED_SHIFT).min(u32::MAX as u128) as u32;
+
+    let mut qopt = Vec::with_capacity(36);
+    // struct tc_ratespec rate
+    qopt.push(0u8); // cell_log
+    qopt.push(TC_LINKLAYER_ETHERNET);
+    qopt.extend_from_slice(&0u16.to_ne_bytes()); // overhead
+    qopt.extend_from_slice(&0i16.to_ne_bytes()); // cell_align
+    qopt.extend_from_slice(&0u16.to_ne_bytes()); // mpu
+    qopt.extend_from_slice(&(rate.min(u32::MAX as u64) as u32).to_ne_bytes());
+    // struct tc_ratespec peakrate
+    qopt.extend_from_slice(&[0u8; 12]);
+    qopt.extend_from_slice(&(limit.min(u32::MAX as u64) as u32).to_ne_bytes());
+    qopt.extend_from_slice(&buffer.to_ne_bytes());
+    qopt.extend_from_slice(&0u32.to_ne_bytes()); // mtu
+    qopt
+}
+
+// QDiscDelRequest deletes a qdisc of the link, like `tc qdisc del dev <link> ingress`,
+// the filters attached to the qdisc are deleted together by the kernel.
+pub struct QDiscDelRequest {
+    handle: Handle,
+    message: TcMessage,
+}
+
+impl QDiscDelRequest {
+    pub(crate) fn new(handle: Handle) -> Self {
+        QDiscDelRequest {
+            handle,
+            message: TcMessage::default(),
+        }
+    }
+
+    pub async fn execute(self) -> Result<(), Error> {
+        execute(
+            self.handle,
+            RouteNetlinkMessage::DelQueueDiscipline(self.message),
+            NLM_F_REQUEST | NLM_F_ACK,
+        )
+        .await
     }
 
     pub fn if_index(mut self, if_index: i32) -> Self {
@@ -59,43 +197,168 @@ impl QDiscAddRequest {
 
     pub fn ingress(mut self) -> Self {
         self.message.header.parent = HANDLE_INGRESS.into();
+        self.message.header.handle = HANDLE_TC_FILTER.into();
+        self
+    }
+
+    pub fn root(mut self) -> Self {
+        self.message.header.parent = HANDLE_ROOT.into();
         self
     }
 }
 
+// TrafficFilterSetRequest adds a filter to the ingress qdisc of the link.
 pub struct TrafficFilterSetRequest {
     handle: Handle,
     message: TcMessage,
 }
 
-#[allow(dead_code)]
 impl TrafficFilterSetRequest {
     pub(crate) fn new(handle: Handle, ifindex: i32) -> Self {
         let mut message = TcMessage::default();
         message.header.index = ifindex;
         message.header.parent = HANDLE_TC_FILTER.into();
+        // the priority is chosen by the kernel, and the filter matches all protocols
+        message.header.info = u16::to_be(ETH_P_ALL as u16) as u32;
 
         Self { handle, message }
     }
 
     pub async fn execute(self) -> Result<(), Error> {
-        let TrafficFilterSetRequest {
-            mut handle,
-            message,
-        } = self;
+        execute(
+            self.handle,
+            RouteNetlinkMessage::NewTrafficFilter(self.message),
+            NLM_F_REQUEST | NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK,
+        )
+        .await
+    }
 
-        let mut req = NetlinkMessage::from(RouteNetlinkMessage::NewTrafficFilter(message));
-        req.header.flags = NLM_F_REQUEST | NLM_F_CREATE | NLM_F_EXCL | NLM_F_ACK;
+    // redirect sends all the packets to the egress of the dest link,
+    // like `tc filter add dev <link> parent ffff: protocol all matchall
+    // action mirred egress redirect dev <dest>`.
+    pub fn redirect(mut self, dest_index: u32) -> Self {
+        let mut mirror = TcMirror::default();
+        mirror.generic.action = TcActionType::Stolen;
+        mirror.eaction = TcMirrorActionType::EgressRedir;
+        mirror.ifindex = dest_index;
+
+        let mut action = TcAction::default();
+        action.attributes = vec![
+            TcActionAttribute::Kind(ACTION_KIND_MIRRED.to_string()),
+            TcActionAttribute::Options(vec![TcActionOption::Mirror(TcActionMirrorOption::Parms(
+                mirror,
+            ))]),
+        ];
+
+        self.message.attributes = vec![
+            TcAttribute::Kind(FILTER_KIND_MATCHALL.to_string()),
+            TcAttribute::Options(vec![TcOption::MatchAll(TcFilterMatchAllOption::Action(
+                vec![action],
+            ))]),
+        ];
+        self
+    }
+}
+
+// TrafficFilterGetRequest lists the filters of the ingress qdisc of the link,
+// like `tc filter show dev <link> parent ffff:`.
+pub struct TrafficFilterGetRequest {
+    handle: Handle,
+    message: TcMessage,
+}
+
+impl TrafficFilterGetRequest {
+    pub(crate) fn new(handle: Handle, ifindex: i32) -> Self {
+        let mut message = TcMessage::default();
+        message.header.index = ifindex;
+        message.header.parent = HANDLE_TC_FILTER.into();
+
+        Self { handle, message }
+    }
+
+    pub async fn execute(self) -> Result<Vec<TcMessage>, Error> {
+        let mut handle = self.handle;
+        let mut req = NetlinkMessage::from(RouteNetlinkMessage::GetTrafficFilter(self.message));
+        req.header.flags = NLM_F_REQUEST | NLM_F_DUMP;
 
         let mut response = handle.request(req)?;
+        let mut filters = vec![];
         while let Some(message) = response.next().await {
             try_nl!(message);
+            if let NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewTrafficFilter(m)) =
+                message.payload
+            {
+                filters.push(m);
+            }
         }
-        Ok(())
+        Ok(filters)
     }
+}
 
-    pub fn mirror(self, _dest_index: i32) -> Self {
-        //TODO
-        self
+// redirect_dests returns the links that the mirred actions of the filter redirect packets to
+pub(crate) fn redirect_dests(filter: &TcMessage) -> Vec<u32> {
+    filter
+        .attributes
+        .iter()
+        .filter_map(|a| match a {
+            TcAttribute::Options(o) => Some(o),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|o| match o {
+            TcOption::MatchAll(TcFilterMatchAllOption::Action(a)) => Some(a),
+            _ => None,
+        })
+        .flatten()
+        .flat_map(|a| a.attributes.iter())
+        .filter_map(|a| match a {
+            TcActionAttribute::Options(o) => Some(o),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|o| match o {
+            TcActionOption::Mirror(TcActionMirrorOption::Parms(m))
+                if matches!(m.eaction, TcMirrorActionType::EgressRedir) =>
+            {
+                Some(m.ifindex)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use netlink_packet_route::tc::{TcAttribute, TcOption};
+
+    use crate::network::netlink::{redirect_dests, tbf_qopt, TrafficFilterSetRequest};
+
+    #[tokio::test]
+    async fn test_redirect_filter() {
+        let (_, handle, _) = rtnetlink::new_connection().unwrap();
+        let req = TrafficFilterSetRequest::new(handle, 3).redirect(5);
+        assert_eq!(req.message.header.index, 3);
+        assert_eq!(req.message.header.info & 0xffff, 0x0300);
+        assert!(matches!(
+            &req.message.attributes[0],
+            TcAttribute::Kind(k) if k == "matchall"
+        ));
+        assert!(matches!(
+            &req.message.attributes[1],
+            TcAttribute::Options(o) if matches!(o[0], TcOption::MatchAll(_))
+        ));
+        assert_eq!(redirect_dests(&req.message), vec![5]);
+    }
+
+    #[test]
+    fn test_tbf_qopt() {
+        // 10Mbit with a burst of 125KB
+        let qopt = tbf_qopt(1250000, 125000, 187500);
+        assert_eq!(qopt.len(), 36);
+        assert_eq!(qopt[1], 1);
+        assert_eq!(&qopt[8..12], &1250000u32.to_ne_bytes());
+        assert_eq!(&qopt[24..28], &187500u32.to_ne_bytes());
+        // 100ms in psched ticks
+        assert_eq!(&qopt[28..32], &1562500u32.to_ne_bytes());
     }
 }
@@ -22,7 +22,7 @@ use serde_derive::{Deserialize, Serialize};
 
 use crate::network::{address::convert_to_ip_address, link::NetworkInterface};
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Route {
     pub device: String,
     #[serde(skip_deserializing)]
@@ -0,0 +1,109 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use containerd_sandbox::{error::Result, SandboxStatus};
+use futures_util::{FutureExt, Stream, StreamExt};
+use log::{debug, error, warn};
+use rtnetlink::{
+    constants::{
+        RTMGRP_IPV4_IFADDR, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_IFADDR, RTMGRP_IPV6_ROUTE, RTMGRP_LINK,
+        RTMGRP_NEIGH,
+    },
+    new_connection,
+    sys::{AsyncSocket, SocketAddr},
+    Handle,
+};
+use tokio::sync::Mutex;
+
+use crate::{network::run_in_new_netns, sandbox::KuasarSandbox, vm::VM};
+
+// changes made by the cni plugins come in bursts, like an address with its routes,
+// so the netns is synced once for the events received in the delay.
+const NETWORK_RESYNC_DELAY_IN_MS: u64 = 500;
+
+// watch_network subscribes the link, address, route and neighbor events of the netns
+// of the running sandbox, and syncs the changes into the guest until the sandbox exits.
+pub(crate) fn watch_network<V: VM + Sync + Send + 'static>(
+    sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>,
+) {
+    tokio::spawn(async move {
+        let (id, netns, exit_signal) = {
+            let sandbox = sandbox_mutex.lock().await;
+            match sandbox.network.as_ref() {
+                Some(n) => (
+                    sandbox.id.to_string(),
+                    n.config().netns.to_string(),
+                    sandbox.exit_signal.clone(),
+                ),
+                None => return,
+            }
+        };
+        let groups = RTMGRP_LINK
+            | RTMGRP_IPV4_IFADDR
+            | RTMGRP_IPV6_IFADDR
+            | RTMGRP_IPV4_ROUTE
+            | RTMGRP_IPV6_ROUTE
+            | RTMGRP_NEIGH;
+        // the handle is kept so that the connection is not closed
+        let (_handle, mut messages) = match subscribe(&netns, groups).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("failed to watch network of sandbox {}: {}", id, e);
+                return;
+            }
+        };
+        loop {
+            tokio::select! {
+                m = messages.next() => {
+                    if m.is_none() {
+                        warn!("network events of sandbox {} are closed", id);
+                        return;
+                    }
+                },
+                _ = exit_signal.wait() => {
+                    debug!("stop network watch of exited sandbox {}", id);
+                    return;
+                },
+            }
+            tokio::time::sleep(Duration::from_millis(NETWORK_RESYNC_DELAY_IN_MS)).await;
+            while let Some(Some(_)) = messages.next().now_or_never() {}
+            let mut sandbox = sandbox_mutex.lock().await;
+            if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+                return;
+            }
+            if let Err(e) = sandbox.resync_network().await {
+                warn!("failed to resync network of sandbox {}: {}", id, e);
+            }
+        }
+    });
+}
+
+async fn subscribe(
+    netns: &str,
+    groups: u32,
+) -> Result<(Handle, impl Stream<Item = impl Send> + Unpin + Send)> {
+    let (mut connection, handle, messages) = run_in_new_netns(netns, new_connection).await??;
+    connection
+        .socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, groups))
+        .map_err(|e| anyhow!("failed to subscribe netlink events: {}", e))?;
+    tokio::spawn(connection);
+    Ok((handle, messages))
+}
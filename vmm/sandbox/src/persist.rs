@@ -0,0 +1,267 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{io::ErrorKind, path::Path};
+
+use anyhow::anyhow;
+use containerd_sandbox::error::{Error, Result};
+use log::warn;
+use serde_json::Value;
+use tokio::fs::{hard_link, remove_file, rename};
+use uuid::Uuid;
+
+use crate::utils::write_file_atomic;
+
+pub(crate) const SANDBOX_DUMP_FILE: &str = "sandbox.json";
+const SANDBOX_BACKUP_FILE: &str = "sandbox.json.bak";
+
+// SCHEMA_VERSION is the version of the sandbox dump written by this sandboxer,
+// it should be increased with a migration appended to MIGRATIONS
+// whenever a change of the dumped structs can not be handled by serde defaults.
+pub(crate) const SCHEMA_VERSION: u64 = 2;
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+// MIGRATIONS[i] upgrades a dump of version i to version i + 1,
+// dumps written before the version was introduced are of version 0.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [migrate_v0_to_v1, migrate_v1_to_v2];
+
+// Fields added to the dump in version 1 all have serde defaults,
+// so the dump only needs to be stamped with the version.
+fn migrate_v0_to_v1(_dump: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+// The overlay storages were keyed by the source of the mount, which is "overlay" for all of
+// them, since version 2 the key is the source with the options, taken from the overlay
+// rootfs of the containers referring the storage.
+fn migrate_v1_to_v2(dump: &mut Value) -> Result<()> {
+    let containers = dump.get("containers").cloned().unwrap_or_default();
+    let storages = match dump.get_mut("storages").and_then(|s| s.as_array_mut()) {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    for storage in storages {
+        if storage["type"] != "overlay" || storage["host_source"] != "overlay" {
+            continue;
+        }
+        let ids = match storage["ref_container"].as_object() {
+            Some(refs) => refs.keys().cloned().collect::<Vec<String>>(),
+            None => continue,
+        };
+        let rootfs = ids.iter().find_map(|id| {
+            containers[id]["data"]["rootfs"]
+                .as_array()?
+                .iter()
+                .find(|m| m["type"] == "overlay")
+        });
+        let m = match rootfs {
+            Some(m) => m,
+            None => {
+                warn!("no overlay rootfs of storage {} is found", storage["id"]);
+                continue;
+            }
+        };
+        let options = m["options"]
+            .as_array()
+            .map(|o| o.iter().filter_map(|x| x.as_str()).collect::<Vec<&str>>())
+            .unwrap_or_default();
+        let host_source = format!(
+            "{}:{}",
+            m["source"].as_str().unwrap_or_default(),
+            options.join(",")
+        );
+        storage["host_source"] = Value::from(host_source);
+    }
+    Ok(())
+}
+
+fn schema_version(dump: &Value) -> Result<u64> {
+    match dump.get(SCHEMA_VERSION_KEY) {
+        None => Ok(0),
+        Some(v) => v
+            .as_u64()
+            .ok_or_else(|| anyhow!("invalid schema version {} of sandbox dump", v).into()),
+    }
+}
+
+// migrate upgrades the dump to the current schema version,
+// dumps written by a newer sandboxer are refused as they may not be understood.
+pub(crate) fn migrate(dump: &mut Value) -> Result<()> {
+    let version = schema_version(dump)?;
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "sandbox dump of schema version {} is newer than {}",
+            version,
+            SCHEMA_VERSION
+        )
+        .into());
+    }
+    for m in MIGRATIONS.iter().skip(version as usize) {
+        m(dump)?;
+    }
+    stamp(dump)
+}
+
+fn stamp(dump: &mut Value) -> Result<()> {
+    let obj = dump
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("sandbox dump is not a json object"))?;
+    obj.insert(SCHEMA_VERSION_KEY.to_string(), Value::from(SCHEMA_VERSION));
+    Ok(())
+}
+
+// write_dump replaces the dump in the dir atomically, the dump should have the schema version
+// of the sandbox in memory. The previous dump is kept as the backup, it is always complete
+// as every dump is written atomically, so it is linked to the backup without being read.
+pub(crate) async fn write_dump(dir: &str, dump: Value) -> Result<()> {
+    let content = serde_json::to_string(&dump)
+        .map_err(|e| anyhow!("failed to serialize sandbox dump, {}", e))?;
+    let dump_path = Path::new(dir).join(SANDBOX_DUMP_FILE);
+    if dump_path.exists() {
+        let backup_path = Path::new(dir).join(SANDBOX_BACKUP_FILE);
+        if let Err(e) = link_atomic(&dump_path, &backup_path).await {
+            warn!("failed to backup sandbox dump in {}: {}", dir, e);
+        }
+    }
+    write_file_atomic(&dump_path, &content).await
+}
+
+// link_atomic replaces the dst with a hard link of the src,
+// the dst is never missing even if the sandboxer crashes in the middle.
+async fn link_atomic(src: &Path, dst: &Path) -> Result<()> {
+    let tmp_path = dst.with_extension(format!("{}.tmp", Uuid::new_v4().simple()));
+    hard_link(src, &tmp_path)
+        .await
+        .map_err(|e| anyhow!("failed to link {}: {}", src.display(), e))?;
+    if let Err(e) = rename(&tmp_path, dst).await {
+        remove_file(&tmp_path).await.unwrap_or_default();
+        return Err(anyhow!("failed to rename to {}: {}", dst.display(), e).into());
+    }
+    Ok(())
+}
+
+// read_dump_or_backup reads the dump in the dir and upgrades it to the current schema version,
+// the backup is used if the dump is missing or corrupted.
+pub(crate) async fn read_dump_or_backup(dir: &Path) -> Result<Value> {
+    let dump_path = dir.join(SANDBOX_DUMP_FILE);
+    let mut dump = match read_dump(&dump_path).await {
+        Ok(d) => d,
+        Err(e) => {
+            let backup_path = dir.join(SANDBOX_BACKUP_FILE);
+            if !backup_path.exists() {
+                return Err(e);
+            }
+            warn!(
+                "failed to read {}: {}, fall back to {}",
+                dump_path.display(),
+                e,
+                backup_path.display()
+            );
+            read_dump(&backup_path).await?
+        }
+    };
+    migrate(&mut dump)?;
+    Ok(dump)
+}
+
+async fn read_dump(path: &Path) -> Result<Value> {
+    let content = tokio::fs::read(path).await.map_err(|e| {
+        if e.kind() == ErrorKind::NotFound {
+            Error::NotFound(path.display().to_string())
+        } else {
+            Error::IO(e)
+        }
+    })?;
+    serde_json::from_slice::<Value>(&content)
+        .map_err(|e| anyhow!("failed to parse {}, {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use temp_dir::TempDir;
+
+    use crate::persist::{
+        migrate, read_dump_or_backup, write_dump, SANDBOX_BACKUP_FILE, SANDBOX_DUMP_FILE,
+        SCHEMA_VERSION,
+    };
+
+    #[test]
+    fn test_migrate() {
+        let mut dump = json!({"id": "sb1"});
+        migrate(&mut dump).unwrap();
+        assert_eq!(dump["schema_version"], SCHEMA_VERSION);
+        assert_eq!(dump["id"], "sb1");
+
+        let mut dump = json!({"id": "sb1", "schema_version": SCHEMA_VERSION + 1});
+        assert!(migrate(&mut dump).is_err());
+
+        let mut dump = json!({"id": "sb1", "schema_version": "1"});
+        assert!(migrate(&mut dump).is_err());
+    }
+
+    #[test]
+    fn test_migrate_overlay_storage() {
+        let mut dump = json!({
+            "id": "sb1",
+            "schema_version": 1,
+            "containers": {
+                "c1": {"data": {"rootfs": [{
+                    "type": "overlay",
+                    "source": "overlay",
+                    "options": ["lowerdir=/l1:/l2", "upperdir=/u", "workdir=/w"],
+                }]}},
+            },
+            "storages": [
+                {"id": "storage1", "type": "overlay", "host_source": "overlay", "ref_container": {"c1": 1}},
+                {"id": "storage2", "type": "bind", "host_source": "/data", "ref_container": {"c1": 1}},
+            ],
+        });
+        migrate(&mut dump).unwrap();
+        assert_eq!(
+            dump["storages"][0]["host_source"],
+            "overlay:lowerdir=/l1:/l2,upperdir=/u,workdir=/w"
+        );
+        assert_eq!(dump["storages"][1]["host_source"], "/data");
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_dump() {
+        let dir = TempDir::new().unwrap();
+        let dir_str = dir.path().to_str().unwrap();
+        write_dump(dir_str, json!({"id": "sb1", "generation": 1}))
+            .await
+            .unwrap();
+        write_dump(dir_str, json!({"id": "sb1", "generation": 2}))
+            .await
+            .unwrap();
+        let dump = read_dump_or_backup(dir.path()).await.unwrap();
+        assert_eq!(dump["generation"], 2);
+        // no tmp file is left by the writes
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // a torn write of the dump falls back to the last good state
+        std::fs::write(dir.path().join(SANDBOX_DUMP_FILE), "{\"id\": \"sb").unwrap();
+        let dump = read_dump_or_backup(dir.path()).await.unwrap();
+        assert_eq!(dump["generation"], 1);
+        assert_eq!(dump["schema_version"], SCHEMA_VERSION);
+
+        std::fs::remove_file(dir.path().join(SANDBOX_BACKUP_FILE)).unwrap();
+        assert!(read_dump_or_backup(dir.path()).await.is_err());
+    }
+}
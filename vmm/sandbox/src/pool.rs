@@ -0,0 +1,332 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{collections::VecDeque, io::ErrorKind, path::Path, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use containerd_sandbox::{data::SandboxData, error::Result, utils::cleanup_mounts, SandboxOption};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use tokio::{
+    fs::{create_dir_all, remove_dir_all},
+    sync::Mutex,
+};
+use uuid::Uuid;
+use vmm_common::{api::sandbox_ttrpc::SandboxServiceClient, SHARED_DIR_SUFFIX};
+
+use crate::{
+    client::{client_check, new_sandbox_client},
+    vm::{VMFactory, VM},
+};
+
+const DEFAULT_POOL_WORK_DIR: &str = "/run/kuasar-vmm-pool";
+const DEFAULT_REFILL_INTERVAL_IN_MS: u64 = 1000;
+const POOLED_VM_ID_PREFIX: &str = "pool-";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct VMPoolConfig {
+    /// Number of pre-warmed vms kept in the pool, the pool is disabled if it is 0
+    #[serde(default)]
+    pub size: usize,
+    /// At most one vm is booted into the pool in every interval
+    #[serde(default = "default_refill_interval_in_ms")]
+    pub refill_interval_in_ms: u64,
+    /// Max host memory used by the idle vms in the pool, 0 means no limit
+    #[serde(default)]
+    pub memory_budget_in_mb: u64,
+    /// Directory of the pooled vms, a pooled vm keeps its directory after assigned to a sandbox
+    #[serde(default = "default_pool_work_dir")]
+    pub work_dir: String,
+}
+
+fn default_refill_interval_in_ms() -> u64 {
+    DEFAULT_REFILL_INTERVAL_IN_MS
+}
+
+fn default_pool_work_dir() -> String {
+    DEFAULT_POOL_WORK_DIR.to_string()
+}
+
+impl Default for VMPoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            refill_interval_in_ms: DEFAULT_REFILL_INTERVAL_IN_MS,
+            memory_budget_in_mb: 0,
+            work_dir: DEFAULT_POOL_WORK_DIR.to_string(),
+        }
+    }
+}
+
+// PooledVM is a booted vm whose agent is ready, but not assigned to any sandbox yet.
+pub(crate) struct PooledVM<V: VM> {
+    pub(crate) id: String,
+    pub(crate) base_dir: String,
+    pub(crate) vm: V,
+    pub(crate) pid: u32,
+    pub(crate) client: SandboxServiceClient,
+}
+
+impl<V: VM> PooledVM<V> {
+    pub(crate) fn shared_path(&self) -> String {
+        format!("{}/{}", self.base_dir, SHARED_DIR_SUFFIX)
+    }
+
+    fn memory_usage_in_mb(&self) -> u64 {
+        let pids = self.vm.pids();
+        pids.vmm_pid
+            .into_iter()
+            .chain(pids.affiliated_pids)
+            .map(get_rss_in_mb)
+            .sum()
+    }
+
+    pub(crate) async fn destroy(mut self) {
+        if let Err(e) = self.vm.stop(true).await {
+            warn!("failed to stop pooled vm {}: {}", self.id, e);
+        }
+        remove_pooled_vm_dir(&self.base_dir).await;
+    }
+}
+
+pub struct VMPool<V: VM> {
+    config: VMPoolConfig,
+    vms: Mutex<VecDeque<PooledVM<V>>>,
+}
+
+impl<V> VMPool<V>
+where
+    V: VM + 'static,
+{
+    pub fn new(config: VMPoolConfig) -> Self {
+        Self {
+            config,
+            vms: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.size > 0
+    }
+
+    pub(crate) fn work_dir(&self) -> &str {
+        &self.config.work_dir
+    }
+
+    pub(crate) async fn vm_ids(&self) -> Vec<String> {
+        self.vms
+            .lock()
+            .await
+            .iter()
+            .map(|v| v.id.to_string())
+            .collect()
+    }
+
+    // take returns an idle vm from the pool, vms exited while waiting in the pool are dropped.
+    pub(crate) async fn take(&self) -> Option<PooledVM<V>> {
+        loop {
+            let pooled = self.vms.lock().await.pop_front()?;
+            match pooled.vm.ping().await {
+                Ok(_) => {
+                    debug!("take pooled vm {} from the pool", pooled.id);
+                    return Some(pooled);
+                }
+                Err(e) => {
+                    warn!("pooled vm {} is not alive, drop it: {}", pooled.id, e);
+                    pooled.destroy().await;
+                }
+            }
+        }
+    }
+
+    // cleanup removes the directories of the pooled vms left by the previous sandboxer process,
+    // except those already assigned to the recovered sandboxes.
+    pub(crate) async fn cleanup(&self, in_use: &[String]) {
+        let mut subs = match tokio::fs::read_dir(&self.config.work_dir).await {
+            Ok(subs) => subs,
+            Err(e) => {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("failed to read pool dir {}: {}", self.config.work_dir, e);
+                }
+                return;
+            }
+        };
+        while let Ok(Some(entry)) = subs.next_entry().await {
+            let path = Path::new(&self.config.work_dir).join(entry.file_name());
+            let path = path.to_string_lossy().to_string();
+            if in_use.contains(&path) {
+                continue;
+            }
+            info!("remove stale pooled vm dir {}", path);
+            remove_pooled_vm_dir(&path).await;
+        }
+    }
+
+    pub(crate) fn run<F>(self: &Arc<Self>, factory: Arc<F>)
+    where
+        F: VMFactory<VM = V> + Sync + Send + 'static,
+    {
+        if !self.enabled() {
+            return;
+        }
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_millis(pool.config.refill_interval_in_ms));
+            loop {
+                interval.tick().await;
+                if !pool.should_refill().await {
+                    continue;
+                }
+                match pool.create_vm(factory.as_ref()).await {
+                    Ok(pooled) => {
+                        info!("pooled vm {} is ready", pooled.id);
+                        pool.vms.lock().await.push_back(pooled);
+                    }
+                    Err(e) => {
+                        warn!("failed to create vm for the pool: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    async fn should_refill(&self) -> bool {
+        let vms = self.vms.lock().await;
+        if vms.len() >= self.config.size {
+            return false;
+        }
+        if self.config.memory_budget_in_mb == 0 {
+            return true;
+        }
+        let used = vms.iter().map(|v| v.memory_usage_in_mb()).sum::<u64>();
+        if used >= self.config.memory_budget_in_mb {
+            debug!(
+                "pooled vms use {}M memory, reach the budget {}M",
+                used, self.config.memory_budget_in_mb
+            );
+            return false;
+        }
+        true
+    }
+
+    async fn create_vm<F>(&self, factory: &F) -> Result<PooledVM<V>>
+    where
+        F: VMFactory<VM = V>,
+    {
+        let id = format!("{}{}", POOLED_VM_ID_PREFIX, Uuid::new_v4().simple());
+        let base_dir = format!("{}/{}", self.config.work_dir, id);
+        let shared_path = format!("{}/{}", base_dir, SHARED_DIR_SUFFIX);
+        create_dir_all(&shared_path)
+            .await
+            .map_err(|e| anyhow!("create pooled vm dir {}: {}", shared_path, e))?;
+
+        let option = SandboxOption::new(base_dir.clone(), SandboxData::default());
+        let mut vm = match factory.create_vm(&id, &option).await {
+            Ok(vm) => vm,
+            Err(e) => {
+                remove_pooled_vm_dir(&base_dir).await;
+                return Err(e);
+            }
+        };
+        let pid = match vm.start().await {
+            Ok(pid) => pid,
+            Err(e) => {
+                if let Err(re) = vm.stop(true).await {
+                    warn!("roll back in start pooled vm {}: {}", id, re);
+                }
+                remove_pooled_vm_dir(&base_dir).await;
+                return Err(e);
+            }
+        };
+        let client = match connect_agent(&vm.socket_address()).await {
+            Ok(c) => c,
+            Err(e) => {
+                if let Err(re) = vm.stop(true).await {
+                    warn!("roll back in connect agent of pooled vm {}: {}", id, re);
+                }
+                remove_pooled_vm_dir(&base_dir).await;
+                return Err(e);
+            }
+        };
+        Ok(PooledVM {
+            id,
+            base_dir,
+            vm,
+            pid,
+            client,
+        })
+    }
+}
+
+async fn connect_agent(addr: &str) -> Result<SandboxServiceClient> {
+    if addr.is_empty() {
+        return Err(anyhow!("VM address is empty").into());
+    }
+    let client = new_sandbox_client(addr).await?;
+    client_check(&client).await?;
+    Ok(client)
+}
+
+pub(crate) async fn remove_pooled_vm_dir(dir: &str) {
+    cleanup_mounts(dir).await.unwrap_or_default();
+    if let Err(e) = remove_dir_all(dir).await {
+        if e.kind() != ErrorKind::NotFound {
+            warn!("failed to remove pooled vm dir {}: {}", dir, e);
+        }
+    }
+}
+
+// get_rss_in_mb returns the resident memory of the process, 0 is returned if it is not found.
+fn get_rss_in_mb(pid: u32) -> u64 {
+    let status = match std::fs::read_to_string(format!("/proc/{}/status", pid)) {
+        Ok(s) => s,
+        Err(_) => return 0,
+    };
+    parse_rss_in_mb(&status)
+}
+
+fn parse_rss_in_mb(status: &str) -> u64 {
+    status
+        .lines()
+        .find(|l| l.starts_with("VmRSS:"))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb / 1024)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pool::{parse_rss_in_mb, VMPoolConfig};
+
+    #[test]
+    fn test_parse_rss_in_mb() {
+        let status =
+            "Name:\tqemu-system-x86\nVmPeak:\t 4452196 kB\nVmRSS:\t  307200 kB\nThreads:\t5\n";
+        assert_eq!(parse_rss_in_mb(status), 300);
+        assert_eq!(parse_rss_in_mb("Name:\tkthreadd\n"), 0);
+    }
+
+    #[test]
+    fn test_vm_pool_config_default() {
+        let config: VMPoolConfig = toml::from_str("size = 2").unwrap();
+        assert_eq!(config.size, 2);
+        assert_eq!(config.refill_interval_in_ms, 1000);
+        assert_eq!(config.memory_budget_in_mb, 0);
+        assert_eq!(config.work_dir, "/run/kuasar-vmm-pool");
+    }
+}
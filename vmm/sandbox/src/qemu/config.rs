@@ -28,6 +28,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     param::ToCmdLineParams,
     utils::{bool_to_on_off, get_host_memory_in_mb},
+    virtiofsd::VirtiofsdConfig,
     vm::{BlockDriver, HypervisorCommonConfig, ShareFsType},
 };
 
@@ -82,10 +83,14 @@ pub struct QemuVMConfig {
     pub default_max_vcpus: u32,
     pub entropy_source: String,
     // memory related configurations
+    // each memory resize of a running vm takes a slot, so it limits the times of growing memory
     pub mem_slots: u8,
     pub mem_offset: u32,
     pub memory_path: String,
     pub file_backend_mem_path: String,
+    // boot vms by restoring from a template vm, file_backend_mem_path is the template dir if set
+    #[serde(default)]
+    pub enable_template: bool,
     pub mem_prealloc: bool,
     pub hugepages: bool,
     pub enable_vhost_user_store: bool,
@@ -115,10 +120,11 @@ impl Default for QemuVMConfig {
             default_bridges: 1,
             default_max_vcpus: 0,
             entropy_source: "/dev/urandom".to_string(),
-            mem_slots: 1,
+            mem_slots: 10,
             mem_offset: 0,
             memory_path: "".to_string(),
             file_backend_mem_path: "".to_string(),
+            enable_template: false,
             mem_prealloc: false,
             hugepages: false,
             enable_vhost_user_store: false,
@@ -136,35 +142,6 @@ impl Default for QemuVMConfig {
     }
 }
 
-#[derive(CmdLineParamSet, Deserialize, Debug, Clone, Serialize)]
-pub struct VirtiofsdConfig {
-    #[param(ignore)]
-    pub path: String,
-    pub log_level: String,
-    pub cache: String,
-    pub thread_pool_size: u32,
-    #[serde(default)]
-    pub socket_path: String,
-    #[serde(default)]
-    pub shared_dir: String,
-    #[serde(default)]
-    pub syslog: bool,
-}
-
-impl Default for VirtiofsdConfig {
-    fn default() -> Self {
-        Self {
-            path: "/usr/local/bin/virtiofsd".to_string(),
-            log_level: "info".to_string(),
-            cache: "never".to_string(),
-            thread_pool_size: 4,
-            socket_path: "".to_string(),
-            shared_dir: "".to_string(),
-            syslog: true,
-        }
-    }
-}
-
 impl QemuVMConfig {
     pub async fn to_qemu_config(&self) -> Result<QemuConfig> {
         let mut result = QemuConfig::default();
@@ -28,8 +28,8 @@ use sandbox_derive::CmdLineParams;
 use serde_json::Value;
 
 use crate::{
-    device::{BusType, Device, Transport},
-    qemu::{devices::HotAttachable, qmp_client::QmpClient},
+    device::{BusType, Device, IoThrottle, Transport},
+    qemu::{devices::HotAttachable, qmp::BlockSetIoThrottle, qmp_client::QmpClient},
 };
 
 pub const VIRTIO_BLK_DRIVER: &str = "virtio-blk";
@@ -84,6 +84,10 @@ pub struct VirtioBlockDevice {
     pub share_rw: bool,
     #[property(param = "drive", generator = "crate::utils::bool_to_on_off")]
     pub readonly: bool,
+    #[property(param = "drive", key = "throttling.iops-total")]
+    pub iops_total: Option<u64>,
+    #[property(param = "drive", key = "throttling.bps-total")]
+    pub bps_total: Option<u64>,
 }
 
 impl_device_no_bus!(VirtioBlockDevice);
@@ -106,12 +110,29 @@ impl VirtioBlockDevice {
             #[cfg(feature = "virtcca")]
             share_rw: true,
             readonly: read_only,
+            iops_total: None,
+            bps_total: None,
             #[cfg(feature = "virtcca")]
             disable_legacy: true,
             #[cfg(feature = "virtcca")]
             iommu_platform: true,
         }
     }
+
+    pub fn set_throttle(&mut self, throttle: &IoThrottle) {
+        self.iops_total = Some(throttle.iops).filter(|x| *x > 0);
+        self.bps_total = Some(throttle.bps).filter(|x| *x > 0);
+    }
+
+    // to_io_throttle limits the hot attached device, which can not be done by blockdev_add
+    pub(crate) fn to_io_throttle(&self) -> BlockSetIoThrottle {
+        BlockSetIoThrottle {
+            id: format!("virtio-{}", self.id()),
+            bps: self.bps_total.unwrap_or_default() as i64,
+            iops: self.iops_total.unwrap_or_default() as i64,
+            ..BlockSetIoThrottle::default()
+        }
+    }
 }
 
 #[async_trait]
@@ -143,10 +164,12 @@ impl HotAttachable for VirtioBlockDevice {
         }
     }
 
-    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
-        debug!("hot detach device {}", self.id);
-        let device_id = format!("virtio-{}", self.id());
-        client.delete_device(&device_id).await?;
+    fn device_id(&self) -> String {
+        format!("virtio-{}", self.id())
+    }
+
+    async fn execute_hot_release(&self, client: &QmpClient) -> Result<()> {
+        debug!("release blockdev of device {}", self.id);
         client.execute(self.to_blockdev_del()).await?;
         Ok(())
     }
@@ -154,25 +177,36 @@ impl HotAttachable for VirtioBlockDevice {
 
 impl VirtioBlockDevice {
     fn to_blockdev_add(&self) -> blockdev_add {
-        blockdev_add(BlockdevOptions::host_device {
-            base: BlockdevOptionsBase {
-                node_name: Some(self.id.to_string()),
-                read_only: Some(self.readonly),
-                auto_read_only: None,
-                cache: None,
-                force_share: None,
-                discard: None,
-                detect_zeroes: None,
-            },
-            host_device: BlockdevOptionsFile {
-                drop_cache: None,
-                locking: None,
-                x_check_cache_dropped: None,
-                filename: self.file.as_ref().unwrap().to_string(),
-                aio: None,
-                pr_manager: None,
-            },
-        })
+        let filename = self.file.as_ref().unwrap().to_string();
+        let base = BlockdevOptionsBase {
+            node_name: Some(self.id.to_string()),
+            read_only: Some(self.readonly),
+            auto_read_only: None,
+            cache: None,
+            force_share: None,
+            discard: None,
+            detect_zeroes: None,
+        };
+        // the filesystem images such as the erofs layers are regular files
+        let is_file = std::fs::metadata(&filename)
+            .map(|m| m.file_type().is_file())
+            .unwrap_or_default();
+        let file = BlockdevOptionsFile {
+            drop_cache: None,
+            locking: None,
+            x_check_cache_dropped: None,
+            filename,
+            aio: None,
+            pr_manager: None,
+        };
+        if is_file {
+            blockdev_add(BlockdevOptions::file { base, file })
+        } else {
+            blockdev_add(BlockdevOptions::host_device {
+                base,
+                host_device: file,
+            })
+        }
     }
 
     fn to_device_add(&self, bus_type: &BusType, bus_id: &str, index: usize) -> device_add {
@@ -226,3 +260,33 @@ impl VirtioBlockDevice {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        device::IoThrottle,
+        param::ToCmdLineParams,
+        qemu::devices::block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
+    };
+
+    #[test]
+    fn test_throttle() {
+        let mut device = VirtioBlockDevice::new(
+            &format!("{}-pci", VIRTIO_BLK_DRIVER),
+            "blk1",
+            Some("/dev/sdb".to_string()),
+            false,
+        );
+        device.set_throttle(&IoThrottle { iops: 1000, bps: 0 });
+        let params = device.to_cmdline_params("-");
+        assert!(params
+            .iter()
+            .any(|x| x.contains("throttling.iops-total=1000")));
+        assert!(!params.iter().any(|x| x.contains("throttling.bps-total")));
+
+        let throttle = device.to_io_throttle();
+        assert_eq!(throttle.id, "virtio-blk1");
+        assert_eq!(throttle.iops, 1000);
+        assert_eq!(throttle.bps, 0);
+    }
+}
@@ -157,9 +157,12 @@ impl HotAttachable for CharDevice {
         }
     }
 
-    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()> {
-        debug!("hot detach {:?}", self);
-        client.delete_device(&self.id).await?;
+    fn device_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    async fn execute_hot_release(&self, client: &QmpClient) -> Result<()> {
+        debug!("release chardev of {:?}", self);
         client.execute(self.to_chardev_remove()).await?;
         Ok(())
     }
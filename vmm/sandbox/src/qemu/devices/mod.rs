@@ -26,6 +26,7 @@ use crate::{
 pub mod block;
 pub mod bridge;
 pub mod char;
+pub mod pvpanic;
 pub mod scsi;
 pub mod serial;
 pub mod vfio;
@@ -52,7 +53,10 @@ pub trait HotAttachable {
         bus_id: &str,
         slot_index: usize,
     ) -> Result<()>;
-    async fn execute_hot_detach(&self, client: &QmpClient) -> Result<()>;
+    // device_id is the id of the device in qemu, which is removed by device_del
+    fn device_id(&self) -> String;
+    // execute_hot_release removes the backends of the device after the device is deleted
+    async fn execute_hot_release(&self, client: &QmpClient) -> Result<()>;
 }
 
 pub trait QemuHotAttachable: Device + HotAttachable {}
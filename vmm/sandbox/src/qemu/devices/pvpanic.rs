@@ -0,0 +1,42 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use sandbox_derive::CmdLineParams;
+
+// the isa pvpanic device is only available on x86_64, the pci one is used on the other arches
+#[cfg(target_arch = "x86_64")]
+pub const PVPANIC_DRIVER: &str = "pvpanic";
+#[cfg(not(target_arch = "x86_64"))]
+pub const PVPANIC_DRIVER: &str = "pvpanic-pci";
+
+#[derive(CmdLineParams, Debug, Clone)]
+#[params("device")]
+pub struct PvpanicDevice {
+    #[property(ignore_key)]
+    pub(crate) driver: String,
+    pub(crate) id: String,
+}
+
+impl_device_no_bus!(PvpanicDevice);
+
+impl PvpanicDevice {
+    pub fn new(id: &str) -> Self {
+        Self {
+            driver: PVPANIC_DRIVER.to_string(),
+            id: id.to_string(),
+        }
+    }
+}
@@ -14,9 +14,17 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::debug;
+use qapi::{qmp::device_add, Dictionary};
 use sandbox_derive::CmdLineParams;
+use serde_json::Value;
 
-use crate::device::Transport;
+use crate::{
+    device::{BusType, Transport},
+    qemu::{devices::HotAttachable, qmp_client::QmpClient},
+};
 
 #[derive(CmdLineParams, Debug, Clone)]
 #[params("device")]
@@ -43,6 +51,50 @@ impl VfioDevice {
     }
 }
 
+#[async_trait]
+impl HotAttachable for VfioDevice {
+    async fn execute_hot_attach(
+        &self,
+        client: &QmpClient,
+        _bus_type: &BusType,
+        bus_id: &str,
+        slot_index: usize,
+    ) -> Result<()> {
+        debug!("hot attach vfio device {} of {}", self.id, self.bdf);
+        client
+            .execute(self.to_device_add(bus_id, slot_index))
+            .await?;
+        Ok(())
+    }
+
+    fn device_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    // the host device is released by qemu once the device is deleted
+    async fn execute_hot_release(&self, _client: &QmpClient) -> Result<()> {
+        debug!("vfio device {} of {} is released", self.id, self.bdf);
+        Ok(())
+    }
+}
+
+impl VfioDevice {
+    fn to_device_add(&self, bus_id: &str, index: usize) -> device_add {
+        let mut args = Dictionary::new();
+        args.insert("host".to_string(), Value::from(self.bdf.to_string()));
+        args.insert("addr".to_string(), Value::from(format!("{:02x}", index)));
+        if let Some(x) = self.romfile.as_ref() {
+            args.insert("romfile".to_string(), Value::from(x.to_string()));
+        }
+        device_add {
+            driver: self.driver.to_string(),
+            bus: Some(bus_id.to_string()),
+            id: Some(self.id.to_string()),
+            arguments: args,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{param::ToParams, qemu::devices::vfio::VfioDevice};
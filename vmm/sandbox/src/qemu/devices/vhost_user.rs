@@ -16,7 +16,26 @@ limitations under the License.
 
 use std::fmt::{Display, Formatter};
 
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::{debug, error};
+use qapi::{
+    qmp::{chardev_remove, device_add},
+    Dictionary,
+};
 use sandbox_derive::CmdLineParams;
+use serde_json::{json, Value};
+
+use crate::{
+    device::BusType,
+    qemu::{
+        devices::HotAttachable,
+        qmp::{ChardevAdd, NetdevAdd, NetdevDel},
+        qmp_client::QmpClient,
+    },
+};
+
+pub(crate) const VHOST_USER_RECONNECT_IN_SEC: u32 = 1;
 
 #[derive(Debug, Clone)]
 pub enum VhostUserType {
@@ -41,6 +60,8 @@ pub struct VhostNetDevice {
     pub(crate) driver: VhostUserType,
     #[property(ignore)]
     pub id: String,
+    #[property(ignore)]
+    pub(crate) socket_path: String,
     #[property(param = "netdev", key = "type")]
     pub(crate) netdev_type: String,
     #[property(param = "netdev", ignore_key)]
@@ -65,12 +86,13 @@ pub struct VhostNetDevice {
 impl_device_no_bus!(VhostNetDevice);
 
 impl VhostNetDevice {
-    pub fn new(id: &str, t: VhostUserType, _socket_path: &str, address: &str) -> Self {
+    pub fn new(id: &str, t: VhostUserType, socket_path: &str, address: &str) -> Self {
         // TODO: length must <= 31
         let char_dev_id: String = format!("{}-{}", "char", id);
         let type_dev_id = format!("{}-{}", "net", id);
         Self {
             id: id.to_string(),
+            socket_path: socket_path.to_string(),
             driver: t,
             netdev_type: "vhost-user".to_string(),
             netdev_param: "vhostforce".to_string(),
@@ -85,6 +107,121 @@ impl VhostNetDevice {
     }
 }
 
+#[async_trait]
+impl HotAttachable for VhostNetDevice {
+    async fn execute_hot_attach(
+        &self,
+        client: &QmpClient,
+        _bus_type: &BusType,
+        bus_id: &str,
+        slot_index: usize,
+    ) -> Result<()> {
+        debug!("hot attach vhost-user net device {}", self.id);
+        client.execute(self.to_chardev_add()).await?;
+        if let Err(e) = client.execute(self.to_netdev_add()).await {
+            self.remove_chardev(client).await;
+            return Err(e);
+        }
+        match client.execute(self.to_device_add(bus_id, slot_index)).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                client
+                    .execute(self.to_netdev_del())
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("failed to delete netdev after device_add failed, {}", e);
+                        qapi::Empty {}
+                    });
+                self.remove_chardev(client).await;
+                Err(e)
+            }
+        }
+    }
+
+    fn device_id(&self) -> String {
+        self.id.to_string()
+    }
+
+    async fn execute_hot_release(&self, client: &QmpClient) -> Result<()> {
+        debug!("release backends of vhost-user net device {}", self.id);
+        client.execute(self.to_netdev_del()).await?;
+        client.execute(self.to_chardev_remove()).await?;
+        Ok(())
+    }
+}
+
+impl VhostNetDevice {
+    fn to_chardev_add(&self) -> ChardevAdd {
+        ChardevAdd {
+            id: self.char_dev_id.to_string(),
+            backend: json!({
+                "type": "socket",
+                "data": {
+                    "addr": {"type": "unix", "data": {"path": self.socket_path}},
+                    "server": false,
+                }
+            }),
+        }
+    }
+
+    fn to_netdev_add(&self) -> NetdevAdd {
+        let mut args = Dictionary::new();
+        args.insert(
+            "chardev".to_string(),
+            Value::from(self.char_dev_id.to_string()),
+        );
+        args.insert("vhostforce".to_string(), Value::from(true));
+        NetdevAdd {
+            r#type: self.netdev_type.to_string(),
+            id: self.net_dev_id.to_string(),
+            arguments: args,
+        }
+    }
+
+    fn to_device_add(&self, bus_id: &str, index: usize) -> device_add {
+        let mut args = Dictionary::new();
+        args.insert(
+            "netdev".to_string(),
+            Value::from(self.net_dev_id.to_string()),
+        );
+        if let Some(x) = self.address.as_ref() {
+            args.insert("mac".to_string(), Value::from(x.to_string()));
+        }
+        args.insert("addr".to_string(), Value::from(format!("{:02x}", index)));
+        if let Some(x) = self.romfile.as_ref() {
+            args.insert("romfile".to_string(), Value::from(x.to_string()));
+        }
+        device_add {
+            driver: self.driver.to_string(),
+            bus: Some(bus_id.to_string()),
+            id: Some(self.id.to_string()),
+            arguments: args,
+        }
+    }
+
+    fn to_netdev_del(&self) -> NetdevDel {
+        NetdevDel {
+            id: self.net_dev_id.to_string(),
+        }
+    }
+
+    fn to_chardev_remove(&self) -> chardev_remove {
+        chardev_remove {
+            id: self.char_dev_id.to_string(),
+        }
+    }
+
+    async fn remove_chardev(&self, client: &QmpClient) {
+        client
+            .execute(self.to_chardev_remove())
+            .await
+            .unwrap_or_else(|e| {
+                error!("failed to remove chardev after hot attach failed, {}", e);
+                qapi::Empty {}
+            });
+    }
+}
+
 #[derive(CmdLineParams, Debug, Clone)]
 #[params("device", "chardev")]
 pub struct VhostCharDevice {
@@ -98,6 +235,9 @@ pub struct VhostCharDevice {
     pub(crate) socket_path: String,
     #[property(param = "chardev", key = "id")]
     pub(crate) char_dev_id: String,
+    // the seconds to wait before reconnecting to the backend after it disconnects
+    #[property(param = "chardev")]
+    pub(crate) reconnect: Option<u32>,
     #[property(param = "device", key = "chardev")]
     pub(crate) net_dev_id: String,
     #[property(param = "device")]
@@ -130,6 +270,7 @@ impl VhostCharDevice {
             chardev_type: "socket".to_string(),
             socket_path: socket_path.to_string(),
             char_dev_id,
+            reconnect: None,
             net_dev_id: type_dev_id,
             tag: Some("kuasar".to_string()),
             cache_size: None,
@@ -145,7 +286,7 @@ impl VhostCharDevice {
 mod tests {
     use crate::{
         param::ToParams,
-        qemu::devices::vhost_user::{VhostNetDevice, VhostUserType},
+        qemu::devices::vhost_user::{VhostCharDevice, VhostNetDevice, VhostUserType},
     };
 
     #[test]
@@ -164,4 +305,19 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_chardev_reconnect() {
+        let mut device = VhostCharDevice::new(
+            "extra-fs-kuasar",
+            VhostUserType::VhostUserChar("vhost-user-fs-pci".to_string()),
+            "/run/kuasar/1/virtiofs.sock",
+            "",
+        );
+        device.reconnect = Some(1);
+        let params = device.to_params();
+        let chardev = params.iter().find(|p| p.name == "chardev").unwrap();
+        assert_eq!(chardev.get("path").unwrap(), "/run/kuasar/1/virtiofs.sock");
+        assert_eq!(chardev.get("reconnect").unwrap(), "1");
+    }
 }
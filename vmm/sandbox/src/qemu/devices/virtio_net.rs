@@ -16,9 +16,22 @@ limitations under the License.
 
 use std::os::unix::io::RawFd;
 
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::{debug, error};
+use qapi::{qmp::device_add, Dictionary};
 use sandbox_derive::CmdLineParams;
+use serde_json::Value;
 
-use crate::{device::Transport, network::NetType};
+use crate::{
+    device::{BusType, Transport},
+    network::NetType,
+    qemu::{
+        devices::HotAttachable,
+        qmp::{NetdevAdd, NetdevDel},
+        qmp_client::QmpClient,
+    },
+};
 
 pub const VIRTIO_NET_DRIVER: &str = "virtio-net";
 
@@ -82,6 +95,9 @@ pub struct VirtioNetDevice {
     pub(crate) romfile: Option<String>,
     #[property(param = "netdev")]
     pub(crate) queues: Option<i32>,
+    // names of the fds of the tap queues passed to qemu by getfd, only for hot attaching
+    #[property(ignore)]
+    pub(crate) fd_names: Vec<String>,
     #[cfg(feature = "virtcca")]
     #[property(
         param = "device",
@@ -135,6 +151,7 @@ impl VirtioNetDevice {
             disable_modern: None,
             romfile: None,
             queues: None,
+            fd_names: vec![],
             #[cfg(feature = "virtcca")]
             disable_legacy: true,
             #[cfg(feature = "virtcca")]
@@ -143,6 +160,99 @@ impl VirtioNetDevice {
     }
 }
 
+#[async_trait]
+impl HotAttachable for VirtioNetDevice {
+    async fn execute_hot_attach(
+        &self,
+        client: &QmpClient,
+        _bus_type: &BusType,
+        bus_id: &str,
+        slot_index: usize,
+    ) -> Result<()> {
+        debug!("hot attach net device {}", self.id);
+        client.execute(self.to_netdev_add()).await?;
+        match client.execute(self.to_device_add(bus_id, slot_index)).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                client
+                    .execute(self.to_netdev_del())
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("failed to delete netdev after device_add failed, {}", e);
+                        qapi::Empty {}
+                    });
+                Err(e)
+            }
+        }
+    }
+
+    fn device_id(&self) -> String {
+        format!("virtio-{}", self.id)
+    }
+
+    async fn execute_hot_release(&self, client: &QmpClient) -> Result<()> {
+        debug!("release netdev of net device {}", self.id);
+        client.execute(self.to_netdev_del()).await?;
+        Ok(())
+    }
+}
+
+impl VirtioNetDevice {
+    // the queues of the tap are the fds opened by the sandboxer and passed to qemu by getfd,
+    // qemu opens the tap by its name only if no fd is passed.
+    fn to_netdev_add(&self) -> NetdevAdd {
+        let mut args = Dictionary::new();
+        args.insert("vhost".to_string(), Value::from(self.vhost));
+        if !self.fd_names.is_empty() {
+            // ifname, script and queues are not allowed together with fds
+            args.insert("fds".to_string(), Value::from(self.fd_names.join(":")));
+        } else {
+            if let Some(x) = self.ifname.as_ref() {
+                args.insert("ifname".to_string(), Value::from(x.to_string()));
+            }
+            args.insert("script".to_string(), Value::from("no"));
+            args.insert("downscript".to_string(), Value::from("no"));
+            if let Some(x) = self.queues.filter(|q| *q > 1) {
+                args.insert("queues".to_string(), Value::from(x));
+            }
+        }
+        NetdevAdd {
+            r#type: self.r#type.to_string(),
+            id: self.id.to_string(),
+            arguments: args,
+        }
+    }
+
+    fn to_device_add(&self, bus_id: &str, index: usize) -> device_add {
+        let mut args = Dictionary::new();
+        args.insert("netdev".to_string(), Value::from(self.id.to_string()));
+        args.insert("mac".to_string(), Value::from(self.mac_address.to_string()));
+        args.insert("addr".to_string(), Value::from(format!("{:02x}", index)));
+        if let Some(x) = self.queues.filter(|q| *q > 1) {
+            args.insert("mq".to_string(), Value::from("on"));
+            args.insert("vectors".to_string(), Value::from(2 * x + 2));
+        }
+        if let Some(x) = self.disable_modern {
+            args.insert("disable-modern".to_string(), Value::from(x));
+        }
+        if let Some(x) = self.romfile.as_ref() {
+            args.insert("romfile".to_string(), Value::from(x.to_string()));
+        }
+        device_add {
+            driver: self.driver.to_string(),
+            bus: Some(bus_id.to_string()),
+            id: Some(self.device_id()),
+            arguments: args,
+        }
+    }
+
+    fn to_netdev_del(&self) -> NetdevDel {
+        NetdevDel {
+            id: self.id.to_string(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -171,6 +281,7 @@ mod tests {
             disable_modern: None,
             romfile: None,
             queues: None,
+            fd_names: vec![],
             #[cfg(feature = "virtcca")]
             disable_legacy: true,
             #[cfg(feature = "virtcca")]
@@ -189,4 +300,40 @@ mod tests {
             .iter()
             .any(|x| x == "virtio-net-pci,netdev=net1,vectors=8,mac=a1:b2:c3:d5:f4,mq=on,disable-legacy=on,iommu_platform=on"));
     }
+
+    #[test]
+    fn test_hot_attach_params() {
+        let mut device = VirtioNetDevice::new(
+            "intf-2",
+            Some("tap_kua_2".to_string()),
+            "a1:b2:c3:d5:f4",
+            Transport::Pci,
+            vec![],
+            vec![],
+        );
+        device.queues = Some(2);
+        let netdev_add = device.to_netdev_add();
+        assert_eq!(netdev_add.r#type, "tap");
+        assert_eq!(netdev_add.id, "intf-2");
+        assert_eq!(netdev_add.arguments.get("ifname").unwrap(), "tap_kua_2");
+        assert_eq!(netdev_add.arguments.get("queues").unwrap(), 2);
+
+        let device_add = device.to_device_add("pci-bridge-0", 3);
+        assert_eq!(device_add.driver, "virtio-net-pci");
+        assert_eq!(device_add.bus.as_deref(), Some("pci-bridge-0"));
+        assert_eq!(device_add.id.as_deref(), Some("virtio-intf-2"));
+        assert_eq!(device_add.arguments.get("netdev").unwrap(), "intf-2");
+        assert_eq!(device_add.arguments.get("addr").unwrap(), "03");
+        assert_eq!(device_add.arguments.get("vectors").unwrap(), 6);
+
+        device.fd_names = vec!["fd-intf-2-0".to_string(), "fd-intf-2-1".to_string()];
+        let netdev_add = device.to_netdev_add();
+        assert_eq!(
+            netdev_add.arguments.get("fds").unwrap(),
+            "fd-intf-2-0:fd-intf-2-1"
+        );
+        assert!(netdev_add.arguments.get("ifname").is_none());
+        assert!(netdev_add.arguments.get("queues").is_none());
+        assert!(netdev_add.arguments.get("script").is_none());
+    }
 }
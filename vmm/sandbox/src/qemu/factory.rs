@@ -14,8 +14,15 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::path::Path;
+
 use async_trait::async_trait;
-use containerd_sandbox::{error::Error, SandboxOption};
+use containerd_sandbox::{
+    data::SandboxData,
+    error::{Error, Result},
+    SandboxOption,
+};
+use log::{debug, warn};
 use tokio::fs::create_dir_all;
 use uuid::Uuid;
 use vmm_common::SHARED_DIR_SUFFIX;
@@ -23,26 +30,32 @@ use vmm_common::SHARED_DIR_SUFFIX;
 use crate::{
     device::Transport,
     qemu::{
-        config::{QemuVMConfig, QmpSocket},
+        config::{QemuVMConfig, QmpSocket, MACHINE_TYPE_MICROVM_PCI},
         devices::{
             block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
             char::{CharDevice, VIRT_CONSOLE_DRIVER, VIRT_SERIAL_PORT_DRIVER},
             create_bridges,
+            pvpanic::PvpanicDevice,
             scsi::ScsiController,
             serial::SerialBridge,
-            vhost_user::{VhostCharDevice, VhostUserType},
+            vhost_user::{VhostCharDevice, VhostUserType, VHOST_USER_RECONNECT_IN_SEC},
             virtio_9p::Virtio9PDevice,
             virtio_rng::VirtioRngDevice,
             vsock::{find_context_id, VSockDevice},
         },
+        template::{QemuTemplate, DEFAULT_TEMPLATE_PATH},
         QemuVM,
     },
+    template::TemplateStore,
     utils::get_netns,
-    vm::{BlockDriver, ShareFsType, VMFactory},
+    virtiofsd::VirtiofsDaemon,
+    vm::{BlockDriver, ShareFsType, VMFactory, VM},
 };
 
+#[derive(Clone)]
 pub struct QemuVMFactory {
     default_config: QemuVMConfig,
+    templates: TemplateStore<QemuTemplate>,
 }
 
 #[async_trait]
@@ -51,8 +64,14 @@ impl VMFactory for QemuVMFactory {
     type Config = QemuVMConfig;
 
     fn new(config: Self::Config) -> Self {
+        let root = if config.file_backend_mem_path.is_empty() {
+            DEFAULT_TEMPLATE_PATH.to_string()
+        } else {
+            config.file_backend_mem_path.to_string()
+        };
         Self {
             default_config: config,
+            templates: TemplateStore::new(&root),
         }
     }
 
@@ -62,11 +81,33 @@ impl VMFactory for QemuVMFactory {
         s: &SandboxOption,
     ) -> containerd_sandbox::error::Result<Self::VM> {
         let netns = get_netns(&s.sandbox);
-        let mut vm = QemuVM::new(id, &netns, &s.base_dir);
+        let mut vm = self.new_vm(id, s).await?;
+        // network devices of the vm restored from template can only be hot attached
+        if self.default_config.enable_template && (netns.is_empty() || vm.support_network_hotplug())
+        {
+            let factory = self.clone();
+            vm.template = self
+                .templates
+                .acquire(id, &s.base_dir, move |dir| async move {
+                    factory.create_template(&dir).await
+                })
+                .await;
+            if vm.template.is_none() {
+                debug!("vm template is not ready, boot vm {} without it", id);
+            }
+        }
+        Ok(vm)
+    }
+}
+
+impl QemuVMFactory {
+    async fn new_vm(&self, id: &str, s: &SandboxOption) -> Result<QemuVM> {
+        let base_dir = s.base_dir.as_str();
+        let mut vm = QemuVM::new(id, &get_netns(&s.sandbox), base_dir);
         vm.config = self.default_config.to_qemu_config().await?;
         vm.config.uuid = Uuid::new_v4().to_string();
         vm.config.name = format!("sandbox-{}", id);
-        vm.config.pid_file = format!("{}/sandbox-{}.pid", s.base_dir, id);
+        vm.config.pid_file = format!("{}/sandbox-{}.pid", base_dir, id);
         vm.block_driver = self.default_config.block_device_driver.clone();
 
         // set qmp socket
@@ -119,6 +160,11 @@ impl VMFactory for QemuVMFactory {
             vm.attach_device(rng_device);
         }
 
+        // set pvpanic device
+        if self.default_config.common.enable_pvpanic {
+            vm.attach_device(PvpanicDevice::new("pvpanic0"));
+        }
+
         // set vsock or serial port as the rpc channel to agent
         if self.default_config.use_vsock {
             let (fd, cid) = find_context_id().await?;
@@ -127,7 +173,7 @@ impl VMFactory for QemuVMFactory {
             vm.attach_device(vsock_device);
             vm.agent_socket = format!("vsock://{}:1024", cid);
         } else {
-            let socket = format!("{}/agent.sock", s.base_dir);
+            let socket = format!("{}/agent.sock", base_dir);
             let agent_sock = CharDevice::new_socket(
                 "channel0",
                 "charch0",
@@ -140,7 +186,7 @@ impl VMFactory for QemuVMFactory {
         }
 
         // share fs
-        let share_fs_path = format!("{}/{}", s.base_dir, SHARED_DIR_SUFFIX);
+        let share_fs_path = format!("{}/{}", base_dir, SHARED_DIR_SUFFIX);
         create_dir_all(&*share_fs_path).await?;
         match self.default_config.share_fs {
             ShareFsType::Virtio9P => {
@@ -163,30 +209,24 @@ impl VMFactory for QemuVMFactory {
                 vm.attach_device(virtio_9p);
             }
             ShareFsType::VirtioFS => {
-                let cfg = self.default_config.virtiofsd.clone();
-                match cfg {
-                    Some(value) => {
-                        let mut virtiofs = value;
-                        virtiofs.socket_path = format!("{}/virtiofs.sock", &s.base_dir);
-                        virtiofs.shared_dir = format!("{}/{}", &s.base_dir, SHARED_DIR_SUFFIX);
-                        vm.virtiofsd_config = Some(virtiofs);
-                    }
+                let virtiofs_daemon = match &self.default_config.virtiofsd {
+                    Some(cfg) => VirtiofsDaemon::new(id, cfg, s, true),
                     None => {
                         return Err(Error::Unimplemented(
                             "virtiofs can not start without virtiofsd config".to_string(),
                         ));
                     }
-                }
-                let virtiofsd = vm.virtiofsd_config.clone().unwrap();
-                if !virtiofsd.socket_path.is_empty() {
-                    let virtio_fs = VhostCharDevice::new(
-                        "extra-fs-kuasar",
-                        VhostUserType::VhostUserChar("vhost-user-fs-pci".to_string()),
-                        &virtiofsd.socket_path,
-                        "",
-                    );
-                    vm.attach_device(virtio_fs);
-                }
+                };
+                let mut virtio_fs = VhostCharDevice::new(
+                    "extra-fs-kuasar",
+                    VhostUserType::VhostUserChar("vhost-user-fs-pci".to_string()),
+                    virtiofs_daemon.socket_path(),
+                    "",
+                );
+                // qemu reconnects to the virtiofsd restarted on the same socket after it crashes
+                virtio_fs.reconnect = Some(VHOST_USER_RECONNECT_IN_SEC);
+                vm.attach_device(virtio_fs);
+                vm.virtiofs_daemon = Some(virtiofs_daemon);
             }
         }
         if !self.default_config.common.image_path.is_empty() {
@@ -208,4 +248,36 @@ impl VMFactory for QemuVMFactory {
         }
         Ok(vm)
     }
+
+    // create_template boots a template vm in the dir and saves it as a new version of the template.
+    async fn create_template(&self, base_dir: &str) -> Result<QemuTemplate> {
+        // 9p is not mounted when the template is saved, but a vhost-user-fs device is never migratable
+        if let ShareFsType::VirtioFS = self.default_config.share_fs {
+            return Err(Error::Unimplemented(
+                "vm template with virtiofs".to_string(),
+            ));
+        }
+        // the memory backend file is only used when numa is enabled
+        if self.default_config.machine_type == MACHINE_TYPE_MICROVM_PCI {
+            return Err(Error::Unimplemented(format!(
+                "vm template with machine type {}",
+                MACHINE_TYPE_MICROVM_PCI
+            )));
+        }
+
+        let id = Path::new(base_dir)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(format!("template-{}", Uuid::new_v4().simple()));
+        let option = SandboxOption::new(base_dir.to_string(), SandboxData::default());
+        let mut vm = self.new_vm(&id, &option).await?;
+        let template = QemuTemplate::new(base_dir, &vm.config);
+        template.apply_template(&mut vm.config);
+
+        let res = vm.save_template(&template).await;
+        if let Err(e) = vm.stop(true).await {
+            warn!("failed to stop template vm {}: {}", id, e);
+        }
+        res.map(|_| template)
+    }
 }
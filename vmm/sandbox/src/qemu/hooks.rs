@@ -18,9 +18,10 @@ use async_trait::async_trait;
 use containerd_sandbox::error::Result;
 
 use crate::{
+    client::{client_online_cpu_mem, client_reseed_random},
     qemu::{config::QemuVMConfig, QemuVM},
     sandbox::KuasarSandbox,
-    utils::get_resources,
+    utils::{get_resources, get_vcpus},
     vm::Hooks,
 };
 
@@ -45,6 +46,14 @@ impl Hooks<QemuVM> for QemuHooks {
 
     async fn post_start(&self, sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
         sandbox.data.task_address = format!("ttrpc+{}", sandbox.vm.agent_socket);
+        if sandbox.vm.is_from_template() {
+            if let Some(client) = &*sandbox.client.lock().await {
+                // online the vcpus hot plugged after restored
+                client_online_cpu_mem(client, get_vcpus(&sandbox.data)).await?;
+                // vms restored from the same template have the same random pool
+                client_reseed_random(client).await?;
+            }
+        }
         // sync clock
         sandbox.sync_clock().await;
         Ok(())
@@ -59,9 +68,15 @@ async fn process_config(sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
     if let Some(resources) = get_resources(&sandbox.data) {
         if resources.cpu_period > 0 && resources.cpu_quota > 0 {
             // get ceil of cpus if it is not integer
-            let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
-            sandbox.vm.config.smp.cpus = base as u32;
-            sandbox.vm.config.smp.max_cpus = base as u32;
+            let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil() as u32;
+            // keep the max cpus from default_max_vcpus so that vcpus can be hot plugged later
+            let smp = &mut sandbox.vm.config.smp;
+            let max_cpus = smp.max_cpus.max(base);
+            // max cpus should be the product of sockets, cores and threads of the topology
+            let cpus_per_socket = smp.cores.max(1) * smp.threads.max(1);
+            smp.cpus = base;
+            smp.sockets = max_cpus.div_ceil(cpus_per_socket);
+            smp.max_cpus = smp.sockets * cpus_per_socket;
         }
         if resources.memory_limit_in_bytes > 0 {
             sandbox.vm.config.memory.size = format!(
@@ -20,7 +20,6 @@ use std::{
         fd::OwnedFd,
         unix::io::{AsRawFd, FromRawFd, RawFd},
     },
-    process::Stdio,
     time::{Duration, SystemTime},
 };
 
@@ -30,24 +29,31 @@ use containerd_sandbox::error::{Error, Result};
 use futures_util::TryFutureExt;
 use log::{debug, error, trace, warn};
 use nix::{fcntl::OFlag, libc::kill, sys::stat::Mode};
-use qapi::qmp::quit;
+use qapi::{
+    qmp::{cont, device_add, quit, stop},
+    Dictionary,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 use tokio::{
     net::UnixStream,
-    process::Child,
-    sync::watch::{channel, Receiver, Sender},
-    task::{spawn_blocking, JoinHandle},
+    sync::{
+        mpsc,
+        watch::{channel, Receiver},
+    },
+    task::spawn_blocking,
     time::sleep,
 };
 use unshare::Fd;
 
 use crate::{
+    crash::{read_console, ConsoleTail},
     device::{BusType, DeviceInfo, SlotStatus, Transport},
     impl_recoverable,
     param::ToCmdLineParams,
     qemu::{
-        config::{QemuConfig, VirtiofsdConfig},
+        config::{Incoming, MemoryBackend, QemuConfig},
         devices::{
             block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
             char::{CharDevice, VIRT_SERIAL_PORT_DRIVER},
@@ -56,21 +62,34 @@ use crate::{
             virtio_net::VirtioNetDevice,
             QemuDevice, QemuHotAttachable,
         },
+        qmp::{BlockResize, Migrate, MigrateIncoming, ObjectAdd, ObjectDel, QueryHotpluggableCpus},
         qmp_client::QmpClient,
-        utils::detect_pid,
+        template::QemuTemplate,
+        utils::{detect_pid, parse_memory_size_in_mb},
     },
-    utils::{read_std, set_cmd_netns, wait_channel, wait_pid, write_file_atomic},
-    vm::{BlockDriver, Pids, VcpuThreads, VM},
+    template::release_template,
+    utils::{read_std, wait_channel, wait_pid},
+    virtiofsd::VirtiofsDaemon,
+    vm::{BlockDriver, Pids, VMEvent, VcpuThreads, VM},
 };
 
 pub mod config;
 mod devices;
 pub mod factory;
 pub mod hooks;
+mod qmp;
 mod qmp_client;
+pub mod template;
 mod utils;
 
 pub(crate) const QEMU_START_TIMEOUT_IN_SEC: u64 = 10;
+const QEMU_MIGRATION_TIMEOUT_IN_SEC: u64 = 600;
+const CHECKPOINT_STATE_FILE: &str = "state";
+// the vcpus and memory hot plugged before checkpoint, the restored vm should have them too
+const CHECKPOINT_HOTPLUG_FILE: &str = "hotplug.json";
+// hot plugged memory should be aligned to the memory block size of the guest kernel
+const MEMORY_HOTPLUG_ALIGN_IN_MB: u64 = 128;
+const PC_DIMM_DRIVER: &str = "pc-dimm";
 
 // restart recovery is not supported yet,
 // so we annotate the QemuVM with Serialize and Deserlize,
@@ -83,6 +102,9 @@ pub struct QemuVM {
     devices: Vec<Box<dyn QemuDevice + Sync + Send>>,
     #[serde(skip)]
     hot_attached_devices: Vec<Box<dyn QemuHotAttachable + Sync + Send>>,
+    // devices detached but not deleted by qemu in time, they keep the slots until deleted
+    #[serde(skip)]
+    removing_devices: Vec<Box<dyn QemuHotAttachable + Sync + Send>>,
     #[serde(skip)]
     fds: Vec<OwnedFd>,
     console_socket: String,
@@ -95,16 +117,65 @@ pub struct QemuVM {
     wait_chan: Option<Receiver<(u32, i128)>>,
     #[serde(skip)]
     client: Option<QmpClient>,
-    virtiofsd_config: Option<VirtiofsdConfig>,
+    #[serde(default)]
+    virtiofs_daemon: Option<VirtiofsDaemon>,
+    #[serde(default)]
+    hot_plugged_vcpus: Vec<String>,
+    #[serde(default)]
+    hot_plugged_memory: Vec<HotPluggedMemory>,
+    // index of the next hot plugged dimm, the ids are never reused as the removal is asynchronous
+    #[serde(default)]
+    next_memory_index: usize,
+    #[serde(default)]
+    template: Option<QemuTemplate>,
+    // devices attached before the vm is restored from template, they are hot attached after restored
+    #[serde(skip)]
+    pending_devices: Vec<DeviceInfo>,
+    #[serde(skip)]
+    console_tail: ConsoleTail,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HotPluggedMemory {
+    id: String,
+    backend_id: String,
+    size_in_mb: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HotPluggedResources {
+    vcpus: Vec<String>,
+    memory: Vec<HotPluggedMemory>,
+    next_memory_index: usize,
 }
 
 #[async_trait]
 impl VM for QemuVM {
     async fn start(&mut self) -> Result<u32> {
         debug!("start vm {}", self.id);
-        if self.virtiofsd_config.is_some() {
-            let virtiofsd_pid = self.start_virtiofsd().await?;
-            self.pids.affiliated_pids.push(virtiofsd_pid);
+        let mut desired = None;
+        if let Some(template) = self.template.clone() {
+            if template.fits(self.config.smp.cpus) {
+                // vcpus and memory are hot plugged after restored from the template
+                desired = Some((
+                    self.config.smp.cpus,
+                    parse_memory_size_in_mb(&self.config.memory.size)?,
+                ));
+                template.apply_restore(&mut self.config);
+            } else {
+                warn!(
+                    "{} vcpus of vm {} exceeds the template, boot it without template",
+                    self.config.smp.cpus, self.id
+                );
+                release_template(&template.path, &self.id).await;
+                self.template = None;
+                for device in std::mem::take(&mut self.pending_devices) {
+                    self.attach(device).await?;
+                }
+            }
+        }
+        if let Some(virtiofs_daemon) = self.virtiofs_daemon.as_mut() {
+            virtiofs_daemon.start().await?;
         }
         let wait_chan = self.launch().await?;
         self.wait_chan = Some(wait_chan);
@@ -127,10 +198,11 @@ impl VM for QemuVM {
         }
 
         let console_socket = self.console_socket.clone();
+        let console_tail = self.console_tail.clone();
         tokio::spawn(async move {
             UnixStream::connect(&*console_socket)
                 .map_err(|e| e.into())
-                .and_then(|s| read_std(s, "console"))
+                .and_then(|s| read_console(s, console_tail))
                 .await
                 .unwrap_or_else(|e| {
                     error!("failed to read console log, {}", e);
@@ -139,10 +211,25 @@ impl VM for QemuVM {
         // update vmm related pids
         let vmm_pid = detect_pid(self.config.pid_file.as_str(), self.config.path.as_str()).await?;
         self.pids.vmm_pid = Some(vmm_pid);
+        if let Some(virtiofs_daemon) = &self.virtiofs_daemon {
+            virtiofs_daemon.set_vmm_pid(vmm_pid);
+        }
+
+        if let (Some(template), Some((vcpus, memory_in_mb))) = (self.template.clone(), desired) {
+            self.restore_from_template(&template).await?;
+            self.resize(vcpus, memory_in_mb).await?;
+        }
         Ok(0)
     }
 
     async fn stop(&mut self, force: bool) -> Result<()> {
+        if let Some(template) = &self.template {
+            release_template(&template.path, &self.id).await;
+        }
+        // the virtiofsd exits with the vmm, it should not be taken as crashed
+        if let Some(virtiofs_daemon) = &self.virtiofs_daemon {
+            virtiofs_daemon.stop_supervising();
+        }
         if !force {
             let client = self.get_client()?;
             client.execute(quit {}).await?;
@@ -162,19 +249,28 @@ impl VM for QemuVM {
                 return Err(e);
             }
         }
+        if let Some(virtiofs_daemon) = &self.virtiofs_daemon {
+            virtiofs_daemon.stop();
+        }
 
         Ok(())
     }
 
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
+        // the devices of the vm restored from template should be the same as the template
+        if self.template.is_some() {
+            self.pending_devices.push(device_info);
+            return Ok(());
+        }
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                let mut device = VirtioBlockDevice::new(
                     &Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
                     &blk_info.id,
                     Some(blk_info.path),
                     blk_info.read_only,
                 );
+                device.set_throttle(&blk_info.throttle);
                 self.attach_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
@@ -223,15 +319,29 @@ impl VM for QemuVM {
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                let mut device = VirtioBlockDevice::new(
                     "",
                     &blk_info.id,
                     Some(blk_info.path),
                     blk_info.read_only,
                 );
+                device.set_throttle(&blk_info.throttle);
+                let throttle = device.to_io_throttle();
                 let (bus_addr, index) = self
                     .hot_attach_device(device, self.block_driver.to_bus_type())
                     .await?;
+                if !blk_info.throttle.is_empty() {
+                    let res = self.get_client()?.execute(throttle).await;
+                    if let Err(e) = res {
+                        if let Err(de) = self.hot_detach(&blk_info.id).await {
+                            warn!(
+                                "failed to detach {} after throttle failed: {}",
+                                blk_info.id, de
+                            );
+                        }
+                        return Err(e);
+                    }
+                }
                 let addr = match self.block_driver {
                     BlockDriver::VirtioBlk => {
                         format!("0000:{}:{:02x}.0", bus_addr, index)
@@ -247,15 +357,60 @@ impl VM for QemuVM {
                 };
                 Ok((self.block_driver.to_bus_type(), addr))
             }
-            DeviceInfo::Tap(_tap_info) => Err(Error::Unimplemented(
-                "hot attach for tap device".to_string(),
-            )),
-            DeviceInfo::Physical(_vfio_info) => Err(Error::Unimplemented(
-                "hot attach for vfio device".to_string(),
-            )),
-            DeviceInfo::VhostUser(_vhost_user_info) => Err(Error::Unimplemented(
-                "hot attach for vhost_user device".to_string(),
-            )),
+            DeviceInfo::Tap(tap_info) => {
+                let mut device = VirtioNetDevice::new(
+                    &tap_info.id,
+                    Some(tap_info.name),
+                    &tap_info.mac_address,
+                    Transport::Pci,
+                    vec![],
+                    vec![],
+                );
+                // each of the fds opened by the sandboxer is a queue of the tap, they are
+                // passed to qemu by name as qemu may not be able to open the tap itself.
+                device.queues = Some(tap_info.fds.len().max(1) as i32);
+                let client = self.get_client()?;
+                for (i, fd) in tap_info.fds.iter().enumerate() {
+                    let name = format!("fd-{}-{}", tap_info.id, i);
+                    let res = client.send_fd(&name, fd.as_raw_fd()).await;
+                    if let Err(e) = res {
+                        for n in &device.fd_names {
+                            client.close_fd(n).await;
+                        }
+                        return Err(e);
+                    }
+                    device.fd_names.push(name);
+                }
+                let fd_names = device.fd_names.clone();
+                match self.hot_attach_device(device, BusType::PCI).await {
+                    Ok((bus_addr, index)) => {
+                        Ok((BusType::PCI, format!("0000:{}:{:02x}.0", bus_addr, index)))
+                    }
+                    Err(e) => {
+                        // the fds not taken by the netdev are left in qemu
+                        let client = self.get_client()?;
+                        for n in &fd_names {
+                            client.close_fd(n).await;
+                        }
+                        Err(e)
+                    }
+                }
+            }
+            DeviceInfo::Physical(vfio_info) => {
+                let device = VfioDevice::new(&vfio_info.id, &vfio_info.bdf);
+                let (bus_addr, index) = self.hot_attach_device(device, BusType::PCI).await?;
+                Ok((BusType::PCI, format!("0000:{}:{:02x}.0", bus_addr, index)))
+            }
+            DeviceInfo::VhostUser(vhost_user_info) => {
+                let device = VhostNetDevice::new(
+                    &vhost_user_info.id,
+                    VhostUserType::VhostUserNet(vhost_user_info.r#type),
+                    &vhost_user_info.socket_path,
+                    &vhost_user_info.mac_address,
+                );
+                let (bus_addr, index) = self.hot_attach_device(device, BusType::PCI).await?;
+                Ok((BusType::PCI, format!("0000:{}:{:02x}.0", bus_addr, index)))
+            }
             DeviceInfo::Char(char_info) => {
                 let device = CharDevice::new_with_backend_type(
                     char_info.backend.clone(),
@@ -272,6 +427,10 @@ impl VM for QemuVM {
     }
 
     async fn hot_detach(&mut self, id: &str) -> Result<()> {
+        self.release_deleted_devices().await;
+        if self.removing_devices.iter().any(|x| x.id() == id) {
+            return Err(anyhow!("device {} is still being deleted", id).into());
+        }
         let index = self.hot_attached_devices.iter().position(|x| x.id() == id);
         let device = match index {
             None => {
@@ -289,15 +448,136 @@ impl VM for QemuVM {
             }
         };
 
-        if let Err(e) = device.execute_hot_detach(client).await {
-            // rollback, add it back to the list
-            self.hot_attached_devices.push(device);
-            return Err(e);
+        debug!("hot detach device {} from vm {}", id, self.id);
+        match client.try_delete_device(&device.device_id()).await {
+            Ok(true) => {}
+            Ok(false) => {
+                // device_del can not be cancelled, so the device is released after it is deleted
+                warn!(
+                    "device {} of vm {} is not deleted in time, release it later",
+                    id, self.id
+                );
+                self.removing_devices.push(device);
+                return Err(anyhow!("timeout waiting for device {} to be deleted", id).into());
+            }
+            Err(e) => {
+                // rollback, add it back to the list
+                self.hot_attached_devices.push(device);
+                return Err(e);
+            }
         }
+        let res = device.execute_hot_release(client).await;
         self.detach_from_bus(id);
+        res
+    }
+
+    async fn resize(&mut self, vcpus: u32, memory_in_mb: u64) -> Result<()> {
+        if vcpus > 0 {
+            self.resize_vcpus(vcpus).await?;
+        }
+        if memory_in_mb > 0 {
+            self.resize_memory(memory_in_mb).await?;
+        }
+        Ok(())
+    }
+
+    async fn resize_block(&mut self, id: &str, size: u64) -> Result<()> {
+        // the node name of the blockdev is the id of the device
+        self.get_client()?
+            .execute(BlockResize {
+                node_name: id.to_string(),
+                size: size as i64,
+            })
+            .await?;
         Ok(())
     }
 
+    // The vm is paused while the device state and memory are migrated to the checkpoint file.
+    // The hot plugged vcpus and memory are recorded and plugged again before the migration of
+    // the restored vm, but the hot attached devices are not supported, as their backends,
+    // such as the block devices of storages and the taps, are not recreated by the restore.
+    async fn checkpoint(&mut self, dir: &str) -> Result<()> {
+        let devices = self.unrestorable_devices();
+        if !devices.is_empty() {
+            return Err(Error::Unimplemented(format!(
+                "checkpoint for qemu vm with hot attached devices {:?}",
+                devices
+            )));
+        }
+        let resources = HotPluggedResources {
+            vcpus: self.hot_plugged_vcpus.clone(),
+            memory: self.hot_plugged_memory.clone(),
+            next_memory_index: self.next_memory_index,
+        };
+        let path = format!("{}/{}", dir, CHECKPOINT_HOTPLUG_FILE);
+        let content = serde_json::to_vec(&resources)
+            .map_err(|e| anyhow!("failed to serialize hot plugged resources: {}", e))?;
+        tokio::fs::write(&path, content)
+            .await
+            .map_err(|e| anyhow!("failed to write {}: {}", path, e))?;
+
+        let client = self.get_client()?;
+        client.execute(stop {}).await?;
+        let res = match client
+            .execute(Migrate {
+                uri: format!("exec:cat>{}/{}", dir, CHECKPOINT_STATE_FILE),
+            })
+            .await
+        {
+            Ok(_) => {
+                client
+                    .wait_migration(Duration::from_secs(QEMU_MIGRATION_TIMEOUT_IN_SEC))
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        client.execute(cont {}).await?;
+        res
+    }
+
+    async fn restore(&mut self, dir: &str) -> Result<u32> {
+        // the vm is restored with the devices of the checkpoint rather than the template
+        if let Some(template) = self.template.take() {
+            release_template(&template.path, &self.id).await;
+            for device in std::mem::take(&mut self.pending_devices) {
+                self.attach(device).await?;
+            }
+        }
+        self.config.incoming = Some(Incoming::default());
+        let pid = self.start().await?;
+        self.replug_resources(dir).await?;
+        let client = self.get_client()?;
+        client
+            .execute(MigrateIncoming {
+                uri: format!("exec:cat {}/{}", dir, CHECKPOINT_STATE_FILE),
+            })
+            .await?;
+        client
+            .wait_migration(Duration::from_secs(QEMU_MIGRATION_TIMEOUT_IN_SEC))
+            .await?;
+        // the run state is migrated, so the vm is paused as it is paused before checkpoint
+        client.execute(cont {}).await?;
+        Ok(pid)
+    }
+
+    // the devices hot attached are not added again before the incoming migration
+    fn unrestorable_devices(&self) -> Vec<String> {
+        self.hot_attached_devices
+            .iter()
+            .chain(self.removing_devices.iter())
+            .map(|d| d.id())
+            .collect()
+    }
+
+    fn support_network_hotplug(&self) -> bool {
+        true
+    }
+
+    // there is no rate limiter of the tap netdev, the tap is shaped by tc
+    fn support_net_rate_limiter(&self) -> bool {
+        false
+    }
+
     async fn ping(&self) -> Result<()> {
         let client = self.get_client()?;
         let _res = client.execute(qapi::qmp::query_status {}).await?;
@@ -321,7 +601,18 @@ impl VM for QemuVM {
 
     fn pids(&self) -> Pids {
         // TODO: support get all vmm related pids
-        Pids::default()
+        let mut pids = Pids::default();
+        pids.affiliated_pids
+            .extend(self.virtiofs_daemon.as_ref().and_then(|d| d.pid()));
+        pids
+    }
+
+    fn take_vm_events(&mut self) -> Option<mpsc::Receiver<VMEvent>> {
+        self.client.as_mut().and_then(|c| c.take_vm_events())
+    }
+
+    fn console_tail(&self) -> String {
+        self.console_tail.content()
     }
 }
 
@@ -332,6 +623,7 @@ impl QemuVM {
             config: QemuConfig::default(),
             devices: vec![],
             hot_attached_devices: vec![],
+            removing_devices: vec![],
             fds: vec![],
             console_socket: format!("{}/console.sock", base_dir),
             agent_socket: "".to_string(),
@@ -340,7 +632,13 @@ impl QemuVM {
             block_driver: BlockDriver::default(),
             wait_chan: None,
             client: None,
-            virtiofsd_config: None,
+            virtiofs_daemon: None,
+            hot_plugged_vcpus: vec![],
+            hot_plugged_memory: vec![],
+            next_memory_index: 0,
+            template: None,
+            pending_devices: vec![],
+            console_tail: ConsoleTail::default(),
         }
     }
 
@@ -460,32 +758,12 @@ impl QemuVM {
         }
     }
 
-    async fn start_virtiofsd(&self) -> Result<u32> {
-        //create_dir_all(&self.virtiofsd_config.shared_dir).await?;
-        let virtiofsd_config = self.virtiofsd_config.clone().unwrap();
-        let params = virtiofsd_config.to_cmdline_params("--");
-        let mut cmd = tokio::process::Command::new(&virtiofsd_config.path);
-        cmd.args(params.as_slice());
-        debug!("start virtiofsd with cmdline: {:?}", cmd);
-        set_cmd_netns(&mut cmd, self.netns.to_string())?;
-        cmd.stderr(Stdio::piped());
-        cmd.stdout(Stdio::piped());
-        let child = cmd
-            .spawn()
-            .map_err(|e| anyhow!("failed to spawn virtiofsd command: {}", e))?;
-        let pid = child
-            .id()
-            .ok_or(anyhow!("the virtiofsd has been polled to completion"))?;
-        warn!("virtiofsd for {} is running with pid {}", self.id, pid);
-        spawn_wait(child, format!("virtiofsd {}", self.id), None, None);
-        Ok(pid)
-    }
-
     async fn hot_attach_device<T: QemuHotAttachable + Sync + Send + 'static>(
         &mut self,
         device: T,
         bus_type: BusType,
     ) -> Result<(String, usize)> {
+        self.release_deleted_devices().await;
         let (bus_id, index) = self.empty_slot(bus_type.clone())?;
         let client = self
             .client
@@ -505,6 +783,207 @@ impl QemuVM {
         Ok((bus_addr, index))
     }
 
+    // Only the hot plugged vcpus can be unplugged, so the vcpus are never less than the boot vcpus.
+    async fn resize_vcpus(&mut self, vcpus: u32) -> Result<()> {
+        let boot_vcpus = self.config.smp.cpus;
+        let max_vcpus = self.config.smp.max_cpus.max(boot_vcpus);
+        if vcpus > max_vcpus {
+            return Err(Error::InvalidArgument(format!(
+                "{} vcpus exceeds the max vcpus {}",
+                vcpus, max_vcpus
+            )));
+        }
+        let target = if vcpus < boot_vcpus {
+            warn!(
+                "vm {} can not be resized to {} vcpus, less than the boot vcpus {}",
+                self.id, vcpus, boot_vcpus
+            );
+            boot_vcpus
+        } else {
+            vcpus
+        };
+
+        let mut current = boot_vcpus + self.hot_plugged_vcpus.len() as u32;
+        if target > current {
+            let cpus = self.get_client()?.execute(QueryHotpluggableCpus {}).await?;
+            for cpu in cpus.iter().filter(|c| c.qom_path.is_none()) {
+                if current >= target {
+                    break;
+                }
+                debug!("hot plug vcpu {} into vm {}", cpu.id(), self.id);
+                self.get_client()?.execute(cpu.to_device_add()).await?;
+                self.hot_plugged_vcpus.push(cpu.id());
+                current += 1;
+            }
+            if current < target {
+                return Err(Error::ResourceExhausted(format!(
+                    "hot pluggable vcpus, only {} of {} vcpus are plugged",
+                    current, target
+                )));
+            }
+        }
+        while current > target {
+            let id = match self.hot_plugged_vcpus.pop() {
+                None => break,
+                Some(id) => id,
+            };
+            debug!("hot unplug vcpu {} from vm {}", id, self.id);
+            if let Err(e) = self.get_client()?.delete_device(&id).await {
+                self.hot_plugged_vcpus.push(id);
+                return Err(e);
+            }
+            current -= 1;
+        }
+        Ok(())
+    }
+
+    // Memory is hot plugged as pc-dimm devices, and only the hot plugged dimms can be unplugged.
+    async fn resize_memory(&mut self, memory_in_mb: u64) -> Result<()> {
+        let boot_memory = parse_memory_size_in_mb(&self.config.memory.size)?;
+        let mut current = boot_memory
+            + self
+                .hot_plugged_memory
+                .iter()
+                .map(|m| m.size_in_mb)
+                .sum::<u64>();
+        if memory_in_mb > current {
+            let size_in_mb = (memory_in_mb - current).div_ceil(MEMORY_HOTPLUG_ALIGN_IN_MB)
+                * MEMORY_HOTPLUG_ALIGN_IN_MB;
+            if self.hot_plugged_memory.len() >= self.config.memory.slots as usize {
+                return Err(Error::ResourceExhausted(format!(
+                    "memory slots of vm {}, all the {} slots are used",
+                    self.id, self.config.memory.slots
+                )));
+            }
+            let index = self.next_memory_index;
+            self.next_memory_index += 1;
+            let memory = HotPluggedMemory {
+                id: format!("dimm-hp{}", index),
+                backend_id: format!("mem-hp{}", index),
+                size_in_mb,
+            };
+            debug!(
+                "hot plug memory {} of {}M into vm {}",
+                memory.id, size_in_mb, self.id
+            );
+            self.plug_memory(&memory).await?;
+            self.hot_plugged_memory.push(memory);
+            return Ok(());
+        }
+
+        while let Some(memory) = self.hot_plugged_memory.last().cloned() {
+            if current - memory.size_in_mb < memory_in_mb {
+                break;
+            }
+            debug!("hot unplug memory {} from vm {}", memory.id, self.id);
+            let client = self.get_client()?;
+            client.delete_device(&memory.id).await?;
+            client
+                .execute(ObjectDel {
+                    id: memory.backend_id.clone(),
+                })
+                .await?;
+            self.hot_plugged_memory.pop();
+            current -= memory.size_in_mb;
+        }
+        if current != memory_in_mb {
+            warn!(
+                "memory of vm {} is {}M after resized to {}M",
+                self.id, current, memory_in_mb
+            );
+        }
+        Ok(())
+    }
+
+    async fn plug_memory(&self, memory: &HotPluggedMemory) -> Result<()> {
+        let client = self.get_client()?;
+        client.execute(self.memory_backend_add(memory)).await?;
+        let mut args = Dictionary::new();
+        args.insert("memdev".to_string(), Value::from(memory.backend_id.clone()));
+        if let Err(e) = client
+            .execute(device_add {
+                driver: PC_DIMM_DRIVER.to_string(),
+                bus: None,
+                id: Some(memory.id.clone()),
+                arguments: args,
+            })
+            .await
+        {
+            client
+                .execute(ObjectDel {
+                    id: memory.backend_id.clone(),
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    error!(
+                        "failed to delete memory backend after device_add failed, {:?}",
+                        e
+                    );
+                    qapi::Empty {}
+                });
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // replug_resources plugs the vcpus and memory hot plugged before the checkpoint into the vm
+    // waiting for the incoming migration, so that the devices are the same as the checkpoint.
+    async fn replug_resources(&mut self, dir: &str) -> Result<()> {
+        let path = format!("{}/{}", dir, CHECKPOINT_HOTPLUG_FILE);
+        let content = match tokio::fs::read(&path).await {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(anyhow!("failed to read {}: {}", path, e).into()),
+        };
+        let resources: HotPluggedResources = serde_json::from_slice(&content)
+            .map_err(|e| anyhow!("failed to parse {}: {}", path, e))?;
+
+        if !resources.vcpus.is_empty() {
+            let cpus = self.get_client()?.execute(QueryHotpluggableCpus {}).await?;
+            for id in resources.vcpus {
+                let cpu = cpus
+                    .iter()
+                    .find(|c| c.qom_path.is_none() && c.id() == id)
+                    .ok_or_else(|| Error::NotFound(format!("hot pluggable vcpu {}", id)))?;
+                self.get_client()?.execute(cpu.to_device_add()).await?;
+                self.hot_plugged_vcpus.push(id);
+            }
+        }
+        for memory in resources.memory {
+            self.plug_memory(&memory).await?;
+            self.hot_plugged_memory.push(memory);
+        }
+        self.next_memory_index = resources.next_memory_index;
+        Ok(())
+    }
+
+    fn memory_backend_add(&self, memory: &HotPluggedMemory) -> ObjectAdd {
+        let mut args = Dictionary::new();
+        args.insert(
+            "size".to_string(),
+            Value::from(memory.size_in_mb * bytefmt::MIB),
+        );
+        args.insert("share".to_string(), Value::from(self.config.memory.shared));
+        args.insert(
+            "prealloc".to_string(),
+            Value::from(self.config.memory.pre_alloc),
+        );
+        let qom_type = match &self.config.memory.backend_type {
+            // the memory file of the template should never be used by the hot plugged memory
+            _ if self.template.is_some() => "memory-backend-ram",
+            MemoryBackend::Ram => "memory-backend-ram",
+            MemoryBackend::File(path) => {
+                args.insert("mem-path".to_string(), Value::from(path.to_string()));
+                "memory-backend-file"
+            }
+        };
+        ObjectAdd {
+            qom_type: qom_type.to_string(),
+            id: memory.backend_id.to_string(),
+            arguments: args,
+        }
+    }
+
     fn empty_slot(&mut self, bus_type: BusType) -> Result<(String, usize)> {
         for b in self
             .devices
@@ -537,6 +1016,36 @@ impl QemuVM {
         Ok((bus.bus_addr.to_string(), index))
     }
 
+    // release_deleted_devices releases the devices deleted by qemu after the timeout of hot detach,
+    // and frees their slots, so that the slots are never reused before the devices are gone.
+    async fn release_deleted_devices(&mut self) {
+        let client = match self.client.as_ref() {
+            Some(c) => c,
+            None => return,
+        };
+        let mut removing = vec![];
+        let mut deleted = vec![];
+        for device in std::mem::take(&mut self.removing_devices) {
+            if !client.is_device_deleted(&device.device_id()).await {
+                removing.push(device);
+                continue;
+            }
+            debug!(
+                "device {} of vm {} is deleted, release it",
+                device.id(),
+                self.id
+            );
+            if let Err(e) = device.execute_hot_release(client).await {
+                warn!("failed to release device {}: {}", device.id(), e);
+            }
+            deleted.push(device.id());
+        }
+        self.removing_devices = removing;
+        for id in deleted {
+            self.detach_from_bus(&id);
+        }
+    }
+
     fn detach_from_bus(&mut self, device_id: &str) {
         self.devices
             .iter_mut()
@@ -556,59 +1065,58 @@ impl QemuVM {
     }
 }
 
-macro_rules! read_stdio {
-    ($stdio:expr, $cmd_name:ident) => {
-        if let Some(std) = $stdio {
-            let cmd_name_clone = $cmd_name.clone();
-            tokio::spawn(async move {
-                read_std(std, &cmd_name_clone).await.unwrap_or_default();
-            });
-        }
+impl_recoverable!(QemuVM);
+
+#[cfg(test)]
+mod tests {
+    use containerd_sandbox::error::Error;
+    use temp_dir::TempDir;
+
+    use crate::{
+        device::Transport,
+        qemu::{
+            devices::block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
+            HotPluggedMemory, HotPluggedResources, QemuVM, CHECKPOINT_HOTPLUG_FILE,
+        },
+        vm::VM,
     };
-}
 
-fn spawn_wait(
-    child: Child,
-    cmd_name: String,
-    pid_file_path: Option<String>,
-    exit_chan: Option<Sender<(u32, i128)>>,
-) -> JoinHandle<()> {
-    let mut child = child;
-    tokio::spawn(async move {
-        if let Some(pid_file) = pid_file_path {
-            if let Some(pid) = child.id() {
-                write_file_atomic(&pid_file, &pid.to_string())
-                    .await
-                    .unwrap_or_default();
-            }
-        }
+    #[tokio::test]
+    async fn test_checkpoint_with_hot_attached_devices() {
+        let tmp = TempDir::new().unwrap();
+        let mut vm = QemuVM::new("vm1", "", tmp.path().to_str().unwrap());
+        vm.hot_attached_devices
+            .push(Box::new(VirtioBlockDevice::new(
+                &Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
+                "blk1",
+                Some("/dev/sdb".to_string()),
+                false,
+            )));
+        assert_eq!(vm.unrestorable_devices(), vec!["blk1".to_string()]);
+        let res = vm.checkpoint(tmp.path().to_str().unwrap()).await;
+        assert!(matches!(res, Err(Error::Unimplemented(_))));
+        assert!(!tmp.child(CHECKPOINT_HOTPLUG_FILE).exists());
+    }
 
-        read_stdio!(child.stdout.take(), cmd_name);
-        read_stdio!(child.stderr.take(), cmd_name);
+    #[tokio::test]
+    async fn test_checkpoint_records_hot_plugged_resources() {
+        let tmp = TempDir::new().unwrap();
+        let mut vm = QemuVM::new("vm1", "", tmp.path().to_str().unwrap());
+        vm.hot_plugged_vcpus = vec!["cpu-1-0-0".to_string()];
+        vm.hot_plugged_memory = vec![HotPluggedMemory {
+            id: "dimm-hp1".to_string(),
+            backend_id: "mem-hp1".to_string(),
+            size_in_mb: 128,
+        }];
+        vm.next_memory_index = 2;
+        // the vm is not started, so the migration fails after the resources are recorded
+        assert!(vm.checkpoint(tmp.path().to_str().unwrap()).await.is_err());
 
-        match child.wait().await {
-            Ok(status) => {
-                if !status.success() {
-                    error!("{} exit {}", cmd_name, status);
-                }
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((
-                        status.code().unwrap_or_default() as u32,
-                        now.unix_timestamp_nanos(),
-                    ))
-                    .unwrap_or_default();
-                }
-            }
-            Err(e) => {
-                error!("{} wait error {}", cmd_name, e);
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((0, now.unix_timestamp_nanos())).unwrap_or_default();
-                }
-            }
-        }
-    })
+        let content = std::fs::read(tmp.child(CHECKPOINT_HOTPLUG_FILE)).unwrap();
+        let resources: HotPluggedResources = serde_json::from_slice(&content).unwrap();
+        assert_eq!(resources.vcpus, vec!["cpu-1-0-0".to_string()]);
+        assert_eq!(resources.memory.len(), 1);
+        assert_eq!(resources.memory[0].id, "dimm-hp1");
+        assert_eq!(resources.next_memory_index, 2);
+    }
 }
-
-impl_recoverable!(QemuVM);
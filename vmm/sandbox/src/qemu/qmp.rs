@@ -0,0 +1,286 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use qapi::{
+    qmp::{device_add, QmpCommand},
+    Dictionary,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryHotpluggableCpus {}
+
+impl QmpCommand for QueryHotpluggableCpus {}
+impl ::qapi_spec::Command for QueryHotpluggableCpus {
+    const NAME: &'static str = "query-hotpluggable-cpus";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = Vec<HotpluggableCpu>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotpluggableCpu {
+    #[serde(rename = "type")]
+    pub r#type: String,
+    #[serde(rename = "vcpus-count")]
+    pub vcpus_count: i64,
+    #[serde(rename = "props")]
+    pub props: CpuInstanceProperties,
+    #[serde(rename = "qom-path", default, skip_serializing_if = "Option::is_none")]
+    pub qom_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CpuInstanceProperties {
+    #[serde(rename = "node-id", default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<i64>,
+    #[serde(rename = "socket-id", default, skip_serializing_if = "Option::is_none")]
+    pub socket_id: Option<i64>,
+    #[serde(rename = "die-id", default, skip_serializing_if = "Option::is_none")]
+    pub die_id: Option<i64>,
+    #[serde(
+        rename = "cluster-id",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cluster_id: Option<i64>,
+    #[serde(rename = "core-id", default, skip_serializing_if = "Option::is_none")]
+    pub core_id: Option<i64>,
+    #[serde(rename = "thread-id", default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<i64>,
+}
+
+impl HotpluggableCpu {
+    pub fn id(&self) -> String {
+        format!(
+            "cpu-{}-{}-{}",
+            self.props.socket_id.unwrap_or_default(),
+            self.props.core_id.unwrap_or_default(),
+            self.props.thread_id.unwrap_or_default()
+        )
+    }
+
+    pub fn to_device_add(&self) -> device_add {
+        let mut args = Dictionary::new();
+        let props = [
+            ("node-id", self.props.node_id),
+            ("socket-id", self.props.socket_id),
+            ("die-id", self.props.die_id),
+            ("cluster-id", self.props.cluster_id),
+            ("core-id", self.props.core_id),
+            ("thread-id", self.props.thread_id),
+        ];
+        for (k, v) in props {
+            if let Some(v) = v {
+                args.insert(k.to_string(), Value::from(v));
+            }
+        }
+        device_add {
+            driver: self.r#type.to_string(),
+            bus: None,
+            id: Some(self.id()),
+            arguments: args,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectAdd {
+    #[serde(rename = "qom-type")]
+    pub qom_type: String,
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(flatten)]
+    pub arguments: Dictionary,
+}
+
+impl QmpCommand for ObjectAdd {}
+impl ::qapi_spec::Command for ObjectAdd {
+    const NAME: &'static str = "object-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectDel {
+    #[serde(rename = "id")]
+    pub id: String,
+}
+
+impl QmpCommand for ObjectDel {}
+impl ::qapi_spec::Command for ObjectDel {
+    const NAME: &'static str = "object-del";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetdevAdd {
+    #[serde(rename = "type")]
+    pub r#type: String,
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(flatten)]
+    pub arguments: Dictionary,
+}
+
+impl QmpCommand for NetdevAdd {}
+impl ::qapi_spec::Command for NetdevAdd {
+    const NAME: &'static str = "netdev_add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetdevDel {
+    #[serde(rename = "id")]
+    pub id: String,
+}
+
+impl QmpCommand for NetdevDel {}
+impl ::qapi_spec::Command for NetdevDel {
+    const NAME: &'static str = "netdev_del";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+// ChardevAdd takes the backend as raw json, as the unix socket backend of vhost-user
+// is not easy to build with the generated qapi types.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChardevAdd {
+    #[serde(rename = "id")]
+    pub id: String,
+    #[serde(rename = "backend")]
+    pub backend: Value,
+}
+
+impl QmpCommand for ChardevAdd {}
+impl ::qapi_spec::Command for ChardevAdd {
+    const NAME: &'static str = "chardev-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+// BlockSetIoThrottle sets the total limits of the device, all the limits are required by qemu,
+// and the read and write limits are 0 as they can not be set with the total ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BlockSetIoThrottle {
+    #[serde(rename = "id")]
+    pub id: String,
+    pub bps: i64,
+    pub bps_rd: i64,
+    pub bps_wr: i64,
+    pub iops: i64,
+    pub iops_rd: i64,
+    pub iops_wr: i64,
+}
+
+impl QmpCommand for BlockSetIoThrottle {}
+impl ::qapi_spec::Command for BlockSetIoThrottle {
+    const NAME: &'static str = "block_set_io_throttle";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockResize {
+    #[serde(rename = "node-name")]
+    pub node_name: String,
+    pub size: i64,
+}
+
+impl QmpCommand for BlockResize {}
+impl ::qapi_spec::Command for BlockResize {
+    const NAME: &'static str = "block_resize";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationCapabilityStatus {
+    pub capability: String,
+    pub state: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateSetCapabilities {
+    pub capabilities: Vec<MigrationCapabilityStatus>,
+}
+
+impl QmpCommand for MigrateSetCapabilities {}
+impl ::qapi_spec::Command for MigrateSetCapabilities {
+    const NAME: &'static str = "migrate-set-capabilities";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Migrate {
+    pub uri: String,
+}
+
+impl QmpCommand for Migrate {}
+impl ::qapi_spec::Command for Migrate {
+    const NAME: &'static str = "migrate";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateIncoming {
+    pub uri: String,
+}
+
+impl QmpCommand for MigrateIncoming {}
+impl ::qapi_spec::Command for MigrateIncoming {
+    const NAME: &'static str = "migrate-incoming";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryMigrate {}
+
+impl QmpCommand for QueryMigrate {}
+impl ::qapi_spec::Command for QueryMigrate {
+    const NAME: &'static str = "query-migrate";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = MigrationInfo;
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(
+        rename = "error-desc",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub error_desc: Option<String>,
+}
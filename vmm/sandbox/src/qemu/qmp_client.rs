@@ -14,28 +14,54 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    io::IoSlice,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::io::{AsRawFd, RawFd},
+    },
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::anyhow;
 use containerd_sandbox::error::Result;
 use futures_util::StreamExt;
-use log::error;
+use log::{error, warn};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags, UnixAddr};
 use qapi::{
     futures::{QapiService, QmpStreamTokio},
-    qmp::{device_del, Event, QmpCommand},
+    qmp::{closefd, device_del, getfd, Event, QmpCommand},
 };
 use tokio::{
     io::WriteHalf,
     net::UnixStream,
     sync::{
+        mpsc,
         oneshot::{channel, Sender},
         Mutex,
     },
+    time::{sleep, timeout},
 };
 
+use crate::{qemu::qmp::QueryMigrate, vm::VMEvent};
+
+// the removal of the device is asynchronous, it is done after the guest releases it
+const DEVICE_DELETED_TIMEOUT_IN_SEC: u64 = 10;
+const VM_EVENT_CHANNEL_SIZE: usize = 16;
+
 pub struct QmpClient {
     qmp: QapiService<QmpStreamTokio<WriteHalf<UnixStream>>>,
+    // a dup of the qmp socket to send fds with, qemu takes the fds received on the
+    // connection by the getfd command executed on the same connection.
+    socket: OwnedFd,
+    fd_lock: Mutex<()>,
     watchers: Arc<Mutex<Vec<QmpEventWatcher>>>,
+    vm_events: Option<mpsc::Receiver<VMEvent>>,
+    // the devices being deleted, with whether the DEVICE_DELETED event of it is received,
+    // the event may come after the timeout of waiting for it if the guest is slow.
+    deleting_devices: Arc<Mutex<HashMap<String, bool>>>,
 }
 
 pub struct QmpEventWatcher {
@@ -45,15 +71,40 @@ pub struct QmpEventWatcher {
 
 impl QmpClient {
     pub async fn new(socket_addr: &str) -> Result<Self> {
-        let stream = qapi::futures::QmpStreamTokio::open_uds(socket_addr).await?;
+        let socket = UnixStream::connect(socket_addr)
+            .await
+            .map_err(|e| anyhow!("failed to connect qmp socket {}: {}", socket_addr, e))?;
+        let socket_dup = socket
+            .as_fd()
+            .try_clone_to_owned()
+            .map_err(|e| anyhow!("failed to dup qmp socket: {}", e))?;
+        let (read, write) = tokio::io::split(socket);
+        let stream = QmpStreamTokio::open_split(read, write).await?;
         let stream = stream.negotiate().await?;
         let (service, mut events) = stream.into_parts();
         let event_watchers = Arc::new(Mutex::new(Vec::<QmpEventWatcher>::new()));
+        let deleting_devices = Arc::new(Mutex::new(HashMap::<String, bool>::new()));
+
+        let (vm_event_tx, vm_event_rx) = mpsc::channel(VM_EVENT_CHANNEL_SIZE);
 
         let w_clone = event_watchers.clone();
+        let d_clone = deleting_devices.clone();
         tokio::spawn(async move {
             while let Some(Ok(event)) = events.next().await {
+                if let Some(vm_event) = to_vm_event(&event) {
+                    warn!("vm event {}: {}", vm_event.name, vm_event.detail);
+                    vm_event_tx.try_send(vm_event).unwrap_or_default();
+                }
+                if let Event::DEVICE_DELETED { ref data, .. } = event {
+                    if let Some(id) = &data.device {
+                        if let Some(deleted) = d_clone.lock().await.get_mut(id) {
+                            *deleted = true;
+                        }
+                    }
+                }
                 let mut ws = w_clone.lock().await;
+                // the watchers timed out are removed
+                ws.retain(|w| !w.sender.is_closed());
                 let mut retained = vec![];
                 while let Some(w) = ws.pop() {
                     if (w.filter)(&event) {
@@ -72,7 +123,11 @@ impl QmpClient {
         });
         let client = Self {
             qmp: service,
+            socket: socket_dup,
+            fd_lock: Mutex::new(()),
             watchers: event_watchers,
+            vm_events: Some(vm_event_rx),
+            deleting_devices,
         };
         Ok(client)
     }
@@ -84,48 +139,149 @@ impl QmpClient {
         }
     }
 
-    pub async fn execute_and_wait_event<C: QmpCommand + 'static>(
-        &self,
-        cmd: C,
-        filter: impl Fn(&Event) -> bool + Sync + Send + 'static,
-    ) -> Result<C::Ok> {
-        let (tx, rx) = channel();
+    // send_fd passes the fd to qemu with the name, so that it can be referred by the name
+    // in the later commands on this connection, it is taken by the first command using it.
+    pub async fn send_fd(&self, name: &str, fd: RawFd) -> Result<()> {
+        let _guard = self.fd_lock.lock().await;
+        // the fd is sent with a whitespace, which is skipped by the json parser of qmp
+        let fds = [fd];
+        sendmsg::<UnixAddr>(
+            self.socket.as_raw_fd(),
+            &[IoSlice::new(b" ")],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .map_err(|e| anyhow!("failed to send fd {} to qmp: {}", name, e))?;
+        self.execute(getfd {
+            fdname: name.to_string(),
+        })
+        .await?;
+        Ok(())
+    }
+
+    // close_fd closes the fd passed to qemu but not taken by any command
+    pub async fn close_fd(&self, name: &str) {
+        if let Err(e) = self
+            .execute(closefd {
+                fdname: name.to_string(),
+            })
+            .await
         {
-            let watcher = QmpEventWatcher {
-                filter: Box::new(filter),
-                sender: tx,
-            };
-            let mut watchers = self.watchers.lock().await;
-            watchers.push(watcher);
-        }
-        match self.qmp.execute(cmd).await {
-            Ok(r) => {
-                //TODO  add timeout
-                let _ = rx.await;
-                Ok(r)
-            }
-            Err(e) => Err(anyhow!("failed to execute qmp, {}", e).into()),
+            warn!("failed to close fd {} in qemu: {}", name, e);
         }
     }
 
+    // take_vm_events returns the channel of the notable events of the vm, it can be taken only once
+    pub fn take_vm_events(&mut self) -> Option<mpsc::Receiver<VMEvent>> {
+        self.vm_events.take()
+    }
+
     pub async fn delete_device(&self, device_id: &str) -> Result<()> {
-        let device_id = device_id.to_string();
-        self.execute_and_wait_event(
-            device_del {
-                id: device_id.clone(),
-            },
-            move |x| {
-                if let qapi::qmp::Event::DEVICE_DELETED { ref data, .. } = x {
-                    if let Some(id) = &data.device {
-                        if id == &device_id {
-                            return true;
-                        }
-                    }
+        if !self.try_delete_device(device_id).await? {
+            self.deleting_devices.lock().await.remove(device_id);
+            return Err(anyhow!(
+                "failed to delete device {}: timeout waiting for qmp event",
+                device_id
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    // try_delete_device returns false if the device is not deleted before the timeout,
+    // then it is still being deleted, and is_device_deleted tells whether it is done later.
+    pub async fn try_delete_device(&self, device_id: &str) -> Result<bool> {
+        let (tx, rx) = channel();
+        let id_clone = device_id.to_string();
+        let watcher = QmpEventWatcher {
+            filter: Box::new(move |x| {
+                if let Event::DEVICE_DELETED { ref data, .. } = x {
+                    return data.device.as_ref() == Some(&id_clone);
                 }
                 false
-            },
-        )
-        .await?;
-        Ok(())
+            }),
+            sender: tx,
+        };
+        self.watchers.lock().await.push(watcher);
+        self.deleting_devices
+            .lock()
+            .await
+            .insert(device_id.to_string(), false);
+        let res = self
+            .execute(device_del {
+                id: device_id.to_string(),
+            })
+            .await;
+        if let Err(e) = res {
+            self.deleting_devices.lock().await.remove(device_id);
+            return Err(anyhow!("failed to delete device {}: {}", device_id, e).into());
+        }
+        match timeout(Duration::from_secs(DEVICE_DELETED_TIMEOUT_IN_SEC), rx).await {
+            Ok(Ok(_)) => {
+                self.deleting_devices.lock().await.remove(device_id);
+                Ok(true)
+            }
+            Ok(Err(_)) => {
+                self.deleting_devices.lock().await.remove(device_id);
+                Err(anyhow!(
+                    "failed to delete device {}: qmp event stream is closed",
+                    device_id
+                )
+                .into())
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    // is_device_deleted checks whether the device that is not deleted in time by
+    // try_delete_device is deleted now, it is forgotten by the client once deleted.
+    pub async fn is_device_deleted(&self, device_id: &str) -> bool {
+        let mut deleting_devices = self.deleting_devices.lock().await;
+        if deleting_devices.get(device_id) == Some(&true) {
+            deleting_devices.remove(device_id);
+            return true;
+        }
+        false
+    }
+
+    // wait_migration waits until the outgoing or incoming migration completed.
+    pub async fn wait_migration(&self, timeout: Duration) -> Result<()> {
+        let start_time = SystemTime::now();
+        loop {
+            let info = self.execute(QueryMigrate {}).await?;
+            match info.status.as_deref() {
+                Some("completed") => return Ok(()),
+                Some(s @ "failed") | Some(s @ "cancelled") => {
+                    return Err(anyhow!(
+                        "migration {}: {}",
+                        s,
+                        info.error_desc.unwrap_or_default()
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+            if start_time.elapsed().unwrap_or_default() > timeout {
+                return Err(
+                    anyhow!("timeout waiting for migration, status {:?}", info.status).into(),
+                );
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
     }
 }
+
+// to_vm_event returns the events that are reported to the sandbox
+fn to_vm_event(event: &Event) -> Option<VMEvent> {
+    let (name, detail) = match event {
+        Event::GUEST_PANICKED { data, .. } => ("GUEST_PANICKED", format!("{:?}", data)),
+        Event::SHUTDOWN { data, .. } => ("SHUTDOWN", format!("{:?}", data)),
+        Event::BLOCK_IO_ERROR { data, .. } => ("BLOCK_IO_ERROR", format!("{:?}", data)),
+        _ => return None,
+    };
+    Some(VMEvent {
+        name: name.to_string(),
+        detail,
+    })
+}
@@ -0,0 +1,223 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{path::Path, time::Duration};
+
+use containerd_sandbox::error::Result;
+use log::debug;
+use qapi::qmp::{cont, stop};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{client_check, new_sandbox_client},
+    qemu::{
+        config::{Incoming, MemoryBackend, QemuConfig, SMP},
+        qmp::{Migrate, MigrateIncoming, MigrateSetCapabilities, MigrationCapabilityStatus},
+        qmp_client::QmpClient,
+        QemuVM,
+    },
+    template::VMTemplate,
+    vm::VM,
+};
+
+pub(crate) const DEFAULT_TEMPLATE_PATH: &str = "/run/kuasar/vmm/template";
+const TEMPLATE_MEMORY_FILE: &str = "memory";
+const TEMPLATE_STATE_FILE: &str = "state";
+const TEMPLATE_MIGRATION_TIMEOUT_IN_SEC: u64 = 60;
+// The agent mounts the sharefs when the sandbox is set up rather than at boot,
+// because qemu refuses to migrate a vm with the 9p fs mounted.
+const LAZY_SHAREFS_KERNEL_PARAM: &str = "task.lazy_sharefs";
+// Memory pages of the template are skipped in migration,
+// as they are already in the memory file shared by all the vms.
+const MIGRATION_CAPABILITY_IGNORE_SHARED: &str = "x-ignore-shared";
+
+// QemuTemplate is a vm booted and paused after the agent is ready,
+// with its memory saved in a file and the device state saved by migration,
+// new vms restored from it map the memory file privately so that memory pages are copied on write.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QemuTemplate {
+    pub path: String,
+    pub smp: SMP,
+    pub memory_size: String,
+    pub kernel_params: Option<String>,
+}
+
+impl QemuTemplate {
+    pub(crate) fn new(path: &str, config: &QemuConfig) -> Self {
+        let kernel_params = match &config.kernel.params {
+            Some(p) if !p.is_empty() => format!("{} {}", p, LAZY_SHAREFS_KERNEL_PARAM),
+            _ => LAZY_SHAREFS_KERNEL_PARAM.to_string(),
+        };
+        Self {
+            path: path.to_string(),
+            smp: config.smp.clone(),
+            memory_size: config.memory.size.clone(),
+            kernel_params: Some(kernel_params),
+        }
+    }
+
+    pub(crate) fn memory_path(&self) -> String {
+        format!("{}/{}", self.path, TEMPLATE_MEMORY_FILE)
+    }
+
+    pub(crate) fn state_path(&self) -> String {
+        format!("{}/{}", self.path, TEMPLATE_STATE_FILE)
+    }
+
+    // vcpus above the max vcpus of the template can not be hot plugged after restored.
+    pub(crate) fn fits(&self, vcpus: u32) -> bool {
+        vcpus <= self.smp.max_cpus.max(self.smp.cpus)
+    }
+
+    // apply_template updates the config of the template vm so that its memory is saved to the file.
+    pub(crate) fn apply_template(&self, config: &mut QemuConfig) {
+        config.kernel.params = self.kernel_params.clone();
+        config.memory.backend_type = MemoryBackend::File(self.memory_path());
+        config.memory.shared = true;
+    }
+
+    // apply_restore updates the config of the vm to be restored from the template,
+    // the topology of the vm has to be the same as the template.
+    pub(crate) fn apply_restore(&self, config: &mut QemuConfig) {
+        config.kernel.params = self.kernel_params.clone();
+        config.smp = self.smp.clone();
+        config.memory.size = self.memory_size.clone();
+        config.memory.backend_type = MemoryBackend::File(self.memory_path());
+        config.memory.shared = false;
+        config.memory.pre_alloc = false;
+        // locking the private mapping in memory breaks all the pages shared with the template
+        config.knobs.mlock.mem_lock = false;
+        config.incoming = Some(Incoming::default());
+    }
+}
+
+impl VMTemplate for QemuTemplate {
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn exists(&self) -> bool {
+        Path::new(&self.memory_path()).exists() && Path::new(&self.state_path()).exists()
+    }
+}
+
+impl QemuVM {
+    pub(crate) fn is_from_template(&self) -> bool {
+        self.template.is_some()
+    }
+
+    // save_template boots the vm and saves it as the template after the agent is ready.
+    pub(crate) async fn save_template(&mut self, template: &QemuTemplate) -> Result<()> {
+        self.start().await?;
+        {
+            let client = new_sandbox_client(&self.agent_socket).await?;
+            client_check(&client).await?;
+        }
+        debug!("agent of template vm {} is ready, save it", self.id);
+
+        let client = self.get_client()?;
+        client.execute(stop {}).await?;
+        set_ignore_shared(client).await?;
+        client
+            .execute(Migrate {
+                uri: format!("exec:cat>{}", template.state_path()),
+            })
+            .await?;
+        client
+            .wait_migration(Duration::from_secs(TEMPLATE_MIGRATION_TIMEOUT_IN_SEC))
+            .await
+    }
+
+    // restore_from_template loads the device state of the template into the vm started with
+    // `-incoming defer`, and resumes the vm after the migration completed.
+    pub(crate) async fn restore_from_template(&mut self, template: &QemuTemplate) -> Result<()> {
+        let client = self.get_client()?;
+        set_ignore_shared(client).await?;
+        client
+            .execute(MigrateIncoming {
+                uri: format!("exec:cat {}", template.state_path()),
+            })
+            .await?;
+        client
+            .wait_migration(Duration::from_secs(TEMPLATE_MIGRATION_TIMEOUT_IN_SEC))
+            .await?;
+        // the template is paused when saved, so is the restored vm
+        client.execute(cont {}).await?;
+        debug!("vm {} is restored from template {}", self.id, template.path);
+
+        for device in std::mem::take(&mut self.pending_devices) {
+            self.hot_attach(device).await?;
+        }
+        Ok(())
+    }
+}
+
+async fn set_ignore_shared(client: &QmpClient) -> Result<()> {
+    client
+        .execute(MigrateSetCapabilities {
+            capabilities: vec![MigrationCapabilityStatus {
+                capability: MIGRATION_CAPABILITY_IGNORE_SHARED.to_string(),
+                state: true,
+            }],
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::qemu::{
+        config::{MemoryBackend, QemuConfig, SMP},
+        template::QemuTemplate,
+    };
+
+    #[test]
+    fn test_apply_restore() {
+        let mut config = QemuConfig::default();
+        config.smp = SMP {
+            cpus: 1,
+            cores: 1,
+            threads: 1,
+            sockets: 4,
+            max_cpus: 4,
+        };
+        config.memory.size = "1024M".to_string();
+        config.kernel.params = Some("console=hvc0".to_string());
+        let template = QemuTemplate::new("/run/kuasar/vmm/template/1", &config);
+        assert_eq!(
+            template.kernel_params.as_deref(),
+            Some("console=hvc0 task.lazy_sharefs")
+        );
+        assert!(template.fits(4));
+        assert!(!template.fits(5));
+
+        let mut restored = QemuConfig::default();
+        restored.smp.cpus = 2;
+        restored.memory.size = "2048M".to_string();
+        restored.memory.shared = true;
+        restored.knobs.mlock.mem_lock = true;
+        template.apply_restore(&mut restored);
+        assert_eq!(restored.smp.cpus, 1);
+        assert_eq!(restored.memory.size, "1024M");
+        assert!(!restored.memory.shared);
+        assert!(!restored.knobs.mlock.mem_lock);
+        assert!(restored.incoming.is_some());
+        match restored.memory.backend_type {
+            MemoryBackend::File(f) => assert_eq!(f, "/run/kuasar/vmm/template/1/memory"),
+            MemoryBackend::Ram => panic!("memory backend should be file"),
+        }
+    }
+}
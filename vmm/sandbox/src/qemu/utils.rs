@@ -60,3 +60,35 @@ pub(crate) async fn detect_pid(path: &str, bin_path: &str) -> Result<u32> {
 
     Err(anyhow!("timeout waiting for the pid file, err: {:?}", err).into())
 }
+
+// parse_memory_size_in_mb parses the memory size of qemu command line, like "1024M" or "2G", into MiB.
+pub(crate) fn parse_memory_size_in_mb(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (num, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, "M"),
+    };
+    let num = num
+        .parse::<u64>()
+        .map_err(|e| anyhow!("failed to parse memory size {}, {}", size, e))?;
+    match unit {
+        "M" | "m" => Ok(num),
+        "G" | "g" => Ok(num * 1024),
+        "T" | "t" => Ok(num * 1024 * 1024),
+        _ => Err(Error::InvalidArgument(format!("memory size {}", size))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::qemu::utils::parse_memory_size_in_mb;
+
+    #[test]
+    fn test_parse_memory_size_in_mb() {
+        assert_eq!(parse_memory_size_in_mb("1024M").unwrap(), 1024);
+        assert_eq!(parse_memory_size_in_mb("2G").unwrap(), 2048);
+        assert_eq!(parse_memory_size_in_mb("512").unwrap(), 512);
+        assert!(parse_memory_size_in_mb("1024K").is_err());
+        assert!(parse_memory_size_in_mb("M").is_err());
+    }
+}
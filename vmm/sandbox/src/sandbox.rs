@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc};
+use std::{collections::HashMap, io::ErrorKind, path::Path, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -28,39 +28,67 @@ use containerd_sandbox::{
 };
 use containerd_shim::{protos::api::Envelope, util::write_str_to_file};
 use log::{debug, error, info, warn};
+use nix::libc::MNT_DETACH;
 use protobuf::{well_known_types::any::Any, MessageField};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::{
-    fs::{copy, create_dir_all, remove_dir_all, OpenOptions},
-    io::{AsyncReadExt, AsyncWriteExt},
+    fs::{copy, create_dir_all, remove_dir_all},
     sync::{Mutex, RwLock},
 };
 use tracing::instrument;
 use ttrpc::context::with_timeout;
 use vmm_common::{
-    api::{empty::Empty, sandbox::SetupSandboxRequest, sandbox_ttrpc::SandboxServiceClient},
+    api::{
+        empty::Empty,
+        sandbox::{
+            SetupSandboxRequest, UpdateInterfacesRequest, UpdateNeighborsRequest,
+            UpdateRoutesRequest,
+        },
+        sandbox_ttrpc::SandboxServiceClient,
+    },
+    mount::{bind_mount, unmount, MNT_NOFOLLOW},
     storage::Storage,
     ETC_HOSTS, ETC_RESOLV, HOSTNAME_FILENAME, HOSTS_FILENAME, RESOLV_FILENAME, SHARED_DIR_SUFFIX,
 };
 
 use crate::{
     cgroup::{SandboxCgroup, DEFAULT_CGROUP_PARENT_PATH},
-    client::{client_check, client_setup_sandbox, client_sync_clock, new_sandbox_client},
+    client::{
+        client_check, client_online_cpu_mem, client_setup_sandbox, client_sync_clock,
+        client_update_interfaces, client_update_neighbors, client_update_routes,
+        new_sandbox_client,
+    },
     container::KuasarContainer,
-    network::{Network, NetworkConfig},
-    utils::{get_dns_config, get_hostname, get_resources, get_sandbox_cgroup_parent_path},
+    gc::{GCConfig, OrphanGC},
+    health::{watch_health, watch_vm_events, HealthCheckConfig},
+    io::stream_io_id,
+    journal::{
+        self, JournalEntry, Journaled, Operation, STEP_COMMITTED, STEP_NETWORK_PREPARED,
+        STEP_VM_STARTED,
+    },
+    network::{watch::watch_network, Network, NetworkConfig},
+    persist::{read_dump_or_backup, write_dump, SCHEMA_VERSION},
+    pool::{remove_pooled_vm_dir, PooledVM, VMPool, VMPoolConfig},
+    storage::watch::watch_block_devices,
+    utils::{
+        get_bandwidth, get_dns_config, get_hostname, get_memory_in_mb, get_resources,
+        get_sandbox_cgroup_parent_path, get_vcpus,
+    },
     vm::{Hooks, Recoverable, VMFactory, VM},
 };
 
 pub const KUASAR_GUEST_SHARE_DIR: &str = "/run/kuasar/storage/containers/";
+const CHECKPOINT_VM_DIR: &str = "vm";
+const GUEST_INTERFACE_UPDATE_RETRIES: u32 = 50;
+const GUEST_INTERFACE_UPDATE_INTERVAL_IN_MS: u64 = 100;
 
 pub struct KuasarSandboxer<F: VMFactory, H: Hooks<F::VM>> {
-    factory: F,
-    hooks: H,
-    #[allow(dead_code)]
+    factory: Arc<F>,
+    hooks: Arc<H>,
     config: SandboxConfig,
     #[allow(clippy::type_complexity)]
     sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<F::VM>>>>>>,
+    pool: Arc<VMPool<F::VM>>,
 }
 
 impl<F, H> KuasarSandboxer<F, H>
@@ -70,11 +98,31 @@ where
     F::VM: VM + Sync + Send,
 {
     pub fn new(config: SandboxConfig, vmm_config: F::Config, hooks: H) -> Self {
+        let pool = Arc::new(VMPool::new(config.vm_pool.clone()));
         Self {
-            factory: F::new(vmm_config),
-            hooks,
+            factory: Arc::new(F::new(vmm_config)),
+            hooks: Arc::new(hooks),
             config,
             sandboxes: Arc::new(Default::default()),
+            pool,
+        }
+    }
+}
+
+// the clones share the sandboxes, so that the admin service works on the same sandboxes
+// as the sandboxer serving containerd.
+impl<F, H> Clone for KuasarSandboxer<F, H>
+where
+    F: VMFactory,
+    H: Hooks<F::VM>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            factory: self.factory.clone(),
+            hooks: self.hooks.clone(),
+            config: self.config.clone(),
+            sandboxes: self.sandboxes.clone(),
+            pool: self.pool.clone(),
         }
     }
 }
@@ -109,6 +157,10 @@ where
                         if let SandboxStatus::Running(_) = status {
                             let sb_clone = sb_mutex.clone();
                             monitor(sb_clone);
+                            watch_health(self.config.health_check.clone(), sb_mutex.clone());
+                            watch_vm_events(sb_mutex.clone());
+                            watch_network(sb_mutex.clone());
+                            watch_block_devices(sb_mutex.clone());
                         }
                         self.sandboxes
                             .write()
@@ -117,15 +169,251 @@ where
                     }
                     Err(e) => {
                         warn!("failed to recover sandbox {:?}, {:?}", entry.file_name(), e);
+                        // the dir is left for the gc, which finds the resources by the dump in it
                         cleanup_mounts(path.to_str().unwrap())
                             .await
                             .unwrap_or_default();
-                        remove_dir_all(&path).await.unwrap_or_default();
                     }
                 }
             }
         }
     }
+
+    // start_vm_pool should be called after recovery, the pooled vms assigned to
+    // recovered sandboxes are kept, and the others left by the previous process are removed.
+    #[instrument(skip_all)]
+    pub async fn start_vm_pool(&self)
+    where
+        F: Sync + Send + 'static,
+    {
+        let mut in_use = vec![];
+        for sb_mutex in self.sandboxes.read().await.values() {
+            if let Some(dir) = &sb_mutex.lock().await.pooled_vm_dir {
+                in_use.push(dir.to_string());
+            }
+        }
+        self.pool.cleanup(&in_use).await;
+        self.pool.run(self.factory.clone());
+    }
+
+    // start_gc reclaims the host resources left by the sandboxes and pooled vms in the dir
+    // but unknown to the sandboxer, they are only reported unless dry_run of gc is disabled.
+    // It should be called after recovery and before the vm pool is started.
+    #[instrument(skip_all)]
+    pub async fn start_gc(&self, dir: &str) {
+        let gc = Arc::new(OrphanGC::new(
+            self.config.gc.clone(),
+            dir,
+            self.sandboxes.clone(),
+            self.pool.clone(),
+        ));
+        gc.run_at_startup().await;
+        gc.run();
+    }
+}
+
+impl<F, H> KuasarSandboxer<F, H>
+where
+    F: VMFactory + Sync + Send,
+    F::VM: VM + DeserializeOwned + Sync + Send + 'static,
+    H: Hooks<F::VM> + Sync + Send,
+{
+    // checkpoint saves the vm state and the sandbox metadata into the dir, so that the sandbox
+    // can be restored from it on the same node. The vm is stopped after the checkpoint unless
+    // leave_running is true, but the containers and storages are kept for the restore.
+    #[instrument(skip_all)]
+    pub async fn checkpoint(&self, id: &str, dir: &str, leave_running: bool) -> Result<()> {
+        let sandbox_mutex = self.sandbox(id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox.checkpoint(dir).await?;
+        info!("sandbox {} is checkpointed to {}", id, dir);
+        if leave_running {
+            return Ok(());
+        }
+        sandbox.vm.stop(true).await?;
+        // the monitor of the sandbox updates the status and destroys the network after vm exited,
+        // wait for it so that the sandbox is not restored before that.
+        let exit_signal = sandbox.exit_signal.clone();
+        drop(sandbox);
+        exit_signal.wait().await;
+        Ok(())
+    }
+
+    // hot_plug_interface attaches the interface of the name in the netns of the sandbox
+    // into the running sandbox.
+    #[instrument(skip_all)]
+    pub async fn hot_plug_interface(&self, id: &str, name: &str) -> Result<()> {
+        let sandbox_mutex = self.sandbox(id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox.hot_plug_interface(name).await?;
+        info!("interface {} is hot plugged into sandbox {}", name, id);
+        Ok(())
+    }
+
+    // hot_unplug_interface detaches the interface of the name from the running sandbox.
+    #[instrument(skip_all)]
+    pub async fn hot_unplug_interface(&self, id: &str, name: &str) -> Result<()> {
+        let sandbox_mutex = self.sandbox(id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        sandbox.hot_unplug_interface(name).await?;
+        info!("interface {} is hot unplugged from sandbox {}", name, id);
+        Ok(())
+    }
+
+    // restore boots a new vm of the stopped sandbox from the checkpoint in the dir,
+    // the containers and storages of the sandbox are replaced by the ones in the checkpoint.
+    #[instrument(skip_all)]
+    pub async fn restore(&self, id: &str, dir: &str) -> Result<()> {
+        let sandbox_mutex = self.sandbox(id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        if let SandboxStatus::Running(_) = sandbox.status {
+            return Err(Error::InvalidArgument(format!(
+                "sandbox {} is running, can not be restored",
+                id
+            )));
+        }
+        let checkpoint = KuasarSandbox::<F::VM>::load(dir).await?;
+        if checkpoint.id != id {
+            return Err(Error::InvalidArgument(format!(
+                "checkpoint in {} is for sandbox {}",
+                dir, checkpoint.id
+            )));
+        }
+
+        let option = SandboxOption::new(sandbox.base_dir.clone(), sandbox.data.clone());
+        sandbox.vm = self.factory.create_vm(id, &option).await?;
+        self.hooks.pre_start(&mut sandbox).await?;
+        sandbox.restore(checkpoint, dir).await?;
+        info!("sandbox {} is restored from {}", id, dir);
+
+        let sandbox_clone = sandbox_mutex.clone();
+        monitor(sandbox_clone);
+        watch_health(self.config.health_check.clone(), sandbox_mutex.clone());
+        watch_vm_events(sandbox_mutex.clone());
+        watch_network(sandbox_mutex.clone());
+        watch_block_devices(sandbox_mutex.clone());
+
+        if let Err(e) = sandbox.add_to_cgroup().await {
+            if let Err(re) = sandbox.vm.stop(true).await {
+                warn!("roll back in add to cgroup {}", re);
+            }
+            sandbox.destroy_network().await;
+            return Err(e);
+        }
+
+        if let Err(e) = self.hooks.post_start(&mut sandbox).await {
+            if let Err(re) = sandbox.vm.stop(true).await {
+                warn!("roll back in sandbox post start {}", re);
+            }
+            sandbox.destroy_network().await;
+            return Err(e);
+        }
+        sandbox.dump().await?;
+        Ok(())
+    }
+}
+
+impl<F, H> KuasarSandboxer<F, H>
+where
+    F: VMFactory + Sync + Send,
+    F::VM: VM + Sync + Send + 'static,
+    H: Hooks<F::VM> + Sync + Send,
+{
+    #[instrument(skip_all)]
+    async fn start_sandbox(
+        &self,
+        sandbox_mutex: &Arc<Mutex<KuasarSandbox<F::VM>>>,
+        sandbox: &mut KuasarSandbox<F::VM>,
+    ) -> Result<()> {
+        self.hooks.pre_start(sandbox).await?;
+
+        // Assign a pre-warmed vm to the sandbox if there is one in the pool,
+        // pod network can only be attached to the pooled vm by hotplug.
+        let mut pooled_vm = None;
+        if self.pool.enabled()
+            && (sandbox.data.netns.is_empty() || sandbox.vm.support_network_hotplug())
+        {
+            pooled_vm = self.pool.take().await;
+        }
+        let mut started = false;
+        if let Some(pooled) = pooled_vm {
+            match sandbox.start_with_pooled_vm(pooled).await {
+                Ok(_) => {
+                    started = true;
+                    if let Err(e) = sandbox.record_step(STEP_VM_STARTED).await {
+                        if let Err(re) = sandbox.stop(true).await {
+                            warn!("roll back in record pooled vm started {}", re);
+                        }
+                        sandbox.destroy_network().await;
+                        return Err(e);
+                    }
+                }
+                Err(e) => warn!(
+                    "failed to start sandbox {} with pooled vm, fall back to boot a new one: {}",
+                    sandbox.id, e
+                ),
+            }
+        }
+
+        if !started {
+            // Prepare pod network if it has a private network namespace
+            if !sandbox.data.netns.is_empty() {
+                sandbox.prepare_network().await?;
+                if let Err(e) = sandbox.record_step(STEP_NETWORK_PREPARED).await {
+                    sandbox.destroy_network().await;
+                    return Err(e);
+                }
+            }
+
+            if let Err(e) = sandbox.start().await {
+                sandbox.destroy_network().await;
+                return Err(e);
+            }
+            if let Err(e) = sandbox.record_step(STEP_VM_STARTED).await {
+                if let Err(re) = sandbox.vm.stop(true).await {
+                    warn!("roll back in record vm started {}", re);
+                }
+                sandbox.destroy_network().await;
+                return Err(e);
+            }
+        }
+
+        let sandbox_clone = sandbox_mutex.clone();
+        monitor(sandbox_clone);
+        watch_health(self.config.health_check.clone(), sandbox_mutex.clone());
+        watch_vm_events(sandbox_mutex.clone());
+        watch_network(sandbox_mutex.clone());
+        watch_block_devices(sandbox_mutex.clone());
+
+        if let Err(e) = sandbox.add_to_cgroup().await {
+            if let Err(re) = sandbox.stop(true).await {
+                warn!("roll back in add to cgroup {}", re);
+                return Err(e);
+            }
+            sandbox.destroy_network().await;
+            return Err(e);
+        }
+
+        if let Err(e) = self.hooks.post_start(sandbox).await {
+            if let Err(re) = sandbox.stop(true).await {
+                warn!("roll back in sandbox post start {}", re);
+                return Err(e);
+            }
+            sandbox.destroy_network().await;
+            return Err(e);
+        }
+
+        if let Err(e) = sandbox.record_step(STEP_COMMITTED).await {
+            if let Err(re) = sandbox.stop(true).await {
+                warn!("roll back in sandbox start dump {}", re);
+                return Err(e);
+            }
+            sandbox.destroy_network().await;
+            return Err(e);
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -145,6 +433,24 @@ pub struct KuasarSandbox<V: VM> {
     pub(crate) exit_signal: Arc<ExitSignal>,
     #[serde(default)]
     pub(crate) sandbox_cgroups: SandboxCgroup,
+    #[serde(default)]
+    pub(crate) pooled_vm_dir: Option<String>,
+    // sizes of the hot attached block volumes, to find the volumes expanded on the host
+    #[serde(default)]
+    pub(crate) block_device_sizes: HashMap<String, u64>,
+    #[serde(default)]
+    pub(crate) shm_size_in_mb: u64,
+    #[serde(default)]
+    pub(crate) layer_fsverity: bool,
+    // detail of the guest panic reported by the vmm, taken when the exit of the vm is reported
+    #[serde(default)]
+    pub(crate) guest_panic: Option<String>,
+    // exit signals of the io streams of the containers and processes
+    #[serde(skip, default)]
+    pub(crate) io_streams: HashMap<String, Arc<ExitSignal>>,
+    // the dump is upgraded to the current schema version when it is loaded
+    #[serde(default)]
+    pub(crate) schema_version: u64,
 }
 
 #[async_trait]
@@ -165,20 +471,17 @@ where
         let mut sandbox_cgroups = SandboxCgroup::default();
         let cgroup_parent_path = get_sandbox_cgroup_parent_path(&s.sandbox)
             .unwrap_or(DEFAULT_CGROUP_PARENT_PATH.to_string());
-        // Currently only support cgroup V1, cgroup V2 is not supported now
-        if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
-            // Create sandbox's cgroup and apply sandbox's resources limit
-            let create_and_update_sandbox_cgroup = (|| {
-                sandbox_cgroups =
-                    SandboxCgroup::create_sandbox_cgroups(&cgroup_parent_path, &s.sandbox.id)?;
-                sandbox_cgroups.update_res_for_sandbox_cgroups(&s.sandbox)?;
-                Ok(())
-            })();
-            // If create and update sandbox cgroup failed, do rollback operation
-            if let Err(e) = create_and_update_sandbox_cgroup {
-                let _ = sandbox_cgroups.remove_sandbox_cgroups();
-                return Err(e);
-            }
+        // Create sandbox's cgroup and apply sandbox's resources limit
+        let create_and_update_sandbox_cgroup = (|| {
+            sandbox_cgroups =
+                SandboxCgroup::create_sandbox_cgroups(&cgroup_parent_path, &s.sandbox.id)?;
+            sandbox_cgroups.update_res_for_sandbox_cgroups(&s.sandbox)?;
+            Ok(())
+        })();
+        // If create and update sandbox cgroup failed, do rollback operation
+        if let Err(e) = create_and_update_sandbox_cgroup {
+            let _ = sandbox_cgroups.remove_sandbox_cgroups();
+            return Err(e);
         }
         let vm = self.factory.create_vm(id, &s).await?;
         let mut sandbox = KuasarSandbox {
@@ -194,6 +497,13 @@ where
             client: Arc::new(Mutex::new(None)),
             exit_signal: Arc::new(ExitSignal::default()),
             sandbox_cgroups,
+            pooled_vm_dir: None,
+            block_device_sizes: HashMap::new(),
+            shm_size_in_mb: self.config.shm_size_in_mb,
+            layer_fsverity: self.config.layer_fsverity,
+            guest_panic: None,
+            io_streams: HashMap::new(),
+            schema_version: SCHEMA_VERSION,
         };
 
         // setup sandbox files: hosts, hostname and resolv.conf for guest
@@ -211,55 +521,21 @@ where
     async fn start(&self, id: &str) -> Result<()> {
         let sandbox_mutex = self.sandbox(id).await?;
         let mut sandbox = sandbox_mutex.lock().await;
-        self.hooks.pre_start(&mut sandbox).await?;
-
-        // Prepare pod network if it has a private network namespace
-        if !sandbox.data.netns.is_empty() {
-            sandbox.prepare_network().await?;
-        }
-
-        if let Err(e) = sandbox.start().await {
-            sandbox.destroy_network().await;
-            return Err(e);
-        }
-
-        let sandbox_clone = sandbox_mutex.clone();
-        monitor(sandbox_clone);
-
-        if let Err(e) = sandbox.add_to_cgroup().await {
-            if let Err(re) = sandbox.stop(true).await {
-                warn!("roll back in add to cgroup {}", re);
-                return Err(e);
-            }
-            sandbox.destroy_network().await;
-            return Err(e);
+        journal::begin(&sandbox.base_dir, Operation::Start).await?;
+        let res = self.start_sandbox(&sandbox_mutex, &mut sandbox).await;
+        // a failed start is already rolled back here, the journal is done either way
+        if let Err(e) = journal::finish(&sandbox.base_dir).await {
+            error!("failed to finish journal of sandbox {}: {}", id, e);
         }
-
-        if let Err(e) = self.hooks.post_start(&mut sandbox).await {
-            if let Err(re) = sandbox.stop(true).await {
-                warn!("roll back in sandbox post start {}", re);
-                return Err(e);
-            }
-            sandbox.destroy_network().await;
-            return Err(e);
-        }
-
-        if let Err(e) = sandbox.dump().await {
-            if let Err(re) = sandbox.stop(true).await {
-                warn!("roll back in sandbox start dump {}", re);
-                return Err(e);
-            }
-            sandbox.destroy_network().await;
-            return Err(e);
-        }
-
-        Ok(())
+        res
     }
 
     #[instrument(skip_all)]
     async fn update(&self, id: &str, data: SandboxData) -> Result<()> {
         let sandbox_mutex = self.sandbox(id).await?;
         let mut sandbox = sandbox_mutex.lock().await;
+        sandbox.update_resources(&data).await?;
+        sandbox.update_bandwidth(&data).await?;
         sandbox.data = data;
         sandbox.dump().await?;
         Ok(())
@@ -294,11 +570,8 @@ where
             let mut sb = sb_mutex.lock().await;
             sb.stop(true).await?;
 
-            // Currently only support cgroup V1, cgroup V2 is not supported now
-            if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
-                // remove the sandbox cgroups
-                sb.sandbox_cgroups.remove_sandbox_cgroups()?;
-            }
+            // remove the sandbox cgroups
+            sb.sandbox_cgroups.remove_sandbox_cgroups()?;
 
             cleanup_mounts(&sb.base_dir).await?;
             // Should Ignore the NotFound error of base dir as it may be already deleted.
@@ -307,6 +580,9 @@ where
                     return Err(e.into());
                 }
             }
+            if let Some(dir) = &sb.pooled_vm_dir {
+                remove_pooled_vm_dir(dir).await;
+            }
         }
         self.sandboxes.write().await.remove(id);
         Ok(())
@@ -342,8 +618,21 @@ where
     #[instrument(skip_all)]
     async fn append_container(&mut self, id: &str, options: ContainerOption) -> Result<()> {
         let handler_chain = self.container_append_handlers(id, options)?;
-        handler_chain.handle(self).await?;
-        self.dump().await?;
+        journal::begin(&self.base_dir, Operation::AppendContainer(id.to_string())).await?;
+        if let Err(e) = handler_chain.handle_journaled(self).await {
+            // the handled steps are rolled back, persist the state without the container
+            if let Err(de) = self.dump().await {
+                warn!(
+                    "dump sandbox {} in append container rollback: {}",
+                    self.id, de
+                );
+                return Err(e);
+            }
+            journal::finish(&self.base_dir).await.unwrap_or_default();
+            return Err(e);
+        }
+        self.record_step(STEP_COMMITTED).await?;
+        journal::finish(&self.base_dir).await?;
         Ok(())
     }
 
@@ -357,25 +646,11 @@ where
 
     #[instrument(skip_all)]
     async fn remove_container(&mut self, id: &str) -> Result<()> {
-        self.deference_container_storages(id).await?;
-
-        let bundle = format!("{}/{}", self.get_sandbox_shared_path(), &id);
-        if let Err(e) = tokio::fs::remove_dir_all(&*bundle).await {
-            if e.kind() != ErrorKind::NotFound {
-                return Err(anyhow!("failed to remove bundle {}, {}", bundle, e).into());
-            }
-        }
-        let container = self.containers.remove(id);
-        // TODO: remove processes first?
-        match container {
-            None => {}
-            Some(c) => {
-                for device_id in c.io_devices {
-                    self.vm.hot_detach(&device_id).await?;
-                }
-            }
-        }
-        self.dump().await?;
+        // the journal is kept if the removal fails, and the recovery will try again
+        journal::begin(&self.base_dir, Operation::RemoveContainer(id.to_string())).await?;
+        self.cleanup_container(id).await?;
+        self.record_step(STEP_COMMITTED).await?;
+        journal::finish(&self.base_dir).await?;
         Ok(())
     }
 
@@ -390,27 +665,48 @@ where
     }
 }
 
+#[async_trait]
+impl<V> Journaled for KuasarSandbox<V>
+where
+    V: VM + Sync + Send,
+{
+    async fn persist(&self) -> Result<()> {
+        self.dump().await
+    }
+
+    // the state is dumped before the step is recorded,
+    // so that the resources of the recorded steps can be found in the dump.
+    async fn record_step(&mut self, step: &str) -> Result<()> {
+        self.dump().await?;
+        journal::step(&self.base_dir, step).await
+    }
+}
+
 impl<V> KuasarSandbox<V>
 where
     V: VM + Sync + Send,
 {
     #[instrument(skip_all)]
-    async fn dump(&self) -> Result<()> {
-        let dump_data =
-            serde_json::to_vec(&self).map_err(|e| anyhow!("failed to serialize sandbox, {}", e))?;
-        let dump_path = format!("{}/sandbox.json", self.base_dir);
-        let mut dump_file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&dump_path)
-            .await
-            .map_err(Error::IO)?;
-        dump_file
-            .write_all(dump_data.as_slice())
-            .await
-            .map_err(Error::IO)?;
-        Ok(())
+    pub(crate) async fn dump(&self) -> Result<()> {
+        self.dump_to(&self.base_dir).await
+    }
+
+    async fn dump_to(&self, dir: &str) -> Result<()> {
+        let dump = serde_json::to_value(self)
+            .map_err(|e| anyhow!("failed to serialize sandbox, {}", e))?;
+        write_dump(dir, dump).await
+    }
+}
+
+impl<V> KuasarSandbox<V>
+where
+    V: VM + DeserializeOwned + Sync + Send,
+{
+    async fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dump = read_dump_or_backup(dir.as_ref()).await?;
+        let sb = serde_json::from_value::<KuasarSandbox<V>>(dump)
+            .map_err(|e| anyhow!("failed to deserialize sandbox, {}", e))?;
+        Ok(sb)
     }
 }
 
@@ -420,19 +716,7 @@ where
 {
     #[instrument(skip_all)]
     async fn recover<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
-        let dump_path = base_dir.as_ref().join("sandbox.json");
-        let mut dump_file = OpenOptions::new()
-            .read(true)
-            .open(&dump_path)
-            .await
-            .map_err(Error::IO)?;
-        let mut content = vec![];
-        dump_file
-            .read_to_end(&mut content)
-            .await
-            .map_err(Error::IO)?;
-        let mut sb = serde_json::from_slice::<KuasarSandbox<V>>(content.as_slice())
-            .map_err(|e| anyhow!("failed to deserialize sandbox, {}", e))?;
+        let mut sb = Self::load(base_dir).await?;
         if let SandboxStatus::Running(_) = sb.status {
             if let Err(e) = sb.vm.recover().await {
                 warn!("failed to recover vm {}: {}, then force kill it!", sb.id, e);
@@ -451,41 +735,129 @@ where
             }
             sb.sync_clock().await;
             sb.forward_events().await;
+            sb.recover_io_streams().await;
         }
         // recover the sandbox_cgroups in the sandbox object
         sb.sandbox_cgroups =
             SandboxCgroup::create_sandbox_cgroups(&sb.sandbox_cgroups.cgroup_parent_path, &sb.id)?;
+        sb.replay_journal().await?;
 
         Ok(sb)
     }
+
+    // replay_journal rolls back the start or the container appending,
+    // or finishes the container removing, which is interrupted by the exit of the sandboxer.
+    #[instrument(skip_all)]
+    async fn replay_journal(&mut self) -> Result<()> {
+        let entry = match journal::read(&self.base_dir).await? {
+            None => return Ok(()),
+            Some(entry) => entry,
+        };
+        if entry.has_step(STEP_COMMITTED) {
+            debug!(
+                "operation {:?} of sandbox {} is committed, finish it",
+                entry.operation, self.id
+            );
+            return journal::finish(&self.base_dir).await;
+        }
+        info!(
+            "replay interrupted operation {:?} of sandbox {}, completed steps: {:?}",
+            entry.operation, self.id, entry.steps
+        );
+        match &entry.operation {
+            Operation::Start => self.rollback_start(&entry).await,
+            Operation::AppendContainer(id) | Operation::RemoveContainer(id) => {
+                // the entry being replayed is finished below, so no new one is begun
+                if let Err(e) = self.cleanup_container(id).await {
+                    warn!(
+                        "failed to remove container {} of sandbox {} in replay: {}",
+                        id, self.id, e
+                    );
+                }
+            }
+        }
+        self.dump().await?;
+        journal::finish(&self.base_dir).await
+    }
+
+    async fn rollback_start(&mut self, entry: &JournalEntry) {
+        if entry.has_step(STEP_VM_STARTED) {
+            // the vm of the running sandbox is already recovered
+            if !matches!(self.status, SandboxStatus::Running(_)) {
+                if let Err(e) = self.vm.recover().await {
+                    warn!("failed to recover vm of sandbox {}: {}", self.id, e);
+                }
+            }
+            if let Err(e) = self.vm.stop(true).await {
+                warn!("failed to stop vm of sandbox {}: {}", self.id, e);
+            }
+        }
+        self.destroy_network().await;
+        self.client.lock().await.take();
+        if let Some(dir) = self.pooled_vm_dir.take() {
+            unmount(&self.get_sandbox_shared_path(), MNT_DETACH | MNT_NOFOLLOW).unwrap_or_default();
+            remove_pooled_vm_dir(&dir).await;
+        }
+        if let SandboxStatus::Running(_) = self.status {
+            self.exit_signal.signal();
+            self.exit_signal = Arc::new(ExitSignal::default());
+        }
+        self.status = SandboxStatus::Created;
+    }
 }
 
 impl<V> KuasarSandbox<V>
 where
     V: VM + Sync + Send,
 {
-    #[instrument(skip_all)]
-    async fn start(&mut self) -> Result<()> {
-        let pid = self.vm.start().await?;
+    // cleanup_container releases the storages, bundle, io streams and devices of the container,
+    // it can be called again after failure, and the removal is journaled by the caller.
+    async fn cleanup_container(&mut self, id: &str) -> Result<()> {
+        self.deference_container_storages(id).await?;
 
-        if let Err(e) = self.init_client().await {
-            if let Err(re) = self.vm.stop(true).await {
-                warn!("roll back in init task client: {}", re);
-                return Err(e);
+        let bundle = format!("{}/{}", self.get_sandbox_shared_path(), &id);
+        if let Err(e) = tokio::fs::remove_dir_all(&*bundle).await {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(anyhow!("failed to remove bundle {}, {}", bundle, e).into());
             }
-            return Err(e);
         }
+        let container = self.containers.remove(id);
+        // TODO: remove processes first?
+        match container {
+            None => {}
+            Some(c) => {
+                self.stop_io_streams(id);
+                for p in &c.processes {
+                    self.stop_io_streams(&stream_io_id(id, &p.id));
+                }
+                for device_id in c.io_devices {
+                    self.vm.hot_detach(&device_id).await?;
+                }
+            }
+        }
+        Ok(())
+    }
 
-        if let Err(e) = self.setup_sandbox().await {
+    #[instrument(skip_all)]
+    async fn start(&mut self) -> Result<()> {
+        let pid = self.vm.start().await?;
+
+        if let Err(e) = self.post_boot(pid).await {
             if let Err(re) = self.vm.stop(true).await {
-                error!("roll back in setup sandbox client: {}", re);
+                error!("roll back in post boot: {}", re);
                 return Err(e);
             }
             return Err(e);
         }
+        Ok(())
+    }
 
+    // post_boot is shared by the vms booted by the sandbox and the pooled vms assigned to it,
+    // it connects the task server in the guest, sets up the sandbox and marks it running.
+    async fn post_boot(&mut self, pid: u32) -> Result<()> {
+        self.init_client().await?;
+        self.setup_sandbox().await?;
         self.forward_events().await;
-
         self.status = SandboxStatus::Running(pid);
         Ok(())
     }
@@ -526,6 +898,65 @@ where
         Ok(())
     }
 
+    #[instrument(skip_all)]
+    async fn checkpoint(&mut self, dir: &str) -> Result<()> {
+        check_checkpoint(&self.id, &self.status, &self.vm.unrestorable_devices())?;
+        let vm_dir = format!("{}/{}", dir, CHECKPOINT_VM_DIR);
+        create_dir_all(&vm_dir)
+            .await
+            .map_err(|e| anyhow!("create checkpoint dir {}: {}", vm_dir, e))?;
+        self.vm.checkpoint(&vm_dir).await?;
+        self.dump_to(dir).await
+    }
+
+    // restore starts the vm from the checkpoint, the vm should be created but not started,
+    // the host side storages of the sandbox should still be there.
+    #[instrument(skip_all)]
+    async fn restore(&mut self, checkpoint: KuasarSandbox<V>, dir: &str) -> Result<()> {
+        let shared_path = self.get_sandbox_shared_path();
+        for s in checkpoint.storages.iter() {
+            let mount_point = format!("{}/{}", shared_path, s.id);
+            if s.device_id.is_none() && s.fstype == "bind" && !Path::new(&mount_point).exists() {
+                return Err(Error::NotFound(format!(
+                    "storage {} of the checkpoint",
+                    s.id
+                )));
+            }
+        }
+        self.containers = checkpoint.containers;
+        self.storages = checkpoint.storages;
+        self.id_generator = checkpoint.id_generator;
+
+        if !self.data.netns.is_empty() {
+            self.prepare_network().await?;
+        }
+        let vm_dir = format!("{}/{}", dir, CHECKPOINT_VM_DIR);
+        let pid = match self.vm.restore(&vm_dir).await {
+            Ok(pid) => pid,
+            Err(e) => {
+                if let Err(re) = self.vm.stop(true).await {
+                    warn!("roll back in restore vm: {}", re);
+                }
+                self.destroy_network().await;
+                return Err(e);
+            }
+        };
+
+        // the ttrpc client of the previous vm is not valid any more
+        self.client.lock().await.take();
+        if let Err(e) = self.init_client().await {
+            if let Err(re) = self.vm.stop(true).await {
+                warn!("roll back in init task client: {}", re);
+            }
+            self.destroy_network().await;
+            return Err(e);
+        }
+        self.exit_signal = Arc::new(ExitSignal::default());
+        self.forward_events().await;
+        self.status = SandboxStatus::Running(pid);
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     pub(crate) fn container_mut(&mut self, id: &str) -> Result<&mut KuasarContainer> {
         self.containers
@@ -585,6 +1016,15 @@ where
             }
 
             client_setup_sandbox(client, &req).await?;
+
+            // static neighbors are not in the setup request for agents not knowing them
+            if let Some(network) = self.network.as_ref() {
+                if !network.neighbors().is_empty() {
+                    let mut req = UpdateNeighborsRequest::new();
+                    req.neighbors = network.neighbors().iter().map(|x| x.into()).collect();
+                    client_update_neighbors(client, &req).await?;
+                }
+            }
         }
 
         Ok(())
@@ -654,6 +1094,12 @@ where
 
     #[instrument(skip_all)]
     pub async fn prepare_network(&mut self) -> Result<()> {
+        let network = self.new_network().await?;
+        network.attach_to(self).await?;
+        Ok(())
+    }
+
+    async fn new_network(&self) -> Result<Network> {
         // get vcpu for interface queue, at least one vcpu
         let mut vcpu = 1;
         if let Some(resources) = get_resources(&self.data) {
@@ -668,12 +1114,68 @@ where
             netns: self.data.netns.to_string(),
             sandbox_id: self.id.to_string(),
             queue: vcpu,
+            bandwidth: get_bandwidth(&self.data)?,
         };
-        let network = Network::new(network_config).await?;
-        network.attach_to(self).await?;
+        Network::new(network_config).await
+    }
+
+    // start_with_pooled_vm replaces the vm of the sandbox with a pre-warmed one, and binds
+    // the shared dir, resources and network of the sandbox to it. The vm of the sandbox is
+    // restored if it fails, so that the sandbox can still be started with it.
+    #[instrument(skip_all)]
+    async fn start_with_pooled_vm(&mut self, pooled: PooledVM<V>) -> Result<()> {
+        debug!("assign pooled vm {} to sandbox {}", pooled.id, self.id);
+        let pooled_shared_path = pooled.shared_path();
+        let PooledVM {
+            base_dir,
+            vm,
+            pid,
+            client,
+            ..
+        } = pooled;
+        let vm = std::mem::replace(&mut self.vm, vm);
+        self.pooled_vm_dir = Some(base_dir);
+        if let Err(e) = self
+            .assign_pooled_vm(&pooled_shared_path, client, pid)
+            .await
+        {
+            if let Err(re) = self.vm.stop(true).await {
+                warn!("roll back in stop pooled vm: {}", re);
+            }
+            self.destroy_network().await;
+            self.client.lock().await.take();
+            unmount(&self.get_sandbox_shared_path(), MNT_DETACH | MNT_NOFOLLOW).unwrap_or_default();
+            if let Some(dir) = self.pooled_vm_dir.take() {
+                remove_pooled_vm_dir(&dir).await;
+            }
+            self.vm = vm;
+            return Err(e);
+        }
         Ok(())
     }
 
+    async fn assign_pooled_vm(
+        &mut self,
+        pooled_shared_path: &str,
+        client: SandboxServiceClient,
+        pid: u32,
+    ) -> Result<()> {
+        // the shared dir of the sandbox is replaced by the one exported by the pooled vm
+        bind_mount(pooled_shared_path, &self.get_sandbox_shared_path(), &[])?;
+        self.setup_sandbox_files().await?;
+
+        let vcpus = get_vcpus(&self.data);
+        self.vm.resize(vcpus, get_memory_in_mb(&self.data)).await?;
+        client_online_cpu_mem(&client, vcpus).await?;
+        *self.client.lock().await = Some(client);
+
+        if !self.data.netns.is_empty() {
+            let network = self.new_network().await?;
+            network.hot_attach_to(self).await?;
+        }
+        self.post_boot(pid).await
+    }
+
     //  If a sandbox is still running, destroy network may hang with its running
     #[instrument(skip_all)]
     pub async fn destroy_network(&mut self) {
@@ -683,33 +1185,266 @@ where
         }
     }
 
+    // hot_plug_interface attaches a new interface of the pod netns into the running sandbox,
+    // and configures the interface and the routes in the guest.
     #[instrument(skip_all)]
-    pub async fn add_to_cgroup(&self) -> Result<()> {
-        // Currently only support cgroup V1, cgroup V2 is not supported now
-        if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
-            // add vmm process into sandbox cgroup
-            if let SandboxStatus::Running(vmm_pid) = self.status {
-                let vcpu_threads = self.vm.vcpus().await?;
-                debug!(
-                    "vmm process pid: {}, vcpu threads pid: {:?}",
-                    vmm_pid, vcpu_threads
-                );
-                self.sandbox_cgroups
-                    .add_process_into_sandbox_cgroups(vmm_pid, Some(vcpu_threads))?;
-                // move all vmm-related process into sandbox cgroup
-                for pid in self.vm.pids().affiliated_pids {
-                    self.sandbox_cgroups
-                        .add_process_into_sandbox_cgroups(pid, None)?;
+    pub async fn hot_plug_interface(&mut self, name: &str) -> Result<()> {
+        self.check_network_hotplug()?;
+        let network = self
+            .network
+            .as_ref()
+            .ok_or_else(|| anyhow!("sandbox {} has no network", self.id))?;
+        if network.intfs.iter().any(|x| x.name == name) {
+            return Err(Error::AlreadyExist(format!("interface {}", name)));
+        }
+        let latest = Network::new_from_netns(network.config().clone()).await?;
+        let intf = latest
+            .intfs
+            .into_iter()
+            .find(|x| x.name == name)
+            .ok_or_else(|| Error::NotFound(format!("interface {}", name)))?;
+        let mut network = self
+            .network
+            .take()
+            .ok_or_else(|| anyhow!("sandbox {} has no network", self.id))?;
+        let name = name.to_string();
+        let mut res = network.hot_plug_interface(intf, self).await;
+        if res.is_ok() {
+            let neighbors = !network.neighbors().is_empty();
+            res = self
+                .update_guest_network(&network, &[name], true, neighbors)
+                .await;
+        }
+        self.network = Some(network);
+        self.dump().await?;
+        res
+    }
+
+    // hot_unplug_interface detaches the interface from the running sandbox,
+    // the guest removes the interface itself, so only the routes are updated.
+    #[instrument(skip_all)]
+    pub async fn hot_unplug_interface(&mut self, name: &str) -> Result<()> {
+        self.check_network_hotplug()?;
+        let mut network = self
+            .network
+            .take()
+            .ok_or_else(|| anyhow!("sandbox {} has no network", self.id))?;
+        let mut res = network.hot_unplug_interface(name, self).await;
+        if res.is_ok() {
+            res = self.update_guest_network(&network, &[], true, false).await;
+        }
+        self.network = Some(network);
+        self.dump().await?;
+        res
+    }
+
+    // resync_network syncs the changes of the netns since the network of the sandbox
+    // is got into the vm and the guest.
+    #[instrument(skip_all)]
+    pub async fn resync_network(&mut self) -> Result<()> {
+        let mut network = match self.network.take() {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+        let res = self.sync_network(&mut network).await;
+        self.network = Some(network);
+        match res {
+            Ok(false) => Ok(()),
+            Ok(true) => self.dump().await,
+            Err(e) => {
+                self.dump().await?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn sync_network(&mut self, network: &mut Network) -> Result<bool> {
+        let latest = Network::new_from_netns(network.config().clone()).await?;
+        let changes = network.apply_snapshot(latest);
+        if changes.is_empty() {
+            return Ok(false);
+        }
+        info!(
+            "network of sandbox {} changed, added: {:?}, removed: {:?}, updated: {:?}",
+            self.id,
+            changes.added.iter().map(|x| &x.name).collect::<Vec<_>>(),
+            changes.removed,
+            changes.updated
+        );
+        let mut names = changes.updated;
+        let mut plugged = false;
+        if !changes.added.is_empty() || !changes.removed.is_empty() {
+            if self.vm.support_network_hotplug() {
+                for name in &changes.removed {
+                    match network.hot_unplug_interface(name, self).await {
+                        Ok(_) => plugged = true,
+                        Err(e) => warn!("failed to hot unplug interface {}: {}", name, e),
+                    }
+                }
+                for intf in changes.added {
+                    let name = intf.name.to_string();
+                    match network.hot_plug_interface(intf, self).await {
+                        Ok(_) => {
+                            plugged = true;
+                            names.push(name);
+                        }
+                        Err(e) => warn!("failed to hot plug interface {}: {}", name, e),
+                    }
                 }
             } else {
-                return Err(Error::Other(anyhow!(
-                    "sandbox status is not Running after started!"
-                )));
+                warn!(
+                    "interfaces of sandbox {} are added or removed, but the vm does not support network hotplug",
+                    self.id
+                );
             }
         }
+        let neighbors = changes.neighbors_changed || (plugged && !network.neighbors().is_empty());
+        self.update_guest_network(
+            network,
+            &names,
+            changes.routes_changed || plugged,
+            neighbors,
+        )
+        .await?;
+        Ok(true)
+    }
+
+    fn check_network_hotplug(&self) -> Result<()> {
+        if !matches!(self.status, SandboxStatus::Running(_)) {
+            return Err(Error::InvalidArgument(format!(
+                "sandbox {} is not running",
+                self.id
+            )));
+        }
+        if !self.vm.support_network_hotplug() {
+            return Err(Error::Unimplemented(format!(
+                "network hotplug of sandbox {}",
+                self.id
+            )));
+        }
         Ok(())
     }
 
+    // update_guest_network configures the interfaces of the names in the guest,
+    // and replaces the routes and neighbors in the guest with those of the network if required.
+    async fn update_guest_network(
+        &self,
+        network: &Network,
+        names: &[String],
+        routes: bool,
+        neighbors: bool,
+    ) -> Result<()> {
+        let client_guard = self.client.lock().await;
+        let client = client_guard
+            .as_ref()
+            .ok_or_else(|| anyhow!("agent of sandbox {} is not connected", self.id))?;
+        if !names.is_empty() {
+            let mut req = UpdateInterfacesRequest::new();
+            req.interfaces = network
+                .interfaces()
+                .iter()
+                .filter(|x| names.contains(&x.name))
+                .map(|x| x.into())
+                .collect();
+            // the hot plugged device may not be probed by the guest kernel yet
+            let mut retries = GUEST_INTERFACE_UPDATE_RETRIES;
+            loop {
+                match client_update_interfaces(client, &req).await {
+                    Ok(_) => break,
+                    Err(e) if retries > 0 => {
+                        debug!("retry to update interfaces {:?} in guest: {}", names, e);
+                        retries -= 1;
+                        tokio::time::sleep(Duration::from_millis(
+                            GUEST_INTERFACE_UPDATE_INTERVAL_IN_MS,
+                        ))
+                        .await;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        if routes {
+            let mut req = UpdateRoutesRequest::new();
+            req.routes = network.routes().iter().map(|x| x.into()).collect();
+            client_update_routes(client, &req).await?;
+        }
+        if neighbors {
+            let mut req = UpdateNeighborsRequest::new();
+            req.neighbors = network.neighbors().iter().map(|x| x.into()).collect();
+            client_update_neighbors(client, &req).await?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    pub async fn add_to_cgroup(&self) -> Result<()> {
+        // add vmm process into sandbox cgroup
+        if let SandboxStatus::Running(vmm_pid) = self.status {
+            let vcpu_threads = self.vm.vcpus().await?;
+            debug!(
+                "vmm process pid: {}, vcpu threads pid: {:?}",
+                vmm_pid, vcpu_threads
+            );
+            self.sandbox_cgroups
+                .add_process_into_sandbox_cgroups(vmm_pid, Some(vcpu_threads))?;
+            // move all vmm-related process into sandbox cgroup
+            for pid in self.vm.pids().affiliated_pids {
+                self.sandbox_cgroups
+                    .add_process_into_sandbox_cgroups(pid, None)?;
+            }
+        } else {
+            return Err(Error::Other(anyhow!(
+                "sandbox status is not Running after started!"
+            )));
+        }
+        Ok(())
+    }
+
+    // update_resources applies the new resources of the sandbox to the sandbox cgroups,
+    // and resizes the vcpus and memory of the vm in place if the sandbox is running.
+    #[instrument(skip_all)]
+    async fn update_resources(&mut self, data: &SandboxData) -> Result<()> {
+        if !matches!(self.status, SandboxStatus::Running(_)) {
+            return self.sandbox_cgroups.update_res_for_sandbox_cgroups(data);
+        }
+
+        let vcpus = get_vcpus(data);
+        let memory_in_mb = get_memory_in_mb(data);
+        // the memory limit of cgroups should be raised before the memory of vm grows,
+        // and be lowered after the memory of vm shrinks, so that the vmm is not oom killed.
+        let grow_memory = memory_in_mb > get_memory_in_mb(&self.data);
+        if grow_memory {
+            self.sandbox_cgroups.update_res_for_sandbox_cgroups(data)?;
+        }
+        match self.vm.resize(vcpus, memory_in_mb).await {
+            Ok(_) => {}
+            Err(Error::Unimplemented(e)) => {
+                warn!("{} is not supported, only sandbox cgroups are updated", e);
+                return self.sandbox_cgroups.update_res_for_sandbox_cgroups(data);
+            }
+            Err(e) => return Err(e),
+        }
+        if let Some(client) = &*self.client.lock().await {
+            client_online_cpu_mem(client, vcpus).await?;
+        }
+        // new vcpu threads should be placed into the vcpu cgroup
+        self.add_to_cgroup().await?;
+        if !grow_memory {
+            self.sandbox_cgroups.update_res_for_sandbox_cgroups(data)?;
+        }
+        Ok(())
+    }
+
+    // update_bandwidth applies the new bandwidth annotations to the network of the sandbox
+    async fn update_bandwidth(&mut self, data: &SandboxData) -> Result<()> {
+        let bandwidth = get_bandwidth(data)?;
+        let network = match self.network.as_mut() {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+        network.update_bandwidth(bandwidth, &self.vm).await
+    }
+
     pub(crate) async fn forward_events(&mut self) {
         if let Some(client) = &*self.client.lock().await {
             let client = client.clone();
@@ -794,12 +1529,26 @@ fn convert_envelope(envelope: vmm_common::api::events::Envelope) -> Envelope {
     }
 }
 
-#[derive(Default, Debug, Deserialize)]
+#[derive(Clone, Default, Debug, Deserialize)]
 pub struct SandboxConfig {
     #[serde(default)]
     pub log_level: String,
     #[serde(default)]
     pub enable_tracing: bool,
+    #[serde(default)]
+    pub vm_pool: VMPoolConfig,
+    #[serde(default)]
+    pub gc: GCConfig,
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    /// Size of the /dev/shm shared by the containers of a pod, if the size is not set
+    /// by the shm mount of containers, 0 means the default 64MiB as runc pods
+    #[serde(default)]
+    pub shm_size_in_mb: u64,
+    /// Image layers attached to the vm as files, like the erofs blobs, must have fs-verity
+    /// enabled, so that every read of the vm is verified by the host against the layer digest
+    #[serde(default)]
+    pub layer_fsverity: bool,
 }
 
 impl SandboxConfig {
@@ -847,6 +1596,7 @@ fn monitor<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
             let (code, ts) = *rx.borrow();
             let mut sandbox = sandbox_mutex.lock().await;
             info!("monitor sandbox {} terminated", sandbox.id);
+            let code = sandbox.report_crash(code).await;
             sandbox.status = SandboxStatus::Stopped(code, ts);
             sandbox.exit_signal.signal();
             // Network destruction should be done after sandbox status changed from running.
@@ -859,6 +1609,7 @@ fn monitor<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
         } else {
             let mut sandbox = sandbox_mutex.lock().await;
             info!("sandbox {} already terminated before monit it", sandbox.id);
+            let code = sandbox.report_crash(code).await;
             sandbox.status = SandboxStatus::Stopped(code, ts);
             sandbox.exit_signal.signal();
             // Network destruction should be done after sandbox status changed from running.
@@ -872,8 +1623,51 @@ fn monitor<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
     });
 }
 
+// check_checkpoint checks that the sandbox can be restored from its checkpoint, the vmm may not
+// restore the devices hot attached to the vm, such as the block devices of container rootfs.
+fn check_checkpoint(
+    id: &str,
+    status: &SandboxStatus,
+    unrestorable_devices: &[String],
+) -> Result<()> {
+    if !matches!(status, SandboxStatus::Running(_)) {
+        return Err(Error::InvalidArgument(format!(
+            "sandbox {} is {:?}, only running sandbox can be checkpointed",
+            id, status
+        )));
+    }
+    if !unrestorable_devices.is_empty() {
+        return Err(Error::Unimplemented(format!(
+            "checkpoint of sandbox {} with devices {} which can not be restored",
+            id,
+            unrestorable_devices.join(",")
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    mod checkpoint {
+        use containerd_sandbox::{error::Error, SandboxStatus};
+
+        use crate::sandbox::check_checkpoint;
+
+        #[test]
+        fn test_check_checkpoint() {
+            let res = check_checkpoint("sb1", &SandboxStatus::Created, &[]);
+            assert!(matches!(res, Err(Error::InvalidArgument(_))));
+
+            let devices = vec!["blk1".to_string(), "intf1".to_string()];
+            match check_checkpoint("sb1", &SandboxStatus::Running(1), &devices) {
+                Err(Error::Unimplemented(e)) => assert!(e.contains("blk1,intf1")),
+                res => panic!("unexpected result {:?}", res),
+            }
+
+            assert!(check_checkpoint("sb1", &SandboxStatus::Running(1), &[]).is_ok());
+        }
+    }
+
     mod dns {
         use crate::sandbox::parse_dnsoptions;
 
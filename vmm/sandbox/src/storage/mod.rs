@@ -22,24 +22,34 @@ use containerd_sandbox::{
     spec::Mount,
 };
 use containerd_shim::mount::mount_rootfs;
-use log::debug;
+use log::{debug, warn};
 use nix::libc::MNT_DETACH;
 pub use utils::*;
 use vmm_common::{
     mount::{bind_mount, unmount, MNT_NOFOLLOW},
-    storage::{Storage, DRIVEREPHEMERALTYPE},
+    storage::{mount_host_source, OverlayLayer, Storage, DRIVEREPHEMERALTYPE, DRIVEROVERLAYTYPE},
     KUASAR_STATE_DIR,
 };
 
 use crate::{
-    device::{BlockDeviceInfo, DeviceInfo},
+    device::{BlockDeviceInfo, DeviceInfo, IoThrottle},
     sandbox::{KuasarSandbox, KUASAR_GUEST_SHARE_DIR},
-    storage::mount::{get_mount_info, is_bind, is_bind_shm, is_overlay},
+    storage::mount::{
+        get_backing_mount, get_mount_info, is_bind, is_bind_shm, is_image_mount, is_overlay,
+    },
+    utils::get_memory_in_mb,
     vm::{BlockDriver, VM},
 };
 
+// the type of the storages of the overlay layers attached to the vm,
+// which are mounted by the overlay storages in the guest.
+const OVERLAY_LAYER_STORAGE_TYPE: &str = "overlay-layer";
+const EMPTY_DIR_MODE: u32 = 0o777;
+const DEFAULT_SHM_SIZE_IN_MB: u64 = 64;
+
 pub mod mount;
 pub mod utils;
+pub(crate) mod watch;
 
 impl<V> KuasarSandbox<V>
 where
@@ -61,16 +71,25 @@ where
             self.handle_block_device(&id, container_id, m).await?;
             return Ok(());
         }
+        if is_image_mount(m).await {
+            self.check_layer_verity(&m.source).await?;
+            self.handle_block_device(&id, container_id, m).await?;
+            return Ok(());
+        }
         // handle tmpfs mount
         let mount_info = get_mount_info(&m.source).await?;
         if let Some(mi) = mount_info {
             // Only allow use tmpfs in emptyDir
-            if mi.fs_type == "tmpfs" && mi.mount_point.contains("kubernetes.io~empty-dir") {
+            if mi.fs_type == "tmpfs" && mi.mount_point.contains(EMPTY_DIR_PATH_KEY) {
                 self.handle_tmpfs_mount(&id, container_id, m, &mi).await?;
                 return Ok(());
             }
         }
+        if self.handle_block_empty_dir(&id, container_id, m).await? {
+            return Ok(());
+        }
         if is_bind_shm(m) {
+            self.handle_shm_mount(&id, container_id, m).await?;
             return Ok(());
         }
 
@@ -80,6 +99,12 @@ where
         }
 
         if is_overlay(m) {
+            if self
+                .handle_block_overlay_mount(&id, container_id, m)
+                .await?
+            {
+                return Ok(());
+            }
             self.handle_overlay_mount(&id, container_id, m).await?;
             return Ok(());
         }
@@ -107,6 +132,12 @@ where
         } else {
             m.source.clone()
         };
+        let throttle = get_io_throttle(
+            &m.options,
+            &source,
+            self.data.config.as_ref().map(|c| &c.annotations),
+        )?;
+        let size = get_block_device_size(&source).await?;
         let device_id = format!("blk{}", self.increment_and_get_id());
         let (bus_type, addr) = self
             .vm
@@ -114,8 +145,10 @@ where
                 id: device_id.to_string(),
                 path: source.clone(),
                 read_only,
+                throttle,
             }))
             .await?;
+        self.block_device_sizes.insert(device_id.to_string(), size);
         // only pass options "ro" to agent, as other mount options may belongs to bind mount only.
         // we have to support mounting the block device(such as /dev/sda) to a directory,
         // but CRI support only bind mount, so the mount options here, which is added by containerd,
@@ -230,7 +263,7 @@ where
             .map_err(|e| anyhow!("mount rootfs: {}", e))?;
 
         let mut storage = Storage {
-            host_source: m.source.clone(),
+            host_source: mount_host_source(m),
             r#type: m.r#type.clone(),
             id: storage_id.to_string(),
             device_id: None,
@@ -249,6 +282,276 @@ where
         Ok(())
     }
 
+    // handle_block_overlay_mount attaches the lower layers of the overlay to the vm as block
+    // devices and assembles the overlay in the guest, instead of sharing the overlay mounted
+    // on the host, it is for the snapshotters like erofs whose layers are filesystem images.
+    // the upper layer, if any, is still shared with the guest by the shared fs.
+    // false is returned if any of the lower layers is not on a read only mounted block device
+    // or image, such as the layers of the overlayfs snapshotter on the host disk.
+    async fn handle_block_overlay_mount(
+        &mut self,
+        storage_id: &str,
+        container_id: &str,
+        m: &Mount,
+    ) -> Result<bool> {
+        let mut lowerdirs = vec![];
+        let mut upperdir = None;
+        let mut workdir = None;
+        let mut options = vec![];
+        for o in &m.options {
+            if let Some(v) = o.strip_prefix("lowerdir=") {
+                lowerdirs.extend(v.split(':').map(|x| x.to_string()));
+            } else if let Some(v) = o.strip_prefix("upperdir=") {
+                upperdir = Some(v.to_string());
+            } else if let Some(v) = o.strip_prefix("workdir=") {
+                workdir = Some(v.to_string());
+            } else {
+                options.push(o.to_string());
+            }
+        }
+        if lowerdirs.is_empty() {
+            return Ok(false);
+        }
+
+        let mut layers = vec![];
+        for dir in &lowerdirs {
+            let mi = match get_backing_mount(dir).await? {
+                Some(mi) => mi,
+                None => return Ok(false),
+            };
+            // the layer is shared with the guest only if it is mounted read only on the host
+            if !mi.options.iter().any(|x| x == "ro")
+                || (!is_block_device(&mi.source).await?
+                    && !is_regular_file(&mi.source).await.unwrap_or_default())
+            {
+                return Ok(false);
+            }
+            let path = Path::new(dir)
+                .strip_prefix(&mi.mount_point)
+                .map_err(|e| anyhow!("failed to get path of {} in {}: {}", dir, mi.source, e))?;
+            layers.push((mi.source, format!("/{}", path.display())));
+        }
+        // the upper and work dirs have to be in the same dir to be shared together
+        let upper_parent = match (&upperdir, &workdir) {
+            (Some(u), Some(w)) => {
+                let parent = Path::new(u).parent();
+                if parent.is_none() || parent != Path::new(w).parent() {
+                    return Ok(false);
+                }
+                parent
+            }
+            (None, None) => None,
+            _ => return Ok(false),
+        };
+
+        debug!("assemble overlay storage for {:?} in guest", m);
+        let mut driver_options = vec![];
+        // the layers are referenced by the overlay storage rather than the container,
+        // so they are detached only after the overlay using them is gone.
+        for (source, path) in layers {
+            let layer = match self.attach_overlay_layer(storage_id, &source).await {
+                Ok(l) => l,
+                Err(e) => {
+                    self.release_overlay_layers(storage_id).await;
+                    return Err(e);
+                }
+            };
+            driver_options.push(
+                OverlayLayer {
+                    driver: layer.driver,
+                    addr: layer.source,
+                    fstype: layer.fstype,
+                    path,
+                }
+                .to_string(),
+            );
+        }
+        if let (Some(parent), Some(u), Some(w)) = (upper_parent, &upperdir, &workdir) {
+            let host_dest = format!("{}/{}", self.get_sandbox_shared_path(), &storage_id);
+            let res = match tokio::fs::create_dir_all(&host_dest).await {
+                Ok(_) => bind_mount(parent, &host_dest, &[]),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = res {
+                self.release_overlay_layers(storage_id).await;
+                return Err(e.into());
+            }
+            for (key, dir) in [("upperdir", u), ("workdir", w)] {
+                let name = Path::new(dir).file_name().unwrap_or_default();
+                options.push(format!(
+                    "{}={}/{}/{}",
+                    key,
+                    KUASAR_STATE_DIR,
+                    storage_id,
+                    name.to_string_lossy()
+                ));
+            }
+        }
+
+        let mut storage = Storage {
+            host_source: mount_host_source(m),
+            r#type: m.r#type.clone(),
+            id: storage_id.to_string(),
+            device_id: None,
+            ref_container: Default::default(),
+            need_guest_handle: true,
+            source: "overlay".to_string(),
+            driver: DRIVEROVERLAYTYPE.to_string(),
+            driver_options,
+            fstype: "overlay".to_string(),
+            options,
+            mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, storage_id),
+        };
+
+        storage.refer(container_id);
+        self.storages.push(storage);
+        Ok(true)
+    }
+
+    // attach_overlay_layer attaches the device of the overlay layer to the vm read only,
+    // the layers shared by the overlay storages are attached only once.
+    async fn attach_overlay_layer(&mut self, overlay_id: &str, source: &str) -> Result<Storage> {
+        if let Some(s) = self
+            .storages
+            .iter_mut()
+            .find(|s| s.host_source == source && s.r#type == OVERLAY_LAYER_STORAGE_TYPE)
+        {
+            s.refer(overlay_id);
+            return Ok(s.clone());
+        }
+
+        self.check_layer_verity(source).await?;
+        let fstype = get_fstype(source).await?;
+        let id = format!("storage{}", self.increment_and_get_id());
+        let device_id = format!("blk{}", self.increment_and_get_id());
+        let (bus_type, addr) = self
+            .vm
+            .hot_attach(DeviceInfo::Block(BlockDeviceInfo {
+                id: device_id.to_string(),
+                path: source.to_string(),
+                read_only: true,
+                throttle: IoThrottle::default(),
+            }))
+            .await?;
+        let mut storage = Storage {
+            host_source: source.to_string(),
+            r#type: OVERLAY_LAYER_STORAGE_TYPE.to_string(),
+            id,
+            device_id: Some(device_id),
+            ref_container: HashMap::new(),
+            need_guest_handle: false,
+            source: addr.to_string(),
+            driver: BlockDriver::from_bus_type(&bus_type).to_driver_string(),
+            driver_options: vec![],
+            fstype,
+            options: vec!["ro".to_string()],
+            mount_point: "".to_string(),
+        };
+        storage.refer(overlay_id);
+        self.storages.push(storage.clone());
+        Ok(storage)
+    }
+
+    // release_overlay_layers drops the references of the overlay storage to its layers,
+    // the layers not used by any other overlay are detached.
+    async fn release_overlay_layers(&mut self, overlay_id: &str) {
+        for s in self
+            .storages
+            .iter_mut()
+            .filter(|s| s.r#type == OVERLAY_LAYER_STORAGE_TYPE)
+        {
+            s.defer(overlay_id);
+        }
+        if let Err(e) = self.gc_storages().await {
+            warn!("failed to detach layers of overlay {}: {}", overlay_id, e);
+        }
+    }
+
+    // check_layer_verity refuses the image layer without fs-verity if it is required,
+    // only the layers of files, or of the loop devices of files, are checked.
+    async fn check_layer_verity(&self, source: &str) -> Result<()> {
+        if !self.layer_fsverity {
+            return Ok(());
+        }
+        if let Some(blob) = get_layer_blob(source).await? {
+            if !is_verity_enabled(&blob)? {
+                return Err(Error::InvalidArgument(format!(
+                    "fs-verity is not enabled on image layer {}",
+                    blob
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // handle_block_empty_dir attaches a sparse ext4 image to the vm for the disk medium emptyDir,
+    // so that the sizeLimit is enforced by the filesystem in the guest, instead of being
+    // only checked by the eviction of kubelet, it is enabled by the annotation of the pod.
+    // false is returned if the mount is not of such an emptyDir with the sizeLimit.
+    async fn handle_block_empty_dir(
+        &mut self,
+        storage_id: &str,
+        container_id: &str,
+        m: &Mount,
+    ) -> Result<bool> {
+        if !is_bind(m) {
+            return Ok(false);
+        }
+        let annotations = self.data.config.as_ref().map(|c| &c.annotations);
+        let enabled = annotations
+            .and_then(|a| a.get(EMPTY_DIR_BLOCK_ANNOTATION))
+            .map(|v| v == "true")
+            .unwrap_or_default();
+        let name = match get_empty_dir_name(&m.source) {
+            Some(name) if enabled => name,
+            _ => return Ok(false),
+        };
+        let size = match get_empty_dir_size_limit(name, annotations)? {
+            Some(size) => size,
+            None => return Ok(false),
+        };
+        let throttle = get_io_throttle(&m.options, &m.source, annotations)?;
+
+        let image = get_empty_dir_image_path(&m.source)
+            .ok_or_else(|| anyhow!("no image path of emptyDir {}", m.source))?;
+        if let Some(dir) = Path::new(&image).parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| anyhow!("failed to create {}: {}", dir.display(), e))?;
+        }
+        create_fs_image(&image, size).await?;
+        let device_id = format!("blk{}", self.increment_and_get_id());
+        let (bus_type, addr) = self
+            .vm
+            .hot_attach(DeviceInfo::Block(BlockDeviceInfo {
+                id: device_id.to_string(),
+                path: image,
+                // the volume is shared by containers, "ro" of the mount is done by the bind mount
+                read_only: false,
+                throttle,
+            }))
+            .await?;
+
+        let mut storage = Storage {
+            host_source: m.source.clone(),
+            r#type: m.r#type.clone(),
+            id: storage_id.to_string(),
+            device_id: Some(device_id),
+            ref_container: HashMap::new(),
+            need_guest_handle: true,
+            source: addr.to_string(),
+            driver: BlockDriver::from_bus_type(&bus_type).to_driver_string(),
+            driver_options: vec![format!("mode={:o}", EMPTY_DIR_MODE)],
+            fstype: "ext4".to_string(),
+            options: vec![],
+            mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, storage_id),
+        };
+
+        storage.refer(container_id);
+        self.storages.push(storage);
+        Ok(true)
+    }
+
     async fn handle_tmpfs_mount(
         &mut self,
         storage_id: &str,
@@ -276,10 +579,38 @@ where
             options,
             mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, storage_id),
         };
-        // only handle size option because other options may not supported in guest
-        for o in &mount_info.options {
-            if o.starts_with("size=") {
-                storage.options.push(o.to_string());
+        let annotations = self.data.config.as_ref().map(|c| &c.annotations);
+        let size_limit = match get_empty_dir_name(&m.source) {
+            Some(name) => get_empty_dir_size_limit(name, annotations)?,
+            None => None,
+        };
+        if let Some(mut size) = size_limit {
+            // the tmpfs takes the memory of the guest, so all of the sized tmpfs together
+            // are no larger than the sandbox memory
+            let memory = get_memory_in_mb(&self.data) * bytefmt::MIB;
+            if memory > 0 {
+                let available = memory.saturating_sub(self.sized_tmpfs_usage());
+                if available == 0 {
+                    return Err(Error::ResourceExhausted(format!(
+                        "no memory of sandbox {} is left for emptyDir {}",
+                        self.id, m.source
+                    )));
+                }
+                if size > available {
+                    warn!(
+                        "size limit {} of emptyDir {} exceeds the memory {} left in the sandbox",
+                        size, m.source, available
+                    );
+                    size = available;
+                }
+            }
+            storage.options.push(format!("size={}", size));
+        } else {
+            // only handle size option because other options may not supported in guest
+            for o in &mount_info.options {
+                if o.starts_with("size=") {
+                    storage.options.push(o.to_string());
+                }
             }
         }
         storage.refer(container_id);
@@ -287,31 +618,120 @@ where
         Ok(())
     }
 
-    async fn gc_storages(&mut self) -> Result<()> {
-        let storage_infos: Vec<(Option<String>, String, String)> = self
-            .storages
+    // sized_tmpfs_usage is the total size of the memory medium emptyDir volumes
+    // that are sized by the size limit annotations.
+    fn sized_tmpfs_usage(&self) -> u64 {
+        let annotations = self.data.config.as_ref().map(|c| &c.annotations);
+        self.storages
             .iter()
-            .filter(|&x| x.ref_count() == 0)
-            .map(|s| (s.device_id.clone(), s.id.clone(), s.fstype.clone()))
-            .collect();
-        for info in storage_infos {
-            self.detach_storage(info.0.clone(), &info.1, &info.2)
-                .await?;
-            self.storages.retain(|x| x.id != info.1);
+            .filter(|s| s.driver == DRIVEREPHEMERALTYPE && s.fstype == "tmpfs")
+            .filter(|s| {
+                get_empty_dir_name(&s.host_source)
+                    .and_then(|name| get_empty_dir_size_limit(name, annotations).ok())
+                    .flatten()
+                    .is_some()
+            })
+            .filter_map(|s| {
+                s.options
+                    .iter()
+                    .find_map(|o| o.strip_prefix("size="))
+                    .and_then(|v| v.parse::<u64>().ok())
+            })
+            .sum()
+    }
+
+    // handle_shm_mount mounts a tmpfs in the guest as the /dev/shm of the containers,
+    // all of the containers of the pod bind mount the same shm source, so they share the
+    // storage and the ipc by the shared memory works like the pods of runc.
+    async fn handle_shm_mount(
+        &mut self,
+        storage_id: &str,
+        container_id: &str,
+        m: &Mount,
+    ) -> Result<()> {
+        // the size is taken from the shm mount, the tmpfs of it on the host, or the config
+        let mut size = m.options.iter().find(|x| x.starts_with("size=")).cloned();
+        if size.is_none() {
+            if let Some(mi) = get_mount_info(&m.source).await? {
+                size = mi.options.into_iter().find(|x| x.starts_with("size="));
+            }
         }
+        let size = size.unwrap_or_else(|| {
+            let size_in_mb = if self.shm_size_in_mb > 0 {
+                self.shm_size_in_mb
+            } else {
+                DEFAULT_SHM_SIZE_IN_MB
+            };
+            format!("size={}", size_in_mb * bytefmt::MIB)
+        });
+
+        let mut storage = Storage {
+            host_source: m.source.clone(),
+            r#type: m.r#type.clone(),
+            id: storage_id.to_string(),
+            device_id: None,
+            ref_container: Default::default(),
+            need_guest_handle: true,
+            source: "shm".to_string(),
+            driver: DRIVEREPHEMERALTYPE.to_string(),
+            driver_options: vec![],
+            fstype: "tmpfs".to_string(),
+            options: vec![
+                "nosuid".to_string(),
+                "nodev".to_string(),
+                "noexec".to_string(),
+                "mode=1777".to_string(),
+                size,
+            ],
+            mount_point: format!("{}{}", KUASAR_GUEST_SHARE_DIR, storage_id),
+        };
+        storage.refer(container_id);
+        self.storages.push(storage);
         Ok(())
     }
 
+    async fn gc_storages(&mut self) -> Result<()> {
+        loop {
+            let storage_infos: Vec<(Option<String>, String, String)> = self
+                .storages
+                .iter()
+                .filter(|&x| x.ref_count() == 0)
+                .map(|s| (s.device_id.clone(), s.id.clone(), s.fstype.clone()))
+                .collect();
+            if storage_infos.is_empty() {
+                return Ok(());
+            }
+            for info in storage_infos {
+                self.detach_storage(info.0.clone(), &info.1, &info.2)
+                    .await?;
+                self.storages.retain(|x| x.id != info.1);
+                // the layers of the removed overlay storage are collected in the next round
+                for s in self
+                    .storages
+                    .iter_mut()
+                    .filter(|s| s.r#type == OVERLAY_LAYER_STORAGE_TYPE)
+                {
+                    s.defer(&info.1);
+                }
+            }
+        }
+    }
+
     async fn detach_storage(
         &mut self,
         device_id: Option<String>,
         id: &str,
         fs_type: &str,
     ) -> Result<()> {
-        if device_id.is_some() {
-            self.vm.hot_detach(&device_id.unwrap()).await?;
-        } else if fs_type == "bind" {
+        if let Some(device_id) = device_id {
+            self.vm.hot_detach(&device_id).await?;
+            self.block_device_sizes.remove(&device_id);
+        } else if fs_type == "bind" || fs_type == "overlay" {
             let mount_point = format!("{}/{}", self.get_sandbox_shared_path(), &id);
+            // only the overlay with the upper layer has the shared mount
+            if fs_type == "overlay" && !Path::new(&mount_point).exists() {
+                return Ok(());
+            }
             unmount(&mount_point, MNT_DETACH | MNT_NOFOLLOW)?;
             if Path::new(&mount_point).is_dir() {
                 tokio::fs::remove_dir(&mount_point).await.map_err(|e| {
@@ -338,6 +758,7 @@ where
 }
 
 pub struct MountInfo {
+    pub source: String,
     pub mount_point: String,
     pub fs_type: String,
     pub options: Vec<String>,
@@ -14,11 +14,16 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::path::Path;
+
 use anyhow::anyhow;
 use containerd_sandbox::{error::Result, spec::Mount};
 use vmm_common::DEV_SHM;
 
-use crate::{storage::MountInfo, utils::read_file};
+use crate::{
+    storage::{is_regular_file, MountInfo},
+    utils::read_file,
+};
 
 pub fn is_bind_shm(m: &Mount) -> bool {
     is_bind(m) && m.destination == DEV_SHM
@@ -32,27 +37,58 @@ pub fn is_overlay(m: &Mount) -> bool {
     m.r#type == "overlay"
 }
 
+// is_image_mount returns true for the filesystem images mounted by the snapshotters,
+// such as the erofs layer blobs, which are attached to the vm as block devices.
+pub async fn is_image_mount(m: &Mount) -> bool {
+    if m.r#type != "erofs" && !m.options.iter().any(|x| x == "loop") {
+        return false;
+    }
+    is_regular_file(&m.source).await.unwrap_or_default()
+}
+
 pub async fn get_mount_info(mount_point: &str) -> Result<Option<MountInfo>> {
     if mount_point.is_empty() {
         return Ok(None);
     }
+    let mounts = get_mounts().await?;
+    Ok(mounts.into_iter().find(|x| x.mount_point == mount_point))
+}
+
+// get_backing_mount returns the mount that the path is in,
+// which is the last mounted one of the longest mount point.
+pub async fn get_backing_mount(path: &str) -> Result<Option<MountInfo>> {
+    let mounts = get_mounts().await?;
+    let mut backing: Option<MountInfo> = None;
+    for mi in mounts {
+        if !Path::new(path).starts_with(&mi.mount_point) {
+            continue;
+        }
+        if backing
+            .as_ref()
+            .map(|x| x.mount_point.len() <= mi.mount_point.len())
+            .unwrap_or(true)
+        {
+            backing = Some(mi);
+        }
+    }
+    Ok(backing)
+}
+
+async fn get_mounts() -> Result<Vec<MountInfo>> {
     let mounts = read_file("/proc/mounts").await?;
+    let mut res = vec![];
     for line in mounts.lines() {
         let fields = line.split_whitespace().collect::<Vec<&str>>();
         if fields.len() < 4 {
             return Err(anyhow!("the line '{}' in /proc/mounts has format error", line).into());
         }
         // format: "/dev/sdc /mnt ext4 rw,relatime,stripe=64 0 0"
-        let mp = fields[1].to_string();
-        if mp == mount_point {
-            let fs_type = fields[2].to_string();
-            let options = fields[3].split(',').map(|x| x.to_string()).collect();
-            return Ok(Some(MountInfo {
-                mount_point: mp,
-                fs_type,
-                options,
-            }));
-        }
+        res.push(MountInfo {
+            source: fields[0].to_string(),
+            mount_point: fields[1].to_string(),
+            fs_type: fields[2].to_string(),
+            options: fields[3].split(',').map(|x| x.to_string()).collect(),
+        });
     }
-    Ok(None)
+    Ok(res)
 }
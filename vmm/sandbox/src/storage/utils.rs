@@ -14,11 +14,41 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{fs::FileType, os::unix::fs::FileTypeExt, path::Path, process::Stdio};
+use std::{
+    collections::HashMap,
+    fs::FileType,
+    io::{ErrorKind, SeekFrom},
+    os::unix::{fs::FileTypeExt, io::AsRawFd},
+    path::Path,
+    process::Stdio,
+};
 
 use anyhow::anyhow;
-use containerd_sandbox::error::Result;
-use tokio::process::Command;
+use containerd_sandbox::error::{Error, Result};
+use nix::{ioctl_read_bad, libc};
+use tokio::{io::AsyncSeekExt, process::Command};
+
+use crate::{device::IoThrottle, network::bandwidth::parse_quantity};
+
+// the io limits of a block volume are set by the annotations with the volume name as the
+// suffix, which is the last component of the host path of the volume.
+pub const BLOCK_IOPS_ANNOTATION_PREFIX: &str = "io.kuasar.block.iops.";
+pub const BLOCK_BPS_ANNOTATION_PREFIX: &str = "io.kuasar.block.bps.";
+// the sizeLimit of the emptyDir volume is set by the annotation with the volume name
+// as the suffix, as the volumes of the pod spec are not passed to the sandboxer.
+pub const EMPTY_DIR_SIZE_LIMIT_ANNOTATION_PREFIX: &str = "io.kuasar.emptydir.size-limit.";
+// the disk medium emptyDir volumes with sizeLimit are attached to the vm as block devices
+// if the annotation is "true", instead of being shared by the shared fs.
+pub const EMPTY_DIR_BLOCK_ANNOTATION: &str = "io.kuasar.emptydir.block";
+pub const EMPTY_DIR_PATH_KEY: &str = "kubernetes.io~empty-dir";
+// the images of the emptyDir volumes attached as block devices are in this dir of the pod dir
+// of kubelet, out of the volume dirs, and they are removed together with the pod dir.
+const EMPTY_DIR_IMAGE_DIR: &str = "kuasar-emptydir";
+const BLOCK_IOPS_OPTION: &str = "iops=";
+const BLOCK_BPS_OPTION: &str = "bps=";
+const FS_VERITY_FL: libc::c_int = 0x00100000;
+
+ioctl_read_bad!(ioctl_get_flags, libc::FS_IOC_GETFLAGS, libc::c_int);
 
 pub async fn is_block_device<P: AsRef<Path>>(path: P) -> Result<bool> {
     let file_type = match get_file_type(path).await {
@@ -80,3 +110,216 @@ pub async fn get_fstype(path: &str) -> Result<String> {
     }
     Err(anyhow!("failed to get fstype of {}", path).into())
 }
+
+// get_block_device_size returns the size in bytes of the block device
+pub async fn get_block_device_size(path: &str) -> Result<u64> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| anyhow!("failed to open {}, {}", path, e))?;
+    let size = file
+        .seek(SeekFrom::End(0))
+        .await
+        .map_err(|e| anyhow!("failed to get size of {}, {}", path, e))?;
+    Ok(size)
+}
+
+// get_layer_blob returns the file of the image layer, which is the source itself,
+// or the backing file of the loop device the layer is mounted from.
+pub async fn get_layer_blob(source: &str) -> Result<Option<String>> {
+    if is_regular_file(source).await.unwrap_or_default() {
+        return Ok(Some(source.to_string()));
+    }
+    let name = match Path::new(source).file_name().and_then(|n| n.to_str()) {
+        Some(n) if n.starts_with("loop") => n,
+        _ => return Ok(None),
+    };
+    let path = format!("/sys/block/{}/loop/backing_file", name);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(f) => Ok(Some(f.trim().to_string())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow!("failed to read {}, {}", path, e).into()),
+    }
+}
+
+// is_verity_enabled returns true if fs-verity is enabled on the file,
+// then every read of it is verified by the host kernel against the digest of the file.
+pub fn is_verity_enabled(path: &str) -> Result<bool> {
+    let file = std::fs::File::open(path).map_err(|e| anyhow!("failed to open {}, {}", path, e))?;
+    let mut flags: libc::c_int = 0;
+    // SAFETY: the flags is a valid pointer to the int written by the ioctl
+    unsafe { ioctl_get_flags(file.as_raw_fd(), &mut flags) }
+        .map_err(|e| anyhow!("failed to get flags of {}, {}", path, e))?;
+    Ok(flags & FS_VERITY_FL != 0)
+}
+
+// get_io_throttle returns the io limits of the block volume, which are taken from the
+// mount options "iops=" and "bps=", or else from the annotations of the volume name.
+pub fn get_io_throttle(
+    options: &[String],
+    source: &str,
+    annotations: Option<&HashMap<String, String>>,
+) -> Result<IoThrottle> {
+    let parse = |key: &str, value: &str| -> Result<u64> {
+        value
+            .parse::<u64>()
+            .map_err(|e| Error::InvalidArgument(format!("{}{}: {}", key, value, e)))
+    };
+    let mut throttle = IoThrottle::default();
+    let name = Path::new(source).file_name().and_then(|n| n.to_str());
+    if let (Some(a), Some(name)) = (annotations, name) {
+        let key = format!("{}{}", BLOCK_IOPS_ANNOTATION_PREFIX, name);
+        if let Some(v) = a.get(&key) {
+            throttle.iops = parse(&key, v)?;
+        }
+        let key = format!("{}{}", BLOCK_BPS_ANNOTATION_PREFIX, name);
+        if let Some(v) = a.get(&key) {
+            throttle.bps = parse(&key, v)?;
+        }
+    }
+    for o in options {
+        if let Some(v) = o.strip_prefix(BLOCK_IOPS_OPTION) {
+            throttle.iops = parse(BLOCK_IOPS_OPTION, v)?;
+        } else if let Some(v) = o.strip_prefix(BLOCK_BPS_OPTION) {
+            throttle.bps = parse(BLOCK_BPS_OPTION, v)?;
+        }
+    }
+    Ok(throttle)
+}
+
+// get_empty_dir_name returns the name of the emptyDir volume of the host path,
+// which is like "/var/lib/kubelet/pods/<pod uid>/volumes/kubernetes.io~empty-dir/<name>".
+pub fn get_empty_dir_name(path: &str) -> Option<&str> {
+    let mut components = Path::new(path).components().rev();
+    let name = components.next()?.as_os_str().to_str()?;
+    if components.next()?.as_os_str() != EMPTY_DIR_PATH_KEY {
+        return None;
+    }
+    Some(name)
+}
+
+// get_empty_dir_image_path returns the path of the image of the emptyDir volume of the host
+// path, which is like "/var/lib/kubelet/pods/<pod uid>/kuasar-emptydir/<name>.img".
+pub fn get_empty_dir_image_path(path: &str) -> Option<String> {
+    let name = get_empty_dir_name(path)?;
+    // the parents are the empty-dir plugin dir and the volumes dir of the pod
+    let pod_dir = Path::new(path).parent()?.parent()?.parent()?;
+    Some(format!(
+        "{}/{}/{}.img",
+        pod_dir.display(),
+        EMPTY_DIR_IMAGE_DIR,
+        name
+    ))
+}
+
+// get_empty_dir_size_limit returns the sizeLimit in bytes of the emptyDir volume
+pub fn get_empty_dir_size_limit(
+    name: &str,
+    annotations: Option<&HashMap<String, String>>,
+) -> Result<Option<u64>> {
+    let key = format!("{}{}", EMPTY_DIR_SIZE_LIMIT_ANNOTATION_PREFIX, name);
+    match annotations.and_then(|a| a.get(&key)) {
+        Some(v) => Ok(Some(parse_quantity(v).map_err(|e| {
+            Error::InvalidArgument(format!("annotation {}: {}", key, e))
+        })?)),
+        None => Ok(None),
+    }
+}
+
+// create_fs_image creates the sparse image file of the size formatted with ext4,
+// the image is reused if it exists already, like the one of the recovered sandbox.
+pub async fn create_fs_image(path: &str, size: u64) -> Result<()> {
+    if Path::new(path).exists() {
+        return Ok(());
+    }
+    let file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| anyhow!("failed to create {}, {}", path, e))?;
+    file.set_len(size)
+        .await
+        .map_err(|e| anyhow!("failed to set size of {}, {}", path, e))?;
+    let output = Command::new("mkfs.ext4")
+        .args(["-q", "-F", path])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .map_err(|e| anyhow!("failed to execute command {}", e));
+    let res = match output {
+        Ok(o) if o.status.success() => Ok(()),
+        Ok(o) => Err(anyhow!(
+            "failed to execute command mkfs.ext4, exit code: {}, error message: {}",
+            o.status,
+            String::from_utf8_lossy(&o.stderr)
+        )
+        .into()),
+        Err(e) => Err(e.into()),
+    };
+    if res.is_err() {
+        tokio::fs::remove_file(path).await.unwrap_or_default();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::storage::utils::{
+        get_empty_dir_image_path, get_empty_dir_name, get_empty_dir_size_limit, get_io_throttle,
+    };
+
+    #[test]
+    fn test_get_io_throttle() {
+        let mut annotations = HashMap::new();
+        annotations.insert("io.kuasar.block.iops.data".to_string(), "500".to_string());
+        annotations.insert(
+            "io.kuasar.block.bps.data".to_string(),
+            "1048576".to_string(),
+        );
+        let source = "/var/lib/kubelet/pods/uid/volumeDevices/kubernetes.io~csi/data";
+        let options = vec!["rbind".to_string(), "iops=1000".to_string()];
+        let throttle = get_io_throttle(&options, source, Some(&annotations)).unwrap();
+        assert_eq!(throttle.iops, 1000);
+        assert_eq!(throttle.bps, 1048576);
+
+        // the limits of the other volumes are not taken
+        let throttle = get_io_throttle(&[], "/dev/sdb", Some(&annotations)).unwrap();
+        assert!(throttle.is_empty());
+
+        assert!(get_io_throttle(&options, source, None).unwrap().bps == 0);
+        assert!(get_io_throttle(&["bps=1M".to_string()], source, None).is_err());
+    }
+
+    #[test]
+    fn test_get_empty_dir_size_limit() {
+        let path = "/var/lib/kubelet/pods/uid/volumes/kubernetes.io~empty-dir/cache";
+        assert_eq!(get_empty_dir_name(path), Some("cache"));
+        assert_eq!(
+            get_empty_dir_image_path(path).as_deref(),
+            Some("/var/lib/kubelet/pods/uid/kuasar-emptydir/cache.img")
+        );
+        assert_eq!(
+            get_empty_dir_name("/var/lib/kubelet/pods/uid/volumes/kubernetes.io~configmap/cm"),
+            None
+        );
+
+        let mut annotations = HashMap::new();
+        annotations.insert(
+            "io.kuasar.emptydir.size-limit.cache".to_string(),
+            "1Gi".to_string(),
+        );
+        assert_eq!(
+            get_empty_dir_size_limit("cache", Some(&annotations)).unwrap(),
+            Some(1 << 30)
+        );
+        assert_eq!(
+            get_empty_dir_size_limit("data", Some(&annotations)).unwrap(),
+            None
+        );
+        annotations.insert(
+            "io.kuasar.emptydir.size-limit.data".to_string(),
+            "1X".to_string(),
+        );
+        assert!(get_empty_dir_size_limit("data", Some(&annotations)).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use containerd_sandbox::{error::Error, SandboxStatus};
+use log::{debug, info, warn};
+use tokio::sync::Mutex;
+
+use crate::{sandbox::KuasarSandbox, storage::utils::get_block_device_size, vm::VM};
+
+const BLOCK_DEVICE_CHECK_INTERVAL_IN_SEC: u64 = 10;
+
+// watch_block_devices checks the size of the block volumes of the running sandbox,
+// and resizes the device in the vm online after the volume is expanded on the host.
+pub(crate) fn watch_block_devices<V: VM + Sync + Send + 'static>(
+    sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>,
+) {
+    tokio::spawn(async move {
+        let (id, exit_signal) = {
+            let sandbox = sandbox_mutex.lock().await;
+            (sandbox.id.to_string(), sandbox.exit_signal.clone())
+        };
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(BLOCK_DEVICE_CHECK_INTERVAL_IN_SEC)) => {},
+                _ = exit_signal.wait() => {
+                    debug!("stop block device watch of exited sandbox {}", id);
+                    return;
+                },
+            }
+            // the sizes are checked without the lock of the sandbox, which is taken again
+            // only to resize the expanded devices, as the vm is only reachable with it.
+            let devices = {
+                let sandbox = sandbox_mutex.lock().await;
+                if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+                    return;
+                }
+                sandbox.block_devices()
+            };
+            let expanded = expanded_block_devices(devices).await;
+            if expanded.is_empty() {
+                continue;
+            }
+            let mut sandbox = sandbox_mutex.lock().await;
+            if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+                return;
+            }
+            sandbox.resize_block_devices(expanded).await;
+        }
+    });
+}
+
+// BlockDevice is the block device attached to the vm for a volume on the host
+struct BlockDevice {
+    id: String,
+    source: String,
+    size: u64,
+}
+
+// expanded_block_devices returns the devices with the new sizes of the expanded volumes
+async fn expanded_block_devices(devices: Vec<BlockDevice>) -> Vec<BlockDevice> {
+    let mut expanded = vec![];
+    for mut device in devices {
+        let new_size = match get_block_device_size(&device.source).await {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("failed to get size of {}: {}", device.source, e);
+                continue;
+            }
+        };
+        // the volume can only be expanded
+        if new_size > device.size {
+            device.size = new_size;
+            expanded.push(device);
+        }
+    }
+    expanded
+}
+
+impl<V> KuasarSandbox<V>
+where
+    V: VM + Sync + Send,
+{
+    fn block_devices(&self) -> Vec<BlockDevice> {
+        self.storages
+            .iter()
+            .filter_map(|s| {
+                let id = s.device_id.as_ref()?;
+                let size = self.block_device_sizes.get(id)?;
+                Some(BlockDevice {
+                    id: id.to_string(),
+                    source: s.host_source.to_string(),
+                    size: *size,
+                })
+            })
+            .collect()
+    }
+
+    async fn resize_block_devices(&mut self, devices: Vec<BlockDevice>) {
+        let mut resized = false;
+        for device in devices {
+            // the device may be detached while the lock is released
+            let size = match self.block_device_sizes.get(&device.id) {
+                Some(s) if *s < device.size => *s,
+                _ => continue,
+            };
+            match self.vm.resize_block(&device.id, device.size).await {
+                Ok(_) => {
+                    info!(
+                        "block device {} of sandbox {} is resized from {} to {}",
+                        device.id, self.id, size, device.size
+                    );
+                }
+                Err(Error::Unimplemented(e)) => {
+                    warn!("{} is not supported, {} is not resized", e, device.source);
+                }
+                Err(e) => {
+                    warn!("failed to resize block device {}: {}", device.id, e);
+                    continue;
+                }
+            }
+            self.block_device_sizes.insert(device.id, device.size);
+            resized = true;
+        }
+        if resized {
+            if let Err(e) = self.dump().await {
+                warn!("failed to dump sandbox {}: {}", self.id, e);
+            }
+        }
+    }
+}
@@ -20,8 +20,10 @@ use sandbox_derive::{CmdLineParamSet, CmdLineParams};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    device::Transport, param::ToCmdLineParams,
-    stratovirt::virtiofs::DEFAULT_VHOST_USER_FS_BIN_PATH, vm::HypervisorCommonConfig,
+    device::Transport,
+    param::ToCmdLineParams,
+    virtiofsd::{VirtiofsdConfig, DEFAULT_VHOST_USER_FS_BIN_PATH},
+    vm::HypervisorCommonConfig,
 };
 
 pub(crate) const MACHINE_TYPE_VIRT: &str = "virt";
@@ -39,11 +41,12 @@ pub struct StratoVirtVMConfig {
     #[serde(flatten)]
     pub common: HypervisorCommonConfig,
     pub virtiofsd_conf: VirtiofsdConfig,
-}
-
-#[derive(Deserialize, Serialize, Clone, Debug)]
-pub struct VirtiofsdConfig {
-    pub path: String,
+    // boot vms by restoring from the snapshot of a template vm
+    #[serde(default)]
+    pub enable_template: bool,
+    // the directory of the vm templates, DEFAULT_TEMPLATE_PATH if it is empty
+    #[serde(default)]
+    pub template_path: String,
 }
 
 impl Default for StratoVirtVMConfig {
@@ -54,8 +57,11 @@ impl Default for StratoVirtVMConfig {
             machine_type: MACHINE_TYPE_VIRT.to_string(),
             virtiofsd_conf: VirtiofsdConfig {
                 path: DEFAULT_VHOST_USER_FS_BIN_PATH.to_string(),
+                ..Default::default()
             },
             block_device_driver: "virtio-blk".to_string(),
+            enable_template: false,
+            template_path: "".to_string(),
         }
     }
 }
@@ -255,6 +261,8 @@ pub struct StratoVirtConfig {
     pub global_params: Vec<Global>,
     pub knobs: Knobs,
     pub firmware: Option<Firmware>,
+    // the snapshot the vm is restored from, "file:<dir>"
+    pub incoming: Option<String>,
 }
 
 #[cfg(test)]
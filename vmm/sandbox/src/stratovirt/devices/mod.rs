@@ -31,6 +31,7 @@ pub mod block;
 pub mod char;
 pub mod console;
 pub mod pcie_rootbus;
+pub mod pvpanic;
 pub mod rng;
 pub mod rootport;
 pub mod serial;
@@ -46,6 +47,7 @@ pub(crate) const DEFAULT_RNG_DEVICE_ID: &str = "rng0";
 pub(crate) const DEFAULT_SERIAL_DEVICE_ID: &str = "virtio-serial0";
 pub(crate) const DEFAULT_CONSOLE_DEVICE_ID: &str = "virtio-console0";
 pub(crate) const DEFAULT_CONSOLE_CHARDEV_ID: &str = "charconsole0";
+pub(crate) const DEFAULT_PVPANIC_DEVICE_ID: &str = "pvpanic0";
 
 pub trait StratoVirtDevice: Device + ToCmdLineParams + SetDeviceAddr {}
 
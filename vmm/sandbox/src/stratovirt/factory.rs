@@ -14,8 +14,11 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::path::Path;
+
 use async_trait::async_trait;
-use containerd_sandbox::SandboxOption;
+use containerd_sandbox::{data::SandboxData, error::Result, SandboxOption};
+use log::{debug, warn};
 use tokio::fs::create_dir_all;
 use uuid::Uuid;
 use vmm_common::SHARED_DIR_SUFFIX;
@@ -25,24 +28,31 @@ use super::devices::{
     char::CharDevice,
     console::VirtConsole,
     create_pcie_root_bus,
+    pvpanic::PvpanicDevice,
     rng::VirtioRngDevice,
     serial::SerialDevice,
     vhost_user_fs::{VhostUserFs, DEFAULT_MOUNT_TAG_NAME},
-    DEFAULT_CONSOLE_CHARDEV_ID, DEFAULT_CONSOLE_DEVICE_ID, DEFAULT_PCIE_BUS, DEFAULT_RNG_DEVICE_ID,
-    DEFAULT_SERIAL_DEVICE_ID, PCIE_ROOTPORT_CAPACITY,
+    DEFAULT_CONSOLE_CHARDEV_ID, DEFAULT_CONSOLE_DEVICE_ID, DEFAULT_PCIE_BUS,
+    DEFAULT_PVPANIC_DEVICE_ID, DEFAULT_RNG_DEVICE_ID, DEFAULT_SERIAL_DEVICE_ID,
+    PCIE_ROOTPORT_CAPACITY,
 };
 use crate::{
     stratovirt::{
         config::{QmpSocket, StratoVirtVMConfig, MACHINE_TYPE_MICROVM},
         devices::vsock::{find_context_id, VSockDevice},
+        template::{StratoVirtTemplate, DEFAULT_TEMPLATE_PATH},
         StratoVirtVM,
     },
+    template::TemplateStore,
     utils::get_netns,
-    vm::{BlockDriver, VMFactory},
+    virtiofsd::VirtiofsDaemon,
+    vm::{BlockDriver, VMFactory, VM},
 };
 
+#[derive(Clone)]
 pub struct StratoVirtVMFactory {
     default_config: StratoVirtVMConfig,
+    templates: TemplateStore<StratoVirtTemplate>,
 }
 
 #[async_trait]
@@ -51,8 +61,14 @@ impl VMFactory for StratoVirtVMFactory {
     type Config = StratoVirtVMConfig;
 
     fn new(config: Self::Config) -> Self {
+        let root = if config.template_path.is_empty() {
+            DEFAULT_TEMPLATE_PATH.to_string()
+        } else {
+            config.template_path.to_string()
+        };
         Self {
             default_config: config,
+            templates: TemplateStore::new(&root),
         }
     }
 
@@ -61,6 +77,26 @@ impl VMFactory for StratoVirtVMFactory {
         id: &str,
         s: &SandboxOption,
     ) -> containerd_sandbox::error::Result<Self::VM> {
+        let mut vm = self.new_vm(id, s).await?;
+        // network devices can not be hot attached, so the vm with pod network boots without template
+        if self.default_config.enable_template && get_netns(&s.sandbox).is_empty() {
+            let factory = self.clone();
+            vm.template = self
+                .templates
+                .acquire(id, &s.base_dir, move |dir| async move {
+                    factory.create_template(&dir).await
+                })
+                .await;
+            if vm.template.is_none() {
+                debug!("vm template is not ready, boot vm {} without it", id);
+            }
+        }
+        Ok(vm)
+    }
+}
+
+impl StratoVirtVMFactory {
+    async fn new_vm(&self, id: &str, s: &SandboxOption) -> Result<StratoVirtVM> {
         let netns = get_netns(&s.sandbox);
         let mut vm = StratoVirtVM::new(id, &netns, &s.base_dir);
         vm.config = self.default_config.to_stratovirt_config().await?;
@@ -98,6 +134,12 @@ impl VMFactory for StratoVirtVMFactory {
         );
         vm.attach_to_bus(rng_device)?;
 
+        // set pvpanic device, it is a pci device so the microvm can not have it
+        if self.default_config.common.enable_pvpanic && vm.pcie_root_bus.is_some() {
+            let pvpanic = PvpanicDevice::new(DEFAULT_PVPANIC_DEVICE_ID, DEFAULT_PCIE_BUS);
+            vm.attach_to_bus(pvpanic)?;
+        }
+
         // set console
         let serial = SerialDevice::new(
             DEFAULT_SERIAL_DEVICE_ID,
@@ -135,9 +177,11 @@ impl VMFactory for StratoVirtVMFactory {
         //share fs, stratovirt only support virtiofs share
         let share_fs_path = format!("{}/{}", s.base_dir, SHARED_DIR_SUFFIX);
         create_dir_all(&share_fs_path).await?;
-        let absolute_virtiofs_sock = format!("{}/virtiofs.sock", s.base_dir);
+        let virtiofs_daemon =
+            VirtiofsDaemon::new(id, &self.default_config.virtiofsd_conf, s, false);
         let chardev_id = format!("virtio-fs-{}", id);
-        let virtiofs_chardev = CharDevice::new("socket", &chardev_id, &absolute_virtiofs_sock);
+        let virtiofs_chardev =
+            CharDevice::new("socket", &chardev_id, virtiofs_daemon.socket_path());
         vm.attach_device(virtiofs_chardev);
         let vhost_user_fs_device = VhostUserFs::new(
             &format!("vhost-user-fs-{}", id),
@@ -148,12 +192,7 @@ impl VMFactory for StratoVirtVMFactory {
         );
         vm.attach_to_bus(vhost_user_fs_device)?;
 
-        // create virtiofs daemon
-        vm.create_vitiofs_daemon(
-            self.default_config.virtiofsd_conf.path.as_str(),
-            s.base_dir.as_str(),
-            share_fs_path.as_str(),
-        );
+        vm.virtiofs_daemon = Some(virtiofs_daemon);
         if machine_array[0] != MACHINE_TYPE_MICROVM {
             // set pcie-root-ports for hotplugging
             vm.create_pcie_root_ports(PCIE_ROOTPORT_CAPACITY)?;
@@ -161,4 +200,22 @@ impl VMFactory for StratoVirtVMFactory {
 
         Ok(vm)
     }
+
+    // create_template boots a template vm in the dir and saves its snapshot as a new version.
+    async fn create_template(&self, base_dir: &str) -> Result<StratoVirtTemplate> {
+        let id = Path::new(base_dir)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(format!("template-{}", Uuid::new_v4().simple()));
+        let option = SandboxOption::new(base_dir.to_string(), SandboxData::default());
+        let mut vm = self.new_vm(&id, &option).await?;
+        let template = StratoVirtTemplate::new(base_dir, &vm.config);
+        template.apply_template(&mut vm.config);
+
+        let res = vm.save_template(&template).await;
+        if let Err(e) = vm.stop(true).await {
+            warn!("failed to stop template vm {}: {}", id, e);
+        }
+        res.map(|_| template)
+    }
 }
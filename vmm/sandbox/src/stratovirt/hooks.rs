@@ -18,6 +18,7 @@ use async_trait::async_trait;
 use containerd_sandbox::error::Result;
 
 use crate::{
+    client::client_reseed_random,
     sandbox::KuasarSandbox,
     stratovirt::{config::StratoVirtVMConfig, StratoVirtVM},
     vm::Hooks,
@@ -43,6 +44,12 @@ impl Hooks<StratoVirtVM> for StratoVirtHooks {
 
     async fn post_start(&self, sandbox: &mut KuasarSandbox<StratoVirtVM>) -> Result<()> {
         sandbox.data.task_address = format!("ttrpc+{}", sandbox.vm.agent_socket);
+        if sandbox.vm.is_from_template() {
+            if let Some(client) = &*sandbox.client.lock().await {
+                // vms restored from the same template have the same random pool
+                client_reseed_random(client).await?;
+            }
+        }
         // sync clock
         sandbox.sync_clock().await;
         Ok(())
@@ -35,7 +35,10 @@ use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use tokio::{
     net::UnixStream,
-    sync::watch::{channel, Receiver},
+    sync::{
+        mpsc,
+        watch::{channel, Receiver},
+    },
     task::spawn_blocking,
     time::sleep,
 };
@@ -43,6 +46,7 @@ use unshare::Fd;
 
 use self::devices::{pcie_rootbus::PcieRootBus, rootport::RootPort, PCIE_ROOTBUS_CAPACITY};
 use crate::{
+    crash::{read_console, ConsoleTail},
     device::{Bus, BusType, DeviceInfo, Slot, SlotStatus},
     impl_recoverable,
     param::ToCmdLineParams,
@@ -53,11 +57,13 @@ use crate::{
             StratoVirtDevice, StratoVirtHotAttachable, DEFAULT_PCIE_BUS,
         },
         qmp_client::QmpClient,
+        template::StratoVirtTemplate,
         utils::detect_pid,
-        virtiofs::VirtiofsDaemon,
     },
+    template::release_template,
     utils::{read_std, wait_channel, wait_pid},
-    vm::{BlockDriver, Pids, VcpuThreads, VM},
+    virtiofsd::VirtiofsDaemon,
+    vm::{BlockDriver, Pids, VMEvent, VcpuThreads, VM},
 };
 
 pub mod config;
@@ -66,8 +72,8 @@ pub mod factory;
 pub mod hooks;
 mod qmp;
 mod qmp_client;
+pub mod template;
 mod utils;
-mod virtiofs;
 
 pub(crate) const STRATOVIRT_START_TIMEOUT_IN_SEC: u64 = 10;
 pub const CONFIG_STRATOVIRT_PATH: &str = "/var/lib/kuasar/config_stratovirt.toml";
@@ -100,14 +106,32 @@ pub struct StratoVirtVM {
     pcie_root_bus: Option<PcieRootBus>,
     #[serde(skip)]
     pcie_root_ports_pool: Option<PCIERootPorts>,
+    #[serde(skip)]
+    console_tail: ConsoleTail,
+    #[serde(default)]
+    template: Option<StratoVirtTemplate>,
 }
 
 #[async_trait]
 impl VM for StratoVirtVM {
     async fn start(&mut self) -> Result<u32> {
+        if let Some(template) = self.template.clone() {
+            if template.fits(&self.config) {
+                template.apply_restore(&mut self.config);
+            } else {
+                warn!(
+                    "size of vm {} is different from the template, boot it without template",
+                    self.id
+                );
+                self.drop_template().await;
+            }
+        }
+
         // launch virtiofs daemon process
-        debug!("start virtiofs daemon process");
-        self.start_virtiofs_daemon().await?;
+        if let Some(virtiofs_daemon) = self.virtiofs_daemon.as_mut() {
+            debug!("start virtiofs daemon process");
+            virtiofs_daemon.start().await?;
+        }
 
         debug!("start vm {}", self.id);
         let wait_chan = self.launch().await?;
@@ -131,10 +155,11 @@ impl VM for StratoVirtVM {
         }
 
         let console_socket = self.console_socket.clone();
+        let console_tail = self.console_tail.clone();
         tokio::spawn(async move {
             UnixStream::connect(&*console_socket)
                 .map_err(|e| e.into())
-                .and_then(|s| read_std(s, "console"))
+                .and_then(|s| read_console(s, console_tail))
                 .await
                 .unwrap_or_else(|e| {
                     error!("failed to read console log, {}", e);
@@ -144,19 +169,25 @@ impl VM for StratoVirtVM {
         // update vmm related pids
         let vmm_pid = detect_pid(self.config.pid_file.as_str(), self.config.path.as_str()).await?;
         self.pids.vmm_pid = Some(vmm_pid);
-        if let Some(virtiofsd) = &self.virtiofs_daemon {
-            if let Some(pid) = virtiofsd.pid {
-                self.pids.affiliated_pids.push(pid);
-            }
+        if let Some(virtiofs_daemon) = &self.virtiofs_daemon {
+            virtiofs_daemon.set_vmm_pid(vmm_pid);
         }
 
+        if self.template.is_some() {
+            self.resume_from_template().await?;
+        }
         Ok(vmm_pid)
     }
 
     async fn stop(&mut self, force: bool) -> Result<()> {
+        if let Some(template) = &self.template {
+            release_template(&template.path, &self.id).await;
+        }
         // before stop the vm process, stop the virtiofs daemon process firstly
-        debug!("stop virtiofs daemon process");
-        self.virtiofs_daemon.as_mut().unwrap().stop()?;
+        if let Some(virtiofs_daemon) = &self.virtiofs_daemon {
+            debug!("stop virtiofs daemon process");
+            virtiofs_daemon.stop();
+        }
 
         debug!("stop vm {}", self.id);
         if !force {
@@ -186,6 +217,14 @@ impl VM for StratoVirtVM {
     }
 
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
+        // the devices of the vm restored from template should be the same as the template
+        if self.template.is_some() {
+            warn!(
+                "device attached to vm {} before start, boot it without template",
+                self.id
+            );
+            self.drop_template().await;
+        }
         match device_info {
             DeviceInfo::Tap(tap_info) => {
                 let mut fd_ints = vec![];
@@ -216,6 +255,12 @@ impl VM for StratoVirtVM {
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
+                if !blk_info.throttle.is_empty() {
+                    warn!(
+                        "io throttle of block device {} is not supported",
+                        blk_info.id
+                    );
+                }
                 let device = VirtioBlockDevice::new(
                     "",
                     &blk_info.id,
@@ -269,6 +314,40 @@ impl VM for StratoVirtVM {
         Ok(())
     }
 
+    async fn resize(&mut self, _vcpus: u32, _memory_in_mb: u64) -> Result<()> {
+        Err(Error::Unimplemented("resize for stratovirt vm".to_string()))
+    }
+
+    async fn resize_block(&mut self, _id: &str, _size: u64) -> Result<()> {
+        Err(Error::Unimplemented(
+            "resize block device for stratovirt vm".to_string(),
+        ))
+    }
+
+    async fn checkpoint(&mut self, _dir: &str) -> Result<()> {
+        Err(Error::Unimplemented(
+            "checkpoint for stratovirt vm".to_string(),
+        ))
+    }
+
+    async fn restore(&mut self, _dir: &str) -> Result<u32> {
+        Err(Error::Unimplemented(
+            "restore for stratovirt vm".to_string(),
+        ))
+    }
+
+    fn unrestorable_devices(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn support_network_hotplug(&self) -> bool {
+        false
+    }
+
+    fn support_net_rate_limiter(&self) -> bool {
+        false
+    }
+
     async fn ping(&self) -> Result<()> {
         let client = self.get_client()?;
         let _res = client.execute(qapi::qmp::query_status {}).await?;
@@ -300,7 +379,19 @@ impl VM for StratoVirtVM {
     }
 
     fn pids(&self) -> Pids {
-        self.pids.clone()
+        // the pid of the virtiofs daemon changes once it is restarted
+        let mut pids = self.pids.clone();
+        pids.affiliated_pids
+            .extend(self.virtiofs_daemon.as_ref().and_then(|d| d.pid()));
+        pids
+    }
+
+    fn take_vm_events(&mut self) -> Option<mpsc::Receiver<VMEvent>> {
+        self.client.as_mut().and_then(|c| c.take_vm_events())
+    }
+
+    fn console_tail(&self) -> String {
+        self.console_tail.content()
     }
 }
 
@@ -322,6 +413,14 @@ impl StratoVirtVM {
             pcie_root_ports_pool: None,
             pcie_root_bus: None,
             pids: Pids::default(),
+            console_tail: ConsoleTail::default(),
+            template: None,
+        }
+    }
+
+    async fn drop_template(&mut self) {
+        if let Some(template) = self.template.take() {
+            release_template(&template.path, &self.id).await;
         }
     }
 
@@ -550,24 +649,6 @@ impl StratoVirtVM {
         Err(Error::ResourceExhausted("slot of rootport".to_string()))
     }
 
-    fn create_vitiofs_daemon(&mut self, daemon_path: &str, base_dir: &str, shared_path: &str) {
-        self.virtiofs_daemon = Some(VirtiofsDaemon {
-            path: daemon_path.to_string(),
-            log_path: format!("{}/virtiofs.log", base_dir),
-            socket_path: format!("{}/virtiofs.sock", base_dir),
-            shared_dir: shared_path.to_string(),
-            pid: None,
-        });
-    }
-
-    async fn start_virtiofs_daemon(&mut self) -> Result<()> {
-        self.virtiofs_daemon
-            .as_mut()
-            .unwrap()
-            .start()
-            .map_err(|e| Error::Other(anyhow!("start virtiofs daemon process failed: {}", e)))
-    }
-
     fn detach_from_bus(&mut self, device_id: &str) {
         self.devices
             .iter_mut()
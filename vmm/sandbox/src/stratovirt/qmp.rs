@@ -28,6 +28,20 @@ impl ::qapi_spec::Command for QueryCpus {
     type Ok = Vec<CpuInfo>;
 }
 
+// Migrate with a "file:" uri takes the snapshot of the paused vm into the dir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Migrate {
+    pub uri: String,
+}
+
+impl QmpCommand for Migrate {}
+impl ::qapi_spec::Command for Migrate {
+    const NAME: &'static str = "migrate";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = qapi::Empty;
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum CpuInfoArch {
     #[serde(rename = "x86")]
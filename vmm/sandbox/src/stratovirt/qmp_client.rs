@@ -14,12 +14,12 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 use containerd_sandbox::error::Result;
 use futures_util::StreamExt;
-use log::error;
+use log::{error, warn};
 use qapi::{
     futures::{QapiService, QmpStreamTokio},
     qmp::{device_del, Event, QmpCommand},
@@ -28,14 +28,23 @@ use tokio::{
     io::WriteHalf,
     net::UnixStream,
     sync::{
+        mpsc,
         oneshot::{channel, Sender},
         Mutex,
     },
+    time::timeout,
 };
 
+use crate::vm::VMEvent;
+
+// the removal of the device is asynchronous, it is done after the guest releases it
+const DEVICE_DELETED_TIMEOUT_IN_SEC: u64 = 10;
+const VM_EVENT_CHANNEL_SIZE: usize = 16;
+
 pub struct QmpClient {
     qmp: QapiService<QmpStreamTokio<WriteHalf<UnixStream>>>,
     watchers: Arc<Mutex<Vec<QmpEventWatcher>>>,
+    vm_events: Option<mpsc::Receiver<VMEvent>>,
 }
 
 pub struct QmpEventWatcher {
@@ -50,10 +59,18 @@ impl QmpClient {
         let (service, mut events) = stream.into_parts();
         let event_watchers = Arc::new(Mutex::new(Vec::<QmpEventWatcher>::new()));
 
+        let (vm_event_tx, vm_event_rx) = mpsc::channel(VM_EVENT_CHANNEL_SIZE);
+
         let w_clone = event_watchers.clone();
         tokio::spawn(async move {
             while let Some(Ok(event)) = events.next().await {
+                if let Some(vm_event) = to_vm_event(&event) {
+                    warn!("vm event {}: {}", vm_event.name, vm_event.detail);
+                    vm_event_tx.try_send(vm_event).unwrap_or_default();
+                }
                 let mut ws = w_clone.lock().await;
+                // the watchers timed out are removed
+                ws.retain(|w| !w.sender.is_closed());
                 let mut retained = vec![];
                 while let Some(w) = ws.pop() {
                     if (w.filter)(&event) {
@@ -73,6 +90,7 @@ impl QmpClient {
         let client = Self {
             qmp: service,
             watchers: event_watchers,
+            vm_events: Some(vm_event_rx),
         };
         Ok(client)
     }
@@ -84,10 +102,16 @@ impl QmpClient {
         }
     }
 
+    // take_vm_events returns the channel of the notable events of the vm, it can be taken only once
+    pub fn take_vm_events(&mut self) -> Option<mpsc::Receiver<VMEvent>> {
+        self.vm_events.take()
+    }
+
     pub async fn execute_and_wait_event<C: QmpCommand + 'static>(
         &self,
         cmd: C,
         filter: impl Fn(&Event) -> bool + Sync + Send + 'static,
+        wait_timeout: Duration,
     ) -> Result<C::Ok> {
         let (tx, rx) = channel();
         {
@@ -99,33 +123,49 @@ impl QmpClient {
             watchers.push(watcher);
         }
         match self.qmp.execute(cmd).await {
-            Ok(r) => {
-                //TODO  add timeout
-                let _ = rx.await;
-                Ok(r)
-            }
+            Ok(r) => match timeout(wait_timeout, rx).await {
+                Ok(Ok(_)) => Ok(r),
+                Ok(Err(_)) => Err(anyhow!("qmp event stream is closed").into()),
+                Err(_) => Err(anyhow!("timeout waiting for qmp event").into()),
+            },
             Err(e) => Err(anyhow!("failed to execute qmp, {}", e).into()),
         }
     }
 
     pub async fn delete_device(&self, device_id: &str) -> Result<()> {
-        let device_id = device_id.to_string();
+        let id_clone = device_id.to_string();
         self.execute_and_wait_event(
             device_del {
-                id: device_id.clone(),
+                id: device_id.to_string(),
             },
             move |x| {
                 if let qapi::qmp::Event::DEVICE_DELETED { ref data, .. } = x {
                     if let Some(id) = &data.device {
-                        if id == &device_id {
+                        if id == &id_clone {
                             return true;
                         }
                     }
                 }
                 false
             },
+            Duration::from_secs(DEVICE_DELETED_TIMEOUT_IN_SEC),
         )
-        .await?;
+        .await
+        .map_err(|e| anyhow!("failed to delete device {}: {}", device_id, e))?;
         Ok(())
     }
 }
+
+// to_vm_event returns the events that are reported to the sandbox
+fn to_vm_event(event: &Event) -> Option<VMEvent> {
+    let (name, detail) = match event {
+        Event::GUEST_PANICKED { data, .. } => ("GUEST_PANICKED", format!("{:?}", data)),
+        Event::SHUTDOWN { data, .. } => ("SHUTDOWN", format!("{:?}", data)),
+        Event::BLOCK_IO_ERROR { data, .. } => ("BLOCK_IO_ERROR", format!("{:?}", data)),
+        _ => return None,
+    };
+    Some(VMEvent {
+        name: name.to_string(),
+        detail,
+    })
+}
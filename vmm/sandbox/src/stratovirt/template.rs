@@ -0,0 +1,181 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use log::debug;
+use qapi::qmp::{cont, query_status, stop};
+use serde::{Deserialize, Serialize};
+use tokio::fs::create_dir_all;
+
+use crate::{
+    client::{client_check, new_sandbox_client},
+    stratovirt::{
+        config::{Memory, StratoVirtConfig, SMP},
+        qmp::Migrate,
+        StratoVirtVM,
+    },
+    template::VMTemplate,
+    vm::VM,
+};
+
+pub(crate) const DEFAULT_TEMPLATE_PATH: &str = "/run/kuasar/vmm/stratovirt-template";
+// the snapshot dir has the memory file and the state file of the template vm
+const TEMPLATE_SNAPSHOT_DIR: &str = "snapshot";
+const TEMPLATE_MEMORY_FILE: &str = "memory";
+const TEMPLATE_STATE_FILE: &str = "state";
+// The agent mounts the sharefs when the sandbox is set up rather than at boot,
+// so that the fuse session is started with the virtiofsd of the restored vm.
+const LAZY_SHAREFS_KERNEL_PARAM: &str = "task.lazy_sharefs";
+
+// StratoVirtTemplate is a snapshot of a vm paused after the agent is ready,
+// new vms with the same vcpus and memory are restored from it by `-incoming file:`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StratoVirtTemplate {
+    pub path: String,
+    pub smp: SMP,
+    pub memory: Memory,
+    pub kernel_params: String,
+}
+
+impl StratoVirtTemplate {
+    pub(crate) fn new(path: &str, config: &StratoVirtConfig) -> Self {
+        Self {
+            path: path.to_string(),
+            smp: config.smp.clone(),
+            memory: config.memory.clone(),
+            kernel_params: format!(
+                "{} {}",
+                config.kernel.kernel_params, LAZY_SHAREFS_KERNEL_PARAM
+            ),
+        }
+    }
+
+    fn snapshot_path(&self) -> String {
+        format!("{}/{}", self.path, TEMPLATE_SNAPSHOT_DIR)
+    }
+
+    // resizing is not supported by stratovirt, so the vm should be the same size as the template.
+    pub(crate) fn fits(&self, config: &StratoVirtConfig) -> bool {
+        self.smp.cpus == config.smp.cpus && self.memory.size == config.memory.size
+    }
+
+    // apply_template updates the config of the template vm so that the sharefs is mounted lazily.
+    pub(crate) fn apply_template(&self, config: &mut StratoVirtConfig) {
+        config.kernel.kernel_params = self.kernel_params.clone();
+    }
+
+    // apply_restore updates the config of the vm to be restored from the template snapshot,
+    // the devices of the vm are still from its own config with its own sockets and vsock cid.
+    pub(crate) fn apply_restore(&self, config: &mut StratoVirtConfig) {
+        config.kernel.kernel_params = self.kernel_params.clone();
+        config.incoming = Some(format!("file:{}", self.snapshot_path()));
+    }
+}
+
+impl VMTemplate for StratoVirtTemplate {
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn exists(&self) -> bool {
+        let snapshot = self.snapshot_path();
+        Path::new(&format!("{}/{}", snapshot, TEMPLATE_MEMORY_FILE)).exists()
+            && Path::new(&format!("{}/{}", snapshot, TEMPLATE_STATE_FILE)).exists()
+    }
+}
+
+impl StratoVirtVM {
+    pub(crate) fn is_from_template(&self) -> bool {
+        self.template.is_some()
+    }
+
+    // save_template boots the vm and takes the snapshot of it after the agent is ready.
+    pub(crate) async fn save_template(&mut self, template: &StratoVirtTemplate) -> Result<()> {
+        self.start().await?;
+        {
+            let client = new_sandbox_client(&self.agent_socket).await?;
+            client_check(&client).await?;
+        }
+        debug!("agent of template vm {} is ready, save it", self.id);
+
+        let snapshot = template.snapshot_path();
+        create_dir_all(&snapshot)
+            .await
+            .map_err(|e| anyhow!("failed to create snapshot dir {}: {}", snapshot, e))?;
+        let client = self.get_client()?;
+        client.execute(stop {}).await?;
+        // the snapshot is taken synchronously by stratovirt
+        client
+            .execute(Migrate {
+                uri: format!("file:{}", snapshot),
+            })
+            .await?;
+        if !template.exists() {
+            return Err(anyhow!("snapshot of template vm {} is not saved", self.id).into());
+        }
+        Ok(())
+    }
+
+    // resume_from_template resumes the vm restored from the snapshot of the template,
+    // which is paused as the template vm is paused when the snapshot is taken.
+    pub(crate) async fn resume_from_template(&self) -> Result<()> {
+        let client = self.get_client()?;
+        let status = client.execute(query_status {}).await?;
+        if !status.running {
+            client.execute(cont {}).await?;
+        }
+        debug!("vm {} is restored from template", self.id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stratovirt::{
+        config::{Memory, StratoVirtConfig, SMP},
+        template::StratoVirtTemplate,
+    };
+
+    #[test]
+    fn test_apply_restore() {
+        let mut config = StratoVirtConfig::default();
+        config.smp = SMP { cpus: 1 };
+        config.memory = Memory {
+            size: "1024M".to_string(),
+        };
+        config.kernel.kernel_params = "console=hvc0".to_string();
+        let template = StratoVirtTemplate::new("/run/kuasar/vmm/stratovirt-template/1", &config);
+        assert_eq!(template.kernel_params, "console=hvc0 task.lazy_sharefs");
+
+        let mut restored = config.clone();
+        assert!(template.fits(&restored));
+        restored.smp.cpus = 2;
+        assert!(!template.fits(&restored));
+
+        template.apply_restore(&mut restored);
+        assert_eq!(
+            restored.kernel.kernel_params,
+            "console=hvc0 task.lazy_sharefs"
+        );
+        assert_eq!(
+            restored.incoming.as_deref(),
+            Some("file:/run/kuasar/vmm/stratovirt-template/1/snapshot")
+        );
+    }
+}
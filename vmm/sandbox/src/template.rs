@@ -0,0 +1,247 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use log::{debug, info, warn};
+use tokio::{
+    fs::{create_dir_all, read_dir, read_to_string, remove_dir_all, remove_file, write},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+const TEMPLATE_DIR_PREFIX: &str = "template-";
+const TEMPLATE_REFS_DIR: &str = "refs";
+// every build of the template boots a vm, so a failed build is not retried too often
+const TEMPLATE_RETRY_INTERVAL_IN_SEC: u64 = 60;
+
+// VMTemplate is the template vm saved in a directory, new vms are restored from it.
+pub(crate) trait VMTemplate: Clone + Send + Sync + 'static {
+    fn path(&self) -> &str;
+    fn exists(&self) -> bool;
+}
+
+// TemplateStore keeps every version of the template in its own directory under the root,
+// and the template is built in the background so that creating a vm never waits for it.
+//
+// Each user of a version holds a reference file in the "refs" dir of it, with a path in it
+// that exists as long as the user lives, which is the base dir of the vm restored from it,
+// or the proc dir of the sandboxer using it as the current template.
+// A version is removed only if it is not the current one and none of the users is alive,
+// so the running vms never lose the memory file and the state file they are restored from.
+#[derive(Clone)]
+pub(crate) struct TemplateStore<T> {
+    root: String,
+    state: Arc<Mutex<TemplateState<T>>>,
+}
+
+struct TemplateState<T> {
+    current: Option<T>,
+    building: bool,
+    failed_at: Option<SystemTime>,
+}
+
+impl<T: VMTemplate> TemplateStore<T> {
+    pub(crate) fn new(root: &str) -> Self {
+        Self {
+            root: root.to_string(),
+            state: Arc::new(Mutex::new(TemplateState {
+                current: None,
+                building: false,
+                failed_at: None,
+            })),
+        }
+    }
+
+    // acquire returns the current template referenced by the vm, or None if it is not ready,
+    // then a new version is built in the background by calling build with its directory.
+    pub(crate) async fn acquire<F, Fut>(&self, vm_id: &str, base_dir: &str, build: F) -> Option<T>
+    where
+        F: FnOnce(String) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+    {
+        let mut state = self.state.lock().await;
+        if let Some(t) = state.current.as_ref().filter(|t| t.exists()) {
+            return match add_ref(t.path(), vm_id, base_dir).await {
+                Ok(_) => Some(t.clone()),
+                Err(e) => {
+                    warn!("failed to reference vm template {}: {}", t.path(), e);
+                    None
+                }
+            };
+        }
+        if state.building {
+            return None;
+        }
+        if let Some(failed_at) = state.failed_at {
+            if failed_at.elapsed().unwrap_or_default()
+                < Duration::from_secs(TEMPLATE_RETRY_INTERVAL_IN_SEC)
+            {
+                return None;
+            }
+        }
+        let dir = format!(
+            "{}/{}{}",
+            self.root,
+            TEMPLATE_DIR_PREFIX,
+            Uuid::new_v4().simple()
+        );
+        // the version being built is referenced by the sandboxer, so no one removes it
+        if let Err(e) = add_ref(&dir, &sandboxer_ref(), &sandboxer_holder()).await {
+            warn!("failed to reference vm template {}: {}", dir, e);
+            return None;
+        }
+        state.building = true;
+        drop(state);
+
+        let store = self.clone();
+        tokio::spawn(async move {
+            let res = build(dir.clone()).await;
+            store.on_built(&dir, res).await;
+        });
+        None
+    }
+
+    async fn on_built(&self, dir: &str, res: Result<T>) {
+        let mut state = self.state.lock().await;
+        state.building = false;
+        match res {
+            Ok(t) => {
+                info!("vm template is created in {}", t.path());
+                if let Some(old) = state.current.replace(t) {
+                    release_template(old.path(), &sandboxer_ref()).await;
+                }
+                state.failed_at = None;
+            }
+            Err(e) => {
+                warn!("failed to create vm template in {}: {}", dir, e);
+                remove_dir_all(dir).await.unwrap_or_default();
+                state.failed_at = Some(SystemTime::now());
+            }
+        }
+        let current = state.current.as_ref().map(|t| t.path().to_string());
+        self.remove_unused(current.as_deref()).await;
+    }
+
+    async fn remove_unused(&self, current: Option<&str>) {
+        let mut entries = match read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("failed to read template dir {}: {}", self.root, e);
+                return;
+            }
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path().to_string_lossy().to_string();
+            if !name.starts_with(TEMPLATE_DIR_PREFIX) || Some(path.as_str()) == current {
+                continue;
+            }
+            if in_use(&path).await {
+                debug!("vm template {} is still in use", path);
+                continue;
+            }
+            info!("remove unused vm template {}", path);
+            if let Err(e) = remove_dir_all(&path).await {
+                warn!("failed to remove vm template {}: {}", path, e);
+            }
+        }
+    }
+}
+
+// release_template drops the reference of the vm to the template when the vm is stopped.
+pub(crate) async fn release_template(template_path: &str, vm_id: &str) {
+    let path = format!("{}/{}/{}", template_path, TEMPLATE_REFS_DIR, vm_id);
+    remove_file(&path).await.unwrap_or_default();
+}
+
+fn sandboxer_ref() -> String {
+    format!("sandboxer-{}", std::process::id())
+}
+
+fn sandboxer_holder() -> String {
+    format!("/proc/{}", std::process::id())
+}
+
+async fn add_ref(template_path: &str, name: &str, holder: &str) -> Result<()> {
+    let dir = format!("{}/{}", template_path, TEMPLATE_REFS_DIR);
+    create_dir_all(&dir)
+        .await
+        .map_err(|e| anyhow!("failed to create {}: {}", dir, e))?;
+    write(format!("{}/{}", dir, name), holder)
+        .await
+        .map_err(|e| anyhow!("failed to write reference {}: {}", name, e))?;
+    Ok(())
+}
+
+// in_use checks whether any holder of the references is still alive, the references of
+// the dead holders are removed, they are left if the vm exits without being stopped.
+async fn in_use(template_path: &str) -> bool {
+    let dir = format!("{}/{}", template_path, TEMPLATE_REFS_DIR);
+    let mut entries = match read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    let mut used = false;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let holder = read_to_string(entry.path()).await.unwrap_or_default();
+        if !holder.is_empty() && Path::new(&holder).exists() {
+            used = true;
+        } else {
+            remove_file(entry.path()).await.unwrap_or_default();
+        }
+    }
+    used
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use temp_dir::TempDir;
+
+    use crate::template::{add_ref, in_use, release_template};
+
+    #[tokio::test]
+    async fn test_template_in_use() {
+        let tmp = TempDir::new().unwrap();
+        let template = tmp.child("template-1");
+        let template = template.to_str().unwrap();
+        let vm_dir = tmp.child("vm-1");
+        std::fs::create_dir_all(&vm_dir).unwrap();
+        assert!(!in_use(template).await);
+
+        add_ref(template, "vm-1", vm_dir.to_str().unwrap())
+            .await
+            .unwrap();
+        add_ref(template, "vm-2", tmp.child("vm-2").to_str().unwrap())
+            .await
+            .unwrap();
+        assert!(in_use(template).await);
+        // the reference of the vm without base dir is removed
+        assert!(!Path::new(&format!("{}/refs/vm-2", template)).exists());
+
+        release_template(template, "vm-1").await;
+        assert!(!in_use(template).await);
+    }
+}
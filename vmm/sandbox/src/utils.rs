@@ -49,8 +49,11 @@ use tokio::{
     sync::watch::Receiver,
     time::sleep,
 };
+use uuid::Uuid;
 use vmm_common::NET_NAMESPACE;
 
+use crate::network::Bandwidth;
+
 pub async fn read_file<P: AsRef<Path>>(filename: P) -> Result<String> {
     let mut file = tokio::fs::File::open(&filename).await?;
     let mut content: String = String::new();
@@ -89,6 +92,29 @@ pub fn get_overhead_resources(data: &SandboxData) -> Option<&LinuxContainerResou
         .and_then(|c| c.linux.as_ref())
         .and_then(|l| l.overhead.as_ref())
 }
+
+// get_vcpus returns the ceil of cpus limited by the cpu quota and period of the sandbox,
+// 0 is returned if the cpu of sandbox is not limited.
+pub fn get_vcpus(data: &SandboxData) -> u32 {
+    match get_resources(data) {
+        Some(resources) if resources.cpu_period > 0 && resources.cpu_quota > 0 => {
+            (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil() as u32
+        }
+        _ => 0,
+    }
+}
+
+// get_memory_in_mb returns the memory limit of the sandbox in MiB,
+// 0 is returned if the memory of sandbox is not limited.
+pub fn get_memory_in_mb(data: &SandboxData) -> u64 {
+    match get_resources(data) {
+        Some(resources) if resources.memory_limit_in_bytes > 0 => {
+            resources.memory_limit_in_bytes as u64 / bytefmt::MIB
+        }
+        _ => 0,
+    }
+}
+
 pub fn get_hostname(data: &SandboxData) -> String {
     data.config
         .as_ref()
@@ -96,6 +122,14 @@ pub fn get_hostname(data: &SandboxData) -> String {
         .unwrap_or_default()
 }
 
+// get_bandwidth returns the bandwidth of the pod network set by the annotations
+pub fn get_bandwidth(data: &SandboxData) -> Result<Bandwidth> {
+    match data.config.as_ref() {
+        Some(c) => Bandwidth::from_annotations(&c.annotations),
+        None => Ok(Bandwidth::default()),
+    }
+}
+
 pub fn get_dns_config(data: &SandboxData) -> Option<&DnsConfig> {
     data.config.as_ref().and_then(|c| c.dns_config.as_ref())
 }
@@ -338,9 +372,17 @@ pub async fn write_file_atomic<P: AsRef<Path>>(path: P, s: &str) -> Result<()> {
     let file = path
         .file_name()
         .ok_or_else(|| Error::InvalidArgument(String::from("path illegal")))?;
+    // the tmp file is unique so that concurrent writers never write to the same one,
+    // and the one left by a crashed writer never blocks the later writes
     let tmp_path = path
         .parent()
-        .map(|x| x.join(format!(".{}", file.to_str().unwrap_or(""))))
+        .map(|x| {
+            x.join(format!(
+                ".{}.{}",
+                file.to_str().unwrap_or(""),
+                Uuid::new_v4().simple()
+            ))
+        })
         .ok_or_else(|| Error::InvalidArgument(String::from("failed to create tmp path")))?;
     let tmp_path = tmp_path.to_str().ok_or_else(|| {
         Error::InvalidArgument(format!("failed to get path: {}", tmp_path.display()))
@@ -351,16 +393,23 @@ pub async fn write_file_atomic<P: AsRef<Path>>(path: P, s: &str) -> Result<()> {
         .open(tmp_path)
         .await
         .map_err(|e| anyhow!("failed to open path {}, {}", tmp_path, e))?;
-    f.write_all(s.as_bytes())
-        .await
-        .map_err(|e| anyhow!("failed to write string to path {}, {}", tmp_path, e))?;
-    f.sync_data()
-        .await
-        .map_err(|e| anyhow!("failed to sync data to path {}, {}", tmp_path, e))?;
-
-    tokio::fs::rename(tmp_path, path)
-        .await
-        .map_err(|e| anyhow!("failed to rename file: {}", e).into())
+    let res = async {
+        f.write_all(s.as_bytes())
+            .await
+            .map_err(|e| anyhow!("failed to write string to path {}, {}", tmp_path, e))?;
+        f.sync_data()
+            .await
+            .map_err(|e| anyhow!("failed to sync data to path {}, {}", tmp_path, e))?;
+        tokio::fs::rename(tmp_path, path)
+            .await
+            .map_err(|e| anyhow!("failed to rename file: {}", e))
+    }
+    .await;
+    if let Err(e) = res {
+        tokio::fs::remove_file(tmp_path).await.unwrap_or_default();
+        return Err(e.into());
+    }
+    Ok(())
 }
 
 pub fn bool_to_on_off(b: &bool) -> String {
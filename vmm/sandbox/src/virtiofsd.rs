@@ -0,0 +1,470 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    ffi::OsStr,
+    path::Path,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use containerd_sandbox::{error::Result, signal::ExitSignal, SandboxOption};
+use log::{debug, error, info, warn};
+use nix::{
+    sys::signal::{kill, SIGKILL},
+    unistd::Pid,
+};
+use sandbox_derive::CmdLineParamSet;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::{
+    fs::{create_dir_all, remove_file},
+    process::Child,
+    time::sleep,
+};
+use vmm_common::SHARED_DIR_SUFFIX;
+
+use crate::{
+    cgroup::{SandboxCgroup, DEFAULT_CGROUP_PARENT_PATH},
+    param::ToCmdLineParams,
+    utils::{get_netns, get_sandbox_cgroup_parent_path, read_std, set_cmd_netns, wait_pid},
+};
+
+pub const DEFAULT_VIRTIOFSD_PATH: &str = "/usr/local/bin/virtiofsd";
+pub(crate) const DEFAULT_VHOST_USER_FS_BIN_PATH: &str = "/usr/bin/vhost_user_fs";
+// the vhost_user_fs of StratoVirt takes single hyphen options different from virtiofsd
+const VHOST_USER_FS_BIN_NAME: &str = "vhost_user_fs";
+const VIRTIOFS_SOCKET_FILENAME: &str = "virtiofs.sock";
+const VIRTIOFS_LOG_FILENAME: &str = "virtiofs.log";
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+const RESTART_DELAY_IN_MS: u64 = 500;
+
+// VirtiofsdConfig is the common config of the shared fs daemon of all the hypervisors
+#[derive(CmdLineParamSet, Deserialize, Debug, Clone, Serialize)]
+#[serde(default)]
+pub struct VirtiofsdConfig {
+    #[param(ignore)]
+    pub path: String,
+    pub log_level: String,
+    pub cache: String,
+    pub thread_pool_size: u32,
+    pub socket_path: String,
+    pub shared_dir: String,
+    pub syslog: bool,
+    pub xattr: bool,
+    // the sandboxing of the daemon, "namespace", "chroot" or "none"
+    pub sandbox: Option<String>,
+    #[param(ignore)]
+    pub rlimit_nofile: u64,
+    // the daemon is not restarted any more after it crashes so many times
+    #[param(ignore)]
+    pub max_restarts: u32,
+}
+
+impl Default for VirtiofsdConfig {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_VIRTIOFSD_PATH.to_string(),
+            log_level: "info".to_string(),
+            cache: "never".to_string(),
+            thread_pool_size: 4,
+            socket_path: "".to_string(),
+            shared_dir: "".to_string(),
+            syslog: true,
+            xattr: false,
+            sandbox: None,
+            rlimit_nofile: 0,
+            max_restarts: DEFAULT_MAX_RESTARTS,
+        }
+    }
+}
+
+impl VirtiofsdConfig {
+    fn is_vhost_user_fs(&self) -> bool {
+        Path::new(&self.path).file_name() == Some(OsStr::new(VHOST_USER_FS_BIN_NAME))
+    }
+
+    fn to_args(&self, log_path: &str) -> Vec<String> {
+        let (mut args, hyphen) = if self.is_vhost_user_fs() {
+            let mut args = vec![
+                "-source".to_string(),
+                self.shared_dir.to_string(),
+                "-socket-path".to_string(),
+                self.socket_path.to_string(),
+                "-D".to_string(),
+                log_path.to_string(),
+            ];
+            if let Some(sandbox) = &self.sandbox {
+                args.push("-sandbox".to_string());
+                args.push(sandbox.to_string());
+            }
+            (args, "-")
+        } else {
+            (self.to_cmdline_params("--"), "--")
+        };
+        if self.rlimit_nofile > 0 {
+            args.push(format!("{}rlimit-nofile", hyphen));
+            args.push(self.rlimit_nofile.to_string());
+        }
+        args
+    }
+}
+
+// VirtiofsDaemon supervises the shared fs daemon of the vm. If the daemon crashes, it is
+// restarted on the same socket when the vmm reconnects to the vhost-user socket, otherwise
+// the vmm is killed, and the sandbox is stopped as the vm exits.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VirtiofsDaemon {
+    id: String,
+    config: VirtiofsdConfig,
+    netns: String,
+    log_path: String,
+    // whether the vhost-user-fs device of the vmm reconnects to a restarted daemon
+    reconnect: bool,
+    // the pooled vms are created without sandbox, so they have no sandbox cgroups
+    cgroup_parent_path: Option<String>,
+    #[serde(with = "shared_pid")]
+    pid: Arc<AtomicU32>,
+    #[serde(with = "shared_pid")]
+    vmm_pid: Arc<AtomicU32>,
+    // the start time of the vmm process, to make sure the vmm_pid is not reused
+    #[serde(with = "shared_start_time")]
+    vmm_start_time: Arc<AtomicU64>,
+    #[serde(skip)]
+    exit_signal: Arc<ExitSignal>,
+}
+
+impl VirtiofsDaemon {
+    pub fn new(id: &str, config: &VirtiofsdConfig, s: &SandboxOption, reconnect: bool) -> Self {
+        let mut config = config.clone();
+        config.socket_path = format!("{}/{}", s.base_dir, VIRTIOFS_SOCKET_FILENAME);
+        config.shared_dir = format!("{}/{}", s.base_dir, SHARED_DIR_SUFFIX);
+        let cgroup_parent_path = if s.sandbox.id.is_empty() {
+            None
+        } else {
+            Some(
+                get_sandbox_cgroup_parent_path(&s.sandbox)
+                    .unwrap_or(DEFAULT_CGROUP_PARENT_PATH.to_string()),
+            )
+        };
+        Self {
+            id: id.to_string(),
+            config,
+            netns: get_netns(&s.sandbox),
+            log_path: format!("{}/{}", s.base_dir, VIRTIOFS_LOG_FILENAME),
+            reconnect,
+            cgroup_parent_path,
+            pid: Arc::new(AtomicU32::new(0)),
+            vmm_pid: Arc::new(AtomicU32::new(0)),
+            vmm_start_time: Arc::new(AtomicU64::new(0)),
+            exit_signal: Arc::new(ExitSignal::default()),
+        }
+    }
+
+    pub fn socket_path(&self) -> &str {
+        &self.config.socket_path
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        match self.pid.load(Ordering::SeqCst) {
+            0 => None,
+            pid => Some(pid),
+        }
+    }
+
+    // set_vmm_pid tells the vmm to be killed if the daemon crashes and can not be restarted,
+    // it is set after the vmm starts
+    pub fn set_vmm_pid(&self, pid: u32) {
+        match process_start_time(pid) {
+            Some(start_time) => {
+                self.vmm_start_time.store(start_time, Ordering::SeqCst);
+                self.vmm_pid.store(pid, Ordering::SeqCst);
+            }
+            None => warn!("failed to get start time of vmm {} of {}", pid, self.id),
+        }
+    }
+
+    // vmm_alive tells whether the vmm is still running, the pid may be reused by another process
+    // after the vmm exits, so the start time of the process is checked.
+    fn vmm_alive(&self) -> bool {
+        let pid = self.vmm_pid.load(Ordering::SeqCst);
+        if pid == 0 {
+            return false;
+        }
+        match procfs::process::Process::new(pid as i32).and_then(|p| p.stat()) {
+            Ok(stat) => {
+                stat.starttime == self.vmm_start_time.load(Ordering::SeqCst) && stat.state != 'Z'
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn kill_vmm(&self) {
+        if self.vmm_alive() {
+            let pid = self.vmm_pid.load(Ordering::SeqCst);
+            error!("kill the vmm {} of {} as virtiofsd crashed", pid, self.id);
+            // the vmm may exit right now, so it's ok not handle error
+            kill(Pid::from_raw(pid as i32), SIGKILL).unwrap_or_default();
+        }
+    }
+
+    pub async fn start(&mut self) -> Result<u32> {
+        create_dir_all(&self.config.shared_dir).await?;
+        self.exit_signal = Arc::new(ExitSignal::default());
+        let child = self.spawn().await?;
+        let pid = self.pid().unwrap_or_default();
+        self.supervise(Some(child));
+        Ok(pid)
+    }
+
+    // recover supervises the daemon again after the sandboxer restarts
+    pub fn recover(&mut self) {
+        if let Some(pid) = self.pid() {
+            info!("recover virtiofsd {} of {}", pid, self.id);
+            self.exit_signal = Arc::new(ExitSignal::default());
+            self.supervise(None);
+        }
+    }
+
+    // stop_supervising stops handling the exit of the daemon, it should be called before the vmm
+    // is stopped, so that the daemon exiting with the vmm is not taken as crashed.
+    pub fn stop_supervising(&self) {
+        self.exit_signal.signal();
+    }
+
+    pub fn stop(&self) {
+        self.stop_supervising();
+        if let Some(pid) = self.pid() {
+            debug!("stop virtiofsd {} of {}", pid, self.id);
+            // the daemon may already exit with the vmm, so it's ok not handle error
+            kill(Pid::from_raw(pid as i32), SIGKILL).unwrap_or_default();
+        }
+    }
+
+    async fn spawn(&self) -> Result<Child> {
+        let mut cmd = tokio::process::Command::new(&self.config.path);
+        cmd.args(self.config.to_args(&self.log_path));
+        debug!("start virtiofsd with cmdline: {:?}", cmd);
+        set_cmd_netns(&mut cmd, self.netns.to_string())?;
+        cmd.stderr(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| anyhow!("failed to spawn virtiofsd command: {}", e))?;
+        let pid = child
+            .id()
+            .ok_or(anyhow!("the virtiofsd has been polled to completion"))?;
+        info!("virtiofsd for {} is running with pid {}", self.id, pid);
+        self.pid.store(pid, Ordering::SeqCst);
+
+        let name = format!("virtiofsd {}", self.id);
+        if let Some(stdout) = child.stdout.take() {
+            let name = name.clone();
+            tokio::spawn(async move { read_std(stdout, &name).await.unwrap_or_default() });
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(async move { read_std(stderr, &name).await.unwrap_or_default() });
+        }
+        Ok(child)
+    }
+
+    // add_to_cgroup puts the restarted daemon into the pod_overhead cgroup, the first daemon is
+    // put there with the affiliated pids of the vm after the sandbox cgroups are created.
+    fn add_to_cgroup(&self, pid: u32) {
+        let cgroups = match &self.cgroup_parent_path {
+            Some(parent) => SandboxCgroup::load_sandbox_cgroups(parent, &self.id),
+            None => None,
+        };
+        if let Some(c) = cgroups {
+            if let Err(e) = c.add_process_into_sandbox_cgroups(pid, None) {
+                warn!(
+                    "failed to add virtiofsd {} into cgroup of {}: {}",
+                    pid, self.id, e
+                );
+            }
+        }
+    }
+
+    // supervise waits for the daemon to exit until it is stopped. The crashed daemon is
+    // restarted on the same socket no more than max_restarts times if the vmm reconnects to it,
+    // otherwise the vmm is killed rather than running on with a broken shared fs.
+    fn supervise(&self, child: Option<Child>) {
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            let mut child = child;
+            let mut restarts = 0;
+            loop {
+                let pid = daemon.pid().unwrap_or_default();
+                let crashed = tokio::select! {
+                    _ = daemon.exit_signal.wait() => return,
+                    crashed = daemon.wait(child.take(), pid) => crashed,
+                };
+                daemon.pid.store(0, Ordering::SeqCst);
+                if !crashed {
+                    info!("virtiofsd {} of {} exited", pid, daemon.id);
+                    return;
+                }
+                if !daemon.reconnect {
+                    error!("virtiofsd {} of {} crashed", pid, daemon.id);
+                    daemon.kill_vmm();
+                    return;
+                }
+                if restarts >= daemon.config.max_restarts {
+                    error!(
+                        "virtiofsd of {} crashed {} times, stop restarting it",
+                        daemon.id, restarts
+                    );
+                    daemon.kill_vmm();
+                    return;
+                }
+                restarts += 1;
+                tokio::select! {
+                    _ = daemon.exit_signal.wait() => return,
+                    _ = sleep(Duration::from_millis(RESTART_DELAY_IN_MS)) => {},
+                };
+                warn!(
+                    "virtiofsd {} of {} crashed, restart it for {} times",
+                    pid, daemon.id, restarts
+                );
+                // the daemon listens on the same socket, remove the one left by the crashed daemon
+                remove_file(&daemon.config.socket_path)
+                    .await
+                    .unwrap_or_default();
+                match daemon.spawn().await {
+                    Ok(c) => {
+                        daemon.add_to_cgroup(daemon.pid().unwrap_or_default());
+                        child = Some(c);
+                    }
+                    Err(e) => {
+                        error!("failed to restart virtiofsd of {}: {}", daemon.id, e);
+                        daemon.kill_vmm();
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    // wait waits for the daemon to exit and tells whether it crashed, the daemon exits normally
+    // after the vmm disconnects. The exit status of the daemon recovered after the sandboxer
+    // restarts is unknown, so it is taken as crashed only if the vmm is still running.
+    async fn wait(&self, child: Option<Child>, pid: u32) -> bool {
+        let mut c = match child {
+            Some(c) => c,
+            None => {
+                wait_pid(pid as i32).await;
+                return self.vmm_alive();
+            }
+        };
+        match c.wait().await {
+            Ok(status) => {
+                if !status.success() {
+                    error!("virtiofsd {} exit {}", pid, status);
+                }
+                !status.success()
+            }
+            Err(e) => {
+                error!("virtiofsd {} wait error {}", pid, e);
+                true
+            }
+        }
+    }
+}
+
+fn process_start_time(pid: u32) -> Option<u64> {
+    procfs::process::Process::new(pid as i32)
+        .and_then(|p| p.stat())
+        .map(|s| s.starttime)
+        .ok()
+}
+
+// shared_start_time persists the start time shared with the supervising task
+mod shared_start_time {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        t: &Arc<AtomicU64>,
+        s: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        t.load(Ordering::SeqCst).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> std::result::Result<Arc<AtomicU64>, D::Error> {
+        Ok(Arc::new(AtomicU64::new(u64::deserialize(d)?)))
+    }
+}
+
+// shared_pid persists the pid shared with the supervising task
+mod shared_pid {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        pid: &Arc<AtomicU32>,
+        s: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        pid.load(Ordering::SeqCst).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> std::result::Result<Arc<AtomicU32>, D::Error> {
+        // the pid was optional before the daemon is supervised
+        let pid = Option::<u32>::deserialize(d)?.unwrap_or_default();
+        Ok(Arc::new(AtomicU32::new(pid)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtiofsdConfig;
+
+    #[test]
+    fn test_virtiofsd_args() {
+        let config = VirtiofsdConfig {
+            socket_path: "/run/kuasar/1/virtiofs.sock".to_string(),
+            shared_dir: "/run/kuasar/1/shared".to_string(),
+            xattr: true,
+            sandbox: Some("chroot".to_string()),
+            rlimit_nofile: 65536,
+            ..Default::default()
+        };
+        let args = config.to_args("/run/kuasar/1/virtiofs.log");
+        assert_eq!(
+            args.join(" "),
+            "--log-level info --cache never --thread-pool-size 4 \
+             --socket-path /run/kuasar/1/virtiofs.sock --shared-dir /run/kuasar/1/shared \
+             --syslog --xattr --sandbox chroot --rlimit-nofile 65536"
+        );
+
+        let config = VirtiofsdConfig {
+            path: "/usr/bin/vhost_user_fs".to_string(),
+            ..config
+        };
+        let args = config.to_args("/run/kuasar/1/virtiofs.log");
+        assert_eq!(
+            args.join(" "),
+            "-source /run/kuasar/1/shared -socket-path /run/kuasar/1/virtiofs.sock \
+             -D /run/kuasar/1/virtiofs.log -sandbox chroot -rlimit-nofile 65536"
+        );
+    }
+}
@@ -22,7 +22,7 @@ use containerd_sandbox::{
     SandboxOption,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::watch::Receiver;
+use tokio::sync::{mpsc, watch::Receiver};
 
 use crate::{
     device::{BusType, DeviceInfo},
@@ -66,11 +66,28 @@ pub trait VM: Serialize + Sync + Send {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()>;
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)>;
     async fn hot_detach(&mut self, id: &str) -> Result<()>;
+    async fn resize(&mut self, vcpus: u32, memory_in_mb: u64) -> Result<()>;
+    async fn resize_block(&mut self, id: &str, size: u64) -> Result<()>;
+    async fn checkpoint(&mut self, dir: &str) -> Result<()>;
+    async fn restore(&mut self, dir: &str) -> Result<u32>;
+    // unrestorable_devices returns the ids of the devices that the checkpoint can not restore
+    fn unrestorable_devices(&self) -> Vec<String>;
+    fn support_network_hotplug(&self) -> bool;
+    fn support_net_rate_limiter(&self) -> bool;
     async fn ping(&self) -> Result<()>;
     fn socket_address(&self) -> String;
     async fn wait_channel(&self) -> Option<Receiver<(u32, i128)>>;
     async fn vcpus(&self) -> Result<VcpuThreads>;
     fn pids(&self) -> Pids;
+    fn take_vm_events(&mut self) -> Option<mpsc::Receiver<VMEvent>>;
+    fn console_tail(&self) -> String;
+}
+
+// VMEvent is the notable event of the vm reported by the vmm, such as the guest panic
+#[derive(Debug, Clone)]
+pub struct VMEvent {
+    pub name: String,
+    pub detail: String,
 }
 
 #[macro_export]
@@ -87,6 +104,9 @@ macro_rules! impl_recoverable {
                     tx.send(wait_result).unwrap_or_default();
                 });
                 self.wait_chan = Some(rx);
+                if let Some(virtiofs_daemon) = self.virtiofs_daemon.as_mut() {
+                    virtiofs_daemon.recover();
+                }
                 Ok(())
             }
         }
@@ -116,6 +136,9 @@ pub struct HypervisorCommonConfig {
     pub firmware: String,
     #[serde(default)]
     pub enable_mem_prealloc: bool,
+    /// Add the pvpanic device, so that the guest panic is reported by the vmm
+    #[serde(default)]
+    pub enable_pvpanic: bool,
 }
 
 impl Default for HypervisorCommonConfig {
@@ -130,6 +153,7 @@ impl Default for HypervisorCommonConfig {
             kernel_params: "".to_string(),
             firmware: "".to_string(),
             enable_mem_prealloc: false,
+            enable_pvpanic: false,
         }
     }
 }
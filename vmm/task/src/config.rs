@@ -22,6 +22,7 @@ const LOG_LEVEL: &str = "task.log_level";
 const TASK_DEBUG: &str = "task.debug";
 const ENABLE_TRACING: &str = "task.enable_tracing";
 const DEBUG_SHELL: &str = "task.debug_shell";
+const LAZY_SHAREFS: &str = "task.lazy_sharefs";
 
 macro_rules! parse_cmdline {
     ($param:ident, $key:ident, $field:expr) => {
@@ -46,6 +47,7 @@ pub struct TaskConfig {
     pub(crate) debug: bool,
     pub(crate) enable_tracing: bool,
     pub(crate) debug_shell: String,
+    pub(crate) lazy_sharefs: bool,
 }
 
 impl Default for TaskConfig {
@@ -56,6 +58,7 @@ impl Default for TaskConfig {
             debug: false,
             enable_tracing: false,
             debug_shell: "/bin/bash".to_string(),
+            lazy_sharefs: false,
         }
     }
 }
@@ -74,6 +77,7 @@ impl TaskConfig {
             parse_cmdline!(param, TASK_DEBUG, config.debug);
             parse_cmdline!(param, ENABLE_TRACING, config.enable_tracing);
             parse_cmdline!(param, DEBUG_SHELL, config.debug_shell, String::from);
+            parse_cmdline!(param, LAZY_SHAREFS, config.lazy_sharefs);
         }
         Ok(config)
     }
@@ -14,15 +14,24 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, fs::read_link, os::unix::prelude::FromRawFd, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::read_link,
+    os::unix::prelude::{AsRawFd, FromRawFd},
+    sync::Arc,
+};
 
 use containerd_shim::{other, Error, Result};
 use lazy_static::lazy_static;
-use log::debug;
+use log::{debug, warn};
 use netlink_sys::{protocols, SocketAddr, TokioSocket};
-use tokio::sync::{
-    mpsc::{channel, Receiver, Sender},
-    Mutex,
+use nix::{ioctl_none, ioctl_write_ptr};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
 };
 
 pub const U_EVENT_ACTION: &str = "ACTION";
@@ -39,6 +48,8 @@ lazy_static! {
         let converters = vec![
             convert_to_scsi_device as DeviceConverter,
             convert_to_blk_device as DeviceConverter,
+            convert_to_mmio_blk_device as DeviceConverter,
+            convert_to_pmem_device as DeviceConverter,
         ];
         converters
     };
@@ -206,21 +217,41 @@ impl DeviceMonitor {
                 let real_path = real_path.to_str().unwrap();
                 let real_path_parts: Vec<&str> = real_path.split('/').collect();
                 for part in real_path_parts {
-                    if part.starts_with("0000:") {
-                        let device = Device {
-                            path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
-                            addr: part.to_string(),
-                            r#type: DeviceType::Blk,
-                        };
-                        debug!("scan add device {:?} of devpath {}", device, part);
-                        self.internal
-                            .lock()
-                            .await
-                            .add_device(part.to_string(), device)
-                            .await;
-                    }
+                    // vdc -> ../../devices/platform/a003e00.virtio_mmio/virtio2/block/vdc/
+                    let r#type = if part.starts_with("0000:") {
+                        DeviceType::Blk
+                    } else if is_virtio_mmio(part) {
+                        DeviceType::MmioBlk
+                    } else {
+                        continue;
+                    };
+                    let device = Device {
+                        path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+                        addr: part.to_string(),
+                        r#type,
+                    };
+                    debug!("scan add device {:?} of devpath {}", device, part);
+                    self.internal
+                        .lock()
+                        .await
+                        .add_device(part.to_string(), device)
+                        .await;
                 }
             }
+            // pmem0 -> ../../devices/LNXSYSTM:00/LNXSYBUS:00/ACPI0012:00/ndbus0/region0/pmem0/block/pmem0
+            if dev_name.starts_with("pmem") && metadata.is_symlink() {
+                let device = Device {
+                    path: format!("{}/{}", SYSTEM_DEV_PATH, dev_name),
+                    addr: dev_name.to_string(),
+                    r#type: DeviceType::Pmem,
+                };
+                debug!("scan add device {:?}", device);
+                self.internal
+                    .lock()
+                    .await
+                    .add_device(dev_name.to_string(), device)
+                    .await;
+            }
         }
     }
 }
@@ -297,11 +328,36 @@ pub const SYSFS_SCSI_DEVICE_PATH: &str = "/sys/class/scsi_device";
 pub const SYSFS_BLK_DEVICE_PATH: &str = "/sys/class/block";
 pub const SYSFS_PCI_BUS_RESCAN_FILE: &str = "/sys/bus/pci/rescan";
 pub const SYSTEM_DEV_PATH: &str = "/dev";
+pub const SYSFS_CPU_PATH: &str = "/sys/devices/system/cpu";
+pub const SYSFS_MEMORY_PATH: &str = "/sys/devices/system/memory";
+pub const RANDOM_DEV_PATH: &str = "/dev/random";
+
+// ioctl requests of the random device, defined in include/uapi/linux/random.h
+const RANDOM_IOC_MAGIC: u8 = b'R';
+const RANDOM_IOC_ADD_TO_ENT_CNT: u8 = 0x01;
+const RANDOM_IOC_RESEED_CRNG: u8 = 0x07;
+
+// RNDADDTOENTCNT
+ioctl_write_ptr!(
+    ioctl_add_to_ent_cnt,
+    RANDOM_IOC_MAGIC,
+    RANDOM_IOC_ADD_TO_ENT_CNT,
+    libc::c_int
+);
+// RNDRESEEDCRNG
+ioctl_none!(ioctl_reseed_crng, RANDOM_IOC_MAGIC, RANDOM_IOC_RESEED_CRNG);
+
+const SYSFS_ONLINE_FILE: &str = "online";
+const SYSFS_STATE_FILE: &str = "state";
+const MEMORY_BLOCK_OFFLINE: &str = "offline";
+const MEMORY_BLOCK_ONLINE: &str = "online";
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub enum DeviceType {
     Blk,
     Scsi,
+    MmioBlk,
+    Pmem,
 }
 
 #[derive(Clone, Debug)]
@@ -365,6 +421,55 @@ pub fn convert_to_blk_device(event: &Uevent) -> Option<Device> {
     }
 }
 
+// the virtio-mmio devices are named by the address and "virtio-mmio" in the device tree,
+// or by the index in the kernel cmdline, or by the hid "LNRO0005" in the acpi table.
+fn is_virtio_mmio(node: &str) -> bool {
+    node.contains("virtio_mmio") || node.contains("virtio-mmio") || node.starts_with("LNRO0005")
+}
+
+// convert_to_mmio_blk_device converts the uevent of virtio-mmio block devices, whose addr
+// is the name of the platform device, such as "a003e00.virtio_mmio" or "virtio-mmio.0".
+pub fn convert_to_mmio_blk_device(event: &Uevent) -> Option<Device> {
+    let path_parts: Vec<_> = event.devpath.split('/').collect();
+    let length = path_parts.len();
+    if path_parts.len() > 4
+        && event.subsystem == "block"
+        && is_virtio_mmio(path_parts[length - 4])
+        && path_parts[length - 3].starts_with("virtio")
+        && path_parts[length - 2] == "block"
+        && !event.devname.is_empty()
+    {
+        Some(Device {
+            path: format!("{}/{}", SYSTEM_DEV_PATH, &event.devname),
+            addr: path_parts[length - 4].to_string(),
+            r#type: DeviceType::MmioBlk,
+        })
+    } else {
+        None
+    }
+}
+
+// convert_to_pmem_device converts the uevent of the pmem devices of nvdimm,
+// whose addr is the device name, such as "pmem0".
+pub fn convert_to_pmem_device(event: &Uevent) -> Option<Device> {
+    let path_parts: Vec<_> = event.devpath.split('/').collect();
+    let length = path_parts.len();
+    if path_parts.len() > 3
+        && event.subsystem == "block"
+        && path_parts[length - 3].starts_with("pmem")
+        && path_parts[length - 2] == "block"
+        && event.devname.starts_with("pmem")
+    {
+        Some(Device {
+            path: format!("{}/{}", SYSTEM_DEV_PATH, &event.devname),
+            addr: event.devname.to_string(),
+            r#type: DeviceType::Pmem,
+        })
+    } else {
+        None
+    }
+}
+
 pub async fn scan_scsi_bus(scsi_addr: &str) -> containerd_shim::Result<()> {
     let tokens: Vec<&str> = scsi_addr.split(':').collect();
     if tokens.len() != 2 {
@@ -392,3 +497,91 @@ pub async fn scan_scsi_bus(scsi_addr: &str) -> containerd_shim::Result<()> {
 
     Ok(())
 }
+
+// online_cpus brings the offline cpus online until there are `nb_cpus` online cpus,
+// all of the offline cpus are brought online if `nb_cpus` is 0,
+// returns the number of online cpus.
+pub async fn online_cpus(nb_cpus: u32) -> Result<u32> {
+    let mut cpus = vec![];
+    let mut entries = tokio::fs::read_dir(SYSFS_CPU_PATH)
+        .await
+        .map_err(|e| other!("failed to read dir {}: {}", SYSFS_CPU_PATH, e))?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(index) = name.strip_prefix("cpu").and_then(|x| x.parse::<u32>().ok()) {
+            cpus.push(index);
+        }
+    }
+    cpus.sort();
+
+    let mut online = 0;
+    let mut offline_cpus = vec![];
+    for index in cpus {
+        let online_path = format!("{}/cpu{}/{}", SYSFS_CPU_PATH, index, SYSFS_ONLINE_FILE);
+        // cpu without online file can not be offline, cpu0 for example.
+        match tokio::fs::read_to_string(&online_path).await {
+            Ok(status) if status.trim() == "0" => offline_cpus.push(online_path),
+            _ => online += 1,
+        }
+    }
+
+    for online_path in offline_cpus {
+        if nb_cpus > 0 && online >= nb_cpus {
+            break;
+        }
+        if let Err(e) = tokio::fs::write(&online_path, "1").await {
+            warn!("failed to online cpu by {}: {}", online_path, e);
+            continue;
+        }
+        online += 1;
+    }
+    debug!("{} cpus are online in the vm", online);
+    Ok(online)
+}
+
+// online_memory brings all the offline memory blocks online,
+// the hot plugged memory may not be onlined automatically by the kernel.
+pub async fn online_memory() -> Result<()> {
+    let mut entries = tokio::fs::read_dir(SYSFS_MEMORY_PATH)
+        .await
+        .map_err(|e| other!("failed to read dir {}: {}", SYSFS_MEMORY_PATH, e))?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with("memory") {
+            continue;
+        }
+        let state_path = format!("{}/{}/{}", SYSFS_MEMORY_PATH, name, SYSFS_STATE_FILE);
+        match tokio::fs::read_to_string(&state_path).await {
+            Ok(state) if state.trim() == MEMORY_BLOCK_OFFLINE => {
+                if let Err(e) = tokio::fs::write(&state_path, MEMORY_BLOCK_ONLINE).await {
+                    warn!("failed to online memory block by {}: {}", state_path, e);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// reseed_random_dev mixes the data into the random pool of the kernel and reseeds the crng,
+// vms restored from the same template would generate the same random numbers otherwise.
+pub async fn reseed_random_dev(data: &[u8]) -> Result<()> {
+    let mut f = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(RANDOM_DEV_PATH)
+        .await
+        .map_err(|e| other!("failed to open {}: {}", RANDOM_DEV_PATH, e))?;
+    f.write_all(data)
+        .await
+        .map_err(|e| other!("failed to write to {}: {}", RANDOM_DEV_PATH, e))?;
+
+    let fd = f.as_raw_fd();
+    let entropy_bits = (data.len() * 8) as libc::c_int;
+    // SAFETY: the fd of the random device is kept open by f, and the pointer is to a c_int
+    // living on the stack across the call, which is the argument the request reads.
+    unsafe { ioctl_add_to_ent_cnt(fd, &entropy_bits) }
+        .map_err(|e| other!("failed to add entropy count: {}", e))?;
+    // SAFETY: the fd of the random device is kept open by f, and the request takes no argument.
+    unsafe { ioctl_reseed_crng(fd) }.map_err(|e| other!("failed to reseed crng: {}", e))?;
+    Ok(())
+}
@@ -439,7 +439,7 @@ pub async fn convert_stdio(stdio: &Stdio) -> containerd_shim::Result<Stdio> {
 }
 
 async fn get_io_file_name(name: &str) -> containerd_shim::Result<String> {
-    if !name.is_empty() && !name.contains(VSOCK) {
+    if !name.is_empty() && !name.contains(VSOCK) && !name.contains(STREAMING) {
         find_serial_dev(name).await
     } else {
         Ok(name.to_string())
@@ -44,7 +44,7 @@ use tracing_subscriber::{
 };
 use vmm_common::{
     api::{sandbox_ttrpc::create_sandbox_service, streaming_ttrpc::create_streaming},
-    mount::mount,
+    mount::{get_mount_type, mount},
     trace, ETC_RESOLV, IPC_NAMESPACE, KUASAR_STATE_DIR, PID_NAMESPACE, RESOLV_FILENAME,
     UTS_NAMESPACE,
 };
@@ -157,16 +157,10 @@ async fn initialize() -> anyhow::Result<TaskConfig> {
 
     info!("Task server start with config: {:?}", config);
 
-    match &*config.sharefs_type {
-        "9p" => {
-            mount_static_mounts(SHAREFS_9P_MOUNTS.clone()).await?;
-        }
-        "virtiofs" => {
-            mount_static_mounts(SHAREFS_VIRTIOFS_MOUNTS.clone()).await?;
-        }
-        _ => {
-            warn!("sharefs_type should be either 9p or virtiofs");
-        }
+    // The sharefs of a vm booted for template is mounted when the sandbox is set up,
+    // as the mounted sharefs can not be saved with the vm state.
+    if !config.lazy_sharefs {
+        mount_sharefs(&config.sharefs_type).await?;
     }
     if config.debug {
         debug!("listen vsock port 1025 for debug console");
@@ -378,7 +372,30 @@ async fn init_vm_rootfs() -> Result<()> {
 // Continue to do initialization that depend on shared path.
 // such as adding guest hook, preparing sandbox files and namespaces.
 async fn late_init_call() -> Result<()> {
-    // Setup DNS, bind mount to /etc/resolv.conf
+    setup_dns()
+}
+
+pub(crate) async fn mount_sharefs(sharefs_type: &str) -> Result<()> {
+    match sharefs_type {
+        "9p" => {
+            mount_static_mounts(SHAREFS_9P_MOUNTS.clone()).await?;
+        }
+        "virtiofs" => {
+            mount_static_mounts(SHAREFS_VIRTIOFS_MOUNTS.clone()).await?;
+        }
+        _ => {
+            warn!("sharefs_type should be either 9p or virtiofs");
+        }
+    }
+    Ok(())
+}
+
+// Setup DNS, bind mount to /etc/resolv.conf,
+// it is skipped if the /etc/resolv.conf is already a mount point.
+pub(crate) fn setup_dns() -> Result<()> {
+    if get_mount_type(ETC_RESOLV).is_ok() {
+        return Ok(());
+    }
     let dns_file = Path::new(KUASAR_STATE_DIR).join(RESOLV_FILENAME);
     if dns_file.exists() {
         nix::mount::mount(
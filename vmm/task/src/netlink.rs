@@ -21,6 +21,7 @@ use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
 use netlink_packet_route::{
     address::{AddressAttribute, AddressMessage},
     link::{LinkAttribute, LinkFlag, LinkMessage},
+    neighbour::{NeighbourMessage, NeighbourState},
     route::{
         RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteProtocol, RouteScope,
         RouteType,
@@ -29,7 +30,7 @@ use netlink_packet_route::{
 };
 use nix::errno::Errno;
 use rtnetlink::{new_connection, IpVersion};
-use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Route};
+use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Neighbor, Route};
 
 /// Search criteria to use when looking for a link in `find_link`.
 pub enum LinkFilter<'a> {
@@ -267,6 +268,62 @@ impl Handle {
         Ok(())
     }
 
+    /// Replaces all the permanent neighbors of the links except loopback with the list.
+    pub async fn update_neighbors<I>(&mut self, list: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Neighbor>,
+    {
+        let old_neighbors: Vec<NeighbourMessage> = self
+            .handle
+            .neighbours()
+            .get()
+            .execute()
+            .try_collect()
+            .await
+            .map_err(other_error!(e, "failed to query neighbors"))?;
+        for neighbor in old_neighbors {
+            if neighbor.header.state != NeighbourState::Permanent {
+                continue;
+            }
+            let link = self
+                .find_link(LinkFilter::Index(neighbor.header.ifindex))
+                .await?;
+            if link.name() == "lo" {
+                continue;
+            }
+            self.handle
+                .neighbours()
+                .del(neighbor)
+                .execute()
+                .await
+                .map_err(other_error!(e, "failed to delete neighbor"))?;
+        }
+
+        for neighbor in list {
+            let link = self.find_link(LinkFilter::Name(&neighbor.device)).await?;
+            let ip = IpAddr::from_str(&neighbor.ip_address).map_err(other_error!(
+                e,
+                format!("invalid neighbor ip address: {}", neighbor.ip_address)
+            ))?;
+            let mut request = self
+                .handle
+                .neighbours()
+                .add(link.index(), ip)
+                .state(NeighbourState::from(neighbor.state as u16))
+                .replace();
+            if !neighbor.lladdr.is_empty() {
+                let lladdr = parse_mac_address(&neighbor.lladdr)?;
+                request = request.link_local_address(&lladdr);
+            }
+            request.execute().await.map_err(other_error!(
+                e,
+                format!("failed to add neighbor {}", neighbor.ip_address)
+            ))?;
+        }
+
+        Ok(())
+    }
+
     async fn query_routes(&self, ip_version: Option<IpVersion>) -> Result<Vec<RouteMessage>> {
         let list = if let Some(ip_version) = ip_version {
             self.handle
@@ -14,7 +14,13 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, os::fd::AsRawFd, path::Path, process::exit, time::Duration};
+use std::{
+    collections::HashMap,
+    os::{fd::AsRawFd, unix::fs::PermissionsExt},
+    path::Path,
+    process::exit,
+    time::Duration,
+};
 
 use containerd_sandbox::{cri::api::v1::NamespaceMode, PodSandboxConfig};
 use containerd_shim::{
@@ -31,16 +37,24 @@ use nix::{
 use tokio::fs::File;
 use vmm_common::{
     mount::{mount, unmount},
-    storage::{Storage, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE, DRIVERSCSITYPE},
+    storage::{
+        OverlayLayer, Storage, DRIVER9PTYPE, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE, DRIVERLOCALTYPE,
+        DRIVERMMIOBLKTYPE, DRIVERNVDIMMTYPE, DRIVEROVERLAYTYPE, DRIVERSCSITYPE, DRIVERVIRTIOFSTYPE,
+    },
     HOSTNAME_FILENAME, IPC_NAMESPACE, KUASAR_STATE_DIR, PID_NAMESPACE, SANDBOX_NS_PATH,
     UTS_NAMESPACE,
 };
 
 use crate::{
-    device::{scan_scsi_bus, Device, DeviceMatcher, DeviceMonitor, DeviceType},
+    device::{scan_scsi_bus, Device, DeviceMatcher, DeviceMonitor, DeviceType, SYSTEM_DEV_PATH},
     CLONE_FLAG_TABLE,
 };
 
+// the lower layers of the overlay storages are mounted in this dir
+const OVERLAY_LAYERS_DIR: &str = "/run/kuasar/storage/layers";
+// the default mode of the local storages, which are shared by the containers of the pod
+const LOCAL_STORAGE_DEFAULT_MODE: u32 = 0o777;
+
 pub struct SandboxResources {
     storages: Vec<Storage>,
     device_monitor: DeviceMonitor,
@@ -87,10 +101,37 @@ impl SandboxResources {
                 mount_storage(&storage).await?;
             }
             DRIVERBLKTYPE => {
-                self.handle_blk_storage(&mut storage).await?;
+                self.handle_device_storage(&mut storage, DeviceType::Blk)
+                    .await?;
+            }
+            DRIVERMMIOBLKTYPE => {
+                self.handle_device_storage(&mut storage, DeviceType::MmioBlk)
+                    .await?;
+            }
+            DRIVERNVDIMMTYPE => {
+                self.handle_nvdimm_storage(&mut storage).await?;
+            }
+            DRIVER9PTYPE => {
+                handle_9p_storage(&mut storage).await?;
+            }
+            DRIVERVIRTIOFSTYPE => {
+                storage.fstype = "virtiofs".to_string();
+                mount_storage(&storage).await?;
+            }
+            DRIVERLOCALTYPE => {
+                handle_local_storage(&storage).await?;
+            }
+            DRIVEROVERLAYTYPE => {
+                if let Err(e) = self.handle_overlay_storage(&mut storage).await {
+                    unmount_overlay_layers(&storage).await;
+                    return Err(e);
+                }
             }
             _ => {
-                unimplemented!("storage driver not implemented {}", storage.driver)
+                return Err(Error::Unimplemented(format!(
+                    "storage driver {} not supported",
+                    storage.driver
+                )));
             }
         }
         self.storages.push(storage);
@@ -109,9 +150,17 @@ impl SandboxResources {
         });
         for s in removed {
             debug!("unmount storage {:?}", s);
-            if let Err(_e) = unmount_storage(&s).await {
+            let res = if s.driver == DRIVERLOCALTYPE {
+                remove_local_storage(&s).await
+            } else {
+                unmount_storage(&s).await
+            };
+            if let Err(_e) = res {
                 warn!("failed to unmount storage {:?}", s);
             }
+            if s.driver == DRIVEROVERLAYTYPE {
+                unmount_overlay_layers(&s).await;
+            }
         }
         let _mounts = tokio::fs::read_to_string("/proc/mounts")
             .await
@@ -130,12 +179,81 @@ impl SandboxResources {
         Ok(())
     }
 
-    async fn handle_blk_storage(&mut self, storage: &mut Storage) -> Result<()> {
-        // Retrieve the device path from pci address.
-        let device = self.get_device(&storage.source, DeviceType::Blk).await?;
+    async fn handle_device_storage(&mut self, storage: &mut Storage, ty: DeviceType) -> Result<()> {
+        // Retrieve the device path from pci address, or the name of the virtio-mmio device.
+        let device = self.get_device(&storage.source, ty).await?;
         let path = device.path.to_string();
         storage.source = path;
 
+        mount_storage(storage).await?;
+        // the root of the filesystem formatted by the host, like the emptyDir volumes,
+        // takes the mode and the ownership in the driver options
+        let (mode, uid, gid) = parse_dir_options(&storage.driver_options)?;
+        set_dir_attrs(&storage.mount_point, mode, uid, gid).await?;
+        Ok(())
+    }
+
+    // handle_nvdimm_storage mounts the pmem device of the nvdimm with dax,
+    // so that the page cache of the guest is bypassed.
+    async fn handle_nvdimm_storage(&mut self, storage: &mut Storage) -> Result<()> {
+        let name = storage
+            .source
+            .trim_start_matches(SYSTEM_DEV_PATH)
+            .trim_start_matches('/')
+            .to_string();
+        let device = self.get_device(&name, DeviceType::Pmem).await?;
+        storage.source = device.path.to_string();
+        if matches!(&*storage.fstype, "ext4" | "xfs") && !storage.options.iter().any(|x| x == "dax")
+        {
+            storage.options.push("dax".to_string());
+        }
+
+        mount_storage(storage).await?;
+        Ok(())
+    }
+
+    // handle_overlay_storage mounts the lower layers on the attached block devices read only,
+    // and then mounts the overlay of them, the upper layer is in the options if any.
+    async fn handle_overlay_storage(&mut self, storage: &mut Storage) -> Result<()> {
+        let mut lowerdirs = vec![];
+        for (i, o) in storage.driver_options.iter().enumerate() {
+            let layer =
+                OverlayLayer::parse(o).ok_or_else(|| other!("invalid overlay layer {}", o))?;
+            let ty = match &*layer.driver {
+                DRIVERSCSITYPE => {
+                    scan_scsi_bus(&layer.addr).await?;
+                    DeviceType::Scsi
+                }
+                DRIVERBLKTYPE => DeviceType::Blk,
+                DRIVERMMIOBLKTYPE => DeviceType::MmioBlk,
+                _ => {
+                    return Err(other!(
+                        "overlay layer driver {} not supported",
+                        layer.driver
+                    ))
+                }
+            };
+            let device = self.get_device(&layer.addr, ty).await?;
+            let target = format!("{}/{}/{}", OVERLAY_LAYERS_DIR, storage.id, i);
+            tokio::fs::create_dir_all(&target)
+                .await
+                .map_err(other_error!(e, format!("failed to create dir {}", target)))?;
+            mount(
+                Some(&layer.fstype),
+                Some(&device.path),
+                &["ro".to_string()],
+                &target,
+            )
+            .map_err(other_error!(e, ""))?;
+            lowerdirs.push(format!("{}{}", target, layer.path.trim_end_matches('/')));
+        }
+        if lowerdirs.is_empty() {
+            return Err(other!("no lower layers of overlay storage {}", storage.id));
+        }
+        storage
+            .options
+            .push(format!("lowerdir={}", lowerdirs.join(":")));
+
         mount_storage(storage).await?;
         Ok(())
     }
@@ -191,6 +309,98 @@ async fn unmount_storage(storage: &Storage) -> Result<()> {
     Ok(())
 }
 
+// handle_9p_storage mounts the 9p share of the storage, whose source is the mount tag.
+async fn handle_9p_storage(storage: &mut Storage) -> Result<()> {
+    storage.fstype = "9p".to_string();
+    if !storage.options.iter().any(|x| x.starts_with("trans=")) {
+        storage.options.push("trans=virtio".to_string());
+        storage.options.push("version=9p2000.L".to_string());
+    }
+    mount_storage(storage).await
+}
+
+// handle_local_storage creates the dir of the local storage in the guest,
+// with the mode and the ownership in the options "mode=", "uid=" and "gid=".
+async fn handle_local_storage(storage: &Storage) -> Result<()> {
+    let (mode, uid, gid) = parse_dir_options(&storage.options)?;
+    tokio::fs::create_dir_all(&storage.mount_point)
+        .await
+        .map_err(other_error!(
+            e,
+            format!("failed to create dir {}", storage.mount_point)
+        ))?;
+    // the mode is set explicitly as it is masked by the umask when created
+    set_dir_attrs(
+        &storage.mount_point,
+        Some(mode.unwrap_or(LOCAL_STORAGE_DEFAULT_MODE)),
+        uid,
+        gid,
+    )
+    .await
+}
+
+// parse_dir_options parses the mode in octal, the uid and the gid of the dir,
+// in the options "mode=", "uid=" and "gid=".
+fn parse_dir_options(options: &[String]) -> Result<(Option<u32>, Option<u32>, Option<u32>)> {
+    let mut mode = None;
+    let mut uid = None;
+    let mut gid = None;
+    for o in options {
+        if let Some(v) = o.strip_prefix("mode=") {
+            mode =
+                Some(u32::from_str_radix(v, 8).map_err(other_error!(e, format!("invalid {}", o)))?);
+        } else if let Some(v) = o.strip_prefix("uid=") {
+            uid = Some(
+                v.parse()
+                    .map_err(other_error!(e, format!("invalid {}", o)))?,
+            );
+        } else if let Some(v) = o.strip_prefix("gid=") {
+            gid = Some(
+                v.parse()
+                    .map_err(other_error!(e, format!("invalid {}", o)))?,
+            );
+        }
+    }
+    Ok((mode, uid, gid))
+}
+
+async fn set_dir_attrs(
+    dir: &str,
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> Result<()> {
+    if let Some(m) = mode {
+        tokio::fs::set_permissions(dir, std::fs::Permissions::from_mode(m))
+            .await
+            .map_err(other_error!(e, format!("failed to set mode of {}", dir)))?;
+    }
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(dir, uid, gid)
+            .map_err(other_error!(e, format!("failed to set owner of {}", dir)))?;
+    }
+    Ok(())
+}
+
+async fn remove_local_storage(storage: &Storage) -> Result<()> {
+    tokio::fs::remove_dir_all(&storage.mount_point)
+        .await
+        .map_err(other_error!(e, ""))
+}
+
+async fn unmount_overlay_layers(storage: &Storage) {
+    let dir = format!("{}/{}", OVERLAY_LAYERS_DIR, storage.id);
+    for i in 0..storage.driver_options.len() {
+        let target = format!("{}/{}", dir, i);
+        if Path::new(&target).exists() {
+            unmount(&target, 0).unwrap_or_else(|e| warn!("failed to unmount {}: {}", target, e));
+        }
+    }
+    tokio::fs::remove_dir_all(&dir)
+        .await
+        .unwrap_or_else(|e| warn!("failed to remove dir {}: {}", dir, e));
+}
+
 async fn ensure_destination_file_exists(path: &Path) -> Result<()> {
     if path.is_file() {
         return Ok(());
@@ -377,7 +587,9 @@ pub async fn setup_sandbox(config: &PodSandboxConfig) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::convert_sysctl_to_proc_path;
+    use vmm_common::storage::OverlayLayer;
+
+    use super::{convert_sysctl_to_proc_path, parse_dir_options};
 
     #[test]
     fn test_convert_sysctl_to_proc_path() {
@@ -390,4 +602,28 @@ mod tests {
             "/proc/sys/net/ipv4/tcp_keepalive_time"
         );
     }
+
+    #[test]
+    fn test_parse_dir_options() {
+        let options = vec!["mode=0750".to_string(), "gid=2000".to_string()];
+        assert_eq!(
+            parse_dir_options(&options).unwrap(),
+            (Some(0o750), None, Some(2000))
+        );
+        assert_eq!(parse_dir_options(&[]).unwrap(), (None, None, None));
+        assert!(parse_dir_options(&["mode=rwx".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_overlay_layer() {
+        let layer = OverlayLayer::parse("blk,0000:00:05.0,erofs,/fs").unwrap();
+        assert_eq!(layer.driver, "blk");
+        assert_eq!(layer.addr, "0000:00:05.0");
+        assert_eq!(layer.fstype, "erofs");
+        assert_eq!(layer.path, "/fs");
+        assert_eq!(layer.to_string(), "blk,0000:00:05.0,erofs,/fs");
+
+        assert!(OverlayLayer::parse("blk,0000:00:05.0,/fs").is_none());
+        assert!(OverlayLayer::parse("blk,,erofs,/").is_none());
+    }
 }
@@ -45,13 +45,27 @@ use vmm_common::{
         empty::Empty,
         events::Envelope,
         sandbox::{
-            CheckRequest, ExecVMProcessRequest, ExecVMProcessResponse, SetupSandboxRequest,
-            SyncClockPacket, UpdateInterfacesRequest, UpdateRoutesRequest,
+            CheckRequest, ExecVMProcessRequest, ExecVMProcessResponse, OnlineCPUMemRequest,
+            ReseedRandomDevRequest, SetupSandboxRequest, SyncClockPacket, UpdateInterfacesRequest,
+            UpdateNeighborsRequest, UpdateRoutesRequest,
         },
     },
+    mount::get_mount_type,
+    KUASAR_STATE_DIR,
 };
 
-use crate::{netlink::Handle, sandbox::setup_sandbox, NAMESPACE};
+use crate::{
+    config::TaskConfig,
+    device::{online_cpus, online_memory, reseed_random_dev},
+    mount_sharefs,
+    netlink::Handle,
+    sandbox::setup_sandbox,
+    setup_dns, NAMESPACE,
+};
+
+// Hot plugged cpus may not show up in sysfs immediately, retry until all of them are online.
+const ONLINE_CPU_RETRY_TIMES: u32 = 50;
+const ONLINE_CPU_RETRY_INTERVAL_IN_MS: u64 = 100;
 
 pub struct SandboxService {
     pub namespace: String,
@@ -99,6 +113,19 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         Ok(Empty::new())
     }
 
+    async fn update_neighbors(
+        &self,
+        _ctx: &TtrpcContext,
+        req: UpdateNeighborsRequest,
+    ) -> TtrpcResult<Empty> {
+        self.handle
+            .lock()
+            .await
+            .update_neighbors(req.neighbors)
+            .await?;
+        Ok(Empty::new())
+    }
+
     async fn setup_sandbox(
         &self,
         _ctx: &TtrpcContext,
@@ -111,6 +138,7 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
                         .map_err(|e| {
                             ttrpc::Error::Others(format!("convert PodSandboxConfig failed: {}", e))
                         })?;
+                setup_shared_path().await?;
                 setup_sandbox(&config).await?;
             }
             _ => {
@@ -180,6 +208,38 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         Ok(resp)
     }
 
+    async fn online_cpu_mem(
+        &self,
+        _ctx: &TtrpcContext,
+        req: OnlineCPUMemRequest,
+    ) -> TtrpcResult<Empty> {
+        let mut online = 0;
+        for _i in 0..ONLINE_CPU_RETRY_TIMES {
+            online = online_cpus(req.nb_cpus).await?;
+            if req.nb_cpus == 0 || online >= req.nb_cpus {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(ONLINE_CPU_RETRY_INTERVAL_IN_MS)).await;
+        }
+        if req.nb_cpus > 0 && online < req.nb_cpus {
+            return Err(other!("only {} of {} cpus are online", online, req.nb_cpus).into());
+        }
+
+        if !req.cpu_only {
+            online_memory().await?;
+        }
+        Ok(Empty::new())
+    }
+
+    async fn reseed_random_dev(
+        &self,
+        _ctx: &TtrpcContext,
+        req: ReseedRandomDevRequest,
+    ) -> TtrpcResult<Empty> {
+        reseed_random_dev(req.data.as_slice()).await?;
+        Ok(Empty::new())
+    }
+
     async fn get_events(&self, _ctx: &TtrpcContext, _: Empty) -> TtrpcResult<Envelope> {
         while let Some((topic, event)) = self.rx.lock().await.recv().await {
             debug!("received event {:?}", event);
@@ -201,6 +261,16 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
     }
 }
 
+// setup_shared_path mounts the sharefs if it is not mounted at boot, and setup the DNS
+// as the resolv.conf in the shared path may be not ready when the vm boots.
+async fn setup_shared_path() -> Result<()> {
+    let config = TaskConfig::new().await?;
+    if config.lazy_sharefs && get_mount_type(KUASAR_STATE_DIR).is_err() {
+        mount_sharefs(&config.sharefs_type).await?;
+    }
+    setup_dns()
+}
+
 async fn do_execute_cmd(cmd_args: &str, stdin: &[u8]) -> Result<String> {
     let mut cmd = tokio::process::Command::new("/bin/bash");
     cmd.arg("-c");
@@ -162,6 +162,13 @@ impl api::streaming_ttrpc::Streaming for Service {
             ));
         };
         debug!("handle stream with id {}", stream_id);
+        // the io channel is created before the ack, so the process started after that
+        // can always find its io channel
+        self.ios
+            .lock()
+            .await
+            .entry(stream_id.to_string())
+            .or_insert(IOChannel::new());
         let a = new_any!(Empty);
         stream.send(&a).await?;
 
@@ -299,7 +306,20 @@ impl Service {
                 }
                 window += WINDOW_SIZE;
             }
-            match stream.recv().await? {
+            // the sender is returned to the io channel if the connection is broken rather than
+            // closed by the client, so that the stdin is not closed and can be streamed again
+            // by a new stream of the same id, such as the one after the sandboxer restarts.
+            let r = match stream.recv().await {
+                Ok(r) => r,
+                Err(e) => {
+                    debug!("failed to receive data of stream {}, {}", stream_id, e);
+                    if let Some(c) = self.ios.lock().await.get_mut(stream_id) {
+                        c.sender = Some(sender);
+                    }
+                    return Err(e);
+                }
+            };
+            match r {
                 Some(d) => {
                     let data_bytes = {
                         let mut data = Data::new();